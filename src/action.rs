@@ -1,6 +1,6 @@
 use std::fmt;
 
-use crate::{database::schema::{city::City, country::Country, ip::IP, isp::ISP, message::MiniMessage, region::Region}, app::models::IOMessage, themes::Themes};
+use crate::{database::schema::{ban_event::BanEvent, city::City, country::Country, asn::ASN, ip::IP, isp::ISP, message::MiniMessage, rdns::ReverseDns, region::Region}, app::models::IOMessage, themes::Themes};
 use rusqlite::{Connection, Result};
 
 
@@ -67,7 +67,7 @@ pub enum HomeAction {
 }
 
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Action {
   Tick,
   Render,
@@ -117,10 +117,18 @@ pub enum Action {
 
   // Core
   IONotify(IOMessage), // String IOMessage
+  /// Historic line read from rotated logs or the journal while catching up
+  Backfill(IOMessage),
   //FetchGeo(gen_structs::Geodata),
 
   // second string is the line, bool is if it came from IO or DB
   GotGeo(IP, IOMessage, bool),
+  /// Fresh lookup of an IP whose stored location expired.
+  GeoRefreshed(IP),
+  /// Re-lookup of an expired location that found nothing or failed, 0: IP
+  GeoRefreshFailed(String),
+  /// Reverse lookup of an IP, 0: IP
+  GotReverseDns(String, ReverseDns),
   //
   /// 0: IP, 1: Line, 2: true if from DB, false if fresh
   PassGeo(IP, IOMessage, bool),
@@ -130,13 +138,17 @@ pub enum Action {
   EnterBan,
   ExitBan,
   RequestBan,
-  BanIP(IP),
+  /// 0: IP, 1: Jail
+  BanIP(IP, String),
+  /// 0: IP, 1: Jail, 2: the blocked locations it comes from
+  GeoBlockIP(IP, String, String),
   Banned(bool),
 
   EnterUnban,
   ExitUnban,
   RequestUnban,
-  UnbanIP(IP),
+  /// 0: IP, 1: Jail
+  UnbanIP(IP, String),
   Unbanned(bool),
 
   Block(IP),
@@ -145,9 +157,10 @@ pub enum Action {
   StartF2BWatcher,
   StopF2BWatcher,
   StoppedF2BWatcher,
-  StartJCtlWatcher,
-  StopJCtlWatcher,
-  StoppedJCtlWatcher,
+  /// 0: name of the configured journal source
+  StartJCtlWatcher(String),
+  StopJCtlWatcher(String),
+  StoppedJCtlWatcher(String),
 
   // Startup
   StartupConnect,
@@ -156,6 +169,7 @@ pub enum Action {
   StartupCreateDB,
   StartupDone,
   StartupGotHome(IP),
+  StartupGotJails(Vec<String>),
   // Select Theme, by themename 
   SelectTheme(String),
 
@@ -167,24 +181,33 @@ pub enum Action {
   StatsGetISPs,
   StatsGetRegions,
   StatsGetCities,
+  StatsGetASNs,
 
   StatsGotCountry(Country, Vec<MiniMessage>),
   StatsGotISP(ISP, Vec<MiniMessage>),
   StatsGotRegion(Region, Vec<MiniMessage>),
   StatsGotCity(City, Vec<MiniMessage>),
+  StatsGotASN(ASN, Vec<MiniMessage>),
 
   StatsBlockCountry(Country),
   StatsBlockRegion(Region),
   StatsBlockCity(City),
   StatsBlockISP(ISP),
+  StatsBlockASN(ASN),
+  /// Blocks an announced network, e.g. `192.0.2.0/24`.
+  StatsBlockPrefix(String),
 
   StatsUnblockCountry(Country),
   StatsUnblockRegion(Region),
   StatsUnblockCity(City),
   StatsUnblockISP(ISP),
+  StatsUnblockASN(ASN),
+  StatsUnblockPrefix(String),
 
   StatsGetIP(String),
   StatsGotIP(IP),
+  /// 0: IP, 1: its bans and unbans, oldest first
+  StatsGotBanEvents(String, Vec<BanEvent>),
 
 }
 
@@ -1,6 +1,7 @@
 use color_eyre::eyre::Result;
 use crossterm::event::KeyEvent;
 //use futures::channel::mpsc::UnboundedSender;
+use std::path::PathBuf;
 use notify::{Event, INotifyWatcher, RecommendedWatcher, Watcher};
 use ratatui::prelude::Rect;
 use serde::{Deserialize, Serialize};
@@ -13,6 +14,7 @@ use rusqlite::{Connection, Result as ConnectionResult};
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 use std::sync::Arc;
+use std::collections::HashMap;
 
 
 
@@ -47,21 +49,23 @@ pub struct App {
   pub stored_geo: Vec<IP>,
   f2bw_handle: Option<JoinHandle<()>>,
   jctl_handle: Option<JoinHandle<()>>,
-  dbconn: Option<Connection>,
 
-  f2b_logpath: String,
   f2b_cancellation_token: CancellationToken,
   f2b_watcher: Option<INotifyWatcher>,
-  jctl_cancellation_token: CancellationToken,
+  /// One watcher per configured journal source, by source name.
+  jctl_cancellation_tokens: HashMap<String, CancellationToken>,
 }
 
 impl App {
-  pub fn new(tick_rate: f64, frame_rate: f64) -> Result<Self> {
+  pub fn new(tick_rate: f64, frame_rate: f64, db: Option<PathBuf>) -> Result<Self> {
     let home = Home::new();
     let fps = FpsCounter::default();
     let startup = Startup::new();
     let stats = Stats::new();
-    let config = Config::new()?;
+    let mut config = Config::new()?;
+    if let Some(db) = db {
+      config.db = db;
+    }
     let mode = Mode::Startup;
     Ok(Self {
       tick_rate,
@@ -76,11 +80,9 @@ impl App {
       stored_geo: Vec::new(),
       f2bw_handle: Option::None,
       jctl_handle: Option::None,
-      dbconn: Option::None,
-      f2b_logpath: String::from("/var/log/fail2ban.log"),
       f2b_cancellation_token: CancellationToken::default(),
       f2b_watcher: Option::None,
-      jctl_cancellation_token: CancellationToken::default(),
+      jctl_cancellation_tokens: HashMap::new(),
     })
   }
 
@@ -181,11 +183,11 @@ impl App {
           Action::StopF2BWatcher => {
             self.stop_f2b_watcher(action_tx.clone()).await?;
           },
-          Action::StartJCtlWatcher => {
-            self.start_jctl_watcher(&action_tx).await?;
+          Action::StartJCtlWatcher(ref name) => {
+            self.start_jctl_watcher(name, &action_tx).await?;
           },
-          Action::StopJCtlWatcher => {
-            self.stop_jctl_watcher(&action_tx).await?;
+          Action::StopJCtlWatcher(ref name) => {
+            self.stop_jctl_watcher(name, &action_tx).await?;
           },
           _ => {},
         }
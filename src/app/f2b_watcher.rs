@@ -5,7 +5,10 @@ use super::App;
 use tokio_util::sync::CancellationToken;
 use color_eyre::eyre::Result;
 
-use std::io::{Seek, BufReader, BufRead};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use notify::{Watcher, RecursiveMode, RecommendedWatcher, Config, Event};
 use serde::Serialize;
 use tokio::time::{interval, Duration};
@@ -27,7 +30,7 @@ impl App {
         }
     
         // get the log path
-        let path = if self.config.logpath.is_empty() {self.f2b_logpath.clone()} else {self.config.logpath.clone()};
+        let path = self.config.log_path();
         log::info!("{}", path);
     
         // clone sender for moving into the watcher
@@ -42,9 +45,12 @@ impl App {
         // construct the watcher
         let (atx, arx) = tokio::sync::mpsc::channel(100);
         let mut watcher: notify::INotifyWatcher = notify::RecommendedWatcher::new(move |result: std::result::Result<Event, notify::Error>| {
-          atx.blocking_send(result).expect("Failed to send event");
+          // the monitor may have returned already, its events don't matter then
+          let _ = atx.blocking_send(result);
         }, notify::Config::default())?;
-        watcher.watch(path.as_ref(), notify::RecursiveMode::NonRecursive)?;
+        // watch the directory, logrotate replaces the file itself
+        let watch_dir = std::path::Path::new(&path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+        watcher.watch(watch_dir, notify::RecursiveMode::NonRecursive)?;
     
         // construct the listener
         let filewatcher = tokio::spawn(async move  {
@@ -80,64 +86,266 @@ impl App {
 
 use notify::Result as NResult;
 
+/// Follows the log file from its current end and sends every new line.
+/// Keeps following when logrotate moves the file aside or truncates it, see `FollowedFile`.
 pub async fn monitor_ionotify_file(path: &str, _event_tx:UnboundedSender<Action>, mut rx: Receiver<NResult<Event>>, _cancellation_token: CancellationToken) -> NResult<()> {
-  let mut pos = std::fs::metadata(path)?.len();
-  let mut f = std::fs::File::open(path)?;
+  let mut followed = FollowedFile::open_at_end(Path::new(path))?;
+  // a missed event must not hold back lines until the next one arrives
+  let mut fallback = interval(Duration::from_secs(1));
 
   loop {
     tokio::select! {
       _ = _cancellation_token.cancelled() => {
         return Ok(())
       }
+      _ = fallback.tick() => {}
       _res = rx.recv() => {
-        if let Some(res) = _res {
-          match res {
-            Ok(_event) => {
-                // ignore any event that didn't change the pos
-                if f.metadata()?.len() == pos {
-                    continue;
-                }
-                if f.metadata()?.len() == 0 {
-                    continue;
-                }
-
-                // read from pos to end of file
-                f.seek(std::io::SeekFrom::Start(pos))?;
-
-                // update post to end of file
-                pos = f.metadata()?.len();
-                let reader = BufReader::new(&f);
-                let mut msgs = vec!["".to_string()];
-
-                for line in reader.lines() {
-                    let cline = line.unwrap();
-                    let nullchar = cline.chars().nth(cline.chars().count());
-                    if !nullchar.is_none()
-                    {
-                        let newchar = nullchar.unwrap();
-                        if newchar.is_whitespace()
-                        {
-                            pos -= 1;
-                        }
-
-                    }
-                    if !cline.is_empty() {
-                      msgs.push(cline.clone());                 
-                    }
-                }
-                
-                let msg = if msgs.len() == 1 {
-                  IOMessage::SingleLine(msgs[0].clone(), IOProducer::Log)
-                } else {
-                  IOMessage::MultiLine(msgs, IOProducer::Log)
-                };
-                _event_tx.send(Action::IONotify(msg)).unwrap();
-
-            }
-            Err(error) => { log::error!("Logwatcher failed with: {error:?}"); return Err(error)},
-          }
+        match _res {
+          Some(Ok(_event)) => {},
+          Some(Err(error)) => { log::error!("Logwatcher failed with: {error:?}"); return Err(error)},
+          None => return Ok(()),
         }
       }
     }
+
+    // one message per line, a batch may hold events for different IPs
+    for line in followed.read_new_lines()? {
+      _event_tx.send(Action::IONotify(IOMessage::from_line(line, IOProducer::Log))).unwrap();
+    }
+  }
+}
+
+/// Read position in a log file that is followed like `tail -F` does.
+///
+/// Rotation is noticed by the path pointing to another inode, the rest of the old file is still read through the
+/// open handle before switching to the new one. A file shrinking below the read position was truncated in place
+/// (copytruncate), the rest of the copy is read before the file is read again from its start.
+pub struct FollowedFile {
+  path: PathBuf,
+  file: Option<File>,
+  inode: u64,
+  pos: u64,
+  /// Last line if it has no line break yet.
+  partial: Vec<u8>,
+}
+
+impl FollowedFile {
+  /// Starts at the end of the file, lines already in it are not returned. The file doesn't have to exist yet.
+  pub fn open_at_end(path: &Path) -> std::io::Result<Self> {
+    let mut followed = FollowedFile { path: path.to_path_buf(), file: None, inode: 0, pos: 0, partial: vec![] };
+    match File::open(path) {
+      Ok(file) => {
+        let meta = file.metadata()?;
+        followed.inode = meta.ino();
+        followed.pos = meta.len();
+        followed.file = Some(file);
+      },
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+      Err(err) => return Err(err),
+    }
+    Ok(followed)
+  }
+
+  /// Returns the complete lines written since the last call, empty lines are skipped.
+  pub fn read_new_lines(&mut self) -> std::io::Result<Vec<String>> {
+    let mut lines = vec![];
+    if self.file.is_some() {
+      self.read_current(&mut lines)?;
+    }
+
+    let meta = match std::fs::metadata(&self.path) {
+      Ok(meta) => meta,
+      // moved aside and not created again yet
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(lines),
+      Err(err) => return Err(err),
+    };
+    if self.file.is_none() || meta.ino() != self.inode {
+      // the rotated file won't get its last line finished anymore
+      if !self.partial.is_empty() {
+        lines.push(String::from_utf8_lossy(&std::mem::take(&mut self.partial)).into_owned());
+      }
+      let file = File::open(&self.path)?;
+      self.inode = file.metadata()?.ino();
+      self.file = Some(file);
+      self.pos = 0;
+      self.read_current(&mut lines)?;
+      log::info!("Following new log file {}", self.path.display());
+    }
+    lines.retain(|line| !line.is_empty());
+    Ok(lines)
+  }
+
+  fn read_current(&mut self, lines: &mut Vec<String>) -> std::io::Result<()> {
+    let Some(file) = self.file.as_ref() else { return Ok(()) };
+    if file.metadata()?.len() < self.pos {
+      log::info!("Log file {} was truncated, reading it from the start", self.path.display());
+      self.read_rotated_copy(lines)?;
+      self.pos = 0;
+      self.partial.clear();
+    }
+    let Some(file) = self.file.as_mut() else { return Ok(()) };
+    file.seek(SeekFrom::Start(self.pos))?;
+    let mut buf = vec![];
+    self.pos += file.read_to_end(&mut buf)? as u64;
+    self.push_bytes(&buf, lines);
+    Ok(())
+  }
+
+  /// Reads what was written between the last read and the truncation from the copy logrotate made, `<path>.1`.
+  /// A copy shorter than the read position is not the one just made, e.g. compressed already, and is left alone.
+  fn read_rotated_copy(&mut self, lines: &mut Vec<String>) -> std::io::Result<()> {
+    let mut copy_path = self.path.clone().into_os_string();
+    copy_path.push(".1");
+    let mut copy = match File::open(&copy_path) {
+      Ok(copy) => copy,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+      Err(err) => return Err(err),
+    };
+    if copy.metadata()?.len() < self.pos {
+      return Ok(());
+    }
+    copy.seek(SeekFrom::Start(self.pos))?;
+    let mut buf = vec![];
+    copy.read_to_end(&mut buf)?;
+    self.push_bytes(&buf, lines);
+    // the copy won't get its last line finished anymore
+    if !self.partial.is_empty() {
+      lines.push(String::from_utf8_lossy(&std::mem::take(&mut self.partial)).into_owned());
+    }
+    Ok(())
+  }
+
+  /// Appends read bytes to the unfinished last line and moves the complete lines out.
+  fn push_bytes(&mut self, buf: &[u8], lines: &mut Vec<String>) {
+    self.partial.extend_from_slice(buf);
+    if let Some(end) = self.partial.iter().rposition(|b| *b == b'\n') {
+      let rest = self.partial.split_off(end + 1);
+      let complete = std::mem::replace(&mut self.partial, rest);
+      lines.extend(String::from_utf8_lossy(&complete).lines().map(String::from));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Write;
+
+  fn temp_log(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("s2b-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("fail2ban.log")
+  }
+
+  fn append(path: &Path, text: &str) {
+    std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+  }
+
+  #[test]
+  fn test_follow_appends() {
+    let path = temp_log("follow");
+    append(&path, "old line\n");
+    let mut followed = FollowedFile::open_at_end(&path).unwrap();
+    assert!(followed.read_new_lines().unwrap().is_empty());
+
+    append(&path, "first\nsecond\nthi");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["first", "second"]);
+    append(&path, "rd\n\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["third"]);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_rotate() {
+    let path = temp_log("rotate");
+    append(&path, "before\n");
+    let mut followed = FollowedFile::open_at_end(&path).unwrap();
+
+    // written right before logrotate moves the file, not read yet
+    append(&path, "last in old file\n");
+    let rotated = path.with_extension("log.1");
+    std::fs::rename(&path, &rotated).unwrap();
+    // fail2ban keeps writing to the old file until it reopens its log
+    append(&rotated, "after move\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["last in old file", "after move"]);
+
+    append(&path, "first in new file\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["first in new file"]);
+    append(&rotated, "late write\n");
+    append(&path, "second in new file\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["second in new file"]);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_copytruncate() {
+    let path = temp_log("copytruncate");
+    append(&path, "a long line that was there before the copy\n");
+    let mut followed = FollowedFile::open_at_end(&path).unwrap();
+
+    append(&path, "not read before the copy\n");
+    std::fs::copy(&path, path.with_extension("log.1")).unwrap();
+    std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(0).unwrap();
+    append(&path, "short\n");
+    // the lines between the last read and the truncation are only in the copy
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["not read before the copy", "short"]);
+    append(&path, "next\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["next"]);
+
+    // compressed right away, there is no copy to read from
+    std::fs::remove_file(path.with_extension("log.1")).unwrap();
+    append(&path, "lost with the copy\n");
+    std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(0).unwrap();
+    append(&path, "new\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["new"]);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[test]
+  fn test_created_later() {
+    let path = temp_log("created");
+    let mut followed = FollowedFile::open_at_end(&path).unwrap();
+    assert!(followed.read_new_lines().unwrap().is_empty());
+    append(&path, "hello\n");
+    assert_eq!(followed.read_new_lines().unwrap(), vec!["hello"]);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_monitor_rotation() {
+    let path = temp_log("monitor");
+    append(&path, "before\n");
+    let (atx, arx) = tokio::sync::mpsc::channel(100);
+    let mut watcher = notify::RecommendedWatcher::new(move |result: NResult<Event>| {
+      let _ = atx.blocking_send(result);
+    }, notify::Config::default()).unwrap();
+    watcher.watch(path.parent().unwrap(), RecursiveMode::NonRecursive).unwrap();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let token = CancellationToken::new();
+    let path_str = path.to_str().unwrap().to_string();
+    let handle = tokio::spawn({
+      let token = token.clone();
+      async move { monitor_ionotify_file(&path_str, tx, arx, token).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    append(&path, "2023-11-19 16:08:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Ban 192.0.2.1\n");
+    std::fs::rename(&path, path.with_extension("log.1")).unwrap();
+    append(&path, "2023-11-19 16:18:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Unban 192.0.2.1\n");
+
+    let mut lines = vec![];
+    while lines.len() < 2 {
+      match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+        Action::IONotify(msg) => lines.push(msg.destructure(" ").0),
+        other => panic!("unexpected action {:?}", other),
+      }
+    }
+    assert!(lines[0].ends_with("Ban 192.0.2.1"));
+    assert!(lines[1].ends_with("Unban 192.0.2.1"));
+    token.cancel();
+    handle.await.unwrap().unwrap();
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
   }
 }
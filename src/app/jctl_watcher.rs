@@ -1,144 +1,101 @@
 use crate::action::Action;
+use crate::journal::{JournalEntry, JournalMatch, JournalReader, JournalSource};
 
 use super::App;
 
-use tokio::io::AsyncSeekExt;
-use tokio::io::AsyncReadExt;
-use tokio::process::Command;
 use tokio_util::sync::CancellationToken;
 use color_eyre::eyre::Result;
 
-use notify::{Event, INotifyWatcher, RecommendedWatcher, Watcher};
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::time::{sleep, Duration, interval};
+use tokio::time::{sleep, Duration};
 
-use std::process::{Stdio, ChildStdout};
-use std::io::Read;
-
-use super::models::{IOMessage, IOProducer};
+use super::models::IOMessage;
 
 impl App {
 
-    pub async fn start_jctl_watcher(&mut self, action_tx: &UnboundedSender<Action>) -> Result<()> {
+    pub async fn start_jctl_watcher(&mut self, name: &str, action_tx: &UnboundedSender<Action>) -> Result<()> {
+
+        if let Some(token) = self.jctl_cancellation_tokens.get(name) {
+          token.cancel();
+          sleep(Duration::from_millis(50)).await; // make sure we're wound down
+        }
 
-        self.jctl_cancellation_token.cancel();
-        sleep(Duration::from_millis(50)).await; // make sure we're wound down
+        let Some(source) = self.config.journal_sources().into_iter().find(|source| source.name == name) else {
+          let fetchmsg = format!(" ❌ Unknown journal source {}", name);
+          action_tx.send(Action::InternalLog(fetchmsg)).expect("LOG: StartJCTLWatcher message failed to send");
+          return Ok(())
+        };
 
         // create cancellation token
         let token = CancellationToken::new();
         let _jctl_cancellation_token = token.child_token();
-        self.jctl_cancellation_token = token;
+        self.jctl_cancellation_tokens.insert(name.to_string(), token);
 
-        // start the fail2ban watcher
+        // start the journal watcher
         let action_tx2 = action_tx.clone();
 
-        let _resp = monitor_journalctl( action_tx2, _jctl_cancellation_token).await?;
+        if let Err(err) = monitor_journal(self.config.journal_source(), name.to_string(), source.journal_matches(), action_tx2, _jctl_cancellation_token).await {
+          let fetchmsg = format!(" ❌ Journal not readable: {}", err);
+          action_tx.send(Action::InternalLog(fetchmsg)).expect("LOG: StartJCTLWatcher message failed to send");
+          return Ok(())
+        }
 
-        //self.jctl_handle = Option::Some(journalwatcher);
-        let fetchmsg = format!(" ✔ STARTED journalctl watcher");
+        let fetchmsg = format!(" ✔ STARTED journal watcher {}", name);
         action_tx.send(Action::InternalLog(fetchmsg)).expect("LOG: StartJCTLWatcher message failed to send");
 
         Ok(())
     }
         
-    pub async fn stop_jctl_watcher(&mut self, action_tx: &UnboundedSender<Action>) -> Result<()> {
+    pub async fn stop_jctl_watcher(&mut self, name: &str, action_tx: &UnboundedSender<Action>) -> Result<()> {
 
-        self.jctl_cancellation_token.cancel();
+        let token = self.jctl_cancellation_tokens.remove(name).unwrap_or_default();
+        token.cancel();
         sleep(Duration::from_millis(50)).await;
 
-        if self.jctl_cancellation_token.is_cancelled() {
-          let fetchmsg = format!(" ❌ STOPPED journalctl watcher");
-          action_tx.send(Action::InternalLog(fetchmsg)).expect("LOG: StopJCTLWatcher message failed to send");
-          log::info!("Stopped jctl watcher");
-          action_tx.send(Action::StoppedJCtlWatcher).expect("LOG: StoppedF2BWatcher message failed to send");
-        } else {
-          sleep(Duration::from_millis(10)).await;
-          action_tx.send(Action::StopJCtlWatcher).unwrap();
-        }
+        let fetchmsg = format!(" ❌ STOPPED journal watcher {}", name);
+        action_tx.send(Action::InternalLog(fetchmsg)).expect("LOG: StopJCTLWatcher message failed to send");
+        log::info!("Stopped jctl watcher {}", name);
+        action_tx.send(Action::StoppedJCtlWatcher(name.to_string())).expect("LOG: StoppedJCtlWatcher message failed to send");
 
         Ok(())
     }
 }
 
-/// Creates a sender and receiver process for reading journalctl's stdout. This function justs sets them up and returns immediately.
-pub async fn monitor_journalctl(action_tx:UnboundedSender<Action>, cancel_token: CancellationToken) -> Result<()> {
+/// Follows the journal entries matching a configured source with a sender task reading the journal files and a receiver
+/// task passing the entries on, tagged with the source name. Only entries written after the start are sent.
+/// This function justs sets them up and returns immediately.
+pub async fn monitor_journal(source: JournalSource, name: String, matches: Vec<JournalMatch>, action_tx:UnboundedSender<Action>, cancel_token: CancellationToken) -> Result<()> {
 
+  let mut reader = JournalReader::new(source, matches);
+  reader.seek_tail()?;
 
-  let (stdout_tx, mut stdout_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
-  //let mut command = Command::new("tail");
-  //let argus = vec!["-n", "1", "-f", "~/Dev/RUST/journal.txt"];
-  
-  let argus = vec!["-n", "1", "-f", "-u", "ssh"];
-  let mut command = Command::new("journalctl");
-  command.args(argus).stdout(Stdio::piped());
+  let (entry_tx, mut entry_rx) = tokio::sync::mpsc::unbounded_channel::<JournalEntry>();
 
-  let child_token = cancel_token.child_token();
-  
   //sender
-  let _joinhandle = tokio::spawn(async move {
-    log::info!("Start jctl sender");
-    let mut tick_interval = interval(Duration::from_nanos(10));
-    if let Ok(mut child) = command.spawn() {
-        if let Some(mut out) = child.stdout.take() {
-          
-          let mut pre_line: Vec<u8> = vec![];
-          let mut buf: [u8; 1] = [0; 1];
-            loop {            
-              
-
-              tokio::select! {
-                _ = child_token.cancelled() => {
-                  log::info!("Stopped jctl sender");
-                  break;
-                },
-                read_result = out.read(&mut buf) => {
-
-                  match read_result {
-                    Ok(0) => break, // End of file reached or no more output
-                    Ok(_) => {
-                        // Check if we encounter a newline character, if yes send chars
-                        if buf[0] == b'\n' || buf[0] == b'\r' {
-                            let line = String::from_utf8(pre_line.clone()).unwrap();
-                            stdout_tx.send(line).unwrap_or_else(|err| {
-                                log::error!("JournalCtl Error: {}", err);
-                            });
-                            pre_line.clear();
-                        } else {
-                            pre_line.push(buf[0]);
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Error reading stdout: {:?}", e);
-                        break;
-                    }
-                  }
-
-                }
-              }
-              tick_interval.tick().await;
-            }  
-        } else {
-
-        }
-    } else {
-      log::error!("Process failed to start");
-    }
+  let child_token = cancel_token.child_token();
+  tokio::spawn(async move {
+    log::info!("Start journal sender");
+    reader.follow(entry_tx, child_token).await;
+    log::info!("Stopped journal sender");
   });
 
   // receiver
   tokio::spawn(async move {
-    log::info!("Start jctl receiver");
+    log::info!("Start journal receiver");
     loop {
         tokio::select! {
           _ = cancel_token.cancelled() => {
-            log::info!("Stopped jctl receiver");
+            log::info!("Stopped journal receiver");
             break;
           }
-          maybe_msg = stdout_rx.recv() => {
-            if let Some(msg) = maybe_msg {
-              if !msg.is_empty() {
-                action_tx.send(Action::IONotify(IOMessage::SingleLine(msg, IOProducer::Journal))).unwrap();
-              }
+          maybe_entry = entry_rx.recv() => {
+            match maybe_entry {
+              Some(entry) => {
+                if !entry.message().is_empty() {
+                  action_tx.send(Action::IONotify(IOMessage::from_journal(&name, entry))).unwrap();
+                }
+              },
+              None => break,
             }
           }
         }
@@ -146,4 +103,4 @@ pub async fn monitor_journalctl(action_tx:UnboundedSender<Action>, cancel_token:
   });
 
   Ok(())
-}
\ No newline at end of file
+}
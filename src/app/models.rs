@@ -1,15 +1,47 @@
 use serde::Serialize;
 
+use chrono::{DateTime, Local};
+
+use crate::fail2ban::{self, F2BEvent};
+use crate::journal::JournalEntry;
+use crate::sshd::{self, SshEvent};
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum IOProducer {
-  Journal,
+  /// systemd journal, tagged with the name of the configured source the line came from.
+  Journal(String),
   Log
 }
 
+/// Source tag of journal lines stored before messages kept their source.
+/// Journal lines were only ever ssh lines before sources became configurable.
+pub const SSH_SOURCE: &str = "ssh";
+
+impl IOProducer {
+  pub fn is_journal(&self) -> bool {
+    matches!(self, IOProducer::Journal(_))
+  }
+
+  /// Name of the journal source, `None` for fail2ban's log.
+  pub fn source(&self) -> Option<&str> {
+    match self {
+      IOProducer::Journal(name) => Some(name),
+      IOProducer::Log => None,
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum IOMessage {
   SingleLine(String, IOProducer),
   MultiLine(Vec<String>, IOProducer),
+  /// fail2ban.log line together with the event parsed from it.
+  Fail2Ban(String, F2BEvent),
+  /// Journal line of sshd together with the event parsed from it and the name of its source.
+  Sshd(String, SshEvent, String),
+  /// Entry read from the systemd journal with the name of its source, all its fields and the sshd event parsed from
+  /// its message.
+  Journal(String, JournalEntry, Option<SshEvent>),
 }
 
 impl IOMessage {
@@ -24,7 +56,75 @@ impl IOMessage {
         prod = p;
         catmsg = vx.join(sep)
       },
+      IOMessage::Fail2Ban(x, _) => {
+        prod = IOProducer::Log;
+        catmsg = x},
+      IOMessage::Sshd(x, _, source) => {
+        prod = IOProducer::Journal(source);
+        catmsg = x},
+      IOMessage::Journal(source, entry, _) => {
+        prod = IOProducer::Journal(source);
+        catmsg = entry.short_line()},
     }
     return (catmsg, prod)
   }
+
+  /// Wraps a line, fail2ban log and sshd journal lines get parsed into an event.
+  pub fn from_line(line: String, prod: IOProducer) -> Self {
+    match prod {
+      IOProducer::Log => {
+        if let Some(event) = fail2ban::parse_line(&line) {
+          return IOMessage::Fail2Ban(line, event);
+        }
+      },
+      IOProducer::Journal(ref source) => {
+        if let Some(event) = sshd::parse_line(&line) {
+          return IOMessage::Sshd(line, event, source.clone());
+        }
+      },
+    }
+    IOMessage::SingleLine(line, prod)
+  }
+
+  pub fn from_journal(source: &str, entry: JournalEntry) -> Self {
+    let event = sshd::parse_line(entry.message());
+    IOMessage::Journal(source.to_string(), entry, event)
+  }
+
+  pub fn f2b_event(&self) -> Option<&F2BEvent> {
+    match self {
+      IOMessage::Fail2Ban(_, event) => Some(event),
+      _ => None,
+    }
+  }
+
+  pub fn ssh_event(&self) -> Option<&SshEvent> {
+    match self {
+      IOMessage::Sshd(_, event, _) => Some(event),
+      IOMessage::Journal(_, _, event) => event.as_ref(),
+      _ => None,
+    }
+  }
+
+  /// Host that logged the line, only the journal records it.
+  pub fn hostname(&self) -> Option<&str> {
+    match self {
+      IOMessage::Journal(_, entry, _) => entry.hostname(),
+      _ => None,
+    }
+  }
+
+  /// When the line was logged, if the source tells us.
+  pub fn timestamp(&self) -> Option<DateTime<Local>> {
+    match self {
+      IOMessage::Fail2Ban(_, event) => event.timestamp.and_then(|ts| ts.and_local_timezone(Local).single()),
+      IOMessage::Journal(_, entry, _) => entry.timestamp(),
+      _ => None,
+    }
+  }
+
+  /// Only new bans from fail2ban.log count, "Unban" and "Restore Ban" lines don't.
+  pub fn is_ban(&self) -> bool {
+    self.f2b_event().is_some_and(|event| event.is_ban())
+  }
 }
@@ -0,0 +1,133 @@
+//! Backfill from historic logs.
+//! Reads what fail2ban and the journal logged while the TUI was closed, including logrotate's rotated files.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use flate2::read::MultiGzDecoder;
+
+use crate::fail2ban;
+use crate::journal::{JournalEntry, JournalMatch, JournalReader, JournalSource};
+
+/// The log and its rotated siblings, oldest first: `fail2ban.log.2.gz`, `fail2ban.log.1`, `fail2ban.log`.
+/// Date suffixes like `fail2ban.log-20231119.gz` are picked up as well.
+pub fn rotated_logs(path: &Path) -> io::Result<Vec<PathBuf>> {
+  let Some(name) = path.file_name().map(|name| name.to_string_lossy().into_owned()) else { return Ok(vec![]) };
+  let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+  let mut rotated = vec![];
+  for entry in std::fs::read_dir(dir)? {
+    let entry = entry?;
+    let file_name = entry.file_name().to_string_lossy().into_owned();
+    let Some(suffix) = file_name.strip_prefix(&name) else { continue };
+    if suffix.starts_with(['.', '-']) && suffix.len() > 1 && entry.file_type()?.is_file() {
+      rotated.push((entry.metadata()?.modified()?, entry.path()));
+    }
+  }
+  // numbers and dates don't sort the same way, the time of the last write does
+  rotated.sort();
+  let mut logs: Vec<PathBuf> = rotated.into_iter().map(|(_, path)| path).collect();
+  if path.is_file() {
+    logs.push(path.to_path_buf());
+  }
+  Ok(logs)
+}
+
+/// All lines of a log file, gzipped files are decompressed.
+pub fn read_log_lines(path: &Path) -> io::Result<Vec<String>> {
+  let file = File::open(path)?;
+  let reader: Box<dyn Read> =
+    if path.extension().is_some_and(|ext| ext == "gz") { Box::new(MultiGzDecoder::new(file)) } else { Box::new(file) };
+  let mut lines = vec![];
+  for line in BufReader::new(reader).split(b'\n') {
+    let line = String::from_utf8_lossy(&line?).into_owned();
+    if !line.is_empty() {
+      lines.push(line);
+    }
+  }
+  Ok(lines)
+}
+
+/// fail2ban.log lines logged after `since` and before `until`, from the log and its rotated files.
+/// Lines without a timestamp can't be placed and are left out.
+pub fn fail2ban_history(path: &Path, since: Option<NaiveDateTime>, until: NaiveDateTime) -> io::Result<Vec<String>> {
+  let mut history = vec![];
+  for log in rotated_logs(path)? {
+    let lines = match read_log_lines(&log) {
+      Ok(lines) => lines,
+      Err(err) => {
+        log::warn!("Skipping {} for backfill: {}", log.display(), err);
+        continue;
+      },
+    };
+    history.extend(lines.into_iter().filter(|line| {
+      fail2ban::parse_line(line)
+        .and_then(|event| event.timestamp)
+        .is_some_and(|ts| since.is_none_or(|since| ts > since) && ts < until)
+    }));
+  }
+  Ok(history)
+}
+
+/// Journal entries of a source logged after `since` and before `until`, in microseconds since the epoch.
+pub fn journal_history(
+  source: JournalSource,
+  matches: Vec<JournalMatch>,
+  since: Option<u64>,
+  until: u64,
+) -> io::Result<Vec<JournalEntry>> {
+  JournalReader::new(source, matches).read_range(since, until)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::GzEncoder, Compression};
+
+  use super::*;
+
+  fn line(time: &str, ip: &str) -> String {
+    format!("2023-11-19 {},000 fail2ban.actions        [804]: NOTICE  [sshd] Ban {}\n", time, ip)
+  }
+
+  fn write(path: &Path, content: &str, age_secs: u64) {
+    if path.extension().is_some_and(|ext| ext == "gz") {
+      let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+      encoder.write_all(content.as_bytes()).unwrap();
+      encoder.finish().unwrap();
+    } else {
+      std::fs::write(path, content).unwrap();
+    }
+    let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(age_secs);
+    File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+  }
+
+  #[test]
+  fn test_fail2ban_history() {
+    let dir = std::env::temp_dir().join(format!("s2b-backfill-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fail2ban.log");
+    write(&dir.join("fail2ban.log.2.gz"), &line("10:00:00", "192.0.2.1"), 300);
+    write(&dir.join("fail2ban.log.1"), &(line("11:00:00", "192.0.2.2") + "Traceback without timestamp\n"), 200);
+    write(&path, &(line("12:00:00", "192.0.2.3") + &line("13:00:00", "192.0.2.4")), 0);
+    write(&dir.join("other.log"), &line("11:30:00", "192.0.2.9"), 100);
+
+    let names: Vec<String> =
+      rotated_logs(&path).unwrap().iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect();
+    assert_eq!(names, vec!["fail2ban.log.2.gz", "fail2ban.log.1", "fail2ban.log"]);
+
+    let ts = |time: &str| NaiveDateTime::parse_from_str(&format!("2023-11-19 {}", time), "%Y-%m-%d %H:%M:%S").unwrap();
+    let all = fail2ban_history(&path, None, ts("23:00:00")).unwrap();
+    assert_eq!(all.len(), 4);
+    assert!(all[0].ends_with("192.0.2.1"));
+
+    let since = fail2ban_history(&path, Some(ts("10:00:00")), ts("13:00:00")).unwrap();
+    assert_eq!(since.len(), 2);
+    assert!(since[0].ends_with("192.0.2.2"));
+    assert!(since[1].ends_with("192.0.2.3"));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
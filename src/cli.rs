@@ -18,4 +18,10 @@ pub struct Cli {
     default_value_t = 4.0
   )]
   pub frame_rate: f64,
+
+  #[arg(long, value_name = "PATH", help = "SQLite database to use instead of the configured one")]
+  pub db: Option<PathBuf>,
+
+  #[arg(long, help = "Count the stats again from the stored messages and exit")]
+  pub recompute_stats: bool,
 }
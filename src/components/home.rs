@@ -13,7 +13,7 @@ use actions::{style_incoming_message, parse_passed_geo};
 pub mod enums;
 use enums::*;
 
-use std::{collections::HashMap, time::Duration, ops::Index};
+use std::{collections::{HashMap, HashSet}, time::Duration, ops::Index};
 
 use color_eyre::eyre::Result;
 use crossterm::event::{KeyCode, KeyEvent, ModifierKeyCode};
@@ -28,7 +28,7 @@ use rand::distributions::Distribution;
 use super::{Component, Frame};
 use crate::{
   action::{Action, HomeAction},
-  config::{Config, KeyBindings, get_first_key_by_action},
+  config::{Config, HomeSite, JournalSourceConfig, KeyBindings, get_first_key_by_action},
   geofetcher, gen_structs::StatefulList,
   themes, animations, database::schema,
   database::schema::ip::IP,
@@ -48,7 +48,7 @@ pub struct Home<'a> {
   command_tx: Option<UnboundedSender<Action>>,
   config: Config,
 
-  available_actions: StatefulList<(&'a str, String)>,
+  available_actions: StatefulList<(String, String)>,
 
   pub last_events: Vec<KeyEvent>,
   pub keymap: HashMap<KeyEvent, Action>,
@@ -65,8 +65,8 @@ pub struct Home<'a> {
   last_lat: f64,
   last_lon: f64,
 
-  home_lat: f64,
-  home_lon: f64,
+  // where lines are drawn to, empty until home is looked up or if it is disabled
+  home_sites: Vec<HomeSite>,
 
   last_direction: (f64, f64), //tuple vector2D that points towards home; 0/x = lon, 1/y = lat
   // home - last 
@@ -89,7 +89,8 @@ pub struct Home<'a> {
 
   apptheme: themes::Theme,
 
-  jctlrunning: bool,
+  /// Names of the journal sources currently watched.
+  jctlrunning: HashSet<String>,
   f2brunning: bool,
 
   last_username: String,
@@ -105,6 +106,7 @@ pub struct Home<'a> {
 
   ipstring: String,
   iperror: String,
+  jails: StatefulList<String>,
 
   available_themes: themes::Themes,
 
@@ -117,6 +119,28 @@ pub struct Home<'a> {
   bg_text_2: Vec<Line<'a>>,
 }
 
+/// Action list entries toggling a journal source start with this, followed by the source name.
+const JOURNAL_ACTION_PREFIX: &str = "monitor-journal:";
+
+/// Entries of the action list, one watcher toggle per configured journal source.
+fn action_items(sources: &[JournalSourceConfig]) -> Vec<(String, String)> {
+  let mut items = vec![
+    (String::from("Ban"), String::from("some ip")),
+    (String::from("Unban"), String::from("some ip")),
+  ];
+  for source in sources {
+    items.push((format!("{}{}", JOURNAL_ACTION_PREFIX, source.name), String::from("inactive")));
+  }
+  items.extend([
+    (String::from("monitor-fail2ban"), String::from("inactive")),
+    (String::from("Stats"), String::from(" E ")),
+    (String::from("Query"), String::from(" Q ")),
+    (String::from("Help"), String::from(" W ")),
+    (String::from("Exit"), String::from("Esc | Ctrl+C")),
+  ]);
+  items
+}
+
 impl<'a> Home<'a> {
   pub fn new() -> Self {
     Self::default().set_items()
@@ -146,23 +170,13 @@ impl<'a> Home<'a> {
 
     self.internal_logs = StatefulList::with_items(vec![]);
     self.iplist_capacity = 10;
-    self.available_actions = StatefulList::with_items(vec![
-      ("Ban", String::from("some ip")),
-      ("Unban", String::from("some ip")),
-      ("monitor-journalctl", String::from("inactive")),
-      ("monitor-fail2ban", String::from("inactive")),
-      ("Stats", String::from(" E ")),
-      ("Query", String::from(" Q ")),
-      ("Help", String::from(" W ")),
-      ("Exit", String::from("Esc | Ctrl+C")),
-    ]);
+    self.available_actions = StatefulList::with_items(action_items(&[JournalSourceConfig::ssh()]));
     self.last_lat = 53.0416;
     self.last_lon = 8.9433;
 
-    self.home_lat = 53.0416;
-    self.home_lon = 8.9433;
+    self.home_sites = vec![];
     self.apptheme = themes::Theme::default();
-    self.jctlrunning = false;
+    self.jctlrunning = HashSet::new();
     self.f2brunning = false;
     self.startup_complete = false;
     self.showing_stats = false;
@@ -234,6 +248,7 @@ impl<'a> Home<'a> {
     let mut visible_points: Vec<PointData> = vec![];
 
     for item in self.iplist.items.clone() {
+      if item.IP.coordinates().is_none() {continue;}
       match self.drawmode {
         DrawMode::Sticky => {
           // push only item with selected ip
@@ -271,22 +286,24 @@ impl<'a> Home<'a> {
               let y2 =  pointdata.lat;
               let dir = (pointdata.dir_home_lon, pointdata.dir_home_lat);
             
-              // draw line to home
-              ctx.draw(&canvas::Line {
-                x1: self.home_lon,
-                y1: self.home_lat,
-                x2,
-                y2,
-                color:self.apptheme.colors_app.accent_color_b_mid.color,
-              }); 
-              // draw animated line
-              ctx.draw(&canvas::Line {
-                x1: x2 + dir.0 * map_range((0.,7.), (0.,1.), self.elapsed_frames),
-                y1: y2 + dir.1 * map_range((0.,7.), (0.,1.), self.elapsed_frames),
-                x2,
-                y2,
-                color:self.apptheme.colors_app.accent_color_b_bright.color,
-              });
+              if let Some((home_lon, home_lat)) = pointdata.home {
+                // draw line to home
+                ctx.draw(&canvas::Line {
+                  x1: home_lon,
+                  y1: home_lat,
+                  x2,
+                  y2,
+                  color:self.apptheme.colors_app.accent_color_b_mid.color,
+                }); 
+                // draw animated line
+                ctx.draw(&canvas::Line {
+                  x1: x2 + dir.0 * map_range((0.,7.), (0.,1.), self.elapsed_frames),
+                  y1: y2 + dir.1 * map_range((0.,7.), (0.,1.), self.elapsed_frames),
+                  x2,
+                  y2,
+                  color:self.apptheme.colors_app.accent_color_b_bright.color,
+                });
+              }
               // draw animated circle
               ctx.draw(&canvas::Circle {
                 x: x2, // lon
@@ -296,18 +313,22 @@ impl<'a> Home<'a> {
               });
 
             }
-            // if nothing is in ip list ie. on startup show a circle around the home coordinates
-            if self.iplist.items.len() == 0 {
-              ctx.draw(&canvas::Circle {
-                x: self.home_lon, // lon
-                y: self.home_lat, // lat
-                radius: self.elapsed_frames,
-                color: circle_color, //self.apptheme.colors_app.accent_color_a.color
-              });
+            for site in &self.home_sites {
+              // if nothing is in ip list ie. on startup show a circle around the home coordinates
+              if self.iplist.items.len() == 0 {
+                ctx.draw(&canvas::Circle {
+                  x: site.lon, // lon
+                  y: site.lat, // lat
+                  radius: self.elapsed_frames,
+                  color: circle_color, //self.apptheme.colors_app.accent_color_a.color
+                });
+              }
+              // name the sites once there is more than one
+              let label = if self.home_sites.len() > 1 {format!("H {}", site.name)} else {String::from("H")};
+              ctx.print(site.lon, site.lat, Line::from(Span::styled(label, Style::default().fg(self.apptheme.colors_app.accent_color_a.color))));
             }
             
             //ctx.print(self.last_lon, self.last_lat, "X".red());
-            ctx.print(self.home_lon, self.home_lat, Line::from(Span::styled("H", Style::default().fg(self.apptheme.colors_app.accent_color_a.color))));
         })
         .x_bounds([-180.0, 180.0])
         .y_bounds([-90.0, 90.0])
@@ -323,7 +344,8 @@ impl<'a> Home<'a> {
 
   fn submit_query(&mut self) -> bool {
     // check if valid IP else return false
-    if self.apptheme.ipregex.is_match(&self.querystring) {
+    if let Some(ip) = crate::ipextract::parse_ip(&self.querystring) {
+      self.querystring = ip.to_string();
       self.command_tx.clone().unwrap().send(Action::SubmitQuery(self.querystring.clone())).unwrap_or_else(|err|{
         println!("Error submitting query from Home {}", err);
       });
@@ -354,6 +376,7 @@ impl<'a> Home<'a> {
       ]);
     //queryline.patch_style(self.apptheme.selected_ip_bg);
     querytext.push(queryline);
+    querytext.push(self.jail_selection_line());
     let mut queryerror =   Line::from(format!("Status: {}", self.iperror));
     queryerror.patch_style(self.apptheme.styles_app.default_style);
     querytext.push(queryerror);
@@ -392,7 +415,7 @@ impl<'a> Home<'a> {
       ]);
     //queryline.patch_style(self.apptheme.selected_ip_bg);
     querytext.push(queryline);
-
+    querytext.push(self.jail_selection_line());
     let mut queryerror =   Line::from(format!("Status: {}", self.iperror));
     queryerror.patch_style(self.apptheme.styles_app.default_style);
     querytext.push(queryerror);
@@ -408,6 +431,19 @@ impl<'a> Home<'a> {
 
   }
 
+  fn selected_jail(&self) -> String {
+    let sel_idx = self.jails.state.selected().unwrap_or(0);
+    self.jails.items.get(sel_idx).cloned().unwrap_or(String::from(crate::config::DEFAULT_JAIL))
+  }
+
+  fn jail_selection_line(&self) -> Line<'_> {
+    Line::from(vec![
+      Span::styled("Jail: ", Style::default().fg(self.apptheme.colors_app.text_color.color)),
+      Span::styled(format!("< {} >", self.selected_jail()), Style::default().fg(self.apptheme.colors_app.accent_color_a.color)),
+      Span::styled(format!(" ({}/{})", self.jails.state.selected().unwrap_or(0) + 1, self.jails.items.len()), Style::default().fg(self.apptheme.colors_app.text_color.color)),
+    ])
+  }
+
   fn add_to_ipstring(&mut self, ch: char) {
     self.ipstring.push(ch);
   }
@@ -416,10 +452,19 @@ impl<'a> Home<'a> {
     self.ipstring.pop();
   }
 
+  /// IP record for the address typed into the ban popups.
+  fn typed_ip(&self) -> IP {
+    match crate::ipextract::parse_ip(&self.ipstring) {
+      Some(addr) => IP::from_addr(addr),
+      None => IP { ip: self.ipstring.clone(), ..Default::default() },
+    }
+  }
+
   fn submit_ip(&mut self, is_ban:bool) -> bool {
     // check if valid IP else return false
     
-    if self.apptheme.ipregex.is_match(&self.ipstring) {
+    if let Some(ip) = crate::ipextract::parse_ip(&self.ipstring) {
+      self.ipstring = ip.to_string();
       if is_ban {
         self.command_tx.clone().unwrap().send(Action::RequestBan).unwrap_or_else(|err|{
           println!("Error submitting query from Home {}", err);
@@ -447,18 +492,18 @@ impl<'a> Home<'a> {
     }
   }
 
-  fn toggle_jctlwatcher(&mut self, action_idx: usize) -> Action {
+  fn toggle_jctlwatcher(&mut self, action_idx: usize, name: &str) -> Action {
       // check if is active
-      if self.jctlrunning{
+      if self.jctlrunning.contains(name) {
         // switch to inactive
         self.available_actions.items[action_idx].1 = String::from("inactive");
-        Action::StopJCtlWatcher
+        Action::StopJCtlWatcher(name.to_string())
       }
       else{
         // switch to active
         self.available_actions.items[action_idx].1 = String::from("active");
-        self.jctlrunning = true;
-        Action::StartJCtlWatcher  
+        self.jctlrunning.insert(name.to_string());
+        Action::StartJCtlWatcher(name.to_string())
       }
   }
 
@@ -510,6 +555,12 @@ impl Component for Home<'_> {
   }
 
   fn register_config_handler(&mut self, config: Config) -> Result<()> {
+    self.jails = StatefulList::with_items(config.jail_list());
+    self.jails.next();
+    self.available_actions = StatefulList::with_items(action_items(&config.journal_sources()));
+    if let Some(sites) = config.home_sites() {
+      self.home_sites = sites;
+    }
     self.config = config;
     Ok(())
   }
@@ -587,11 +638,12 @@ impl Component for Home<'_> {
             KeyCode::Up => {Action::ActionsPrevious},
             KeyCode::Right | KeyCode::Enter => {
               let action_idx = self.available_actions.state.selected().unwrap();
-              match self.available_actions.items[action_idx].0 {
+              let action_name = self.available_actions.items[action_idx].0.clone();
+              match action_name.as_str() {
                 "Ban" => {if self.displaymode == DisplayMode::Ban {return Ok(Some(Action::ExitBan))} else {return Ok(Some(Action::EnterBan))}},
                 "Unban" => {if self.displaymode == DisplayMode::Unban {return Ok(Some(Action::ExitUnban))} else {return Ok(Some(Action::EnterUnban))}},
                 "monitor-fail2ban" => {self.toggle_f2bwatcher(action_idx)},
-                "monitor-journalctl" => {self.toggle_jctlwatcher(action_idx)},
+                name if name.starts_with(JOURNAL_ACTION_PREFIX) => {self.toggle_jctlwatcher(action_idx, &name[JOURNAL_ACTION_PREFIX.len()..])},
                 "Stats" => {return Ok(Some(Action::StatsShow))},
                 "Query" => {if self.displaymode == DisplayMode::Query {Action::ExitQuery} else {Action::EnterQuery}},
                 "Help" => {if self.displaymode == DisplayMode::Help {self.displaymode = DisplayMode::Normal;} else {self.displaymode = DisplayMode::Help;} Action::Blank},
@@ -618,7 +670,7 @@ impl Component for Home<'_> {
             }}
             KeyCode::Char(keychar) => {
               match keychar {
-                // Digits & Dot
+                // Digits, Dot & IPv6 hex digits
                 '1' => {self.add_to_querystring('1'); Action::Render}, // Action render makes it feel way more responsive
                 '2' => {self.add_to_querystring('2'); Action::Render},
                 '3' => {self.add_to_querystring('3'); Action::Render},
@@ -630,6 +682,7 @@ impl Component for Home<'_> {
                 '9' => {self.add_to_querystring('9'); Action::Render},
                 '0' => {self.add_to_querystring('0'); Action::Render},
                 '.' => {self.add_to_querystring('.'); Action::Render},
+                ':' | '[' | ']' | 'a'..='f' | 'A'..='F' => {self.add_to_querystring(keychar); Action::Render}, // IPv6
                 _ => {//Action::Render
                   Action::Blank}
               }
@@ -700,7 +753,7 @@ impl Component for Home<'_> {
           }}
           KeyCode::Char(keychar) => {
             match keychar {
-              // Digits & Dot
+              // Digits, Dot & IPv6 hex digits
               '1' => {self.add_to_ipstring('1'); Action::Render}, // Action render makes it feel way more responsive
               '2' => {self.add_to_ipstring('2'); Action::Render},
               '3' => {self.add_to_ipstring('3'); Action::Render},
@@ -712,11 +765,14 @@ impl Component for Home<'_> {
               '9' => {self.add_to_ipstring('9'); Action::Render},
               '0' => {self.add_to_ipstring('0'); Action::Render},
               '.' => {self.add_to_ipstring('.'); Action::Render},
+              ':' | '[' | ']' | 'a'..='f' | 'A'..='F' => {self.add_to_ipstring(keychar); Action::Render}, // IPv6
               _ => {//Action::Render
                 Action::Blank}
             }
           },
           KeyCode::Backspace => {self.rm_last_char_from_ipstring(); Action::Render},
+          KeyCode::Up => {self.jails.previous(); Action::Render},
+          KeyCode::Down => {self.jails.next(); Action::Render},
           KeyCode::Enter => {if self.submit_ip(true) {self.iperror = String::from("Success!"); Action::Blank} else {self.iperror = String::from("Invalid IP"); Action::Blank}}, // print something to the querybox, best -> mark invalid chars / num chars
           _ => {
             self.input.handle_event(&crossterm::event::Event::Key(key));
@@ -735,7 +791,7 @@ impl Component for Home<'_> {
         }}
         KeyCode::Char(keychar) => {
           match keychar {
-            // Digits & Dot
+            // Digits, Dot & IPv6 hex digits
             '1' => {self.add_to_ipstring('1'); Action::Render}, // Action render makes it feel way more responsive
             '2' => {self.add_to_ipstring('2'); Action::Render},
             '3' => {self.add_to_ipstring('3'); Action::Render},
@@ -747,11 +803,14 @@ impl Component for Home<'_> {
             '9' => {self.add_to_ipstring('9'); Action::Render},
             '0' => {self.add_to_ipstring('0'); Action::Render},
             '.' => {self.add_to_ipstring('.'); Action::Render},
+            ':' | '[' | ']' | 'a'..='f' | 'A'..='F' => {self.add_to_ipstring(keychar); Action::Render}, // IPv6
             _ => {//Action::Render
               Action::Blank}
           }
         },
         KeyCode::Backspace => {self.rm_last_char_from_ipstring(); Action::Render},
+        KeyCode::Up => {self.jails.previous(); Action::Render},
+        KeyCode::Down => {self.jails.next(); Action::Render},
         KeyCode::Enter => {if self.submit_ip(false) {self.iperror = String::from("Success!"); Action::Blank} else {self.iperror = String::from("Invalid IP"); Action::Blank}},
         _ => {
           self.input.handle_event(&crossterm::event::Event::Key(key));
@@ -783,15 +842,21 @@ impl Component for Home<'_> {
       Action::EnterUnban => {self.last_mode = self.mode; self.mode = Mode::Unban; self.iperror = String::default(); self.displaymode = DisplayMode::Unban;},
       Action::ExitUnban => {self.mode = self.last_mode; self.displaymode = DisplayMode::Normal; self.ipstring = String::from("")},
       Action::InternalLog(x) => {self.internal_logs.items.push(x); self.internal_logs.trim_to_length(10); self.internal_logs.next()},
+      Action::StartupGotJails(x) => {
+        for jail in x {
+          if !self.jails.items.contains(&jail) {self.jails.items.push(jail);}
+        }
+        if self.jails.state.selected().is_none() {self.jails.next();}
+      },
       Action::StartupGotHome(x) => {
-        let lat = x.lat.clone().parse::<f64>().unwrap();
-        let lon = x.lon.clone().parse::<f64>().unwrap();
-        self.home_lon = lon; self.home_lat = lat;
-        self.last_lon = lon; self.last_lat = lat;
-
         let tx = self.command_tx.clone().unwrap();
-        tx.send(Action::InternalLog(format!(" ✓ Got home: {}, {}", x.city, x.country)))?;
-        
+        if let Some((lat, lon)) = x.coordinates() {
+          self.home_sites = vec![HomeSite::new("Home", lat, lon)];
+          self.last_lon = lon; self.last_lat = lat;
+          tx.send(Action::InternalLog(format!(" ✓ Got home: {}, {}", x.city, x.country)))?;
+        } else {
+          tx.send(Action::InternalLog(String::from(" ❌ Home location unknown")))?;
+        }
       }
       Action::Home(x) => {
         match x {
@@ -858,7 +923,7 @@ impl Component for Home<'_> {
       // ACTION LIST self.available_action
       Action::ActionsNext => {self.available_actions.next();},
       Action::ActionsPrevious => {self.available_actions.previous();},
-      Action::StoppedJCtlWatcher => {self.jctlrunning = false;},
+      Action::StoppedJCtlWatcher(name) => {self.jctlrunning.remove(&name);},
       Action::StoppedF2BWatcher => {self.f2brunning = false}
       Action::IONotify(x) => {self.elapsed_notify += 1;},
 
@@ -867,6 +932,11 @@ impl Component for Home<'_> {
       Action::SubmitQuery(x) => {self.querystring = String::from("") ;self.queryerror = format!("Querying IP: {}", x);},
 
       Action::PassGeo(x,y, z) => {parse_passed_geo(self, x.clone(), y.clone(), z)?;},
+      Action::GotReverseDns(ref ip, ref rdns) => {
+        for item in self.iplist.items.iter_mut().filter(|item| &item.IP.ip == ip) {
+          item.IP.rdns = Some(rdns.clone());
+        }
+      },
 
       // Stats
       Action::StatsShow => {self.showing_stats = true;},
@@ -878,16 +948,14 @@ impl Component for Home<'_> {
         if sel_ip.is_some() {
           banip = self.iplist.items[sel_ip.unwrap()].IP.clone();
           if banip.ip == self.ipstring {
-            self.command_tx.clone().unwrap().send(Action::BanIP(banip))?;
+            self.command_tx.clone().unwrap().send(Action::BanIP(banip, self.selected_jail()))?;
           } else {
-            let mut _ip = IP::default();
-            _ip.ip = self.ipstring.clone();
-            self.command_tx.clone().unwrap().send(Action::BanIP(_ip))?;
+            let _ip = self.typed_ip();
+            self.command_tx.clone().unwrap().send(Action::BanIP(_ip, self.selected_jail()))?;
           }
         } else {
-          let mut _ip = IP::default();
-          _ip.ip = self.ipstring.clone();
-          self.command_tx.clone().unwrap().send(Action::BanIP(_ip))?;
+          let _ip = self.typed_ip();
+          self.command_tx.clone().unwrap().send(Action::BanIP(_ip, self.selected_jail()))?;
         }
 
       },
@@ -897,17 +965,15 @@ impl Component for Home<'_> {
         if sel_ip.is_some() {
           banip = self.iplist.items[sel_ip.unwrap()].IP.clone();
           if banip.ip == self.ipstring {
-            self.command_tx.clone().unwrap().send(Action::UnbanIP(banip))?;
+            self.command_tx.clone().unwrap().send(Action::UnbanIP(banip, self.selected_jail()))?;
           } else {
-            let mut _ip = IP::default();
-            _ip.ip = self.ipstring.clone();
-            self.command_tx.clone().unwrap().send(Action::UnbanIP(_ip))?;
+            let _ip = self.typed_ip();
+            self.command_tx.clone().unwrap().send(Action::UnbanIP(_ip, self.selected_jail()))?;
           }
           
         } else {
-          let mut _ip = IP::default();
-          _ip.ip = self.ipstring.clone();
-          self.command_tx.clone().unwrap().send(Action::UnbanIP(_ip))?;          
+          let _ip = self.typed_ip();
+          self.command_tx.clone().unwrap().send(Action::UnbanIP(_ip, self.selected_jail()))?;          
 
         }
       },
@@ -1036,13 +1102,13 @@ impl Component for Home<'_> {
         },
         DisplayMode::Ban => {
           self.anim_querycursor.next();
-          let p_area = centered_rect(f.size(), 20, 7);
+          let p_area = centered_rect(f.size(), 20, 9);
           f.render_widget(Clear, p_area);
           f.render_widget(self.popup_ban() ,p_area)
         },
         DisplayMode::Unban => {
           self.anim_querycursor.next();
-          let p_area = centered_rect(f.size(), 20, 7);
+          let p_area = centered_rect(f.size(), 20, 9);
           f.render_widget(Clear, p_area);
           f.render_widget(self.popup_unban() ,p_area)
         },
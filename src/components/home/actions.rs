@@ -1,5 +1,6 @@
 use ratatui::prelude::Style;
 use crate::app::models::{IOMessage, IOProducer};
+use crate::config::HomeSite;
 
 use super::{Home, StyledLine, IP, PointData, IPListItem, IOMode, Action, UnboundedSender, Result};
 
@@ -9,7 +10,9 @@ use super::{Home, StyledLine, IP, PointData, IPListItem, IOMode, Action, Unbound
 pub fn style_incoming_message(home: &mut Home, msg: IOMessage) {
   let mut dbg: String;
 
-  home.last_username = String::from("");
+  // username from the parsed sshd line, fail2ban lines don't carry one
+  let ssh_user = msg.ssh_event().and_then(|event| event.user.clone());
+  home.last_username = ssh_user.clone().unwrap_or_default();
 
   let prod: IOProducer;
   let collected = match msg {
@@ -20,10 +23,22 @@ pub fn style_incoming_message(home: &mut Home, msg: IOMessage) {
     IOMessage::MultiLine(vx, p) => {
       prod = p;
       vx
-    }
+    },
+    IOMessage::Fail2Ban(x, _) => {
+      prod = IOProducer::Log;
+      vec![x]
+    },
+    IOMessage::Sshd(x, _, source) => {
+      prod = IOProducer::Journal(source);
+      vec![x]
+    },
+    IOMessage::Journal(source, entry, _) => {
+      prod = IOProducer::Journal(source);
+      vec![entry.short_line()]
+    },
   };
   let last_io = match prod {
-    IOProducer::Journal => {
+    IOProducer::Journal(_) => {
       String::from("Journal")
     },
     IOProducer::Log => {
@@ -42,19 +57,11 @@ pub fn style_incoming_message(home: &mut Home, msg: IOMessage) {
     let mut thisline: StyledLine = StyledLine::default();
     // do word_map matching first then regex match splitting
     // look for ip quickly to send it out to the list
-    let results: Vec<&str> = home.apptheme.ipregex
-    .captures_iter(&tmp_line)
-    .filter_map(|capture| capture.get(1).map(|m| m.as_str()))
-    .collect();
-    let mut cip: &str = "";
-    if !results.is_empty() {
-      cip = results[0];
-    }
+    let cip = crate::ipextract::first_ip(&tmp_line).unwrap_or_default();
 
 
     let words: Vec<&str> = tmp_line.split(" ").collect();
     let mut held_unstyled_words: Vec<&str> = vec![];
-    let mut last_word: &str= "";
 
     for word in words.clone(){
       let mut word_style = home.apptheme.word_style_map.get_style_or_default(word.to_string()); // Detector for constant word
@@ -62,9 +69,8 @@ pub fn style_incoming_message(home: &mut Home, msg: IOMessage) {
         // try regex styling on word
         word_style = home.apptheme.regex_style_map.get_style_or_default(word.to_string()); // Detector for regex
       } 
-      if last_word == "user" {
+      if ssh_user.as_deref() == Some(word) {
         word_style = Style::default().fg(home.apptheme.colors_app.accent_color_a.color);
-        home.last_username = word.to_string();
       }
       
 
@@ -84,7 +90,6 @@ pub fn style_incoming_message(home: &mut Home, msg: IOMessage) {
         thisline.words.push((format!(" {}", word.to_string()), word_style));
 
       }
-      last_word = word;
 
       // terminate
       if &word == words.last().unwrap() {
@@ -95,7 +100,7 @@ pub fn style_incoming_message(home: &mut Home, msg: IOMessage) {
     }
 
     //home.stored_styled_lines.push(thisline);
-    home.stored_styled_iostreamed.items.push((thisline, last_io.clone(), cip.to_string()));
+    home.stored_styled_iostreamed.items.push((thisline, last_io.clone(), cip));
     home.stored_styled_iostreamed.trim_to_length(home.iostreamed_capacity);
 
   }// end per line
@@ -113,17 +118,15 @@ pub fn parse_passed_geo(home: &mut Home, x: IP, y: IOMessage, z: bool) -> Result
 
   if !cipvec.iter().any(|i| i.IP.ip==cip) {
     // if cip isnt in vector yet
-    let lat = x.lat.clone().parse::<f64>().unwrap();
-    let lon = x.lon.clone().parse::<f64>().unwrap();
-    let dir_lat = home.home_lat - lat;
-    let dir_lon = home.home_lon - lon;
-
-    home.last_lat = lat.clone();
-    home.last_lon = lon.clone();
+    // unlocated IPs are listed but not drawn, see `IP::coordinates`
+    let (lat, lon) = x.coordinates().unwrap_or_default();
+    let site = HomeSite::pick(&home.home_sites, y.hostname(), lat, lon).map(|site| (site.lon, site.lat));
+    let pointdata = PointData::new(cip.clone(), lon, lat, site);
 
-    home.last_direction = (dir_lon, dir_lat);
+    home.last_lat = lat;
+    home.last_lon = lon;
 
-    let pointdata = PointData::new(cip.clone(), lon, lat, dir_lon, dir_lat);
+    home.last_direction = (pointdata.dir_home_lon, pointdata.dir_home_lat);
 
     let iplistitem = IPListItem::new(x.clone(), home.last_username.clone(), pointdata);
 
@@ -14,13 +14,16 @@ pub struct PointData {
   pub lat: f64,
   pub dir_home_lon: f64,
   pub dir_home_lat: f64,
+  /// lon, lat of the home site the line is drawn to, none if home is unknown or disabled
+  pub home: Option<(f64, f64)>,
   pub start_time: tokio::time::Instant,
   pub is_alive: bool,
 }
 
 impl PointData {
-  pub fn new(ip: String, lon:f64, lat:f64, dir_lon: f64, dir_lat: f64)-> Self {
-    PointData { ip, lon, lat, dir_home_lon: dir_lon, dir_home_lat: dir_lat, start_time: tokio::time::Instant::now(), is_alive: true }
+  pub fn new(ip: String, lon:f64, lat:f64, home: Option<(f64, f64)>)-> Self {
+    let (dir_lon, dir_lat) = home.map(|(home_lon, home_lat)| (home_lon - lon, home_lat - lat)).unwrap_or_default();
+    PointData { ip, lon, lat, dir_home_lon: dir_lon, dir_home_lat: dir_lat, home, start_time: tokio::time::Instant::now(), is_alive: true }
   }
   pub fn decay_point(&mut self, decaytime: tokio::time::Duration) {
     if self.start_time.elapsed() > decaytime {
@@ -41,7 +44,7 @@ impl PointData {
 
 impl Default for PointData {
    fn default() -> Self {
-      PointData::new(String::default(), f64::default(), f64::default(), f64::default(), f64::default())
+      PointData::new(String::default(), f64::default(), f64::default(), None)
   }
 }
 
@@ -29,7 +29,7 @@ pub fn create_io_list<'a>(
   iostreamed_capacity: usize,
   theme: &Theme,
   term_w: usize,
-  av_actions: StatefulList<(&'a str, String)>,
+  av_actions: StatefulList<(String, String)>,
   selected_ip: String,
   elapsed_rticks: usize,
 ) -> List<'a> {
@@ -79,9 +79,11 @@ pub fn create_io_list<'a>(
     })
     .collect();
 
+  // 0: no watcher running, 1: some, 2: all
+  let monitors: Vec<&(String, String)> = av_actions.items.iter().filter(|i| i.0.starts_with("monitor-")).collect();
   let mut ioactive: u8 = 0;
-  if av_actions.items[2].1 == "active" || av_actions.items[3].1 == "active" {
-    if av_actions.items[2].1 == "active" && av_actions.items[3].1 == "active" {
+  if monitors.iter().any(|i| i.1 == "active") {
+    if monitors.iter().all(|i| i.1 == "active") {
       ioactive = 2;
     } else {
       ioactive = 1;
@@ -161,6 +163,21 @@ pub fn create_ip_list(iplist: StatefulList<IPListItem>, theme: &Theme, mode: Mod
             .italic()
             .into(),
       );
+      if let Some(hostname) = i.IP.rdns.as_ref().and_then(|rdns| rdns.hostname.as_ref().map(|host| (host, rdns.confirmed))) {
+        // unconfirmed names are whatever the owner of the address chose
+        lines.push(
+          format!("    {} {}", hostname.0, if hostname.1 {"✓"} else {"?"})
+              .italic()
+              .into(),
+        );
+      }
+      if !i.IP.jails.is_empty() {
+        lines.push(
+          format!("    [{}]", i.IP.jails.join(", "))
+              .italic()
+              .into(),
+        );
+      }
       ListItem::new(lines).style(Style::default().fg(theme.colors_app.text_color.color))
   })
   .collect();
@@ -183,12 +200,12 @@ pub fn create_ip_list(iplist: StatefulList<IPListItem>, theme: &Theme, mode: Mod
     iplist
 }
 
-pub fn create_action_list<'a>(available_actions:StatefulList<(&'a str, String)>, theme: &Theme, mode: Mode, last_mode:Mode , selected_ip: String) -> List<'a> {
+pub fn create_action_list<'a>(available_actions:StatefulList<(String, String)>, theme: &Theme, mode: Mode, last_mode:Mode , selected_ip: String) -> List<'a> {
   let av_actions: Vec<ListItem> = available_actions
   .items
   .iter()
   .map(|i| {
-      let mut lines = vec![Line::from(i.0)];
+      let mut lines = vec![Line::from(i.0.clone())];
       if i.0 == "Ban" || i.0 == "Unban"
       {
         lines.push(
@@ -197,7 +214,7 @@ pub fn create_action_list<'a>(available_actions:StatefulList<(&'a str, String)>,
               .into(),
         );          
       }
-      else if i.0 == "monitor-fail2ban" || i.0 == "monitor-journalctl"{
+      else if i.0.starts_with("monitor-") {
         let mut symb = "X";
         if i.1 == String::from("active") {
           symb = "✓";
@@ -6,6 +6,7 @@ use std::process::Output;
 /// 
 /// Holds the DB connection and handles queries.
 mod actions;
+mod backfill_actions;
 mod db_actions;
 mod f2b_actions;
 
@@ -27,8 +28,9 @@ use tui_input::{backend::crossterm::EventHandler, Input};
 use super::{Component, Frame};
 use crate::gen_structs::StatefulList;
 use crate::themes::ThemeContainer;
-use crate::{action::Action, config::key_event_to_string, config::Config, themes, animations::Animation, database::schema, geofetcher};
-use crate::database::schema::{message, isp, city, region, country, ip};
+use crate::{action::Action, config::key_event_to_string, config::Config, themes, animations::Animation, database::{schema, DbConn}, geofetcher};
+use crate::database::schema::{message, isp, city, region, country, ip, asn, prefix, rdns, ban_event::{self, BanSource}};
+use crate::fail2ban::{BannedSet, F2BClient};
 
 
 
@@ -80,7 +82,7 @@ pub struct Startup <'a>{
   points: Vec<(f64,f64,f64,f64)>,
 
   // db connection,
-  dbconn: Option<Connection>,
+  dbconn: Option<DbConn>,
 
 
   log_messages: Vec<String>,
@@ -90,9 +92,19 @@ pub struct Startup <'a>{
   anim_dotdotdot: Animation<&'a str>,
   anim_charsoup: Animation<&'a str>,
 
-  // tmp
+  // fail2ban jails we know of, configured and discovered
+  jails: Vec<String>,
+  f2b: F2BClient,
+  // addresses fail2ban currently bans, kept fresh in the background
+  banned: BannedSet,
+  // where locations come from and the worker feeding new IPs to it, set up on connect
+  geo: Option<Arc<dyn geofetcher::GeoProvider>>,
+  geo_queue: Option<geofetcher::GeoQueue>,
+  // reverse lookups of located IPs, `None` if disabled in the config
+  rdns: Option<crate::rdns::ReverseLookup>,
+
+  // IP of the last ssh journal line, ssh lines without an IP are about it
   last_ip: String,
-  fetching_ips:Vec<String>,
   //stored_geo: Vec<ip::IP>,
 
   // startup line
@@ -148,21 +160,28 @@ impl <'a> Startup <'a> {
          "Calling home",
           "Tracing routes",];
     self.countdown_to_start = 10;
-    self.fetching_ips = vec![];
     self.available_themes.items = themes::Themes::default().theme_collection;
     self
   }
 
-  pub fn create_db(&mut self) {
+  pub fn create_db(&mut self, conn: &Connection) -> Result<()> {
     let dt = Utc::now();
     self.log_messages.push(format!("{}            init db", dt.to_string()));
 
-    schema::create_tables(self.dbconn.as_ref().unwrap()).expect("Error setting up tables");
+    let from = schema::migrations::migrate(conn)?;
+    if from != schema::migrations::LATEST {
+      let tx = self.action_tx.clone().unwrap();
+      tx.send(Action::InternalLog(format!(" ✔ Migrated database from schema version {} to {}", from, schema::migrations::LATEST)))?;
+    }
 
     let dt = Utc::now();
     self.log_messages.push(format!("{}            db ready", dt.to_string()));
+    Ok(())
+  }
 
-
+  /// Handle to the database worker, set up on connect.
+  fn db(&self) -> Result<DbConn> {
+    self.dbconn.clone().ok_or_else(|| color_eyre::eyre::eyre!("Database is not connected yet"))
   }
 
   pub fn get_initial_stats(&mut self) {
@@ -178,6 +197,8 @@ impl <'a> Startup <'a> {
       tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
       tx.send(Action::StatsGetISPs).expect("Failed to get ISPs on Startup");  
       tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;    
+      tx.send(Action::StatsGetASNs).expect("Failed to get ASNs on Startup");
+      tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
     });
     let rndline = self.get_rnd_start_msg(self.startup_lines.clone());
     self.log_messages.push(format!("{}            {}", dt.to_string(), rndline));
@@ -210,30 +231,9 @@ impl <'a> Startup <'a> {
     self
   }
 
-  pub fn f2b_check_banned(&self, cip: &str) -> bool {
-    let output = std::process::Command::new("fail2ban-client")
-      .arg("status")
-      .arg("sshd")
-      // Tell the OS to record the command's output
-      .stdout(std::process::Stdio::piped())
-      // execute the command, wait for it to complete, then capture the output
-      .output();
-      // Blow up if the OS was unable to start the program // No pls dont
-    let stdout: String;
-    if output.is_ok() {
-      stdout = String::from_utf8(output.unwrap().stdout).unwrap();
-    }  else {
-      let fetchmsg = format!("   Failed to run fail2ban client for IP look-up");
-      self.action_tx.clone().unwrap().send(Action::InternalLog(fetchmsg)).expect("CRITICAL: Fail to run message failed to send, yes... really... EpicFail");
-      stdout = String::from("");
-    }  
-    // extract the raw bytes that we captured and interpret them as a string        
-    if stdout.contains(cip) {
-      true
-    }
-    else {
-      false
-    }
+  /// Jail used for automatic bans, e.g. geo-blocks.
+  pub fn default_jail(&self) -> String {
+    self.jails.first().cloned().unwrap_or(String::from(crate::config::DEFAULT_JAIL))
   }
 
 
@@ -312,6 +312,9 @@ impl Component for Startup <'_> {
   }
   
   fn register_config_handler(&mut self, config: Config) -> Result<()> {
+    self.jails = config.jail_list();
+    self.jails.iter().for_each(|jail| self.banned.track(jail));
+    self.f2b = F2BClient::new(config.socket_path());
     self.config = config;
     Ok(())
   }
@@ -355,192 +358,244 @@ impl Component for Startup <'_> {
       Action::IONotify(iomsg) => {
         self.io_notify(iomsg)?;
       },
+      Action::Backfill(iomsg) => {
+        self.backfill(iomsg)?;
+      },
+      Action::StartF2BWatcher => {
+        self.backfill_f2b();
+      },
+      Action::StartJCtlWatcher(ref name) => {
+        self.backfill_journal(name);
+      },
       Action::GotGeo(x, iomsg, from_db) => {
         self.got_geo(x, iomsg, from_db)?;
         //self.stored_geo.push(x.clone()); 
       },
+      Action::GeoRefreshed(x) => {
+        self.geo_refreshed(x)?;
+      },
+      Action::GeoRefreshFailed(ip) => {
+        // the IP waits a full ttl before the next try instead of coming up first every hour
+        let now = chrono::offset::Local::now();
+        self.db()?.execute(move |conn| {
+          if let Err(e) = ip::set_geo_checked(conn, &ip, now) {
+            log::error!("Storing the lookup time of {} failed: {}", ip, e);
+          }
+        })?;
+      },
+      Action::GotReverseDns(ip, x) => {
+        self.db()?.execute(move |conn| {
+          if let Err(e) = rdns::insert_rdns(conn, &ip, &x) {
+            log::error!("Storing reverse DNS of {} failed: {}", ip, e);
+          }
+        })?;
+      },
       Action::SubmitQuery(querystr) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let tx = self.action_tx.clone().unwrap();
-        db_actions::process_query(conn, querystr, tx);
+        self.db()?.execute(move |conn| db_actions::process_query(conn, querystr, tx))?;
       },
       Action::StatsGetCountries => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let countries = country::get_all_countries(conn).unwrap_or(vec![]);
         let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for country in countries {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_country(&conn, &country.name).unwrap_or(vec![]);
+        self.db()?.execute(move |conn| {
+          for country in country::get_all_countries(conn).unwrap_or(vec![]) {
+            let timestamps = message::get_message_timestamps_by_country(conn, &country.name).unwrap_or(vec![]);
             tx.send(Action::StatsGotCountry(country, timestamps)).expect("Failed to send Country to Stats");
-         }
-        });
+          }
+        })?;
 
       },
       Action::StatsGetRegions => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let regions = region::get_all_regions(conn).unwrap_or(vec![]);
         let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for region in regions {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_region(&conn, &region.name).unwrap_or(vec![]);
+        self.db()?.execute(move |conn| {
+          for region in region::get_all_regions(conn).unwrap_or(vec![]) {
+            let timestamps = message::get_message_timestamps_by_region(conn, &region.name).unwrap_or(vec![]);
             tx.send(Action::StatsGotRegion(region, timestamps)).expect("Failed to send Region to Stats");
-         }
-        });
+          }
+        })?;
 
       },
       Action::StatsGetISPs => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let isps = isp::get_all_isps(conn).unwrap_or(vec![]);
         let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for isp in isps {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_isp(&conn, &isp.name).unwrap_or(vec![]);
+        self.db()?.execute(move |conn| {
+          for isp in isp::get_all_isps(conn).unwrap_or(vec![]) {
+            let timestamps = message::get_message_timestamps_by_isp(conn, &isp.name).unwrap_or(vec![]);
             tx.send(Action::StatsGotISP(isp, timestamps)).expect("Failed to send ISP to Stats");
-         }
-        });
+          }
+        })?;
       },
       Action::StatsGetCities => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let cities = city::get_all_cities(conn).unwrap_or(vec![]);
         let tx = self.action_tx.clone().unwrap();
-        tokio::spawn(async move {
-          let conn = Connection::open("iplogs.db").expect("Async thread DB connection failed");
-          for city in cities {
-            std::thread::sleep(std::time::Duration::from_millis(10)); // Debounce
-            let timestamps = message::get_message_timestamps_by_city(&conn, &city.name).unwrap_or(vec![]);
+        self.db()?.execute(move |conn| {
+          for city in city::get_all_cities(conn).unwrap_or(vec![]) {
+            let timestamps = message::get_message_timestamps_by_city(conn, &city.name).unwrap_or(vec![]);
             tx.send(Action::StatsGotCity(city, timestamps)).expect("Failed to send City to Stats");
-         }
-        });
+          }
+        })?;
+      },
+      Action::StatsGetASNs => {
+        let tx = self.action_tx.clone().unwrap();
+        self.db()?.execute(move |conn| {
+          for asn in asn::get_all_asns(conn).unwrap_or(vec![]) {
+            let timestamps = message::get_message_timestamps_by_asn(conn, asn.number).unwrap_or(vec![]);
+            tx.send(Action::StatsGotASN(asn, timestamps)).expect("Failed to send ASN to Stats");
+          }
+        })?;
       },
 
       Action::StatsBlockCountry(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let country = country::select_country(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), true).unwrap();
-        let fetchmsg = format!(" {} Blocked Country: {}", self.apptheme.symbols.block, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Country message failed to send");
+        let symbol = self.apptheme.symbols.block.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let country = country::select_country(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as blocked
+          let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), true).unwrap();
+          let fetchmsg = format!(" {} Blocked Country: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Country message failed to send");
+        })?;
       },
       Action::StatsUnblockCountry(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let country = country::select_country(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), false).unwrap();
-        let fetchmsg = format!(" {} Unblocked Country: {}", self.apptheme.symbols.unblock, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Country message failed to send");
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let country = country::select_country(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as unblocked
+          let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), false).unwrap();
+          let fetchmsg = format!(" {} Unblocked Country: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Country message failed to send");
+        })?;
       },      
       Action::StatsBlockRegion(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let region = region::select_region(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), true).unwrap();
-        let fetchmsg = format!(" {} Blocked Region: {}", self.apptheme.symbols.block, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Region message failed to send");
+        let symbol = self.apptheme.symbols.block.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let region = region::select_region(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as blocked
+          let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), true).unwrap();
+          let fetchmsg = format!(" {} Blocked Region: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block Region message failed to send");
+        })?;
       },
       Action::StatsUnblockRegion(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let region = region::select_region(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), false).unwrap();
-        let fetchmsg = format!(" {} Unblocked Region: {}", self.apptheme.symbols.unblock, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Region message failed to send");
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let region = region::select_region(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as unblocked
+          let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), false).unwrap();
+          let fetchmsg = format!(" {} Unblocked Region: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock Region message failed to send");
+        })?;
       }, 
       Action::StatsBlockCity(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let city = city::select_city(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), true).unwrap();
-        let fetchmsg = format!(" {} Blocked City: {}", self.apptheme.symbols.block, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block City message failed to send");
+        let symbol = self.apptheme.symbols.block.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let city = city::select_city(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as blocked
+          let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), true).unwrap();
+          let fetchmsg = format!(" {} Blocked City: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block City message failed to send");
+        })?;
       },
       Action::StatsUnblockCity(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let city = city::select_city(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), false).unwrap();
-        let fetchmsg = format!(" {} Unblocked City: {}", self.apptheme.symbols.unblock, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock City message failed to send");
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let city = city::select_city(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as unblocked
+          let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(), city.region.as_str(),Some(city.banned), Some(city.warnings), false).unwrap();
+          let fetchmsg = format!(" {} Unblocked City: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock City message failed to send");
+        })?;
       },    
       Action::StatsBlockISP(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let isp = isp::select_isp(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as blocked
-        let _ = isp::insert_new_ISP(conn, isp.name.as_str(),Some(isp.banned), Some(isp.warnings),isp.country.as_str(), true).unwrap();
-        let fetchmsg = format!(" {} Blocked ISP: {}", self.apptheme.symbols.unblock, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block ISP message failed to send");
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let isp = isp::select_isp(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as blocked
+          let _ = isp::insert_new_ISP(conn, isp.name.as_str(),Some(isp.banned), Some(isp.warnings),isp.country.as_str(), true).unwrap();
+          let fetchmsg = format!(" {} Blocked ISP: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block ISP message failed to send");
+        })?;
       },
       Action::StatsUnblockISP(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        // get item from db to see if it was updated, it will exist becasue we query from stats.
-        let isp = isp::select_isp(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
-        // insert new as unblocked
-        let _ = isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings),isp.country.as_str(), false).unwrap();
-        let fetchmsg = format!(" {} Unblocked ISP: {}", self.apptheme.symbols.unblock, &x.name);
-        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock ISP message failed to send");
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          // get item from db to see if it was updated, it will exist becasue we query from stats.
+          let isp = isp::select_isp(conn, x.name.as_str()).unwrap_or_default().unwrap_or_default();
+          // insert new as unblocked
+          let _ = isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings),isp.country.as_str(), false).unwrap();
+          let fetchmsg = format!(" {} Unblocked ISP: {}", symbol, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock ISP message failed to send");
+        })?;
       }, 
+      Action::StatsBlockASN(x) => {
+        let symbol = self.apptheme.symbols.block.clone();
+        self.db()?.execute(move |conn| {
+          let asn = asn::select_asn(conn, x.number).unwrap_or_default().unwrap_or(x.clone());
+          asn::insert_new_ASN(conn, asn.number, asn.name.as_str(), Some(asn.banned), Some(asn.warnings), asn.country.as_str(), true).unwrap();
+          let fetchmsg = format!(" {} Blocked AS{}: {}", symbol, x.number, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block ASN message failed to send");
+        })?;
+      },
+      Action::StatsUnblockASN(x) => {
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          let asn = asn::select_asn(conn, x.number).unwrap_or_default().unwrap_or(x.clone());
+          asn::insert_new_ASN(conn, asn.number, asn.name.as_str(), Some(asn.banned), Some(asn.warnings), asn.country.as_str(), false).unwrap();
+          let fetchmsg = format!(" {} Unblocked AS{}: {}", symbol, x.number, &x.name);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock ASN message failed to send");
+        })?;
+      },
+      Action::StatsBlockPrefix(x) => {
+        let symbol = self.apptheme.symbols.block.clone();
+        self.db()?.execute(move |conn| {
+          // a prefix is only known once an IP in it was seen, but blocking it shouldn't depend on that
+          let prefix = prefix::select_prefix(conn, x.as_str()).unwrap_or_default().unwrap_or_default();
+          prefix::insert_new_prefix(conn, x.as_str(), prefix.asn, Some(prefix.banned), Some(prefix.warnings), true).unwrap();
+          let fetchmsg = format!(" {} Blocked prefix: {}", symbol, &x);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Block prefix message failed to send");
+        })?;
+      },
+      Action::StatsUnblockPrefix(x) => {
+        let symbol = self.apptheme.symbols.unblock.clone();
+        self.db()?.execute(move |conn| {
+          let prefix = prefix::select_prefix(conn, x.as_str()).unwrap_or_default().unwrap_or_default();
+          prefix::insert_new_prefix(conn, x.as_str(), prefix.asn, Some(prefix.banned), Some(prefix.warnings), false).unwrap();
+          let fetchmsg = format!(" {} Unblocked prefix: {}", symbol, &x);
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unblock prefix message failed to send");
+        })?;
+      },
       Action::StatsGetIP(x) => {
-        let conn = self.dbconn.as_ref().unwrap();
-        let ipdata = ip::select_ip(conn, x.as_str()).unwrap_or_default().take().unwrap_or_default();
-        if ipdata != ip::IP::default() {
-          let tx = self.action_tx.clone().unwrap();
-          tx.send(Action::StatsGotIP(ipdata)).expect("Failed to send IP data back to Stats");
-        }
+        self.db()?.execute(move |conn| {
+          let ipdata = ip::select_ip(conn, x.as_str()).unwrap_or_default().take().unwrap_or_default();
+          if ipdata != ip::IP::default() {
+            tx.send(Action::StatsGotIP(ipdata)).expect("Failed to send IP data back to Stats");
+          }
+          // IPs banned by hand may never have logged a line
+          let events = ban_event::select_ban_events_by_ip(conn, x.as_str()).unwrap_or_default();
+          tx.send(Action::StatsGotBanEvents(x, events)).expect("Failed to send ban events back to Stats");
+        })?;
       },
 
-      Action::BanIP(x) => {
-        let cip = x.clone();
+      Action::BanIP(x, jail) => {
         let symb = self.apptheme.symbols.ban.clone();
-        let _symb = self.apptheme.symbols.ban.clone();
-        if !x.is_banned {
-
-          let besure = self.f2b_check_banned(&x.ip);
-          
-          if !besure {        
-            let tx = self.action_tx.clone().unwrap();
-            f2b_actions::send_ban(x.clone(), symb, tx);
-          } else {
-            let blockmsg = format!(" {} IP already banned {}", _symb, &cip.ip);
-            tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");   
-            tx.send(Action::Banned(true)).expect("Failed to Ban ...");
-          }
-          tx.send(Action::Banned(true)).expect("Failed to Ban ...");
-        } else {
-          let blockmsg = format!(" {} IP already banned {}", _symb, &cip.ip);
-          tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");  
-          tx.send(Action::Banned(true)).expect("Failed to Ban ...");
-        }
+        f2b_actions::send_ban(self.f2b.clone(), self.banned.clone(), self.dbconn.clone(), x, jail, BanSource::Manual, None, symb, tx);
       },
-      Action::UnbanIP(x) => {
-        let cip = x.clone();
-        let besure: bool;
-        if !x.is_banned {
-          besure = self.f2b_check_banned(&x.ip);
-        } else {
-          // x is banned
-          besure = true;
-        }
-        if besure {
-          let tx = self.action_tx.clone().unwrap();
-          let symb = self.apptheme.symbols.unblock.clone();
-          f2b_actions::send_unban(x.clone(), symb, tx);
-        } else {
-          let blockmsg = format!(" ! IP is not banned {}", &cip.ip);
-          tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");   
+      Action::GeoBlockIP(x, jail, reason) => {
+        let symb = self.apptheme.symbols.ban.clone();
+        f2b_actions::send_ban(self.f2b.clone(), self.banned.clone(), self.dbconn.clone(), x, jail, BanSource::GeoBlock, Some(reason), symb, tx);
+      },
+      Action::UnbanIP(x, jail) => {
+        let symb = self.apptheme.symbols.unblock.clone();
+        f2b_actions::send_unban(self.f2b.clone(), self.banned.clone(), self.dbconn.clone(), x, jail, symb, tx);
+      },
+      Action::StartupGotJails(x) => {
+        for jail in x {
+          self.banned.track(&jail);
+          if !self.jails.contains(&jail) {self.jails.push(jail);}
         }
-      },      
+      },
 
       _ => (),
     }
@@ -1,113 +1,231 @@
+use std::sync::Arc;
+
 use chrono::Utc;
-use rusqlite::Connection;
 
-use crate::{action::Action, database::schema::{self, city, country, ip::{self, IP}, isp, message, region}, geofetcher::{self, deserialize_geolocation, fetch_geolocation}, app::models::{IOMessage, IOProducer}};
+use crate::{action::Action, database::{self, DbConn, schema::{self, ban_event::{self, BanEvent}, city, country, ip::{self, IP}, isp, message, region, retention::{self, Retention}}}, geofetcher::{self, GeoProvider, GeoRequest}, fail2ban, app::models::{IOMessage, IOProducer}};
 
-use super::{Mode, Startup};
+use super::{f2b_actions, Mode, Startup};
 
 use color_eyre::eyre::Result;
 
+/// How often stored locations are checked for expiry.
+const GEO_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Stale IPs queued per check, the rest waits for the next one.
+const GEO_REFRESH_LIMIT: usize = 500;
+/// How often messages past the retention are rolled up.
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
 impl <'a> Startup <'a> {
   pub fn connect(&mut self) -> Result<Option<Action>> {
     let dt = Utc::now();
     let tx = self.action_tx.clone().unwrap();
-    fetch_home_and_report(tx);
-    self.log_messages.push(format!("{}            Connecting to db", dt.to_string()));
-    let conn = Connection::open("iplogs.db")?;
-    self.dbconn = Some(conn);
-    self.create_db();
+    let path = self.config.db_path();
+    self.log_messages.push(format!("{}            Connecting to db {}", dt.to_string(), path.display()));
+    let conn = database::open(&path)?;
+    self.create_db(&conn)?;
+    let db = DbConn::spawn(conn);
+    self.dbconn = Some(db.clone());
+    tx.send(Action::InternalLog(format!(" ✔ Using database {}", path.display())))?;
+    match geofetcher::provider_from_config(&self.config.geo) {
+      Ok(provider) => {
+        tx.send(Action::InternalLog(format!(" ✔ Locating IPs with {}", provider.name())))?;
+        let queue = geofetcher::GeoQueue::spawn(provider.clone(), tx.clone());
+        if let Some(ttl) = self.config.geo_ttl() {
+          spawn_geo_refresher(queue.clone(), db.clone(), ttl, tx.clone());
+        }
+        self.geo_queue = Some(queue);
+        self.geo = Some(provider);
+      },
+      Err(e) => tx.send(Action::InternalLog(format!(" ❌ Geo provider not usable: {}", e)))?,
+    }
+    spawn_pruner(db.clone(), self.config.retention(), self.config.vacuum_interval(), tx.clone());
+    self.rdns = self.config.rdns_timeout().map(|timeout| crate::rdns::ReverseLookup::new(Arc::new(crate::rdns::SystemResolver::new()), timeout));
+    // home sites from the config need no lookup
+    if self.config.home_sites().is_none() {
+      fetch_home_and_report(self.geo.clone(), tx.clone());
+    }
+    f2b_actions::fetch_jails_and_report(self.f2b.clone(), tx.clone());
+    fail2ban::spawn_refresher(self.f2b.clone(), self.banned.clone(), tx);
     self.get_initial_stats();
     self.mode = Mode::Done;
     Ok(None)
   }
 
   pub fn io_notify(&mut self, iomsg: IOMessage) -> Result<Option<Action>> {
+    self.locate(iomsg, true)
+  }
+
+  /// Historic line read by a backfill, lines already stored are dropped.
+  pub fn backfill(&mut self, iomsg: IOMessage) -> Result<Option<Action>> {
+    self.locate(iomsg, false)
+  }
+
+  /// Lines of sshd, the only lines without an IP that are about the IP before them.
+  /// Known from the config of the line's source or from the line parsing as an sshd event.
+  fn is_ssh(&self, iomsg: &IOMessage, prod: &IOProducer) -> bool {
+    iomsg.ssh_event().is_some() || prod.source().is_some_and(|name| {
+      self.config.journal_sources().iter().any(|source| source.name == name && source.is_sshd())
+    })
+  }
+
+  /// Looks up the IP of a line, `live` lines also update the banned set and retry without an IP.
+  fn locate(&mut self, iomsg: IOMessage, live: bool) -> Result<Option<Action>> {
        // got new line
        let (catmsg, prod) = iomsg.destructure(" ");
-        // regex for finding an IPv4
-        let results: Vec<&str> = self.apptheme.ipregex
-          .captures_iter(&catmsg)
-          .filter_map(|capture| capture.get(1).map(|m| m.as_str()))
-          .collect();
+        let is_ssh = self.is_ssh(&iomsg, &prod);
+        // find IPv4 and IPv6 addresses
+        let results: Vec<String> = crate::ipextract::extract_ips(&catmsg).iter().map(|ip| ip.to_string()).collect();
         if results.is_empty() {
-          // results were empty, might happen if journalctl sends error message -> in that case just insert the last ip into the message and try again
-          if !self.last_ip.is_empty() && prod == IOProducer::Journal {
-            let msg = IOMessage::SingleLine(format!("{} for {}", catmsg, self.last_ip), prod);
+          // results were empty, might happen if sshd logs an error message -> in that case just insert the last ip into the message and try again
+          // other sources interleave lines about other hosts, their lines without an IP are dropped
+          if live && !self.last_ip.is_empty() && is_ssh {
+            let msg = IOMessage::from_line(format!("{} for {}", catmsg, self.last_ip), prod);
             self.action_tx.clone().unwrap().send(Action::IONotify(msg))?;
           }
           return Ok(None)
         }
-        // Just take the first occurance of an ip into account
-        let cip = results[0];
-        let mut is_banned = catmsg.contains("Ban");
-        if self.last_ip != String::from(cip) {
-          std::thread::sleep(std::time::Duration::from_millis(50));
-          // check if is banned
-          is_banned = self.f2b_check_banned(cip);
-        };
-        self.last_ip = String::from(cip);
-
-        let conn = self.dbconn.as_ref().unwrap();
-        let mut maybe_data = ip::select_ip(conn, cip).unwrap_or_default().take().unwrap_or_default();
-        
-        if maybe_data == ip::IP::default() {
-          // we have to fetch the data
-          let sender = self.action_tx.clone().unwrap();
-          self.fetching_ips.push(cip.to_string());
-          fetch_geolocation_and_report(cip.to_string(), is_banned.clone(), iomsg, sender);
+        // keep the banned set up to date without waiting for the next refresh
+        if let Some(event) = iomsg.f2b_event().filter(|_| live) {
+          self.banned.apply_event(event);
         }
-        else {
-          // data is stored
-          maybe_data.is_banned = is_banned;
-          self.action_tx.clone().unwrap().send(Action::GotGeo(maybe_data, iomsg, true))?;  // return true, GeoData came from DB
+        // Just take the first occurance of an ip into account, unless sshd told us which one it is about
+        let ssh_ip = iomsg.ssh_event().and_then(|event| event.ip).map(|ip| ip.to_string());
+        let cip = ssh_ip.as_deref().unwrap_or(results[0].as_str());
+        let banned_jails = self.banned.banned_jails(cip);
+        let is_banned = !banned_jails.is_empty();
+        if is_ssh {
+          self.last_ip = String::from(cip);
         }
+
+        let ip = String::from(cip);
+        let queue = self.geo_queue.clone();
+        let tx = self.action_tx.clone().unwrap();
+        self.db()?.execute(move |conn| {
+          // a backfill reads lines that may be stored already
+          if !live && message::contains_text(conn, &catmsg).unwrap_or(false) {
+            return
+          }
+          match ip::select_ip(conn, &ip).unwrap_or_default() {
+            Some(mut stored) => {
+              // data is stored
+              stored.is_banned = is_banned;
+              stored.jails = banned_jails;
+              tx.send(Action::GotGeo(stored, iomsg, true)).unwrap_or_default();  // return true, GeoData came from DB
+            },
+            // we have to fetch the data
+            None => request_geo(queue.as_ref(), &tx, &ip, is_banned, banned_jails, iomsg),
+          }
+        })?;
+    Ok(None)
+  }
+
+  /// Stores the re-lookup of an expired location, a changed location is logged and kept in the history.
+  pub fn geo_refreshed(&mut self, x: IP) -> Result<Option<Action>> {
+    let tx = self.action_tx.clone().unwrap();
+    let symbol = self.apptheme.symbols.request.clone();
+    self.db()?.execute(move |conn| match schema::refresh_ip_location(conn, &x) {
+      Ok(Some(former)) => {
+        tx.send(Action::InternalLog(format!(" {} IP {} moved from {}, {} to {}, {}", symbol, x.ip, former.city, former.country, x.city, x.country))).unwrap_or_default();
+      },
+      Ok(None) => (),
+      Err(e) => log::error!("Storing the new location of {} failed: {}", x.ip, e),
+    })?;
     Ok(None)
   }
 
   pub fn got_geo(&mut self, x: IP, iomsg: IOMessage, from_db: bool) -> Result<Option<Action>> {
-      
-      let conn = self.dbconn.as_ref().unwrap();
+      let (catmsg, prod) = iomsg.destructure(" ");
 
-      let meta = crate::database::schema::update_db_on_new_log(conn, x.clone(), from_db);
-      crate::database::schema::update_ip_db_on_new_log(conn, x.clone(), from_db);
+      let is_jctl: bool = prod.is_journal();
+      let is_ban = iomsg.is_ban();
+      // lines that already ban or unban the IP must not trigger a geo-block
+      let is_ban_action = iomsg.f2b_event().is_some_and(|event| event.is_ban_action());
 
-      let (catmsg, prod) = iomsg.destructure(" ");
+      if let (Some(lookups), Some(addr)) = (self.rdns.as_ref(), crate::ipextract::parse_ip(&x.ip)) {
+        if crate::rdns::is_stale(x.rdns.as_ref()) {
+          lookups.request(addr, self.action_tx.clone().unwrap());
+        }
+      }
 
-      let is_jctl: bool = prod == IOProducer::Journal;
-      let is_ban  = catmsg.contains("Ban");
       let tx = self.action_tx.clone().unwrap();
       tx.send(Action::PassGeo(x.clone(), iomsg.clone(), from_db)).expect("PassGeo failed to send");
       let symb = if from_db {self.apptheme.symbols.database.clone()} else {self.apptheme.symbols.request.clone()};
       let fetchmsg = format!(" {} Got location for IP {} ", symb, x.ip);
       tx.send(Action::InternalLog(fetchmsg)).expect("Fetchlog message failed to send");
 
-      geo_block_and_log(x.clone(), is_ban, meta, tx.clone(), self.apptheme.symbols.block.clone());
+      let jail = self.default_jail();
+      let symbol_block = self.apptheme.symbols.block.clone();
+      self.db()?.execute(move |conn| {
+        let meta = crate::database::schema::update_db_on_new_log(conn, x.clone());
+        crate::database::schema::update_ip_db_on_new_log(conn, x.clone(), from_db, is_ban);
+        geo_block_and_log(x.clone(), is_ban_action, meta, jail, tx.clone(), symbol_block);
 
-      let timestamp = chrono::offset::Local::now().to_rfc3339();
-      match iomsg {
-        IOMessage::SingleLine(msg, _) => {
-          message::insert_new_message(conn, Option::None, &timestamp, &msg, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban).unwrap();
-        },
-        IOMessage::MultiLine(vx, _) => {
-          for msg in vx {
-            message::insert_new_message(conn, Option::None, &timestamp, &msg, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban).unwrap();
-          }
-        },
-      };
+        // prefer the time fail2ban or the journal logged the event at
+        let timestamp = iomsg.timestamp().unwrap_or(chrono::offset::Local::now());
+        let ban_event = iomsg.f2b_event().and_then(|event| BanEvent::from_f2b(event, &x.ip, timestamp));
+        // journal lines keep their source, each source backfills from its own newest line
+        let source = prod.source();
+        match iomsg {
+          IOMessage::SingleLine(msg, _) => {
+            message::insert_new_message_with_ssh(conn, Option::None, timestamp, &msg, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban, None, source).unwrap();
+          },
+          IOMessage::MultiLine(vx, _) => {
+            for msg in vx {
+              message::insert_new_message_with_ssh(conn, Option::None, timestamp, &msg, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban, None, source).unwrap();
+            }
+          },
+          IOMessage::Fail2Ban(msg, _) => {
+            message::insert_new_message(conn, Option::None, timestamp, &msg, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban).unwrap();
+          },
+          IOMessage::Sshd(msg, event, _) => {
+            message::insert_new_message_with_ssh(conn, Option::None, timestamp, &msg, &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban, Some(&event), source).unwrap();
+          },
+          IOMessage::Journal(_, entry, event) => {
+            message::insert_new_message_with_ssh(conn, Option::None, timestamp, &entry.short_line(), &x.ip, &x.country, &x.region, &x.city, &x.isp, is_jctl, is_ban, event.as_ref(), source).unwrap();
+          },
+        };
+        if let Some(ban_event) = ban_event {
+          ban_event::record_ban_event(conn, &ban_event).unwrap();
+        }
+      })?;
       Ok(None)
   }
 
 }
 
-fn geo_block_and_log(x: IP, is_ban: bool, meta: schema::MetaInfo, tx: tokio::sync::mpsc::UnboundedSender<Action>, symbol_block: String) {
-  if meta.country.is_blocked || meta.city.is_blocked || meta.isp.is_blocked || meta.region.is_blocked { 
-    if !x.is_banned && !is_ban {
-      tx.send(Action::BanIP(x.clone())).expect("Block failed to send");
+/// Hands the IP to the geolocation worker, which answers with `Action::GotGeo`.
+/// Local and reserved addresses get a synthetic record right away.
+fn request_geo(queue: Option<&geofetcher::GeoQueue>, tx: &tokio::sync::mpsc::UnboundedSender<Action>, ip: &str, is_banned: bool, jails: Vec<String>, message: IOMessage) {
+  // LAN and reserved addresses have no location to look up
+  if let Some(mut geodata) = crate::ipextract::parse_ip(ip).and_then(geofetcher::unlocatable) {
+    geodata.is_banned = is_banned;
+    geodata.banned_times = if is_banned {1} else {0};
+    geodata.jails = jails;
+    tx.send(Action::GotGeo(geodata, message, false)).unwrap_or_default();
+    return
+  }
+  let (Some(queue), Some(addr)) = (queue, crate::ipextract::parse_ip(ip)) else {
+    tx.send(Action::InternalLog(format!("  No geo provider to locate IP {} ", ip))).unwrap_or_default();
+    return
+  };
+  if !queue.request(GeoRequest { addr, is_banned, jails, message: Some(message) }) {
+    tx.send(Action::InternalLog(format!(" ❌ Geo queue full, not locating IP {}", ip))).unwrap_or_default();
+  }
+}
+
+fn geo_block_and_log(x: IP, is_ban_action: bool, meta: schema::MetaInfo, jail: String, tx: tokio::sync::mpsc::UnboundedSender<Action>, symbol_block: String) {
+  let asn_blocked = meta.asn.as_ref().is_some_and(|asn| asn.is_blocked);
+  let prefix_blocked = meta.prefix.as_ref().is_some_and(|prefix| prefix.is_blocked);
+  if meta.country.is_blocked || meta.city.is_blocked || meta.isp.is_blocked || meta.region.is_blocked || asn_blocked || prefix_blocked { 
+    if !x.is_banned && !is_ban_action {
       let mut reasons: Vec<String> = vec![];
       if meta.country.is_blocked {reasons.push(format!("Country: {}", meta.country.name));}
       if meta.region.is_blocked {reasons.push(format!("Region: {}", meta.region.name));}
       if meta.city.is_blocked {reasons.push(format!("City: {}", meta.city.name));}
       if meta.isp.is_blocked {reasons.push(format!("ISP: {}", meta.isp.name));}
+      if let Some(asn) = meta.asn.as_ref().filter(|asn| asn.is_blocked) {reasons.push(format!("AS{}: {}", asn.number, asn.name));}
+      if let Some(prefix) = meta.prefix.as_ref().filter(|prefix| prefix.is_blocked) {reasons.push(format!("Prefix: {}", prefix.prefix));}
+      tx.send(Action::GeoBlockIP(x.clone(), jail, reasons.join(", "))).expect("Block failed to send");
 
       let blockmsg = format!(" {} Blocked IP {} ", symbol_block, x.ip);
       tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");
@@ -122,25 +240,44 @@ fn geo_block_and_log(x: IP, is_ban: bool, meta: schema::MetaInfo, tx: tokio::syn
   }
 }
 
-fn fetch_geolocation_and_report(ip: String, is_banned: bool, original_message: IOMessage, tx: tokio::sync::mpsc::UnboundedSender<Action>) {
-    tokio::task::spawn(async move {      
-        let geodat = fetch_geolocation(ip.as_str()).await.unwrap_or(serde_json::Value::default());
-        let geodata = deserialize_geolocation(geodat, is_banned);
-        if geodata.is_some() {
-          tx.send(Action::GotGeo(geodata.unwrap(), original_message, false)).unwrap_or_default(); // false, GeoData was acquired freshly
-        } else {
-          let fetchmsg = format!("  Could not find location for IP {} ", ip);
-          tx.send(Action::InternalLog(fetchmsg)).expect("Fetchlog message failed to send");
+/// Queues re-lookups of stored locations older than `ttl`, a minute after startup and then every hour.
+fn spawn_geo_refresher(queue: geofetcher::GeoQueue, db: DbConn, ttl: chrono::Duration, tx: tokio::sync::mpsc::UnboundedSender<Action>) {
+  tokio::spawn(async move {
+    // leave the first minute to the lines coming in on startup
+    let start = tokio::time::Instant::now() + std::time::Duration::from_secs(60);
+    let mut interval = tokio::time::interval_at(start, GEO_REFRESH_INTERVAL);
+    loop {
+      interval.tick().await;
+      if tx.is_closed() {
+        break;
+      }
+      let before = chrono::offset::Local::now() - ttl;
+      let Ok(stale) = db.call(move |conn| ip::select_stale(conn, before, GEO_REFRESH_LIMIT).unwrap_or_default()).await else { break };
+      let mut queued = 0;
+      for addr in stale.iter().filter_map(|ip| crate::ipextract::parse_ip(ip)) {
+        // LAN and reserved addresses were never looked up
+        if geofetcher::unlocatable(addr).is_none() && queue.request(GeoRequest::refresh(addr)) {
+          queued += 1;
         }
-      }); 
+      }
+      if queued > 0 {
+        tx.send(Action::InternalLog(format!(" ⌖ Looking up {} expired locations again", queued))).unwrap_or_default();
+      }
+    }
+  });
 }
 
-fn fetch_home_and_report(tx: tokio::sync::mpsc::UnboundedSender<Action>) {
+fn fetch_home_and_report(provider: Option<Arc<dyn GeoProvider>>, tx: tokio::sync::mpsc::UnboundedSender<Action>) {
   tokio::spawn(async move {
     // get my local ip from somewhere
     let my_local_ip = geofetcher::fetch_home().await.unwrap_or_default();
-    let geodat = geofetcher::fetch_geolocation(my_local_ip.to_string().as_str()).await.unwrap_or(serde_json::Value::default());
-    let geodata = geofetcher::deserialize_geolocation(geodat, false);
+    let geodata = match (provider, crate::ipextract::parse_ip(&my_local_ip)) {
+      (Some(provider), Some(addr)) => provider.lookup(addr).await.unwrap_or_else(|e| {
+        tx.send(Action::InternalLog(format!(" ❌ Could not locate home: {}", e))).unwrap_or_default();
+        None
+      }),
+      _ => None,
+    };
 
     if geodata.is_some() {
       tx.send(Action::StartupGotHome(geodata.unwrap())).unwrap_or_default();          
@@ -150,5 +287,94 @@ fn fetch_home_and_report(tx: tokio::sync::mpsc::UnboundedSender<Action>) {
   });
 }
 
+/// Rolls messages past the retention into daily counts a minute after startup and then every hour,
+/// vacuums the database when it's due.
+fn spawn_pruner(db: DbConn, retention: Retention, vacuum_interval: Option<chrono::Duration>, tx: tokio::sync::mpsc::UnboundedSender<Action>) {
+  tokio::spawn(async move {
+    let start = tokio::time::Instant::now() + std::time::Duration::from_secs(60);
+    let mut interval = tokio::time::interval_at(start, PRUNE_INTERVAL);
+    loop {
+      interval.tick().await;
+      if tx.is_closed() {
+        break;
+      }
+      let Ok(pruned) = db.call(move |conn| retention::prune(conn, &retention, chrono::offset::Local::now())).await else { break };
+      match pruned {
+        Ok(0) => (),
+        Ok(pruned) => tx.send(Action::InternalLog(format!(" ✔ Rolled {} old messages into daily counts", pruned))).unwrap_or_default(),
+        Err(e) => tx.send(Action::InternalLog(format!(" ❌ Pruning messages failed: {}", e))).unwrap_or_default(),
+      }
+      let Some(vacuum_interval) = vacuum_interval else { continue };
+      let vacuumed = db.call(move |conn| {
+        let now = chrono::offset::Local::now();
+        match retention::vacuum_due(conn, vacuum_interval, now) {
+          Ok(true) => retention::vacuum(conn, now).map(|_| true),
+          due => due,
+        }
+      });
+      match vacuumed.await {
+        Ok(Ok(true)) => tx.send(Action::InternalLog(String::from(" ✔ Vacuumed the database"))).unwrap_or_default(),
+        Ok(Ok(false)) => (),
+        Ok(Err(e)) => tx.send(Action::InternalLog(format!(" ❌ Vacuuming the database failed: {}", e))).unwrap_or_default(),
+        Err(_) => break,
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::models::SSH_SOURCE;
+  use crate::config::JournalSourceConfig;
+
+  #[test]
+  fn test_line_without_ip() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut startup = Startup::new();
+    startup.action_tx = Some(tx);
+    let journal = |source: &str, line: &str| IOMessage::from_line(String::from(line), IOProducer::Journal(String::from(source)));
 
+    // not connected yet, the line isn't stored but its IP is remembered
+    assert!(startup.locate(journal(SSH_SOURCE, "Invalid user admin from 192.0.2.1 port 52234"), true).is_err());
+    assert!(startup.locate(journal("nginx", "GET / from 198.51.100.7"), true).is_err());
+    assert_eq!(startup.last_ip, "192.0.2.1");
 
+    startup.locate(journal("nginx", "upstream timed out"), true).unwrap();
+    assert!(rx.try_recv().is_err());
+    startup.locate(IOMessage::from_line(String::from("fail2ban.server [1]: INFO Reload finished"), IOProducer::Log), true).unwrap();
+    assert!(rx.try_recv().is_err());
+
+    startup.locate(journal(SSH_SOURCE, "Connection closed by authenticating user root"), true).unwrap();
+    match rx.try_recv() {
+      Ok(Action::IONotify(msg)) => assert_eq!(msg.destructure(" ").0, "Connection closed by authenticating user root for 192.0.2.1"),
+      action => panic!("unexpected {:?}", action),
+    }
+  }
+
+  #[test]
+  fn test_ssh_source_by_config() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut startup = Startup::new();
+    startup.action_tx = Some(tx);
+    startup.config.journal.sources = vec![
+      JournalSourceConfig { name: String::from("auth"), identifiers: vec![String::from("sshd")], ..Default::default() },
+      JournalSourceConfig { name: String::from("nginx"), units: vec![String::from("nginx")], ..Default::default() },
+    ];
+    let journal = |source: &str, line: &str| IOMessage::from_line(String::from(line), IOProducer::Journal(String::from(source)));
+
+    // parses as sshd, whatever the source is called
+    assert!(startup.locate(journal("nginx", "Invalid user admin from 192.0.2.1 port 52234"), true).is_err());
+    assert_eq!(startup.last_ip, "192.0.2.1");
+    assert!(startup.locate(journal("nginx", "GET / from 198.51.100.7"), true).is_err());
+    assert_eq!(startup.last_ip, "192.0.2.1");
+
+    startup.locate(journal("nginx", "Connection closed by authenticating user root"), true).unwrap();
+    assert!(rx.try_recv().is_err());
+    startup.locate(journal("auth", "Connection closed by authenticating user root"), true).unwrap();
+    match rx.try_recv() {
+      Ok(Action::IONotify(msg)) => assert_eq!(msg.destructure(" "), (String::from("Connection closed by authenticating user root for 192.0.2.1"), IOProducer::Journal(String::from("auth")))),
+      action => panic!("unexpected {:?}", action),
+    }
+  }
+}
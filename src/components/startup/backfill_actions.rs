@@ -0,0 +1,72 @@
+use chrono::{DateTime, Local};
+
+use crate::{action::Action, app::models::{IOMessage, IOProducer}, backfill, database::schema::message};
+
+use super::Startup;
+
+impl <'a> Startup <'a> {
+  /// Replays fail2ban.log and its rotated files since the last stored fail2ban line.
+  pub fn backfill_f2b(&mut self) {
+    let Some(db) = self.dbconn.clone() else { return };
+    let path = self.config.log_path();
+    let max_age = self.config.retention().max_age;
+    let tx = self.action_tx.clone().unwrap();
+    tokio::spawn(async move {
+      let since = db.call(move |conn| backfill_since(conn, None, max_age)).await.ok().flatten();
+      tokio::task::spawn_blocking(move || {
+        let until = Local::now().naive_local();
+        let since = since.map(|ts| ts.naive_local());
+        match backfill::fail2ban_history(std::path::Path::new(&path), since, until) {
+          Ok(lines) => {
+            let count = lines.len();
+            for line in lines {
+              tx.send(Action::Backfill(IOMessage::from_line(line, IOProducer::Log))).expect("Backfill failed to send");
+            }
+            tx.send(Action::InternalLog(format!(" ⟲ Backfilled {} lines from {}", count, path))).expect("LOG: Backfill message failed to send");
+          },
+          Err(e) => {
+            tx.send(Action::InternalLog(format!(" ! Backfill of {} failed: {}", path, e))).expect("LOG: Backfill message failed to send");
+          },
+        }
+      });
+    });
+  }
+
+  /// Replays what a journal source logged since the last line stored from that source.
+  pub fn backfill_journal(&mut self, name: &str) {
+    let Some(db) = self.dbconn.clone() else { return };
+    let Some(source) = self.config.journal_sources().into_iter().find(|source| source.name == name) else { return };
+    let journal = self.config.journal_source();
+    let name = name.to_string();
+    let max_age = self.config.retention().max_age;
+    let tx = self.action_tx.clone().unwrap();
+    let mark = name.clone();
+    tokio::spawn(async move {
+      let since = db.call(move |conn| backfill_since(conn, Some(&mark), max_age)).await.ok().flatten();
+      tokio::task::spawn_blocking(move || {
+        let until = Local::now().timestamp_micros() as u64;
+        let since = since.map(|ts| ts.timestamp_micros() as u64);
+        match backfill::journal_history(journal, source.journal_matches(), since, until) {
+          Ok(entries) => {
+            let count = entries.len();
+            for entry in entries {
+              tx.send(Action::Backfill(IOMessage::from_journal(&name, entry))).expect("Backfill failed to send");
+            }
+            tx.send(Action::InternalLog(format!(" ⟲ Backfilled {} journal entries for {}", count, name))).expect("LOG: Backfill message failed to send");
+          },
+          Err(e) => {
+            tx.send(Action::InternalLog(format!(" ! Journal backfill for {} failed: {}", name, e))).expect("LOG: Backfill message failed to send");
+          },
+        }
+      });
+    });
+  }
+}
+
+/// Newest stored timestamp of the journal source, or of fail2ban's log for `None`, `None` when nothing was stored
+/// which backfills everything.
+/// Never older than the retention, pruned lines can't be told apart from new ones and would be counted twice.
+fn backfill_since(conn: &rusqlite::Connection, source: Option<&str>, max_age: Option<chrono::Duration>) -> Option<DateTime<Local>> {
+  let last = message::select_last_timestamp(conn, source).ok().flatten();
+  last.max(max_age.map(|max_age| Local::now() - max_age))
+}
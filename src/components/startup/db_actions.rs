@@ -1,6 +1,6 @@
 use rusqlite::Connection;
 
-use crate::{action::Action, database::schema::{ip::{self, IP}, message}, app::models::{IOMessage, IOProducer}};
+use crate::{action::Action, database::schema::{ip::{self, IP}, message}, app::models::{IOMessage, IOProducer, SSH_SOURCE}};
 
 
 pub fn process_query(conn: &Connection, querystr: String, tx: tokio::sync::mpsc::UnboundedSender<Action>) {
@@ -17,13 +17,10 @@ pub fn process_query(conn: &Connection, querystr: String, tx: tokio::sync::mpsc:
       // send back query not found
       tx.send(Action::QueryNotFound(querystr)).expect("QueryNotFound failed to send!");
     } else {
-      // spawn thread to send debounced messages
-      tokio::spawn(async move{
-        for msg in actmsgs {
-          let prod = if msg.is_jctl {IOProducer::Journal} else {IOProducer::Log};
-          tx.send(Action::PassGeo(ip.clone(), IOMessage::SingleLine(msg.text, prod), true)).expect("PassGeo failed to send on query!"); 
-          tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;}
-          // inefficient but else but require me to set up a duplicate receiver or refactor receive function
-      });
-    }        
+      for msg in actmsgs {
+        // journal lines stored before messages kept their source were all ssh lines
+        let prod = if msg.is_jctl {IOProducer::Journal(msg.source.unwrap_or(String::from(SSH_SOURCE)))} else {IOProducer::Log};
+        tx.send(Action::PassGeo(ip.clone(), IOMessage::from_line(msg.text, prod), true)).expect("PassGeo failed to send on query!");
+      }
+    }
 }
\ No newline at end of file
@@ -1,67 +1,94 @@
-use crate::{action::Action, database::schema::ip::IP};
+use crate::{action::Action, database::{schema::{ban_event::{self, BanAction, BanEvent, BanSource}, ip::IP}, DbConn}, fail2ban::{BannedSet, F2BClient}};
 
 
 
-pub fn send_ban(ip: IP, ban_symbol: String,  tx: tokio::sync::mpsc::UnboundedSender<Action>) {
+/// Bans the IP in the jail, bans that went through are recorded with `source` and `reason`.
+#[allow(clippy::too_many_arguments)]
+pub fn send_ban(client: F2BClient, banned: BannedSet, db: Option<DbConn>, ip: IP, jail: String, source: BanSource, reason: Option<String>, ban_symbol: String,  tx: tokio::sync::mpsc::UnboundedSender<Action>) {
 
     tokio::spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-        // check if is banned
-        let output = std::process::Command::new("fail2ban-client")
-          .arg("set")
-          .arg("sshd")
-          .arg("banip")
-          .arg(&ip.ip)
-          // Tell the OS to record the command's output
-          .stdout(std::process::Stdio::piped())
-          // execute the command, wait for it to complete, then capture the output
-          .output()
-          // Blow up if the OS was unable to start the program
-          .unwrap();
-
-        // extract the raw bytes that we captured and interpret them as a string
-        let stdout = String::from_utf8(output.stdout).unwrap();
-        if stdout.contains("0") {
+        // check the cached set, the refresher keeps it in sync with fail2ban
+        let besure = banned.banned_jails(&ip.ip).contains(&jail);
+        if besure {
+          let blockmsg = format!(" {} IP already banned {} in {}", ban_symbol, &ip.ip, &jail);
+          tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");
           tx.send(Action::Banned(true)).expect("Failed to Ban ...");
-          let fetchmsg = format!(" {} Banned IP: {}", ban_symbol, &ip.ip);
-          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Ban IP message failed to send");
-        } else {
-          let fetchmsg = format!(" {} Banned IP: {}", ban_symbol, &ip.ip);
-          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Ban IP message failed to send");                 
-          tx.send(Action::Banned(false)).expect("Failed to Ban ...");
+          return;
+        }
+
+        match client.ban_ip(&jail, &ip.ip).await {
+          Ok(_) => {
+            if let Ok(addr) = ip.ip.parse() {banned.insert(&jail, addr);}
+            let duration = client.bantime(&jail).await.ok();
+            record(db, BanEvent { ip: ip.ip.clone(), jail: Some(jail.clone()), action: BanAction::Ban, source, reason, timestamp: chrono::offset::Local::now(), duration });
+            let fetchmsg = format!(" {} Banned IP: {} in {}", ban_symbol, &ip.ip, &jail);
+            tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Ban IP message failed to send");
+            tx.send(Action::Banned(true)).expect("Failed to Ban ...");
+          },
+          Err(e) => {
+            let fetchmsg = format!(" ! Failed to ban IP: {} in {}: {}", &ip.ip, &jail, e);
+            tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Ban IP message failed to send");
+            tx.send(Action::Banned(false)).expect("Failed to Ban ...");
+          },
         }
       });
 
 }
 
-pub fn send_unban(ip: IP, unban_symbol: String,  tx: tokio::sync::mpsc::UnboundedSender<Action>) {
+pub fn send_unban(client: F2BClient, banned: BannedSet, db: Option<DbConn>, ip: IP, jail: String, unban_symbol: String,  tx: tokio::sync::mpsc::UnboundedSender<Action>) {
 
     tokio::spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-        // check if is banned
-        let output = std::process::Command::new("fail2ban-client")
-          .arg("set")
-          .arg("sshd")
-          .arg("unbanip")
-          .arg(&ip.ip)
-          // Tell the OS to record the command's output
-          .stdout(std::process::Stdio::piped())
-          // execute the command, wait for it to complete, then capture the output
-          .output()
-          // Blow up if the OS was unable to start the program
-          .unwrap();
+        // check the cached set, the refresher keeps it in sync with fail2ban
+        let besure = banned.banned_jails(&ip.ip).contains(&jail);
+        if !besure {
+          let blockmsg = format!(" ! IP is not banned {} in {}", &ip.ip, &jail);
+          tx.send(Action::InternalLog(blockmsg)).expect("Blocklog message failed to send");
+          return;
+        }
 
-        // extract the raw bytes that we captured and interpret them as a string
-        let stdout = String::from_utf8(output.stdout).unwrap();
-        if stdout.contains("0") {
-          tx.send(Action::Unbanned(true)).expect("Failed to Unban !!!");
-          let fetchmsg = format!(" {} Unbanned IP: {}", unban_symbol, &ip.ip);
-          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unban IP message failed to send");
-        } else {
-          let fetchmsg = format!(" {} Unbanned IP: {}", unban_symbol, &ip.ip);
-          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unban IP message failed to send");
-          tx.send(Action::Unbanned(false)).expect("Failed to Unban !!!"); // idfkgetit
+        match client.unban_ip(&jail, &ip.ip).await {
+          Ok(_) => {
+            if let Ok(addr) = ip.ip.parse() {banned.remove(&jail, &addr);}
+            record(db, BanEvent { ip: ip.ip.clone(), jail: Some(jail.clone()), action: BanAction::Unban, source: BanSource::Manual, reason: None, timestamp: chrono::offset::Local::now(), duration: None });
+            let fetchmsg = format!(" {} Unbanned IP: {} in {}", unban_symbol, &ip.ip, &jail);
+            tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unban IP message failed to send");
+            tx.send(Action::Unbanned(true)).expect("Failed to Unban !!!");
+          },
+          Err(e) => {
+            let fetchmsg = format!(" ! Failed to unban IP: {} in {}: {}", &ip.ip, &jail, e);
+            tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Unban IP message failed to send");
+            tx.send(Action::Unbanned(false)).expect("Failed to Unban !!!");
+          },
         }
       });
 
 }
+
+/// Stores a ban or unban, without a database there is nothing to record it in.
+fn record(db: Option<DbConn>, event: BanEvent) {
+  let Some(db) = db else { return };
+  let _ = db.execute(move |conn| {
+    if let Err(e) = ban_event::record_ban_event(conn, &event) {
+      log::error!("Recording the {} of {} failed: {}", event.action.as_str(), event.ip, e);
+    }
+  });
+}
+
+/// Asks fail2ban for its active jails and reports them back to the components.
+pub fn fetch_jails_and_report(client: F2BClient, tx: tokio::sync::mpsc::UnboundedSender<Action>) {
+  tokio::spawn(async move {
+    match client.jails().await {
+      Ok(jails) => {
+        if !jails.is_empty() {
+          let fetchmsg = format!(" ✓ Found jails: {}", jails.join(", "));
+          tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Found jails message failed to send");
+          tx.send(Action::StartupGotJails(jails)).unwrap_or_default();
+        }
+      },
+      Err(_) => {
+        let fetchmsg = format!("   Failed to reach fail2ban at {} for jail discovery", client.socket_path().display());
+        tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Jail discovery message failed to send");
+      },
+    }
+  });
+}
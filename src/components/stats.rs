@@ -5,7 +5,7 @@ pub mod enums;
 use enums::{Mode, DisplayMode, DrawMode, SelectionMode, SortMode, SortState, BlockMode};
 
 pub mod utils;
-use utils::{convert_strings_to_utc, get_msgs_per_ip};
+use utils::{sorted_timestamps, get_msgs_per_ip};
 
 pub mod actions;
 use actions::refresh_countries;
@@ -28,14 +28,16 @@ use super::{Component, Frame};
 use crate::ui::{centered_rect_inner_fixed, help};
 use crate::{action::{Action, StatAction}, config::{Config, KeyBindings, get_first_key_simple, get_first_key_by_action}, components::home::utils::centered_rect};
 
-use crate::{database::schema::{city::City, region::Region, isp::ISP, country::Country, message::MiniMessage, ip::IP},
+use crate::{database::schema::{ban_event::BanEvent, city::City, region::Region, isp::ISP, asn::ASN, country::Country, message::MiniMessage, ip::IP},
 themes::Theme, gen_structs::StatefulList, themes::Themes};
 
+/// Timestamps of messages with the number of messages at each, oldest first.
+pub type Timestamps = Vec<(chrono::DateTime<chrono::FixedOffset>, usize)>;
 
 #[derive(Default, Clone, PartialEq, Eq)]
 pub struct StatIP {
   ip: String,
-  timestamps: Vec<chrono::DateTime<chrono::FixedOffset>>,
+  timestamps: Timestamps,
   warnings: usize,
 }
 
@@ -62,21 +64,26 @@ pub struct Stats {
   pub sort_mode: SortMode,
   pub selected_timeframe: Timeframe,
   //
-  pub countries: StatefulList<(Country, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
-  pub full_regions: Vec<(Region, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
-  pub full_cities: Vec<(City, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
-  pub full_isps: Vec<(ISP, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
+  pub countries: StatefulList<(Country, Timestamps, Vec<StatIP>)>,
+  pub full_regions: Vec<(Region, Timestamps, Vec<StatIP>)>,
+  pub full_cities: Vec<(City, Timestamps, Vec<StatIP>)>,
+  pub full_isps: Vec<(ISP, Timestamps, Vec<StatIP>)>,
+  pub full_asns: Vec<(ASN, Timestamps, Vec<StatIP>)>,
   //
-  pub regions: StatefulList<(Region, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
-  pub cities: StatefulList<(City, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
-  pub isps: StatefulList<(ISP, Vec<chrono::DateTime<chrono::FixedOffset>>, Vec<StatIP>)>,
+  pub regions: StatefulList<(Region, Timestamps, Vec<StatIP>)>,
+  pub cities: StatefulList<(City, Timestamps, Vec<StatIP>)>,
+  pub isps: StatefulList<(ISP, Timestamps, Vec<StatIP>)>,
+  pub asns: StatefulList<(ASN, Timestamps, Vec<StatIP>)>,
   pub ips: StatefulList<StatIP>,
   pub selected_ip: IP,
+  /// Bans and unbans of the selected IP, oldest first.
+  pub ban_events: Vec<BanEvent>,
   //
   pub countries_sort: SortState,
   pub regions_sort: SortState,
   pub cities_sort: SortState,
   pub isps_sort: SortState,
+  pub asns_sort: SortState,
   pub ips_sort: SortState,
   //
   pub apptheme: Theme,
@@ -97,11 +104,13 @@ impl <'a> Stats  {
     this.regions = StatefulList::with_items(vec![]);
     this.cities = StatefulList::with_items(vec![]);
     this.isps = StatefulList::with_items(vec![]);
+    this.asns = StatefulList::with_items(vec![]);
     this.ips = StatefulList::with_items(vec![]);
 
     this.full_regions = vec![];
     this.full_cities = vec![];
     this.full_isps = vec![];
+    this.full_asns = vec![];
     this.available_themes = Themes::default();
 
     this
@@ -166,6 +175,13 @@ impl <'a> Stats  {
       if tuple.0.country == sel_country.name {self.isps.items.push(tuple);}
     }    
     self.isps.next();
+    // and asns
+    self.asns.unselect();
+    self.asns = StatefulList::with_items(vec![]);
+    for tuple in self.full_asns.clone().into_iter() {
+      if tuple.0.country == sel_country.name {self.asns.items.push(tuple);}
+    }
+    self.asns.next();
 
     // do same for ips
     self.ips.unselect();
@@ -225,6 +241,17 @@ impl <'a> Stats  {
     self.selected_ip();
   }
 
+  pub fn selected_asn(&mut self) {
+    let Some(sel_idx) = self.asns.state.selected() else {return};
+    if self.asns.items.is_empty() {return;}
+    let sel_asn_ips = self.asns.items[sel_idx].2.clone();
+
+    self.ips.unselect();
+    self.ips = StatefulList::with_items(sel_asn_ips);
+    self.ips.next();
+    self.selected_ip();
+  }
+
   pub fn selected_ip(&mut self) {
     // find selected ip
     let sel_idx = self.ips.state.selected();
@@ -235,11 +262,11 @@ impl <'a> Stats  {
     tx.send(Action::StatsGetIP(sel_ip)).expect("Failed to reuest IP from Stats");
   }
 
-  pub fn get_timestamps_from_msgs(&self, msgs: Vec<MiniMessage>) -> Vec<String> {
-    let mut timestamps: Vec<String> = vec![];
+  pub fn get_timestamps_from_msgs(&self, msgs: Vec<MiniMessage>) -> Vec<(chrono::DateTime<chrono::Local>, usize)> {
+    let mut timestamps: Vec<(chrono::DateTime<chrono::Local>, usize)> = vec![];
     if msgs.is_empty() {return timestamps;}
     for msg in msgs {
-      timestamps.push(msg.created_at);
+      timestamps.push((msg.created_at, msg.count));
     }
     timestamps
   }
@@ -253,6 +280,7 @@ impl <'a> Stats  {
           SelectionMode::Region => {actions::block_selected_region(self)?;},
           SelectionMode::City => {actions::block_selected_city(self)?;},
           SelectionMode::ISP => {actions::block_selected_isp(self)?;},
+          SelectionMode::ASN => {actions::block_selected_asn(self)?;},
           SelectionMode::IP => {actions::block_selected_ip(self)?;},
         }
       },
@@ -262,6 +290,7 @@ impl <'a> Stats  {
           SelectionMode::Region => {actions::unblock_selected_region(self)?;},
           SelectionMode::City => {actions::unblock_selected_city(self)?;},
           SelectionMode::ISP => {actions::unblock_selected_isp(self)?;},
+          SelectionMode::ASN => {actions::unblock_selected_asn(self)?;},
           SelectionMode::IP => {actions::unblock_selected_ip(self)?;},
         }
       },
@@ -343,6 +372,8 @@ impl Component for Stats {
                 KeyCode::Char(keychar) => {
                     match keychar {
                         'Y'|'y' => {self.block_by_selected_mode()?; self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal;},
+                        // the announced network of the selected IP instead of the whole AS
+                        'P'|'p' if self.selection_mode == SelectionMode::ASN => {actions::un_block_selected_prefix(self)?; self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal;},
                         'N'|'n' => {self.mode = Mode::Normal; self.display_mode = DisplayMode::Normal;},
                         _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
                     }
@@ -405,7 +436,7 @@ impl Component for Stats {
                     KeyCode::Up => {self.isps.previous(); self.selected_isp();},
                     KeyCode::Down => {self.isps.next(); self.selected_isp();},
                     KeyCode::BackTab => {self.selection_mode = SelectionMode::City;},
-                    KeyCode::Tab => {self.selection_mode = SelectionMode::IP;},
+                    KeyCode::Tab => {self.selection_mode = SelectionMode::ASN;},
                     KeyCode::Char(keychar) => {
                         match keychar {
                             'R'|'r' => {return Ok(Some(Action::StatsGetISPs))},
@@ -415,11 +446,26 @@ impl Component for Stats {
                     _ => {},
                     }
             },
+            SelectionMode::ASN => {
+                match key.code {
+                    KeyCode::Up => {self.asns.previous(); self.selected_asn();},
+                    KeyCode::Down => {self.asns.next(); self.selected_asn();},
+                    KeyCode::BackTab => {self.selection_mode = SelectionMode::ISP;},
+                    KeyCode::Tab => {self.selection_mode = SelectionMode::IP;},
+                    KeyCode::Char(keychar) => {
+                        match keychar {
+                            'R'|'r' => {return Ok(Some(Action::StatsGetASNs))},
+                            _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
+                        }
+                    },
+                    _ => {},
+                    }
+            },
             SelectionMode::IP => {
               match key.code {
                   KeyCode::Up => {self.ips.previous(); self.selected_ip();},
                   KeyCode::Down => {self.ips.next(); self.selected_ip();},
-                  KeyCode::BackTab => {self.selection_mode = SelectionMode::ISP;},
+                  KeyCode::BackTab => {self.selection_mode = SelectionMode::ASN;},
                   KeyCode::Tab => {self.selection_mode = SelectionMode::Country;},
                   _ => {self.input.handle_event(&crossterm::event::Event::Key(key));},
                   }
@@ -470,24 +516,31 @@ impl Component for Stats {
             Action::StatsGetRegions => {self.regions.unselect(); self.regions = StatefulList::with_items(vec![]); self.full_regions = vec![];},
             Action::StatsGetCities => {self.cities.unselect(); self.cities = StatefulList::with_items(vec![]); self.full_cities = vec![];},
             Action::StatsGetISPs => {self.isps.unselect(); self.isps = StatefulList::with_items(vec![]); self.full_isps = vec![];},
+            Action::StatsGetASNs => {self.asns.unselect(); self.asns = StatefulList::with_items(vec![]); self.full_asns = vec![];},
 
             Action::StatsGotCountry(x, y) => {
-              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let timestamps = sorted_timestamps(self.get_timestamps_from_msgs(y.clone()));
               let statips = get_msgs_per_ip(y);
-              self.countries.items.push((x, timestamps, statips));}, //self.countries.items.push((x, convert_strings_to_utc(y)));
+              self.countries.items.push((x, timestamps, statips));}, //self.countries.items.push((x, sorted_timestamps(y)));
             Action::StatsGotRegion(x, y) => {
-              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let timestamps = sorted_timestamps(self.get_timestamps_from_msgs(y.clone()));
               let statips = get_msgs_per_ip(y);
-              self.full_regions.push((x, timestamps, statips));}, // self.regions.items.push((x, convert_strings_to_utc(y)));
+              self.full_regions.push((x, timestamps, statips));}, // self.regions.items.push((x, sorted_timestamps(y)));
             Action::StatsGotCity(x, y) => {
-              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let timestamps = sorted_timestamps(self.get_timestamps_from_msgs(y.clone()));
               let statips = get_msgs_per_ip(y);              
               self.full_cities.push((x, timestamps, statips));},
             Action::StatsGotISP(x, y) => {
-              let timestamps = convert_strings_to_utc(self.get_timestamps_from_msgs(y.clone()));
+              let timestamps = sorted_timestamps(self.get_timestamps_from_msgs(y.clone()));
               let statips = get_msgs_per_ip(y);              
               self.full_isps.push((x, timestamps, statips));},
+            Action::StatsGotASN(x, y) => {
+              let timestamps = sorted_timestamps(self.get_timestamps_from_msgs(y.clone()));
+              let statips = get_msgs_per_ip(y);
+              self.full_asns.push((x, timestamps, statips));},
             Action::StatsGotIP(x) => {self.selected_ip = x;},
+            Action::StatsGotBanEvents(ip, events) if self.selected_ip.ip == ip => {self.ban_events = events;},
+            Action::GotReverseDns(ip, rdns) if self.selected_ip.ip == ip => {self.selected_ip.rdns = Some(rdns);},
             Action::SelectTheme(x) => {self.select_new_theme(x)},   
             _ => (),
         }
@@ -504,19 +557,21 @@ impl Component for Stats {
         f.render_widget(bg, rect);
 
         let layout_a = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(rect);
-        let layout_left = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20)].as_ref()).direction(Direction::Vertical).split(layout_a[0]);
-        let layout_right = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20), Constraint::Percentage(20)].as_ref()).direction(Direction::Vertical).split(layout_a[1]);
+        let layout_left = Layout::default().constraints([Constraint::Percentage(16), Constraint::Percentage(16), Constraint::Percentage(17), Constraint::Percentage(17), Constraint::Percentage(17), Constraint::Percentage(17)].as_ref()).direction(Direction::Vertical).split(layout_a[0]);
+        let layout_right = Layout::default().constraints([Constraint::Percentage(16), Constraint::Percentage(16), Constraint::Percentage(17), Constraint::Percentage(17), Constraint::Percentage(17), Constraint::Percentage(17)].as_ref()).direction(Direction::Vertical).split(layout_a[1]);
 
         let layout_country = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[0]);
         let layout_region = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[1]);
         let layout_city = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[2]);
         let layout_isp = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[3]);
-        let layout_ip = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[4]);
+        let layout_asn = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref()).direction(Direction::Horizontal).split(layout_right[4]);
+        let layout_ip = Layout::default().constraints([Constraint::Percentage(20), Constraint::Percentage(55), Constraint::Percentage(25)].as_ref()).direction(Direction::Horizontal).split(layout_right[5]);
 
         let countrylist = ui::make_country_list(self);
         let regionlist = ui::make_region_list(self);
         let citylist = ui::make_city_list(self);
         let isplist = ui::make_isp_list(self);
+        let asnlist = ui::make_asn_list(self);
         let iplist = ui::make_ip_list(self);
 
         // timestamp chart == barchart -> bar for every day with number of messages 
@@ -566,6 +621,15 @@ impl Component for Stats {
             f.render_widget(overview, layout_isp[0]);
         }
 
+        if let Some(sel_asn) = self.asns.state.selected().filter(|_| !self.asns.items.is_empty()) {
+            let bars = ui::make_bars_for_timestamps(&self.apptheme, self.asns.items[sel_asn].clone().1, self.selected_timeframe);
+            let dtbars_asn = ui::create_barchart(&self.apptheme, bars, "Log entries per Day");
+            f.render_widget(dtbars_asn, layout_asn[1]);
+
+            let overview = ui::make_asn_overview(self);
+            f.render_widget(overview, layout_asn[0]);
+        }
+
         let sel_ip = self.ips.state.selected();
         if sel_ip.is_some() && self.ips.items.len() > 0 {
             let bars = ui::make_bars_for_timestamps(&self.apptheme, self.ips.items[sel_ip.unwrap()].clone().timestamps, self.selected_timeframe);
@@ -574,13 +638,17 @@ impl Component for Stats {
 
             let overview = ui::make_ip_overview(&self.apptheme, self.selected_ip.clone());
             f.render_widget(overview, layout_ip[0]);
+
+            let timeline = ui::make_ban_timeline(&self.apptheme, self.ban_events.clone());
+            f.render_widget(timeline, layout_ip[2]);
         }        
 
         f.render_stateful_widget(countrylist, layout_left[0], &mut self.countries.state);
         f.render_stateful_widget(regionlist, layout_left[1], &mut self.regions.state);
         f.render_stateful_widget(citylist, layout_left[2], &mut self.cities.state);
         f.render_stateful_widget(isplist, layout_left[3], &mut self.isps.state);
-        f.render_stateful_widget(iplist, layout_left[4], &mut self.ips.state);
+        f.render_stateful_widget(asnlist, layout_left[4], &mut self.asns.state);
+        f.render_stateful_widget(iplist, layout_left[5], &mut self.ips.state);
 
         match self.display_mode {
           DisplayMode::Confirm => {
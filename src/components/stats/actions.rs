@@ -1,11 +1,11 @@
 //! Defines functions for Stats that happen on Key Events
 use color_eyre::eyre::Result;
 use tokio::sync::mpsc::UnboundedSender;
-use crate::{action::Action, database::schema::ip::IP};
+use crate::{action::Action, database::schema::{ban_event::BanAction, ip::IP}};
 use tokio::time::{self, Duration};
-use super::{Stats, enums::{SelectionMode, SortMode, SortState}};
+use super::{Stats, enums::{BlockMode, SelectionMode, SortMode, SortState}};
 
-///  Fetches Country data from DB, also fetches all related data (City, Region, ISP)
+///  Fetches Country data from DB, also fetches all related data (City, Region, ISP, ASN)
 pub fn refresh_countries(tx: UnboundedSender<Action>) -> Result<()> {
   // 🔃
   tokio::spawn(async move {
@@ -16,6 +16,8 @@ pub fn refresh_countries(tx: UnboundedSender<Action>) -> Result<()> {
     tx.send(Action::StatsGetCities).expect("Failed to refresh cities; E404");
     time::sleep(Duration::from_millis(25)).await;
     tx.send(Action::StatsGetISPs).expect("Failed to refresh ISPs; E404");
+    time::sleep(Duration::from_millis(25)).await;
+    tx.send(Action::StatsGetASNs).expect("Failed to refresh ASNs; E404");
     time::sleep(Duration::from_millis(5)).await;
     let fetchmsg = format!(" 🔃 Refreshed Stats ");
     tx.send(Action::InternalLog(fetchmsg)).expect("LOG: Refresh stats message failed to send");
@@ -107,6 +109,39 @@ pub fn unblock_selected_isp(stats: &mut Stats) -> Result<()> {
   Ok(())
 }
 
+// BLOCKING ASN// --------------------------------------------------------------- //
+pub fn block_selected_asn(stats: &mut Stats) -> Result<()> {
+  if stats.asns.items.is_empty() {return Ok(())}
+  let tx = stats.action_tx.clone().unwrap();
+  let sel_idx = stats.asns.state.selected().unwrap();
+  let sel_asn = stats.asns.items[sel_idx].clone().0;
+  tx.send(Action::StatsBlockASN(sel_asn)).expect("Failed to send request to block ASN");
+  stats.asns.items[sel_idx].0.is_blocked = true;
+  Ok(())
+}
+
+pub fn unblock_selected_asn(stats: &mut Stats) -> Result<()> {
+  if stats.asns.items.is_empty() {return Ok(())}
+  let tx = stats.action_tx.clone().unwrap();
+  let sel_idx = stats.asns.state.selected().unwrap();
+  let sel_asn = stats.asns.items[sel_idx].clone().0;
+  tx.send(Action::StatsUnblockASN(sel_asn)).expect("Failed to send request to unblock ASN");
+  stats.asns.items[sel_idx].0.is_blocked = false;
+  Ok(())
+}
+
+/// Blocks or unblocks the announced network of the selected IP, following the block mode.
+pub fn un_block_selected_prefix(stats: &mut Stats) -> Result<()> {
+  let prefix = stats.selected_ip.prefix.clone();
+  if prefix.is_empty() {return Ok(())}
+  let tx = stats.action_tx.clone().unwrap();
+  match stats.block_mode {
+    BlockMode::Block => tx.send(Action::StatsBlockPrefix(prefix)).expect("Failed to send request to block prefix"),
+    BlockMode::Unblock => tx.send(Action::StatsUnblockPrefix(prefix)).expect("Failed to send request to unblock prefix"),
+  }
+  Ok(())
+}
+
 // BLOCKING IP// --------------------------------------------------------------- //
 pub fn block_selected_ip(stats: &mut Stats) -> Result<()> {
   if stats.ips.items.is_empty() {return Ok(())}
@@ -116,8 +151,14 @@ pub fn block_selected_ip(stats: &mut Stats) -> Result<()> {
   let sel_ip = stats.selected_ip.clone();
   if sel_ip.is_banned {return Ok(())}
 
-  tx.send(Action::BanIP(sel_ip)).expect("Failed to send request to block IP");
+  // ban again where it was banned last, otherwise in the first configured jail
+  let last_jail = stats.ban_events.iter().rev()
+    .filter(|event| event.ip == sel_ip.ip && event.action == BanAction::Ban)
+    .find_map(|event| event.jail.clone());
+  let jail = last_jail.unwrap_or_else(|| stats.config.jail_list().remove(0));
+  tx.send(Action::BanIP(sel_ip, jail.clone())).expect("Failed to send request to block IP");
   stats.selected_ip.is_banned = true;
+  stats.selected_ip.jails = vec![jail];
   Ok(())
 }
 
@@ -129,8 +170,13 @@ pub fn unblock_selected_ip(stats: &mut Stats) -> Result<()> {
   let sel_ip = stats.selected_ip.clone();
   if !sel_ip.is_banned {return Ok(())}
 
-  tx.send(Action::UnbanIP(sel_ip)).expect("Failed to send request to block IP");
-  stats.selected_ip.is_banned = true;
+  // unban in every jail that bans it, try the configured ones if they aren't known
+  let jails = if sel_ip.jails.is_empty() {stats.config.jail_list()} else {sel_ip.jails.clone()};
+  for jail in jails {
+    tx.send(Action::UnbanIP(sel_ip.clone(), jail)).expect("Failed to send request to unblock IP");
+  }
+  stats.selected_ip.is_banned = false;
+  stats.selected_ip.jails.clear();
   Ok(())
 }
 
@@ -197,6 +243,21 @@ pub fn sort_by_alphabetical(stats: &mut Stats) -> Result<()> {
         _ => {stats.isps_sort = SortState::Alphabetical;},
       }
     },
+    SelectionMode::ASN => {
+      match stats.asns_sort {
+        SortState::AlphabeticalRev => {
+          stats.asns.items.sort_by(|a, b|
+            a.0.number.partial_cmp(&b.0.number).unwrap());
+          stats.asns.items.reverse();
+          stats.asns_sort = SortState::Alphabetical;},
+        SortState::Alphabetical => {
+          stats.asns.items.sort_by(|a, b|
+            a.0.number.partial_cmp(&b.0.number).unwrap());
+          stats.asns_sort = SortState::AlphabeticalRev;
+        },
+        _ => {stats.asns_sort = SortState::Alphabetical;},
+      }
+    },
     SelectionMode::IP => {
       match stats.ips_sort {
         SortState::AlphabeticalRev => {
@@ -279,6 +340,21 @@ pub fn sort_by_numwarn(stats: &mut Stats) -> Result<()> {
         _ => {stats.isps_sort = SortState::NumWarns;},
       }
     },
+    SelectionMode::ASN => {
+      match stats.asns_sort {
+        SortState::NumWarnsRev => {
+          stats.asns.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.asns.items.reverse();
+          stats.asns_sort = SortState::NumWarns;},
+        SortState::NumWarns => {
+          stats.asns.items.sort_by(|a, b|
+            a.0.warnings.partial_cmp(&b.0.warnings).unwrap());
+          stats.asns_sort = SortState::NumWarnsRev;
+        },
+        _ => {stats.asns_sort = SortState::NumWarns;},
+      }
+    },
     SelectionMode::IP => {
       match stats.ips_sort {
         SortState::NumWarnsRev => {
@@ -361,6 +437,21 @@ pub fn sort_by_blocked(stats: &mut Stats) -> Result<()> {
         _ => {stats.isps_sort = SortState::Blocked;},
       }
     },
+    SelectionMode::ASN => {
+      match stats.asns_sort {
+        SortState::BlockedRev => {
+          stats.asns.items.sort_by(|a, b|
+            a.0.is_blocked.partial_cmp(&b.0.is_blocked).unwrap());
+          stats.asns.items.reverse();
+          stats.asns_sort = SortState::Blocked;},
+        SortState::Blocked => {
+          stats.asns.items.sort_by(|a, b|
+            a.0.is_blocked.partial_cmp(&b.0.is_blocked).unwrap());
+          stats.asns_sort = SortState::BlockedRev;
+        },
+        _ => {stats.asns_sort = SortState::Blocked;},
+      }
+    },
     SelectionMode::IP => {
       match stats.ips_sort {
         SortState::BlockedRev => {
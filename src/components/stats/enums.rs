@@ -24,6 +24,7 @@ pub enum SelectionMode {
   Region,
   City,
   ISP,
+  ASN,
   IP,
 }
 
@@ -1,5 +1,5 @@
-use super::{SelectionMode, SortState, Stats, Action, StatAction, KeyBindings, KeyEvent, Config, get_first_key_simple, get_first_key_by_action};
-use crate::database::schema::{city::City, country::Country, ip::IP, isp::ISP, message::MiniMessage, region::Region};
+use super::{SelectionMode, SortState, Stats, Timestamps, Action, StatAction, KeyBindings, KeyEvent, Config, get_first_key_simple, get_first_key_by_action};
+use crate::database::schema::{ban_event::{BanAction, BanEvent}, asn::ASN, city::City, country::Country, ip::IP, isp::ISP, message::MiniMessage, region::Region};
 use crate::ui::help;
 use crate::{gen_structs::StatefulList, themes::Theme, mode::Mode as AppMode};
 use chrono::{DateTime, Datelike, FixedOffset};
@@ -192,6 +192,47 @@ pub fn make_isp_list<'a>(stats: &Stats) -> List<'a> {
   isplist
 }
 
+pub fn make_asn_list<'a>(stats: &Stats) -> List<'a> {
+  let av_asns: Vec<ListItem> = stats
+    .asns
+    .items
+    .iter()
+    .map(|i| {
+      let is_blocked = i.0.is_blocked;
+      let mut line = Line::from(format!("AS{} {}", i.0.number, i.0.name));
+      line.patch_style(if is_blocked {
+        Style::default().fg(stats.apptheme.colors_app.text_color.color).bg(stats.apptheme.colors_app.warn_color.color)
+      } else {
+        Style::default().fg(stats.apptheme.colors_app.text_color.color)
+      });
+      ListItem::new(line)
+    })
+    .collect();
+  let sel_item = stats.asns.state.selected().and_then(|idx| stats.asns.items.get(idx)).map(|tuple| tuple.0.clone()).unwrap_or_default();
+  let sort_indicator = make_sort_state_indicator(&stats.apptheme, stats.asns_sort);
+  // Create a List from all list items and highlight the currently selected one
+  let asnlist: List<'_> = List::new(av_asns)
+    .bg(stats.apptheme.colors_app.background_darkest.color)
+    .block(
+      Block::default()
+        .borders(Borders::ALL)
+        .border_style(match stats.selection_mode {
+          SelectionMode::ASN => stats.apptheme.styles_app.active_border_style,
+          _ => stats.apptheme.styles_app.border_style,
+        })
+        .title(Title::from("ASNs").alignment(Alignment::Left))
+        .title(Title::from(sort_indicator).alignment(Alignment::Right)),
+    )
+    .highlight_style(if sel_item.is_blocked {
+      stats.apptheme.styles_app.highlight_item_style.bg(stats.apptheme.colors_app.warn_color.color).fg(stats.apptheme.colors_app.text_color.color)
+    } else {
+      stats.apptheme.styles_app.highlight_item_style
+    })
+    .highlight_symbol(">> ");
+
+  asnlist
+}
+
 pub fn make_ip_list<'a>(stats: &Stats) -> List<'a> {
   let av_ips: Vec<ListItem> = stats
     .ips
@@ -475,6 +516,29 @@ pub fn make_isp_overview(stats: &Stats) -> impl Widget + '_ {
   .set_style(Style::new().bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
 }
 
+pub fn make_asn_overview(stats: &Stats) -> impl Widget + '_ {
+  let mut paragraph = Paragraph::new(vec![]);
+
+  if let Some(sel_idx) = stats.asns.state.selected() {
+    // get totals
+    let total_warn = stats.asns.items.iter().fold(0u32, |total, tuple| total.saturating_add(tuple.0.warnings.try_into().unwrap_or(0)));
+    let total_banned = stats.asns.items.iter().fold(0u32, |total, tuple| total.saturating_add(tuple.0.banned.try_into().unwrap_or(0)));
+    let asn = &stats.asns.items[sel_idx].0;
+    paragraph = make_overview_paragraph(
+      "ASN",
+      &stats.apptheme,
+      &format!("AS{} {}", asn.number, asn.name),
+      asn.warnings.try_into().unwrap_or(0),
+      total_warn,
+      asn.banned.try_into().unwrap_or(0),
+      total_banned,
+      asn.is_blocked,
+    );
+  }
+  paragraph.block(Block::default().borders(Borders::ALL).title("ASN Stats").bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+  .set_style(Style::new().bg(stats.apptheme.colors_app.background_darkest.color).fg(stats.apptheme.colors_app.text_color.color))
+}
+
 pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
   // get totals
   if sel_ip == IP::default() {
@@ -491,6 +555,18 @@ pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
   let city = selected_ip.city;
   let region = selected_ip.region;
   let isp = selected_ip.isp;
+  let hostname = match &selected_ip.rdns {
+    Some(rdns) => match &rdns.hostname {
+      Some(host) if rdns.confirmed => format!("{host} (confirmed)"),
+      Some(host) => format!("{host} (unconfirmed)"),
+      None => String::from("no PTR record"),
+    },
+    None => String::from("not looked up"),
+  };
+  let network = match selected_ip.asn {
+    Some(asn) => format!("AS{} {}", asn, selected_ip.prefix),
+    None => selected_ip.prefix,
+  };
 
   let default_text_style = Style::default().fg(theme.colors_app.text_color.color);
   let lines: Vec<Line> = vec![
@@ -505,6 +581,8 @@ pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
     Line::from(vec![Span::styled(format!(" Banned times : {}", banned_times), default_text_style)]),
     Line::from(vec![Span::styled(format!(" {city}, {region}, {country} ",), default_text_style)]),
     Line::from(vec![Span::styled(format!(" {isp} "), default_text_style)]),
+    Line::from(vec![Span::styled(format!(" {network} "), default_text_style)]),
+    Line::from(vec![Span::styled(format!(" {hostname} "), default_text_style)]),
   ];
 
   let paragraph = Paragraph::new(lines);
@@ -513,6 +591,47 @@ pub fn make_ip_overview(theme: &Theme, sel_ip: IP) -> impl Widget + '_ {
   .set_style(Style::new().bg(theme.colors_app.background_darkest.color).fg(theme.colors_app.text_color.color))
 }
 
+/// Bans and unbans of the selected IP, newest first.
+pub fn make_ban_timeline(theme: &Theme, events: Vec<BanEvent>) -> impl Widget + '_ {
+  let default_text_style = Style::default().fg(theme.colors_app.text_color.color);
+  let mut lines: Vec<Line> = vec![];
+  if events.is_empty() {
+    lines.push(Line::from(vec![Span::styled(" No bans recorded", default_text_style)]));
+  }
+  for event in events.iter().rev() {
+    let time = event.timestamp.format("%Y-%m-%d %H:%M").to_string();
+    let action = match event.action {
+      BanAction::Ban => Span::styled("Ban  ", Style::default().fg(theme.colors_app.accent_color_a.color)),
+      BanAction::Unban => Span::styled("Unban", Style::default().fg(theme.colors_app.confirm_color.color)),
+    };
+    let mut details = vec![event.jail.clone().unwrap_or(String::from("?")), event.source.as_str().to_string()];
+    if let Some(duration) = event.duration {
+      details.push(format_bantime(duration));
+    }
+    details.extend(event.reason.clone());
+    lines.push(Line::from(vec![
+      Span::styled(format!(" {} ", time), default_text_style),
+      action,
+      Span::styled(format!(" {}", details.join(", ")), default_text_style),
+    ]));
+  }
+
+  Paragraph::new(lines)
+    .block(Block::default().borders(Borders::ALL).title("Ban timeline").bg(theme.colors_app.background_darkest.color).fg(theme.colors_app.text_color.color))
+    .set_style(Style::new().bg(theme.colors_app.background_darkest.color).fg(theme.colors_app.text_color.color))
+}
+
+/// fail2ban's ban time, negative ones never run out.
+fn format_bantime(secs: i64) -> String {
+  match secs {
+    secs if secs < 0 => String::from("forever"),
+    secs if secs % 86400 == 0 && secs > 0 => format!("{}d", secs / 86400),
+    secs if secs % 3600 == 0 && secs > 0 => format!("{}h", secs / 3600),
+    secs if secs % 60 == 0 && secs > 0 => format!("{}m", secs / 60),
+    secs => format!("{}s", secs),
+  }
+}
+
 // POPUPS // ---------------------------------------------------------------- //
 
 pub fn create_help(config: Config) -> help::HelpOptions {
@@ -548,6 +667,7 @@ pub fn create_help(config: Config) -> help::HelpOptions {
           help::HelpOpt::new_opt(key_exit.as_str(), "Back", "Return to main screen"),
           help::HelpOpt::new_opt(key_block.as_str(), "Block", "Blocks all IPs for selected"),
           help::HelpOpt::new_opt(key_unblock.as_str(), "Unblock", "Lifts the Block for selected"), 
+          help::HelpOpt::new_opt("p", "Prefix", "Confirms for the selected IP's prefix in ASNs"),
         ]
       ),
       help::HelpOptCategory::default()
@@ -580,6 +700,7 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
     SelectionMode::Region => "Region",
     SelectionMode::City => "City",
     SelectionMode::ISP => "ISP",
+    SelectionMode::ASN => "AS",
     SelectionMode::IP => "IP",
   };
   let sel_str = match smode {
@@ -619,6 +740,15 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
         sel_item
       }
     },
+    SelectionMode::ASN => {
+      if stats.asns.items.is_empty() {
+        String::new()
+      } else {
+        let sel_idx = stats.asns.state.selected().unwrap();
+        let sel_item = &stats.asns.items[sel_idx].0;
+        format!("AS{} {}", sel_item.number, sel_item.name)
+      }
+    },
     SelectionMode::IP => {
       if stats.countries.items.is_empty() {
         format!("")
@@ -651,6 +781,13 @@ pub fn popup_un_block_selected(stats: &Stats, is_block: bool) -> impl Widget + '
   //clearlistline.patch_style(stats.apptheme.selected_ip_bg);
   clearlisttext.push(Line::from(vec![infospan]));
   clearlisttext.push(clearlistline);
+  if smode == SelectionMode::ASN && !stats.selected_ip.prefix.is_empty() {
+    clearlisttext.push(Line::from(vec![
+      Span::styled("Press ", default_text_style),
+      Span::styled("P | p ", Style::default().fg(stats.apptheme.colors_app.confirm_color.color)),
+      Span::styled(format!("for the prefix {} of the selected IP only.", stats.selected_ip.prefix), default_text_style),
+    ]));
+  }
 
   let clearlistbox =
     Paragraph::new(clearlisttext).alignment(Alignment::Center)
@@ -676,15 +813,16 @@ pub enum Timeframe {
   Year,
 }
 
-pub fn make_bars_for_timestamps<'a>(theme: &Theme, timestamps: Vec<DateTime<FixedOffset>>, timeframe: Timeframe) -> Vec<Bar<'a>> {
+/// Bars of the number of messages per timeframe, each timestamp counts with the messages it stands for.
+pub fn make_bars_for_timestamps<'a>(theme: &Theme, timestamps: Timestamps, timeframe: Timeframe) -> Vec<Bar<'a>> {
   if timestamps.is_empty() {
     return vec![Bar::default()];
   };
   let mut babars: Vec<Bar> = vec![];
-  let mut adate: DateTime<FixedOffset> = timestamps[0];
+  let mut adate: DateTime<FixedOffset> = timestamps[0].0;
   let mut num_adate: usize = 0;
   let mut color_switcher: bool = false;
-  for stamp in timestamps {
+  for (stamp, count) in timestamps {
     let date_diff = match timeframe {
       Timeframe::Day => {stamp.day() - adate.day()},
       Timeframe::Week => {stamp.iso_week().week() - adate.iso_week().week()},
@@ -711,11 +849,12 @@ pub fn make_bars_for_timestamps<'a>(theme: &Theme, timestamps: Vec<DateTime<Fixe
         })
         .value_style(Style::default().bg(theme.colors_app.background_brightest.color));
       babars.push(abar);
-      num_adate = 0;
+      // the new bar starts with the messages of its first timestamp, a whole pruned day for daily counts
+      num_adate = count;
       adate = stamp;
     } else {
       // add to old bar
-      num_adate = num_adate.saturating_add(1);
+      num_adate = num_adate.saturating_add(count);
     }
   }
   if babars.is_empty() {
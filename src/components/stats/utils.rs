@@ -1,15 +1,12 @@
-use chrono::{DateTime, Datelike, FixedOffset};
+use chrono::{DateTime, Datelike, FixedOffset, Local};
 
-use super::StatIP;
+use super::{StatIP, Timestamps};
 use crate::database::schema::message::MiniMessage;
 
-pub fn convert_strings_to_utc(strings: Vec<String>) -> Vec<DateTime<FixedOffset>> {
-  let mut ts: Vec<DateTime<FixedOffset>> = vec![];
-  for stampstr in strings {
-    let cts: DateTime<FixedOffset> = DateTime::parse_from_rfc3339(&stampstr).unwrap();
-    ts.push(cts);
-  }
-  ts.sort_by(|a, b| a.cmp(b));
+/// Timestamps in local time with the number of messages at each, oldest first.
+pub fn sorted_timestamps(timestamps: Vec<(DateTime<Local>, usize)>) -> Timestamps {
+  let mut ts: Timestamps = timestamps.into_iter().map(|(ts, count)| (ts.fixed_offset(), count)).collect();
+  ts.sort();
   ts
 }
 
@@ -19,15 +16,15 @@ pub fn get_msgs_per_ip(msgs: Vec<MiniMessage>) -> Vec<StatIP> {
     return ipvec;
   }
   let mut last_ip = msgs[0].ip.clone();
-  let mut last_timestamps: Vec<String> = vec![];
+  let mut last_timestamps: Vec<(DateTime<Local>, usize)> = vec![];
   let mut num_this: usize = 0;
   for msg in msgs {
     let cip = msg.ip;
     if cip == last_ip {
-      last_timestamps.push(msg.created_at);
-      num_this = num_this.saturating_add(1);
+      last_timestamps.push((msg.created_at, msg.count));
+      num_this = num_this.saturating_add(msg.count);
     } else {
-      let rt: Vec<DateTime<FixedOffset>> = convert_strings_to_utc(last_timestamps);
+      let rt: Timestamps = sorted_timestamps(last_timestamps);
       let statip = StatIP { ip: last_ip, timestamps: rt, warnings: num_this };
       ipvec.push(statip);
       last_ip = cip;
@@ -37,7 +34,7 @@ pub fn get_msgs_per_ip(msgs: Vec<MiniMessage>) -> Vec<StatIP> {
   }
 
   if ipvec.is_empty() {
-    let rt: Vec<DateTime<FixedOffset>> = convert_strings_to_utc(last_timestamps);
+    let rt: Timestamps = sorted_timestamps(last_timestamps);
     let statip = StatIP { ip: last_ip, timestamps: rt, warnings: num_this };
     ipvec.push(statip);
   }
@@ -45,3 +42,19 @@ pub fn get_msgs_per_ip(msgs: Vec<MiniMessage>) -> Vec<StatIP> {
   ipvec
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_msgs_per_ip_counts_days() {
+    let at = |ts: &str| DateTime::parse_from_rfc3339(ts).unwrap().with_timezone(&Local);
+    let msgs = vec![
+      MiniMessage { ip: String::from("192.0.2.1"), created_at: at("2023-01-10T12:00:00+00:00"), count: 5 },
+      MiniMessage { ip: String::from("192.0.2.1"), created_at: at("2023-11-19T12:00:00+00:00"), count: 1 },
+    ];
+    let statips = get_msgs_per_ip(msgs);
+    assert_eq!(statips[0].warnings, 6);
+    assert_eq!(statips[0].timestamps.iter().map(|(_, count)| count).collect::<Vec<_>>(), vec![&5, &1]);
+  }
+}
@@ -11,10 +11,37 @@ use serde::{
 };
 use serde_json::Value as JsonValue;
 
-use crate::{action::Action, mode::Mode};
+use crate::{action::Action, database::schema::retention::Retention, journal::{JournalMatch, JournalSource}, mode::Mode};
 
 const CONFIG: &str = include_str!("../.config/config.json5");
 
+/// fail2ban log followed when no `logpath` is configured.
+pub const DEFAULT_LOGPATH: &str = "/var/log/fail2ban.log";
+
+/// Jail used when no jails are configured or discovered.
+pub const DEFAULT_JAIL: &str = "sshd";
+
+/// Milliseconds a reverse or forward DNS lookup may take.
+pub const DEFAULT_RDNS_TIMEOUT_MS: u64 = 2000;
+
+/// Database file in the data dir when no `db` is configured.
+pub const DEFAULT_DB_FILE: &str = "iplogs.db";
+
+/// Days a stored location is trusted before it's looked up again.
+pub const DEFAULT_GEO_TTL_DAYS: u64 = 90;
+
+/// Days messages are kept in full before they only count per day.
+pub const DEFAULT_RETENTION_DAYS: u64 = 365;
+
+/// Messages kept in full, the oldest beyond are rolled up.
+pub const DEFAULT_MAX_MESSAGES: u64 = 1_000_000;
+
+/// Messages kept in full per IP, a single noisy IP shouldn't push out everyone else.
+pub const DEFAULT_MAX_MESSAGES_PER_IP: u64 = 10_000;
+
+/// Days between VACUUMs of the database.
+pub const DEFAULT_VACUUM_DAYS: u64 = 7;
+
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct AppConfig {
   #[serde(default)]
@@ -33,6 +60,202 @@ pub struct Config {
   pub styles: Styles,
   #[serde(default)]
   pub logpath: String,
+  #[serde(default)]
+  pub jails: Vec<String>,
+  #[serde(default)]
+  pub f2b_socket: String,
+  #[serde(default)]
+  pub db: PathBuf,
+  #[serde(default)]
+  pub journal: JournalConfig,
+  #[serde(default)]
+  pub geo: GeoConfig,
+  #[serde(default)]
+  pub home: HomeConfig,
+  #[serde(default)]
+  pub rdns: RdnsConfig,
+  #[serde(default)]
+  pub retention: RetentionConfig,
+}
+
+/// Where the map draws home.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HomeMode {
+  /// Locate our public address, found through ident.me
+  #[default]
+  Lookup,
+  /// The configured `lat` and `lon`
+  Fixed,
+  /// The configured named sites
+  Sites,
+  /// No home, the map only shows the attackers
+  Disabled,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct HomeConfig {
+  #[serde(default)]
+  pub mode: HomeMode,
+  #[serde(default)]
+  pub lat: f64,
+  #[serde(default)]
+  pub lon: f64,
+  #[serde(default)]
+  pub sites: Vec<HomeSite>,
+}
+
+/// A monitored server on the map, lines are drawn to the site owning the host that logged them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct HomeSite {
+  pub name: String,
+  pub lat: f64,
+  pub lon: f64,
+  /// Journal hostnames of the servers at this site.
+  #[serde(default)]
+  pub hosts: Vec<String>,
+}
+
+impl HomeSite {
+  pub fn new(name: &str, lat: f64, lon: f64) -> Self {
+    Self { name: String::from(name), lat, lon, hosts: vec![] }
+  }
+
+  /// The site owning `host`, otherwise the one nearest to the point.
+  pub fn pick<'a>(sites: &'a [HomeSite], host: Option<&str>, lat: f64, lon: f64) -> Option<&'a HomeSite> {
+    host
+      .and_then(|host| sites.iter().find(|site| site.hosts.iter().any(|owned| owned == host)))
+      .or_else(|| sites.iter().min_by(|a, b| a.distance(lat, lon).total_cmp(&b.distance(lat, lon))))
+  }
+
+  /// Great-circle distance in km.
+  pub fn distance(&self, lat: f64, lon: f64) -> f64 {
+    let (lat1, lat2) = (self.lat.to_radians(), lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon - self.lon).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * 6371.0 * a.sqrt().asin()
+  }
+}
+
+/// Where IP locations come from.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GeoProviderKind {
+  /// ip-api.com, needs outbound internet
+  #[default]
+  IpApi,
+  /// GeoLite2 or DB-IP `.mmdb` files
+  Mmdb,
+  /// CSV file of IP ranges
+  Csv,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GeoConfig {
+  #[serde(default)]
+  pub provider: GeoProviderKind,
+  /// City database for the `mmdb` provider.
+  #[serde(default)]
+  pub city_db: String,
+  /// Optional ASN database for the `mmdb` provider, fills in the ISP.
+  #[serde(default)]
+  pub asn_db: String,
+  /// Range database for the `csv` provider.
+  #[serde(default)]
+  pub csv_db: String,
+  /// Days until a stored location is looked up again, 0 keeps locations forever.
+  #[serde(default)]
+  pub ttl_days: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RdnsConfig {
+  /// Skips reverse lookups, e.g. where DNS queries must not reach the attackers' name servers.
+  #[serde(default)]
+  pub disabled: bool,
+  /// Timeout of each lookup, the default if 0.
+  #[serde(default)]
+  pub timeout_ms: u64,
+}
+
+/// How much of the messages table is kept, each limit falls back to its default and 0 turns it off.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RetentionConfig {
+  /// Days messages are kept in full.
+  #[serde(default)]
+  pub max_age_days: Option<u64>,
+  /// Messages kept in full.
+  #[serde(default)]
+  pub max_rows: Option<u64>,
+  /// Messages kept in full per IP.
+  #[serde(default)]
+  pub max_per_ip: Option<u64>,
+  /// Days between VACUUMs.
+  #[serde(default)]
+  pub vacuum_days: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct JournalConfig {
+  /// Journal directory, `.journal` file or export file to read, the system journal if empty.
+  #[serde(default)]
+  pub path: String,
+  #[serde(default)]
+  pub sources: Vec<JournalSourceConfig>,
+}
+
+/// Journal entries that are watched together and can be toggled on their own, e.g. everything sshd logs.
+/// An entry belongs to the source if it matches any of the units, identifiers or matches.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct JournalSourceConfig {
+  /// Tag the entries are passed on with.
+  pub name: String,
+  /// Systemd units, `.service` is added if the name has no suffix like `journalctl -u` does.
+  #[serde(default)]
+  pub units: Vec<String>,
+  /// Syslog identifiers, e.g. `sshd` or `postfix/smtpd`.
+  #[serde(default)]
+  pub identifiers: Vec<String>,
+  /// Raw journal matches in `FIELD=value` form, e.g. `_COMM=nginx`.
+  #[serde(default)]
+  pub matches: Vec<String>,
+}
+
+impl JournalSourceConfig {
+  /// The ssh unit under both names distributions use.
+  pub fn ssh() -> Self {
+    Self {
+      name: String::from("ssh"),
+      units: vec![String::from("ssh.service"), String::from("sshd.service")],
+      identifiers: vec![String::from("sshd")],
+      matches: vec![],
+    }
+  }
+
+  /// Sources of sshd's entries, the unit is called `ssh` on some distributions but the identifier stays `sshd`.
+  pub fn is_sshd(&self) -> bool {
+    self.units.iter().chain(&self.identifiers).any(|name| name.contains("sshd"))
+  }
+
+  pub fn journal_matches(&self) -> Vec<JournalMatch> {
+    let units = self.units.iter().map(|unit| {
+      if unit.contains('.') {
+        JournalMatch::new("_SYSTEMD_UNIT", unit)
+      } else {
+        JournalMatch::new("_SYSTEMD_UNIT", &format!("{}.service", unit))
+      }
+    });
+    let identifiers = self.identifiers.iter().map(|identifier| JournalMatch::new("SYSLOG_IDENTIFIER", identifier));
+    let matches = self.matches.iter().filter_map(|expr| {
+      let parsed = JournalMatch::parse(expr);
+      if parsed.is_none() {
+        log::error!("Invalid journal match {:?} in source {}, expected FIELD=value", expr, self.name);
+      }
+      parsed
+    });
+    units.chain(identifiers).chain(matches).collect()
+  }
 }
 
 impl Config {
@@ -80,6 +303,104 @@ impl Config {
 
     Ok(cfg)
   }
+
+  /// Returns the configured fail2ban jails, falls back to `sshd` if none are set.
+  pub fn jail_list(&self) -> Vec<String> {
+    if self.jails.is_empty() {
+      vec![String::from(DEFAULT_JAIL)]
+    } else {
+      self.jails.clone()
+    }
+  }
+
+  /// Returns the configured fail2ban log, falls back to the default location.
+  pub fn log_path(&self) -> String {
+    if self.logpath.is_empty() {
+      String::from(DEFAULT_LOGPATH)
+    } else {
+      self.logpath.clone()
+    }
+  }
+
+  /// Returns the configured database, falls back to `iplogs.db` in the data dir.
+  pub fn db_path(&self) -> PathBuf {
+    if self.db.as_os_str().is_empty() {
+      crate::utils::get_data_dir().join(DEFAULT_DB_FILE)
+    } else {
+      self.db.clone()
+    }
+  }
+
+  /// How long a stored location is trusted, falls back to the default, `None` if it never expires.
+  pub fn geo_ttl(&self) -> Option<chrono::Duration> {
+    match self.geo.ttl_days.unwrap_or(DEFAULT_GEO_TTL_DAYS) {
+      0 => None,
+      days => Some(chrono::Duration::days(days as i64)),
+    }
+  }
+
+  /// Which messages are kept in full, older ones are rolled into daily counts.
+  pub fn retention(&self) -> Retention {
+    let limit = |configured: Option<u64>, default: u64| Some(configured.unwrap_or(default)).filter(|limit| *limit > 0);
+    Retention {
+      max_age: limit(self.retention.max_age_days, DEFAULT_RETENTION_DAYS).map(|days| chrono::Duration::days(days as i64)),
+      max_rows: limit(self.retention.max_rows, DEFAULT_MAX_MESSAGES).map(|rows| rows as usize),
+      max_per_ip: limit(self.retention.max_per_ip, DEFAULT_MAX_MESSAGES_PER_IP).map(|rows| rows as usize),
+    }
+  }
+
+  /// Time between VACUUMs of the database, `None` if it's never vacuumed.
+  pub fn vacuum_interval(&self) -> Option<chrono::Duration> {
+    match self.retention.vacuum_days.unwrap_or(DEFAULT_VACUUM_DAYS) {
+      0 => None,
+      days => Some(chrono::Duration::days(days as i64)),
+    }
+  }
+
+  /// Timeout of reverse DNS lookups, `None` if they are disabled.
+  pub fn rdns_timeout(&self) -> Option<std::time::Duration> {
+    match (self.rdns.disabled, self.rdns.timeout_ms) {
+      (true, _) => None,
+      (false, 0) => Some(std::time::Duration::from_millis(DEFAULT_RDNS_TIMEOUT_MS)),
+      (false, ms) => Some(std::time::Duration::from_millis(ms)),
+    }
+  }
+
+  /// Home sites from the config, `None` if home is looked up from our public address.
+  pub fn home_sites(&self) -> Option<Vec<HomeSite>> {
+    match self.home.mode {
+      HomeMode::Lookup => None,
+      HomeMode::Fixed => Some(vec![HomeSite::new("Home", self.home.lat, self.home.lon)]),
+      HomeMode::Sites => Some(self.home.sites.clone()),
+      HomeMode::Disabled => Some(vec![]),
+    }
+  }
+
+  /// Returns the configured journal sources, falls back to the ssh unit if none are set.
+  pub fn journal_sources(&self) -> Vec<JournalSourceConfig> {
+    if self.journal.sources.is_empty() {
+      vec![JournalSourceConfig::ssh()]
+    } else {
+      self.journal.sources.clone()
+    }
+  }
+
+  pub fn journal_source(&self) -> JournalSource {
+    if self.journal.path.is_empty() {
+      JournalSource::System
+    } else {
+      JournalSource::from_path(std::path::Path::new(&self.journal.path))
+    }
+  }
+
+  /// Returns the configured fail2ban server socket, falls back to the default location.
+  pub fn socket_path(&self) -> String {
+    if self.f2b_socket.is_empty() {
+      String::from(crate::fail2ban::DEFAULT_SOCKET)
+    } else {
+      self.f2b_socket.clone()
+    }
+  }
 }
 
 #[derive(Clone, Debug, Default, Deref, DerefMut)]
@@ -502,6 +823,98 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  fn test_journal_sources() {
+    let config: Config = json5::from_str(
+      r#"{ "journal": { "sources": [
+        { "name": "nginx", "units": ["nginx"] },
+        { "name": "postfix", "identifiers": ["postfix/smtpd"], "matches": ["_COMM=smtpd", "broken"] },
+      ] } }"#,
+    )
+    .unwrap();
+    let sources = config.journal_sources();
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources[0].journal_matches(), vec![JournalMatch::new("_SYSTEMD_UNIT", "nginx.service")]);
+    assert_eq!(
+      sources[1].journal_matches(),
+      vec![JournalMatch::new("SYSLOG_IDENTIFIER", "postfix/smtpd"), JournalMatch::new("_COMM", "smtpd")]
+    );
+    assert_eq!(config.journal_source(), JournalSource::System);
+
+    let empty = Config::default();
+    assert_eq!(empty.journal_sources(), vec![JournalSourceConfig::ssh()]);
+    assert_eq!(JournalSourceConfig::ssh().journal_matches(), JournalMatch::ssh_defaults());
+  }
+
+  #[test]
+  fn test_geo_config() -> Result<()> {
+    let config: Config = config::Config::builder()
+      .add_source(config::File::from_str(
+        r#"{ "geo": { "provider": "mmdb", "city_db": "/usr/share/GeoIP/GeoLite2-City.mmdb" } }"#,
+        config::FileFormat::Json5,
+      ))
+      .build()?
+      .try_deserialize()?;
+    assert_eq!(config.geo.provider, GeoProviderKind::Mmdb);
+    assert_eq!(config.geo.city_db, "/usr/share/GeoIP/GeoLite2-City.mmdb");
+    assert_eq!(config.geo.asn_db, "");
+    assert_eq!(Config::default().geo.provider, GeoProviderKind::IpApi);
+    assert_eq!(config.geo_ttl(), Some(chrono::Duration::days(DEFAULT_GEO_TTL_DAYS as i64)));
+    let forever: Config = json5::from_str(r#"{ "geo": { "ttl_days": 0 } }"#).unwrap();
+    assert_eq!(forever.geo_ttl(), None);
+    assert_eq!(config.rdns_timeout(), Some(std::time::Duration::from_millis(DEFAULT_RDNS_TIMEOUT_MS)));
+    let no_rdns: Config = json5::from_str(r#"{ "rdns": { "disabled": true } }"#).unwrap();
+    assert_eq!(no_rdns.rdns_timeout(), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_retention() {
+    let defaults = Config::default().retention();
+    assert_eq!(defaults.max_age, Some(chrono::Duration::days(DEFAULT_RETENTION_DAYS as i64)));
+    assert_eq!((defaults.max_rows, defaults.max_per_ip), (Some(DEFAULT_MAX_MESSAGES as usize), Some(DEFAULT_MAX_MESSAGES_PER_IP as usize)));
+    assert_eq!(Config::default().vacuum_interval(), Some(chrono::Duration::days(DEFAULT_VACUUM_DAYS as i64)));
+
+    let config: Config = json5::from_str(r#"{ "retention": { "max_age_days": 30, "max_rows": 0, "vacuum_days": 0 } }"#).unwrap();
+    let retention = config.retention();
+    assert_eq!(retention.max_age, Some(chrono::Duration::days(30)));
+    assert_eq!((retention.max_rows, retention.max_per_ip), (None, Some(DEFAULT_MAX_MESSAGES_PER_IP as usize)));
+    assert_eq!(config.vacuum_interval(), None);
+  }
+
+  #[test]
+  fn test_db_path() {
+    assert_eq!(Config::default().db_path(), crate::utils::get_data_dir().join(DEFAULT_DB_FILE));
+    let config: Config = json5::from_str(r#"{ "db": "/var/lib/succeed2ban/iplogs.db" }"#).unwrap();
+    assert_eq!(config.db_path(), PathBuf::from("/var/lib/succeed2ban/iplogs.db"));
+  }
+
+  #[test]
+  fn test_home_sites() {
+    let config: Config = json5::from_str(
+      r#"{ "home": { "mode": "sites", "sites": [
+        { "name": "fra", "lat": 50.11, "lon": 8.68, "hosts": ["web1"] },
+        { "name": "nyc", "lat": 40.71, "lon": -74.0, "hosts": ["web2", "db1"] },
+      ] } }"#,
+    )
+    .unwrap();
+    let sites = config.home_sites().unwrap();
+    assert_eq!(sites.len(), 2);
+    // Sao Paulo is nearer to New York, Moscow to Frankfurt
+    assert_eq!(HomeSite::pick(&sites, None, -23.55, -46.63).unwrap().name, "nyc");
+    assert_eq!(HomeSite::pick(&sites, None, 55.75, 37.62).unwrap().name, "fra");
+    assert_eq!(HomeSite::pick(&sites, Some("db1"), 55.75, 37.62).unwrap().name, "nyc");
+    assert_eq!(HomeSite::pick(&sites, Some("unknown"), 55.75, 37.62).unwrap().name, "fra");
+    assert!((sites[0].distance(40.71, -74.0) - 6200.0).abs() < 100.0);
+
+    let fixed: Config = json5::from_str(r#"{ "home": { "mode": "fixed", "lat": 53.04, "lon": 8.94 } }"#).unwrap();
+    assert_eq!(fixed.home_sites(), Some(vec![HomeSite::new("Home", 53.04, 8.94)]));
+    let disabled: Config = json5::from_str(r#"{ "home": { "mode": "disabled" } }"#).unwrap();
+    assert_eq!(disabled.home_sites(), Some(vec![]));
+    assert!(HomeSite::pick(&[], None, 0.0, 0.0).is_none());
+    assert_eq!(Config::default().home_sites(), None);
+  }
+
   #[test]
   fn test_config_inv() -> Result<()> {
     let c = Config::new()?;
@@ -1 +1,31 @@
 pub mod schema;
+pub mod worker;
+
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+pub use worker::DbConn;
+
+/// Opens the database at `path`, creating its directory if needed.
+pub fn open(path: &Path) -> color_eyre::eyre::Result<Connection> {
+  if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+    std::fs::create_dir_all(dir)?;
+  }
+  Ok(Connection::open(path)?)
+}
+
+/// Counts the stats of the configured database, or the one at `db`, again from its messages.
+/// Runs instead of the TUI for `--recompute-stats`.
+pub fn repair_stats(db: Option<PathBuf>) -> color_eyre::eyre::Result<()> {
+  let mut config = crate::config::Config::new()?;
+  if let Some(db) = db {
+    config.db = db;
+  }
+  let path = config.db_path();
+  let conn = open(&path)?;
+  schema::create_tables(&conn)?;
+  let corrected = schema::recompute_stats(&conn)?;
+  println!("Recomputed the stats of {}, {} rows were off", path.display(), corrected);
+  Ok(())
+}
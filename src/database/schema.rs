@@ -9,100 +9,176 @@ pub mod city;
 pub mod region;
 pub mod country;
 pub mod ip;
+pub mod asn;
+pub mod prefix;
+pub mod ip_history;
+pub mod rdns;
+pub mod ban_event;
+pub mod retention;
+pub mod migrations;
 
 
+/// Creates the tables of a new database or migrates an existing one to the current schema.
 pub fn create_tables(conn: &Connection) -> Result<()> {
-    conn.execute(country::CREATE_COUNTRY_DB_SQL, []).expect("Error setting up country db");
-    conn.execute(city::CREATE_CITY_DB_SQL, []).expect("Error setting up city db");
-    conn.execute(region::CREATE_REGION_DB_SQL, []).expect("Error setting up Region db");
-    conn.execute(isp::CREATE_ISP_DB_SQL, []).expect("Error setting up ISP db");
-    conn.execute(ip::CREATE_IP_DB_SQL, []).expect("Error setting up IP db");
-    conn.execute(message::CREATE_MESSAGE_DB_SQL, []).expect("Error setting up IP db");
+    migrations::migrate(conn)?;
     Ok(())
 }
 
+/// Reads a timestamp column, timestamps are stored as seconds since the UNIX epoch.
+pub fn timestamp(row: &rusqlite::Row, idx: usize) -> Result<chrono::DateTime<chrono::Local>> {
+    let secs: i64 = row.get(idx)?;
+    chrono::DateTime::from_timestamp(secs, 0)
+        .map(|utc| utc.with_timezone(&chrono::Local))
+        .ok_or(rusqlite::Error::IntegralValueOutOfRange(idx, secs))
+}
+
+/// Reads a timestamp column that may be NULL.
+pub fn optional_timestamp(row: &rusqlite::Row, idx: usize) -> Result<Option<chrono::DateTime<chrono::Local>>> {
+    match row.get::<_, Option<i64>>(idx)? {
+        Some(_) => timestamp(row, idx).map(Some),
+        None => Ok(None),
+    }
+}
+
 pub struct MetaInfo {
     pub country: country::Country,
     pub region: region::Region,
     pub city: city::City,
-    pub isp: isp::ISP
+    pub isp: isp::ISP,
+    /// None if the provider didn't know the AS.
+    pub asn: Option<asn::ASN>,
+    /// None if the provider didn't know the announced network.
+    pub prefix: Option<prefix::Prefix>,
   }
   
-pub fn update_db_on_new_log(conn: &Connection, x:ip::IP, from_db:bool) -> MetaInfo {
-    let mut country = country::select_country(conn, x.country.as_str()).unwrap_or_default().unwrap_or_default();
-    if country == country::Country::default() {
-        let _ = country::insert_new_country(conn, x.country.as_str(), Some(x.countrycode.as_str()), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), false).unwrap();
-    }
-    else {
-        country.warnings += 1;
-        if !from_db && x.is_banned {country.banned += 1;}
-        let _ = country::insert_new_country(conn, country.name.as_str(), Some(country.code.as_str()),Some(country.banned), Some(country.warnings), country.is_blocked).unwrap();
+/// Makes sure the IP's country, region, city, ISP, AS and prefix have rows and returns them.
+/// Their counters follow the messages table, the line is counted once its message is stored.
+pub fn update_db_on_new_log(conn: &Connection, x: ip::IP) -> MetaInfo {
+    insert_missing_locations(conn, &x).unwrap();
+    MetaInfo {
+        country: country::select_country(conn, x.country.as_str()).unwrap_or_default().unwrap_or_default(),
+        region: region::select_region(conn, x.region.as_str()).unwrap_or_default().unwrap_or_default(),
+        city: city::select_city(conn, x.city.as_str()).unwrap_or_default().unwrap_or_default(),
+        isp: isp::select_isp(conn, x.isp.as_str()).unwrap_or_default().unwrap_or_default(),
+        asn: x.asn.and_then(|number| asn::select_asn(conn, number).unwrap_or_default()),
+        prefix: (!x.prefix.is_empty()).then(|| prefix::select_prefix(conn, x.prefix.as_str()).unwrap_or_default()).flatten(),
     }
+}
 
-    let mut region = region::select_region(conn, x.region.as_str()).unwrap_or_default().unwrap_or_default();
-    if region == region::Region::default() {
-        let _ = region::insert_new_region(conn, x.region.as_str(), x.country.as_str(), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), false).unwrap();
+/// Stores the IP of a new line, `is_ban` marks a line that banned it.
+/// A fresh lookup of a known IP updates its location, its counters are left to the messages.
+pub fn update_ip_db_on_new_log(conn: &Connection, x: ip::IP, from_db: bool, is_ban: bool) {
+    let is_banned = x.is_banned || is_ban;
+    if ip::select_ip(conn, x.ip.as_str()).unwrap_or_default().is_none() {
+      let _ = ip::insert_new_IP(conn,
+        x.ip.as_str(), x.created_at,
+        x.lon, x.lat,
+        x.isp.as_str(), x.city.as_str(),
+        Some(x.region.as_str()), x.country.as_str(),
+        Some(x.countrycode.as_str()), 0,
+          is_banned, 0).unwrap();
+      // the insert only knows the columns of the first schema
+      let _ = ip::set_network(conn, &x);
     }
     else {
-        region.warnings += 1;
-        if !from_db && x.is_banned {region.banned += 1;}
-        let _ = region::insert_new_region(conn, region.name.as_str(), region.country.as_str(),Some(region.banned), Some(region.warnings), region.is_blocked).unwrap();
+      if !from_db {
+        let _ = ip::set_location(conn, &x);
+      }
+      let _ = ip::set_banned(conn, x.ip.as_str(), is_banned);
     }
+  }
 
-    let mut city = city::select_city(conn, x.city.as_str()).unwrap_or_default().unwrap_or_default();
-    if city == city::City::default() {
-        let _ = city::insert_new_city(conn, x.city.as_str(), x.country.as_str(), x.region.as_str(), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), false).unwrap();
+/// Rows of everything the IP is counted for that don't exist yet, with nothing counted.
+fn insert_missing_locations(conn: &Connection, x: &ip::IP) -> Result<()> {
+    if country::select_country(conn, x.country.as_str())?.is_none() {
+        country::insert_new_country(conn, x.country.as_str(), Some(x.countrycode.as_str()), Some(0), Some(0), false)?;
     }
-    else {
-        city.warnings += 1;
-        if !from_db && x.is_banned {city.banned += 1;}
-        let _ = city::insert_new_city(conn, city.name.as_str(), city.country.as_str(),city.region.as_str(), Some(city.banned), Some(city.warnings), city.is_blocked).unwrap();
+    if region::select_region(conn, x.region.as_str())?.is_none() {
+        region::insert_new_region(conn, x.region.as_str(), x.country.as_str(), Some(0), Some(0), false)?;
     }
-
-    let mut isp: isp::ISP = isp::select_isp(conn, x.isp.as_str()).unwrap_or_default().unwrap_or_default();
-    if isp == isp::ISP::default() {
-        let _ = isp::insert_new_ISP(conn, x.isp.as_str(), match x.is_banned {false => Some(0), true => Some(1)}, Some(1), x.country.as_str(), false).unwrap();
+    if city::select_city(conn, x.city.as_str())?.is_none() {
+        city::insert_new_city(conn, x.city.as_str(), x.country.as_str(), x.region.as_str(), Some(0), Some(0), false)?;
     }
-    else {
-        isp.warnings += 1;
-        if !from_db && x.is_banned {isp.banned += 1;}
-        let _ = isp::insert_new_ISP(conn, isp.name.as_str(), Some(isp.banned), Some(isp.warnings), x.country.as_str(), isp.is_blocked).unwrap();
+    if isp::select_isp(conn, x.isp.as_str())?.is_none() {
+        isp::insert_new_ISP(conn, x.isp.as_str(), Some(0), Some(0), x.country.as_str(), false)?;
+    }
+    if let Some(number) = x.asn {
+        if asn::select_asn(conn, number)?.is_none() {
+            asn::insert_new_ASN(conn, number, x.as_org.as_str(), Some(0), Some(0), x.country.as_str(), false)?;
+        }
+    }
+    if !x.prefix.is_empty() && prefix::select_prefix(conn, x.prefix.as_str())?.is_none() {
+        prefix::insert_new_prefix(conn, x.prefix.as_str(), x.asn, Some(0), Some(0), false)?;
     }
-    return MetaInfo { country, region, city, isp }
+    Ok(())
 }
 
-pub fn update_ip_db_on_new_log(conn: &Connection, x: ip::IP, from_db: bool) {
-    if !from_db {
-      let _ = ip::insert_new_IP(conn, 
-        x.ip.as_str(), x.created_at.as_str(), 
-        x.lon.as_str(), x.lat.as_str(), 
-        x.isp.as_str(), x.city.as_str(), 
-        Some(x.region.as_str()), x.country.as_str(),
-        Some(x.countrycode.as_str()), x.banned_times, 
-          x.is_banned, x.warnings).unwrap();
-    }
-    else {
-      // ip is in db
-      let _ = ip::insert_new_IP(conn,
-        x.ip.as_str(), x.created_at.as_str(), 
-        x.lon.as_str(), x.lat.as_str(), 
-        x.isp.as_str(), x.city.as_str(), 
-        Some(x.region.as_str()), x.country.as_str(),
-        Some(x.countrycode.as_str()), x.banned_times, 
-          x.is_banned, x.warnings + 1).unwrap();
+/// Counted tables with their warning and ban counters and which messages a row counts,
+/// the condition holds for `messages` and the daily counts of pruned ones alike.
+const COUNTERS: [(&str, &str, &str, &str); 7] = [
+    ("country", "warnings", "banned", "country = country.name"),
+    ("region", "warnings", "banned", "region = region.name"),
+    ("city", "warnings", "banned", "city = city.name"),
+    ("isp", "messages", "banned", "isp = isp.name"),
+    ("asn", "messages", "banned", "ip IN (SELECT ip FROM ipmeta WHERE ipmeta.asn = asn.number)"),
+    ("prefix", "messages", "banned", "ip IN (SELECT ip FROM ipmeta WHERE ipmeta.prefix = prefix.prefix)"),
+    ("ipmeta", "warnings", "banned_times", "ip = ipmeta.ip"),
+];
+
+/// Counts every counter again from the messages, in case rows were changed without the triggers seeing it.
+/// Returns how many rows were off.
+pub fn recompute_stats(conn: &Connection) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut corrected = 0;
+    for (table, warnings, banned, counts) in COUNTERS {
+        let counted = format!(
+            "(SELECT (SELECT COUNT(*) FROM messages WHERE {0}) + (SELECT COALESCE(SUM(warnings), 0) FROM messages_daily WHERE {0}),
+                (SELECT COALESCE(SUM(is_ban), 0) FROM messages WHERE {0}) + (SELECT COALESCE(SUM(banned), 0) FROM messages_daily WHERE {0}))",
+            counts
+        );
+        corrected += tx.execute(
+            &format!("UPDATE {0} SET ({1}, {2}) = {3} WHERE ({1}, {2}) IS NOT {3}", table, warnings, banned, counted),
+            [],
+        )?;
     }
-  }
+    tx.commit()?;
+    Ok(corrected)
+}
 
+/// Stores a re-lookup of a known IP, the former location goes to the history if it changed.
+/// Returns the former location if it did, messages keep the location they were logged with.
+pub fn refresh_ip_location(conn: &Connection, fresh: &ip::IP) -> Result<Option<ip_history::LocationHistory>> {
+    let Some(old) = ip::select_ip(conn, fresh.ip.as_str())? else { return Ok(None) };
+    // records from before the AS was stored only gain one
+    let network_changed = old.asn.is_some() && (old.asn, &old.prefix) != (fresh.asn, &fresh.prefix);
+    let moved = network_changed
+        || (&old.country, &old.region, &old.city, &old.isp, &old.lat, &old.lon) != (&fresh.country, &fresh.region, &fresh.city, &fresh.isp, &fresh.lat, &fresh.lon);
+    let archived = if moved {
+        let archived = ip_history::LocationHistory::archive(&old, fresh.geo_updated_at.unwrap_or_else(chrono::offset::Local::now));
+        ip_history::insert_location_history(conn, &archived)?;
+        Some(archived)
+    } else {
+        None
+    };
+    // ipmeta references them, they get counted once lines come in from the new location
+    insert_missing_locations(conn, fresh)?;
+    ip::set_location(conn, fresh)?;
+    Ok(archived)
+}
 
 // Tests need to be run sequentially on non-existant db
 //#[cfg(test)]
 mod test {
     use crate::database::schema::{self, update_ip_db_on_new_log};
-    use crate::database::schema::{message, isp, city, region, country, ip};
+    use crate::database::schema::{message, isp, city, region, country, ip, asn, prefix, ip_history, rdns, ban_event};
     use rusqlite::{Connection, Result};
 
     use super::update_db_on_new_log;
 
+    fn ts(rfc3339: &str) -> chrono::DateTime<chrono::Local> {
+        chrono::DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&chrono::Local)
+    }
+
     fn cleanup_db(filename: &str) {
         let file = std::path::Path::new(filename);
         let cleanup = std::fs::remove_file(file);
@@ -114,11 +190,23 @@ mod test {
         let _ = region::insert_new_region(&conn, "Undetussen", "Doitschland", Some(0), Some(0), false).expect("Region insertion failed");
         let _ = city::insert_new_city(&conn, "Humburg", "Doitschland", "Undetussen",Some(0), Some(0), false).expect("City insertion failed");
         let _ = isp::insert_new_ISP(&conn,"Telecum", Some(0), Some(0), "Doitschland", false).expect("ISP insertion failed");
-        let _ = ip::insert_new_IP(&conn, "111.233.456.678", "2022-03-11 23:45:31:512", "3.12", "59.79", "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed");
-        let _ = message::insert_new_message(&conn, Option::None, "2022-03-11 23:45:31:512","OMG SUCH A MESSAGE", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum",true, false).expect("Message insertion failed");
+        let _ = ip::insert_new_IP(&conn, "111.233.456.678", ts("2022-03-11T23:45:31+01:00"), Some(3.12), Some(59.79), "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed");
+        let _ = message::insert_new_message(&conn, Option::None, ts("2022-03-11T23:45:31+01:00"),"OMG SUCH A MESSAGE", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum",true, false).expect("Message insertion failed");
         Ok(())       
     }
 
+    /// IPs located in Bremen, Germany with the ISP Example, for the tests of the schema's modules.
+    pub(super) fn insert_located(conn: &Connection, ips: &[&str], created_at: chrono::DateTime<chrono::Local>) -> Result<()> {
+        country::insert_new_country(conn, "Germany", Some("DE"), Some(0), Some(0), false)?;
+        region::insert_new_region(conn, "Bremen", "Germany", Some(0), Some(0), false)?;
+        city::insert_new_city(conn, "Bremen", "Germany", "Bremen", Some(0), Some(0), false)?;
+        isp::insert_new_ISP(conn, "Example", Some(0), Some(0), "Germany", false)?;
+        for ip in ips {
+            ip::insert_new_IP(conn, ip, created_at, Some(8.8), Some(53.1), "Example", "Bremen", Some("Bremen"), "Germany", Some("DE"), 0, false, 0)?;
+        }
+        Ok(())
+    }
+
     #[test]
     pub fn test_db_create_tables() -> Result<()>{
         let db_name ="test.db";
@@ -222,7 +310,7 @@ mod test {
             // stuff in vec
             res = msgs[0].clone();
         }
-        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at:"2022-03-11 23:45:31:512".to_string()};
+        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at: ts("2022-03-11T23:45:31+01:00"), count: 1};
         assert_eq!(res, ass);
 
         // by region
@@ -232,7 +320,7 @@ mod test {
             // stuff in vec
             res = msgs[0].clone();
         }
-        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at:"2022-03-11 23:45:31:512".to_string()};
+        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at: ts("2022-03-11T23:45:31+01:00"), count: 1};
         assert_eq!(res, ass);
 
         // by city        
@@ -242,7 +330,7 @@ mod test {
             // stuff in vec
             res = msgs[0].clone();
         }
-        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at:"2022-03-11 23:45:31:512".to_string()};
+        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at: ts("2022-03-11T23:45:31+01:00"), count: 1};
         assert_eq!(res, ass);
 
         // by isp
@@ -252,7 +340,7 @@ mod test {
             // stuff in vec
             res = msgs[0].clone();
         }
-        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at:"2022-03-11 23:45:31:512".to_string()};
+        let ass = message::MiniMessage{ip:"111.233.456.678".to_string(), created_at: ts("2022-03-11T23:45:31+01:00"), count: 1};
         assert_eq!(res, ass);
 
         cleanup_db(db_name);
@@ -267,14 +355,14 @@ mod test {
         schema::create_tables(&conn)?;
         insert_all(&conn)?;
 
-        // "111.233.456.678", "2022-03-11 23:45:31:512", "3.12", "59.79", "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed")
+        // "111.233.456.678", ts("2022-03-11T23:45:31+01:00"), Some(3.12), Some(59.79), "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed")
         let before_city = city::select_city(&conn, "Humburg")?;
 
         let ip = ip::IP{
             ip: "111.233.456.678".to_string(),
-            created_at: "2022-03-11 23:45:31:512".to_string(),
-            lon: "3.12".to_string(),
-            lat: "59.79".to_string(),
+            created_at: ts("2022-03-11T23:45:31+01:00"),
+            lon: Some(3.12),
+            lat: Some(59.79),
             isp: "Telecum".to_string(),
             city: "Humburg".to_string(),
             region: "Undetussen".to_string(),
@@ -283,12 +371,21 @@ mod test {
             banned_times: 0,
             is_banned: false,
             warnings: 1,
+            jails: vec![],
+            addr: None,
+            asn: None,
+            as_org: String::new(),
+            prefix: String::new(),
+            geo_updated_at: None,
+            rdns: None,
         };
-        let meta = update_db_on_new_log(&conn, ip, true);
+        let meta = update_db_on_new_log(&conn, ip);
+        assert_eq!(meta.city, before_city.clone().unwrap());
+        message::insert_new_message(&conn, None, ts("2022-03-12T08:00:00+01:00"), "OMG ANOTHER MESSAGE", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, true)?;
 
-        let after_city = city::select_city(&conn, "Humburg")?;
-
-        assert_eq!(before_city.unwrap().warnings+1, after_city.unwrap().warnings);
+        let after_city = city::select_city(&conn, "Humburg")?.unwrap();
+        let before_city = before_city.unwrap();
+        assert_eq!((before_city.warnings+1, before_city.banned+1), (after_city.warnings, after_city.banned));
 
         cleanup_db(db_name);
         Ok(())
@@ -301,14 +398,14 @@ mod test {
         schema::create_tables(&conn)?;
         insert_all(&conn)?;
 
-        // "111.233.456.678", "2022-03-11 23:45:31:512", "3.12", "59.79", "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed")
+        // "111.233.456.678", ts("2022-03-11T23:45:31+01:00"), Some(3.12), Some(59.79), "Telecum", "Humburg", Some("Undetussen"), "Doitschland", Some("DDE"), 0, false, 0).expect("IP insertion failed")
         let before_ip = ip::select_ip(&conn, "111.233.456.678")?;
 
         let ip = ip::IP{
             ip: "111.233.456.678".to_string(),
-            created_at: "2022-03-11 23:45:31:512".to_string(),
-            lon: "3.12".to_string(),
-            lat: "59.79".to_string(),
+            created_at: ts("2022-03-11T23:45:31+01:00"),
+            lon: Some(3.12),
+            lat: Some(59.79),
             isp: "Telecum".to_string(),
             city: "Humburg".to_string(),
             region: "Undetussen".to_string(),
@@ -317,8 +414,17 @@ mod test {
             banned_times: 0,
             is_banned: false,
             warnings: 0,
+            jails: vec![],
+            addr: None,
+            asn: None,
+            as_org: String::new(),
+            prefix: String::new(),
+            geo_updated_at: None,
+            rdns: None,
         };
-        update_ip_db_on_new_log(&conn, ip, true);
+        update_ip_db_on_new_log(&conn, ip, true, false);
+        assert_eq!(ip::select_ip(&conn, "111.233.456.678")?, before_ip);
+        message::insert_new_message(&conn, None, ts("2022-03-12T08:00:00+01:00"), "OMG ANOTHER MESSAGE", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, false)?;
 
         let after_ip= ip::select_ip(&conn, "111.233.456.678")?;
 
@@ -328,5 +434,323 @@ mod test {
         Ok(())
     } 
 
-}
+    #[test]
+    pub fn test_db_message_ssh_columns() -> Result<()>{
+        let db_name ="test_ssh_columns.db";
+        let conn = Connection::open(db_name)?;
+        // messages table as created before the sshd columns existed
+        conn.execute("CREATE TABLE messages(
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            text TEXT NOT NULL,
+            ip TEXT NOT NULL,
+            country TEXT NOT NULL,
+            region TEXT NOT NULL,
+            city TEXT NOT NULL,
+            isp TEXT NOT NULL,
+            is_jctl INTEGER NOT NULL,
+            is_ban INTEGER NOT NULL
+        )", [])?;
+        schema::create_tables(&conn)?;
+        insert_all(&conn)?;
+
+        let line = "Failed password for invalid user admin from 111.233.45.67 port 52234 ssh2";
+        let event = crate::sshd::parse_line(line).unwrap();
+        message::insert_new_message_with_ssh(&conn, Option::None, ts("2022-03-11T23:45:32+01:00"), line, "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, false, Some(&event), Some("ssh"))?;
+
+        let msgs: Vec<message::Message> = message::select_message_by_ip(&conn, "111.233.456.678")?.into_iter().flatten().collect();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].ssh_user, None);
+        assert_eq!(msgs[1].ssh_user.as_deref(), Some("admin"));
+        assert_eq!((msgs[0].source.as_deref(), msgs[1].source.as_deref()), (None, Some("ssh")));
+        assert_eq!(msgs[1].ssh_port, Some(52234));
+        assert_eq!(msgs[1].ssh_method.as_deref(), Some("password"));
+        assert_eq!(msgs[1].ssh_outcome.as_deref(), Some("failed"));
+
+        // rewriting a message by id keeps every column
+        message::insert_new_message_with_ssh(&conn, Some(msgs[1].id), ts("2022-03-11T23:45:32+01:00"), line, "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, true, Some(&event), Some("ssh"))?;
+        let rewritten: Vec<message::Message> = message::select_message_by_ip(&conn, "111.233.456.678")?.into_iter().flatten().collect();
+        assert_eq!(rewritten.len(), 2);
+        assert_eq!(rewritten[1], message::Message { is_ban: true, ..msgs[1].clone() });
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_db_asn_and_prefix() -> Result<()>{
+        let db_name ="test_asn.db";
+        let conn = Connection::open(db_name)?;
+        // ipmeta as created before the network columns existed
+        conn.execute("CREATE TABLE ipmeta(
+            ip TEXT NOT NULL PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            lon TEXT NOT NULL,
+            lat TEXT NOT NULL,
+            isp TEXT NOT NULL,
+            city TEXT NOT NULL,
+            region TEXT,
+            country TEXT NOT NULL,
+            countrycode TEXT,
+            banned_times INTEGER NOT NULL,
+            is_banned INTEGER NOT NULL,
+            warnings INTEGER NOT NULL
+        )", [])?;
+        schema::create_tables(&conn)?;
+        insert_all(&conn)?;
+        assert_eq!(ip::select_ip(&conn, "111.233.456.678")?.unwrap().asn, None);
+
+        let mut x = ip::select_ip(&conn, "111.233.456.678")?.unwrap();
+        x.asn = Some(64496);
+        x.as_org = "Telecum AS".to_string();
+        x.prefix = "111.233.0.0/16".to_string();
+        let meta = update_db_on_new_log(&conn, x.clone());
+        update_ip_db_on_new_log(&conn, x.clone(), false, true);
+        // counted once the message is stored
+        assert_eq!(meta.asn.as_ref().map(|asn| (asn.number, asn.warnings, asn.banned)), Some((64496, 0, 0)));
+        assert_eq!(meta.prefix.as_ref().map(|prefix| (prefix.asn, prefix.warnings)), Some((Some(64496), 0)));
+        message::insert_new_message(&conn, None, ts("2022-03-12T08:00:00+01:00"), "Ban 111.233.456.678", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", false, true)?;
+        // the message from before the AS was known moved over with the IP
+        let counted = asn::select_asn(&conn, 64496)?.unwrap();
+        assert_eq!((counted.warnings, counted.banned), (2, 1));
+
+        let stored = ip::select_ip(&conn, "111.233.456.678")?.unwrap();
+        assert_eq!((stored.asn, stored.as_org.as_str(), stored.prefix.as_str()), (Some(64496), "Telecum AS", "111.233.0.0/16"));
+        assert!(stored.is_banned);
+
+        asn::insert_new_ASN(&conn, 64496, "Telecum AS", Some(1), Some(1), "Doitschland", true)?;
+        let meta = update_db_on_new_log(&conn, x);
+        assert!(meta.asn.unwrap().is_blocked);
+        assert!(!meta.prefix.unwrap().is_blocked);
+        assert_eq!(asn::get_all_asns(&conn)?.len(), 1);
+        assert_eq!(prefix::get_all_prefixes(&conn)?[0].warnings, 2);
+
+        assert_eq!(message::get_message_timestamps_by_asn(&conn, 64496)?.len(), 2);
+        assert!(message::get_message_timestamps_by_asn(&conn, 64497)?.is_empty());
+
+        cleanup_db(db_name);
+        Ok(())
+    }
 
+    #[test]
+    pub fn test_db_refresh_location() -> Result<()>{
+        let db_name ="test_refresh_location.db";
+        let conn = Connection::open(db_name)?;
+        schema::create_tables(&conn)?;
+        insert_all(&conn)?;
+        let before = ip::select_ip(&conn, "111.233.456.678")?.unwrap();
+        // located before the lookup time was stored
+        assert_eq!(before.geo_updated_at, None);
+
+        let mut fresh = before.clone();
+        fresh.geo_updated_at = Some(ts("2023-11-19T12:00:00+00:00"));
+        ip::set_network(&conn, &fresh)?;
+        assert_eq!(ip::select_stale(&conn, ts("2023-11-20T00:00:00+00:00"), 10)?, vec!["111.233.456.678".to_string()]);
+        assert!(ip::select_stale(&conn, ts("2023-11-19T11:00:00+00:00"), 10)?.is_empty());
+        let plan: String = conn.query_row(
+            "EXPLAIN QUERY PLAN SELECT ip FROM ipmeta WHERE COALESCE(geo_updated_at, created_at) < 0 ORDER BY COALESCE(geo_updated_at, created_at) LIMIT 1",
+            [],
+            |row| row.get(3),
+        )?;
+        assert!(plan.contains("ipmeta_geo_updated_at"), "{}", plan);
+
+        // same location only moves the lookup time
+        fresh.geo_updated_at = Some(ts("2024-03-01T12:00:00+00:00"));
+        assert_eq!(schema::refresh_ip_location(&conn, &fresh)?, None);
+        assert!(ip_history::select_location_history(&conn, "111.233.456.678")?.is_empty());
+
+        let mut moved = fresh.clone();
+        moved.city = "Bremen".to_string();
+        moved.lat = Some(53.08);
+        moved.geo_updated_at = Some(ts("2024-06-01T12:00:00+00:00"));
+        let former = schema::refresh_ip_location(&conn, &moved)?.unwrap();
+        assert_eq!((former.city.as_str(), former.valid_from, former.valid_until), ("Humburg", ts("2024-03-01T12:00:00+00:00"), ts("2024-06-01T12:00:00+00:00")));
+        assert_eq!(ip_history::select_location_history(&conn, "111.233.456.678")?, vec![former]);
+
+        let after = ip::select_ip(&conn, "111.233.456.678")?.unwrap();
+        assert_eq!((after.city.as_str(), after.geo_updated_at), ("Bremen", Some(ts("2024-06-01T12:00:00+00:00"))));
+        assert_eq!((after.warnings, after.created_at), (before.warnings, before.created_at));
+        // messages keep the location they were logged with
+        assert_eq!(message::get_message_timestamps_by_city(&conn, "Humburg")?.len(), 1);
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_db_geo_checked() -> Result<()>{
+        let db_name ="test_geo_checked.db";
+        let conn = Connection::open(db_name)?;
+        schema::create_tables(&conn)?;
+        insert_all(&conn)?;
+        let ttl = chrono::Duration::days(30);
+        let before = chrono::offset::Local::now() - ttl;
+        assert_eq!(ip::select_stale(&conn, before, 10)?, vec!["111.233.456.678".to_string()]);
+
+        // a re-lookup that found nothing waits a full ttl, the location is kept
+        ip::set_geo_checked(&conn, "111.233.456.678", chrono::offset::Local::now())?;
+        assert!(ip::select_stale(&conn, before, 10)?.is_empty());
+        let kept = ip::select_ip(&conn, "111.233.456.678")?.unwrap();
+        assert_eq!((kept.city.as_str(), kept.lat), ("Humburg", Some(59.79)));
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_db_rdns() -> Result<()>{
+        let db_name ="test_rdns.db";
+        let conn = Connection::open(db_name)?;
+        schema::create_tables(&conn)?;
+        insert_all(&conn)?;
+        assert_eq!(ip::select_ip(&conn, "111.233.456.678")?.unwrap().rdns, None);
+
+        let found = rdns::ReverseDns { hostname: Some("mail.example.net".to_string()), confirmed: true, looked_up_at: ts("2023-11-19T12:00:00+00:00") };
+        rdns::insert_rdns(&conn, "111.233.456.678", &found)?;
+        assert_eq!(ip::select_ip(&conn, "111.233.456.678")?.unwrap().rdns, Some(found));
+
+        // a later lookup replaces the cached one
+        let gone = rdns::ReverseDns { hostname: None, confirmed: false, looked_up_at: ts("2023-11-20T12:00:00+00:00") };
+        rdns::insert_rdns(&conn, "111.233.456.678", &gone)?;
+        assert_eq!(rdns::select_rdns(&conn, "111.233.456.678")?, Some(gone));
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_db_backfill_mark() -> Result<()>{
+        let db_name ="test_backfill_mark.db";
+        let conn = Connection::open(db_name)?;
+        schema::create_tables(&conn)?;
+        assert_eq!(message::select_last_timestamp(&conn, Some("ssh"))?, None);
+        insert_all(&conn)?;
+        // stored before messages kept their source, counts for every source
+        assert_eq!(message::select_last_timestamp(&conn, Some("nginx"))?, Some(ts("2022-03-11T23:45:31+01:00")));
+        message::insert_new_message_with_ssh(&conn, Option::None, ts("2023-11-19T12:00:00+01:00"), "newer journal line", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, false, None, Some("ssh"))?;
+        // later, though it sorts first as text
+        message::insert_new_message_with_ssh(&conn, Option::None, ts("2023-11-19T11:30:00+00:00"), "newest journal line", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, false, None, Some("ssh"))?;
+        message::insert_new_message_with_ssh(&conn, Option::None, ts("2023-11-20T08:00:00+01:00"), "nginx line", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, false, None, Some("nginx"))?;
+        message::insert_new_message(&conn, Option::None, ts("2023-11-19T13:00:00+01:00"), "fail2ban line", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", false, true)?;
+
+        // each journal source continues from its own newest line
+        assert_eq!(message::select_last_timestamp(&conn, Some("ssh"))?, Some(ts("2023-11-19T11:30:00+00:00")));
+        assert_eq!(message::select_last_timestamp(&conn, Some("nginx"))?, Some(ts("2023-11-20T08:00:00+01:00")));
+        assert_eq!(message::select_last_timestamp(&conn, Some("postfix"))?, Some(ts("2022-03-11T23:45:31+01:00")));
+        assert_eq!(message::select_last_timestamp(&conn, None)?, Some(ts("2023-11-19T13:00:00+01:00")));
+        assert!(message::contains_text(&conn, "fail2ban line")?);
+        assert!(!message::contains_text(&conn, "fail2ban")?);
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+    /// Every counter, in the order of the counted tables.
+    fn counters(conn: &Connection) -> Result<Vec<(i64, i64)>> {
+        let mut all = vec![];
+        for (table, warnings, banned, _) in schema::COUNTERS {
+            let mut stmt = conn.prepare(&format!("SELECT {}, {} FROM {} ORDER BY rowid", warnings, banned, table))?;
+            all.extend(stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<Vec<_>>>()?);
+        }
+        Ok(all)
+    }
+
+    #[test]
+    pub fn test_db_recompute_stats() -> Result<()>{
+        let db_name ="test_recompute.db";
+        let conn = Connection::open(db_name)?;
+        schema::create_tables(&conn)?;
+        insert_all(&conn)?;
+
+        let mut x = ip::select_ip(&conn, "111.233.456.678")?.unwrap();
+        x.asn = Some(64496);
+        x.prefix = "111.233.0.0/16".to_string();
+        // a replayed line is stored once, however often the IP is looked at
+        for _ in 0..2 {
+            update_db_on_new_log(&conn, x.clone());
+            update_ip_db_on_new_log(&conn, x.clone(), false, true);
+        }
+        message::insert_new_message(&conn, None, ts("2022-03-12T08:00:00+01:00"), "Ban 111.233.456.678", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", false, true)?;
+        message::insert_new_message(&conn, Some(10), ts("2022-03-12T09:00:00+01:00"), "first take", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, true)?;
+        message::insert_new_message(&conn, Some(10), ts("2022-03-12T09:00:00+01:00"), "second take", "111.233.456.678", "Doitschland", "Undetussen", "Humburg", "Telecum", true, false)?;
+        conn.execute("DELETE FROM messages WHERE text = 'OMG SUCH A MESSAGE'", [])?;
+
+        // the triggers counted what GROUP BY counts
+        let grouped: (i64, i64) = conn.query_row("SELECT COUNT(*), SUM(is_ban) FROM messages GROUP BY country", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let country = country::select_country(&conn, "Doitschland")?.unwrap();
+        assert_eq!(grouped, (country.warnings as i64, country.banned as i64));
+        assert_eq!(grouped, (2, 1));
+        let kept = counters(&conn)?;
+        assert_eq!(schema::recompute_stats(&conn)?, 0);
+        assert_eq!(counters(&conn)?, kept);
+
+        // rewritten the way counters used to be
+        country::insert_new_country(&conn, "Doitschland", Some("DE"), Some(3), Some(7), false)?;
+        conn.execute("UPDATE ipmeta SET warnings = 0", [])?;
+        assert_eq!(schema::recompute_stats(&conn)?, 2);
+        assert_eq!(counters(&conn)?, kept);
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_db_ban_events() -> Result<()>{
+        let db_name ="test_ban_events.db";
+        let conn = Connection::open(db_name)?;
+        schema::create_tables(&conn)?;
+
+        let line = "2023-11-19 16:08:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Restore Ban 111.233.456.678";
+        let restored = crate::fail2ban::parse_line(line).and_then(|event| ban_event::BanEvent::from_f2b(&event, "111.233.456.678", ts("2023-11-19T16:08:12+01:00"))).unwrap();
+        assert_eq!((restored.action, restored.jail.as_deref(), restored.reason.as_deref()), (ban_event::BanAction::Ban, Some("sshd"), Some("restored on restart")));
+        let found = "2023-11-19 16:08:10,000 fail2ban.filter         [804]: INFO    [sshd] Found 111.233.456.678";
+        assert!(crate::fail2ban::parse_line(found).and_then(|event| ban_event::BanEvent::from_f2b(&event, "111.233.456.678", ts("2023-11-19T16:08:10+01:00"))).is_none());
+
+        // IPs banned by hand need not have logged anything
+        let blocked = ban_event::BanEvent {
+            ip: "111.233.456.678".to_string(),
+            jail: Some("sshd".to_string()),
+            action: ban_event::BanAction::Unban,
+            source: ban_event::BanSource::Manual,
+            reason: None,
+            timestamp: ts("2023-11-19T17:00:00+01:00"),
+            duration: None,
+        };
+        ban_event::insert_ban_event(&conn, &blocked)?;
+        let geo = ban_event::BanEvent { action: ban_event::BanAction::Ban, source: ban_event::BanSource::GeoBlock, reason: Some("Country: Doitschland".to_string()), timestamp: ts("2023-11-19T18:00:00+01:00"), duration: Some(600), ..blocked.clone() };
+        ban_event::insert_ban_event(&conn, &geo)?;
+        ban_event::insert_ban_event(&conn, &restored)?;
+
+        assert_eq!(ban_event::select_ban_events_by_ip(&conn, "111.233.456.678")?, vec![restored.clone(), blocked.clone(), geo.clone()]);
+        assert!(ban_event::select_ban_events_by_ip(&conn, "111.233.456.679")?.is_empty());
+
+        // fail2ban logs the bans and unbans done from the TUI too, they stay one event
+        let logged = |line: &str, timestamp: &str| {
+            let timestamp = ts(timestamp);
+            crate::fail2ban::parse_line(line).and_then(|event| ban_event::BanEvent::from_f2b(&event, "111.233.456.678", timestamp)).unwrap()
+        };
+        ban_event::record_ban_event(&conn, &logged("2023-11-19 18:00:01,120 fail2ban.actions        [804]: NOTICE  [sshd] Ban 111.233.456.678", "2023-11-19T18:00:01+01:00"))?;
+        ban_event::record_ban_event(&conn, &logged("2023-11-19 17:00:00,512 fail2ban.actions        [804]: NOTICE  [sshd] Unban 111.233.456.678", "2023-11-19T17:00:00+01:00"))?;
+        assert_eq!(ban_event::select_ban_events_by_ip(&conn, "111.233.456.678")?, vec![restored.clone(), blocked.clone(), geo.clone()]);
+        // even if the log line is stored before the TUI's record
+        let unbanned = logged("2023-11-19 19:00:02,000 fail2ban.actions        [804]: NOTICE  [sshd] Unban 111.233.456.678", "2023-11-19T19:00:02+01:00");
+        ban_event::record_ban_event(&conn, &unbanned)?;
+        ban_event::record_ban_event(&conn, &ban_event::BanEvent { timestamp: ts("2023-11-19T19:00:00+01:00"), ..blocked.clone() })?;
+        let manual = ban_event::BanEvent { source: ban_event::BanSource::Manual, ..unbanned };
+        // a ban of its own is kept
+        let rebanned = logged("2023-11-19 20:00:00,000 fail2ban.actions        [804]: NOTICE  [sshd] Ban 111.233.456.678", "2023-11-19T20:00:00+01:00");
+        ban_event::record_ban_event(&conn, &rebanned)?;
+        assert_eq!(ban_event::select_ban_events_by_ip(&conn, "111.233.456.678")?, vec![restored.clone(), blocked.clone(), geo.clone(), manual.clone(), rebanned.clone()]);
+
+        // events are ordered by when they happened, whatever offset they were logged with
+        let utc = ban_event::BanEvent { timestamp: ts("2023-11-19T19:30:00+00:00"), ..rebanned.clone() };
+        ban_event::insert_ban_event(&conn, &utc)?;
+        let east = ban_event::BanEvent { timestamp: ts("2023-11-20T04:00:00+09:00"), ..rebanned.clone() };
+        ban_event::insert_ban_event(&conn, &east)?;
+        assert_eq!(ban_event::select_ban_events_by_ip(&conn, "111.233.456.678")?, vec![restored, blocked, geo, manual, rebanned, east, utc]);
+
+        cleanup_db(db_name);
+        Ok(())
+    }
+
+}
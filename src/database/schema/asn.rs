@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result, Row};
+
+
+/// An autonomous system, counted like an ISP but keyed by its number.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct ASN {
+    pub number: u32,
+    pub name: String,
+    pub banned: usize,
+    pub warnings: usize,
+    pub country: String,
+    pub is_blocked: bool,
+}
+fn from_row(row: &Row) -> Result<ASN> {
+    Ok( ASN {
+        number: row.get(0)?,
+        name: row.get(1)?,
+        banned: row.get(2)?,
+        warnings: row.get(3)?,
+        country: row.get(4)?,
+        is_blocked: row.get(5)?,
+    })
+}
+
+#[allow(non_snake_case)]
+pub fn insert_new_ASN(conn: &Connection, number: u32, name: &str, num_banned:Option<usize>, num_messages:Option<usize>, country:&str, is_blocked: bool) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO asn (number, name, banned, messages, country, is_blocked) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (number, name, num_banned.unwrap_or(0), num_messages.unwrap_or(0), country, is_blocked),
+    )?;
+    Ok(())
+}
+
+pub fn select_asn(conn: &Connection, number: u32) -> Result<Option<ASN>> {
+    let mut stmt = conn.prepare(
+        "SELECT number, name, banned, messages, country, is_blocked FROM asn WHERE number=?1;"
+    )?;
+    let mut rows = stmt.query_map([number], from_row)?;
+    rows.next().transpose()
+}
+
+pub fn get_all_asns(conn: &Connection) -> Result<Vec<ASN>> {
+    let mut stmt = conn.prepare(
+        "SELECT number, name, banned, messages, country, is_blocked FROM asn"
+    )?;
+    let asns = stmt.query_map([], from_row)?.flatten().collect();
+    Ok(asns)
+}
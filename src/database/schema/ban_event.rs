@@ -0,0 +1,153 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result};
+
+use crate::fail2ban::{F2BEvent, F2BEventKind};
+
+/// Seconds between a ban or unban done from the TUI and fail2ban logging it.
+const LOGGED_WITHIN_SECS: i64 = 10;
+
+/// Whether the IP was banned or unbanned.
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BanAction {
+    #[default]
+    Ban,
+    Unban,
+}
+
+impl BanAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BanAction::Ban => "ban",
+            BanAction::Unban => "unban",
+        }
+    }
+
+    fn parse(x: &str) -> Self {
+        if x == "unban" {BanAction::Unban} else {BanAction::Ban}
+    }
+}
+
+/// Who banned or unbanned the IP.
+#[derive(Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BanSource {
+    /// fail2ban itself, as read from its log.
+    #[default]
+    Fail2Ban,
+    /// By hand from the TUI.
+    Manual,
+    /// Automatically, for coming from a blocked location.
+    GeoBlock,
+}
+
+impl BanSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BanSource::Fail2Ban => "fail2ban",
+            BanSource::Manual => "manual",
+            BanSource::GeoBlock => "geo-block",
+        }
+    }
+
+    fn parse(x: &str) -> Self {
+        match x {
+            "manual" => BanSource::Manual,
+            "geo-block" => BanSource::GeoBlock,
+            _ => BanSource::Fail2Ban,
+        }
+    }
+}
+
+/// A ban or unban of an IP.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct BanEvent {
+    pub ip: String,
+    /// `None` if the line didn't name the jail.
+    pub jail: Option<String>,
+    pub action: BanAction,
+    pub source: BanSource,
+    /// Why it was banned, e.g. the blocked locations of a geo-block.
+    pub reason: Option<String>,
+    pub timestamp: DateTime<Local>,
+    /// Ban time of the jail in seconds, `None` if it isn't known.
+    pub duration: Option<i64>,
+}
+
+impl BanEvent {
+    /// The ban or unban a fail2ban.log line reports about `ip`, `None` for other lines.
+    pub fn from_f2b(event: &F2BEvent, ip: &str, timestamp: DateTime<Local>) -> Option<Self> {
+        let (action, reason) = match event.kind {
+            F2BEventKind::Ban => (BanAction::Ban, None),
+            F2BEventKind::RestoreBan => (BanAction::Ban, Some(String::from("restored on restart"))),
+            F2BEventKind::Unban => (BanAction::Unban, None),
+            _ => return None,
+        };
+        Some(BanEvent {
+            ip: ip.to_string(),
+            jail: event.jail.clone(),
+            action,
+            source: BanSource::Fail2Ban,
+            reason,
+            timestamp,
+            duration: None,
+        })
+    }
+}
+
+pub fn insert_ban_event(conn: &Connection, x: &BanEvent) -> Result<()> {
+    conn.execute(
+        "INSERT INTO ban_events (ip, jail, action, source, reason, timestamp, duration) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (&x.ip, &x.jail, x.action.as_str(), x.source.as_str(), &x.reason, x.timestamp.timestamp(), x.duration),
+    )?;
+    Ok(())
+}
+
+/// Stores a ban or unban once. fail2ban logs the bans and unbans done from the TUI as well, the log line and
+/// the TUI's own record of the same action become one event with the TUI's source and reason.
+pub fn record_ban_event(conn: &Connection, x: &BanEvent) -> Result<()> {
+    let same = "ip = ?1 AND jail IS ?2 AND action = ?3 AND abs(timestamp - ?4) <= ?5";
+    let params = (&x.ip, &x.jail, x.action.as_str(), x.timestamp.timestamp(), LOGGED_WITHIN_SECS);
+    if x.source == BanSource::Fail2Ban {
+        let recorded: bool = conn.query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM ban_events WHERE {} AND source != 'fail2ban')", same),
+            params,
+            |row| row.get(0),
+        )?;
+        return if recorded { Ok(()) } else { insert_ban_event(conn, x) };
+    }
+    // the log line may have been stored first
+    let logged: Option<i64> = conn.query_row(
+        &format!("SELECT MAX(id) FROM ban_events WHERE {} AND source = 'fail2ban'", same),
+        params,
+        |row| row.get(0),
+    )?;
+    match logged {
+        Some(id) => {
+            conn.execute(
+                "UPDATE ban_events SET source = ?2, reason = ?3, duration = COALESCE(?4, duration) WHERE id = ?1",
+                (id, x.source.as_str(), &x.reason, x.duration),
+            )?;
+            Ok(())
+        },
+        None => insert_ban_event(conn, x),
+    }
+}
+
+/// Bans and unbans of an IP, oldest first.
+pub fn select_ban_events_by_ip(conn: &Connection, ip: &str) -> Result<Vec<BanEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT ip, jail, action, source, reason, timestamp, duration FROM ban_events WHERE ip=?1 ORDER BY timestamp, id;"
+    )?;
+    let events = stmt.query_map([ip], |row| {
+        Ok(BanEvent {
+            ip: row.get(0)?,
+            jail: row.get(1)?,
+            action: BanAction::parse(&row.get::<_, String>(2)?),
+            source: BanSource::parse(&row.get::<_, String>(3)?),
+            reason: row.get(4)?,
+            timestamp: super::timestamp(row, 5)?,
+            duration: row.get(6)?,
+        })
+    })?;
+    events.collect()
+}
@@ -13,15 +13,6 @@ pub struct City {
     pub country: String,
     pub is_blocked: bool,
 }
-pub const CREATE_CITY_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS city(
-    name TEXT NOT NULL PRIMARY KEY,
-    banned INTEGER,
-    warnings INTEGER,
-    region TEXT REFERENCES region(name),
-    country TEXT NOT NULL REFERENCES country(name),
-    is_blocked INTEGER NOT NULL
-)
-";
 pub fn insert_new_city(conn: &Connection, name: &str, country: &str, region:&str, num_banned:Option<usize>, num_messages:Option<usize>, is_blocked:bool) -> Result<()> {
     let _banned = num_banned.unwrap_or(0);
     let _msgs = num_messages.unwrap_or(0);
@@ -12,15 +12,6 @@ pub struct Country {
     pub is_blocked: bool,
 }
 
-pub const CREATE_COUNTRY_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS country(
-    name TEXT NOT NULL PRIMARY KEY,
-    code TEXT,
-    banned INTEGER,
-    warnings INTEGER,
-    is_blocked INTEGER NOT NULL
-)
-";
-
 pub fn insert_new_country(conn: &Connection, name: &str, code:Option<&str>, num_banned:Option<usize>, num_messages:Option<usize>, is_blocked: bool) -> Result<()> {
     let _code = code.unwrap_or("");
     let _banned = num_banned.unwrap_or(0);
@@ -1,16 +1,22 @@
 
 
 use std::default;
+use std::net::IpAddr;
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use rusqlite::{params, Connection, Result};
 
+use super::rdns::ReverseDns;
 
-#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Debug)]
 pub struct IP {
     pub ip: String,
-    pub created_at: String,
-    pub lon: String,
-    pub lat: String,
+    /// Stored as seconds since the UNIX epoch.
+    pub created_at: DateTime<Local>,
+    /// `None` if the lookup couldn't place the address.
+    pub lon: Option<f64>,
+    pub lat: Option<f64>,
     pub isp: String,
     pub city: String,
     pub region: String,
@@ -19,29 +25,87 @@ pub struct IP {
     pub banned_times: usize,
     pub is_banned: bool,
     pub warnings: usize,
+    /// Jails the IP is currently banned in, not persisted, filled from fail2ban.
+    #[serde(default)]
+    pub jails: Vec<String>,
+    /// Typed address, `ip` holds its canonical text form which is used as key in the db.
+    #[serde(default)]
+    pub addr: Option<IpAddr>,
+    /// Autonomous system announcing the address, if the provider knows it.
+    #[serde(default)]
+    pub asn: Option<u32>,
+    /// Organisation running the autonomous system.
+    #[serde(default)]
+    pub as_org: String,
+    /// Announced network the address is in, e.g. `192.0.2.0/24`, empty if unknown.
+    #[serde(default)]
+    pub prefix: String,
+    /// When the location was last looked up, the record is stale once it's older than `geo.ttl_days`.
+    /// `None` for records from before lookups were dated, they count from `created_at`.
+    #[serde(default)]
+    pub geo_updated_at: Option<DateTime<Local>>,
+    /// Cached reverse lookup, kept in its own table so rewriting the record doesn't drop it.
+    #[serde(default)]
+    pub rdns: Option<ReverseDns>,
+}
+
+impl IP {
+    /// Empty record for the address, to be filled by a geo lookup.
+    pub fn from_addr(addr: IpAddr) -> Self {
+        IP { ip: addr.to_string(), addr: Some(addr), ..Default::default() }
+    }
+
+    /// Latitude and longitude, `None` if the lookup couldn't place the address.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        Some((self.lat?, self.lon?))
+    }
+}
+/// Stores the AS number, organisation, prefix and lookup time of an IP that is already in ipmeta.
+pub fn set_network(conn: &Connection, x: &IP) -> Result<()> {
+    conn.execute(
+        "UPDATE ipmeta SET asn = ?2, as_org = ?3, prefix = ?4, geo_updated_at = ?5 WHERE ip = ?1",
+        (&x.ip, x.asn, (!x.as_org.is_empty()).then_some(&x.as_org), (!x.prefix.is_empty()).then_some(&x.prefix), x.geo_updated_at.map(|at| at.timestamp())),
+    )?;
+    Ok(())
+}
+
+/// Replaces the location of an IP with a fresh lookup, its counters are kept.
+pub fn set_location(conn: &Connection, x: &IP) -> Result<()> {
+    conn.execute(
+        "UPDATE ipmeta SET lon = ?2, lat = ?3, isp = ?4, city = ?5, region = ?6, country = ?7, countrycode = ?8 WHERE ip = ?1",
+        (&x.ip, x.lon, x.lat, &x.isp, &x.city, &x.region, &x.country, &x.countrycode),
+    )?;
+    set_network(conn, x)
+}
+
+/// Marks the location of an IP as looked up at `at` and kept, for re-lookups that found nothing.
+pub fn set_geo_checked(conn: &Connection, ip: &str, at: DateTime<Local>) -> Result<()> {
+    conn.execute("UPDATE ipmeta SET geo_updated_at = ?2 WHERE ip = ?1", (ip, at.timestamp()))?;
+    Ok(())
+}
+
+/// Whether fail2ban bans the IP right now.
+pub fn set_banned(conn: &Connection, ip: &str, is_banned: bool) -> Result<()> {
+    conn.execute("UPDATE ipmeta SET is_banned = ?2 WHERE ip = ?1", (ip, is_banned))?;
+    Ok(())
+}
+
+/// IPs whose location was looked up before `before`, oldest first.
+/// Records from before lookups were dated count from when they were created.
+pub fn select_stale(conn: &Connection, before: DateTime<Local>, limit: usize) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT ip FROM ipmeta WHERE COALESCE(geo_updated_at, created_at) < ?1 ORDER BY COALESCE(geo_updated_at, created_at) LIMIT ?2"
+    )?;
+    let stale = stmt.query_map(params![before.timestamp(), limit], |row| row.get(0))?;
+    stale.collect()
 }
-pub const CREATE_IP_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS ipmeta(
-    ip TEXT NOT NULL PRIMARY KEY,
-    created_at TEXT NOT NULL,
-    lon TEXT NOT NULL,
-    lat TEXT NOT NULL,
-    isp TEXT NOT NULL REFERENCES isp(name),
-    city TEXT NOT NULL REFERENCES city(name),
-    region TEXT REFERENCES region(name),
-    country TEXT NOT NULL REFERENCES country(name),
-    countrycode TEXT,
-    banned_times INTEGER NOT NULL,
-    is_banned INTEGER NOT NULL,
-    warnings INTEGER NOT NULL
-)
-";
 
 #[allow(non_snake_case)]
 pub fn insert_new_IP(conn: &Connection, 
     ip: &str, 
-    created_at: &str,
-    lon: &str,
-    lat: &str,
+    created_at: DateTime<Local>,
+    lon: Option<f64>,
+    lat: Option<f64>,
     isp: &str,
     city: &str,
     region: Option<&str>,
@@ -55,19 +119,22 @@ pub fn insert_new_IP(conn: &Connection,
     let _cc = countrycode.unwrap_or("");
     conn.execute(
         "INSERT OR REPLACE INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
-        (ip, created_at, lon, lat, isp, city, _region, country, _cc, num_banned, is_banned, num_warnings),
+        (ip, created_at.timestamp(), lon, lat, isp, city, _region, country, _cc, num_banned, is_banned, num_warnings),
     )?;
     Ok(())
 }
 
 pub fn select_ip(conn: &Connection, ip:&str) -> Result<Option<IP>> {
     let mut stmt = conn.prepare(
-        "SELECT * FROM ipmeta WHERE ip=:ip;"
+        "SELECT ipmeta.*, rdns.hostname, rdns.confirmed, rdns.looked_up_at FROM ipmeta LEFT JOIN rdns ON rdns.ip = ipmeta.ip WHERE ipmeta.ip=:ip;"
     )?;    
     let ip_iter = stmt.query_map(&[(":ip", ip)], |row| {
+        let ip: String = row.get(0)?;
+        let created_at = super::timestamp(row, 1)?;
         Ok( IP {
-            ip: row.get(0)?,
-            created_at: row.get(1)?,
+            addr: ip.parse().ok(),
+            ip,
+            created_at,
             lon: row.get(2)?,
             lat: row.get(3)?,
             isp: row.get(4)?,
@@ -78,6 +145,15 @@ pub fn select_ip(conn: &Connection, ip:&str) -> Result<Option<IP>> {
             banned_times: row.get(9)?,
             is_banned: row.get(10)?,
             warnings: row.get(11)?,
+            jails: vec![],
+            asn: row.get(12)?,
+            as_org: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
+            prefix: row.get::<_, Option<String>>(14)?.unwrap_or_default(),
+            geo_updated_at: super::optional_timestamp(row, 15)?,
+            rdns: match super::optional_timestamp(row, 18)? {
+                Some(looked_up_at) => Some(ReverseDns { hostname: row.get(16)?, confirmed: row.get(17)?, looked_up_at }),
+                None => None,
+            },
         })
     })?;
 
@@ -0,0 +1,79 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result};
+
+use super::ip::IP;
+
+/// Where an IP was located before a re-lookup moved it.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct LocationHistory {
+    pub ip: String,
+    pub lon: Option<f64>,
+    pub lat: Option<f64>,
+    pub isp: String,
+    pub city: String,
+    pub region: String,
+    pub country: String,
+    pub countrycode: String,
+    pub asn: Option<u32>,
+    pub as_org: String,
+    pub prefix: String,
+    /// When the location was looked up.
+    pub valid_from: DateTime<Local>,
+    /// When a re-lookup found a different one.
+    pub valid_until: DateTime<Local>,
+}
+
+impl LocationHistory {
+    /// The location of `old`, replaced at `valid_until`.
+    pub fn archive(old: &IP, valid_until: DateTime<Local>) -> Self {
+        LocationHistory {
+            ip: old.ip.clone(),
+            lon: old.lon,
+            lat: old.lat,
+            isp: old.isp.clone(),
+            city: old.city.clone(),
+            region: old.region.clone(),
+            country: old.country.clone(),
+            countrycode: old.countrycode.clone(),
+            asn: old.asn,
+            as_org: old.as_org.clone(),
+            prefix: old.prefix.clone(),
+            valid_from: old.geo_updated_at.unwrap_or(old.created_at),
+            valid_until,
+        }
+    }
+}
+
+pub fn insert_location_history(conn: &Connection, x: &LocationHistory) -> Result<()> {
+    conn.execute(
+        "INSERT INTO ipmeta_history (ip, lon, lat, isp, city, region, country, countrycode, asn, as_org, prefix, valid_from, valid_until) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        rusqlite::params![x.ip, x.lon, x.lat, x.isp, x.city, x.region, x.country, x.countrycode, x.asn, x.as_org, x.prefix, x.valid_from.timestamp(), x.valid_until.timestamp()],
+    )?;
+    Ok(())
+}
+
+/// Former locations of an IP, oldest first.
+pub fn select_location_history(conn: &Connection, ip: &str) -> Result<Vec<LocationHistory>> {
+    let mut stmt = conn.prepare(
+        "SELECT ip, lon, lat, isp, city, region, country, countrycode, asn, as_org, prefix, valid_from, valid_until FROM ipmeta_history WHERE ip=?1 ORDER BY id;"
+    )?;
+    let history = stmt.query_map([ip], |row| {
+        Ok(LocationHistory {
+            ip: row.get(0)?,
+            lon: row.get(1)?,
+            lat: row.get(2)?,
+            isp: row.get(3)?,
+            city: row.get(4)?,
+            region: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+            country: row.get(6)?,
+            countrycode: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+            asn: row.get(8)?,
+            as_org: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
+            prefix: row.get::<_, Option<String>>(10)?.unwrap_or_default(),
+            valid_from: super::timestamp(row, 11)?,
+            valid_until: super::timestamp(row, 12)?,
+        })
+    })?.flatten().collect();
+    Ok(history)
+}
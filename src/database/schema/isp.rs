@@ -11,15 +11,6 @@ pub struct ISP {
     pub country: String,
     pub is_blocked: bool,
 }
-pub const CREATE_ISP_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS isp(
-    name TEXT NOT NULL PRIMARY KEY,
-    banned INTEGER,
-    messages INTEGER,
-    country TEXT NOT NULL REFERENCES country(name),
-    is_blocked INTEGER NOT NULL
-
-)
-";
 #[allow(non_snake_case)]
 pub fn insert_new_ISP(conn: &Connection, name: &str, num_banned:Option<usize>, num_messages:Option<usize>, country:&str, is_blocked: bool) -> Result<()> {
     let _banned = num_banned.unwrap_or(0);
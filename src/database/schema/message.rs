@@ -1,18 +1,24 @@
 use std::default;
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use rusqlite::{params, Connection, Result};
 
+use crate::sshd::SshEvent;
+
 #[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
 pub struct MiniMessage {
     pub ip: String,
-    pub created_at: String,
+    pub created_at: DateTime<Local>,
+    /// Messages this stands for, more than one for a day of pruned messages.
+    pub count: usize,
 }
 
 
 #[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
 pub struct Message {
     pub id: usize,
-    pub created_at: String,
+    /// Stored as seconds since the UNIX epoch.
+    pub created_at: DateTime<Local>,
     pub text: String,
     pub ip: String,
     pub country: String,
@@ -21,38 +27,53 @@ pub struct Message {
     pub isp: String,
     pub is_jctl: bool,
     pub is_ban:bool,
+    // parsed sshd fields, empty for fail2ban lines
+    pub ssh_user: Option<String>,
+    pub ssh_port: Option<u16>,
+    pub ssh_method: Option<String>,
+    pub ssh_outcome: Option<String>,
+    /// Journal source that read the line, `None` for fail2ban lines and lines stored before sources were kept.
+    pub source: Option<String>,
 }
 
-pub const CREATE_MESSAGE_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS messages(
-    id INTEGER PRIMARY KEY,
-    created_at TEXT NOT NULL,
-    text TEXT NOT NULL,
-    ip TEXT NOT NULL REFERENCES ipmeta(ip),
-    country TEXT NOT NULL REFERENCES country(name),
-    region TEXT NOT NULL REFERENCES region(name),
-    city TEXT NOT NULL REFERENCES city(name),
-    isp TEXT NOT NULL REFERENCES isp(name),
-    is_jctl INTEGER NOT NULL,
-    is_ban INTEGER NOT NULL
-)
-";
-
-pub fn insert_new_message(conn: &Connection, id: Option<usize>, created_at:&str,  text:&str, ip:&str, country:&str, region:&str, city:&str, isp:&str, is_jctl:bool, is_ban:bool) -> Result<()> {
-    let _id = id.unwrap_or(0);
-    if _id == 0 {
-        conn.execute(
-            "INSERT OR REPLACE INTO messages (created_at, text, ip, country, region, city, isp, is_jctl, is_ban) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            (created_at, text, ip, country, region, city, isp, is_jctl, is_ban),
-        )?;
-    } else {
-        conn.execute(
-            "INSERT OR REPLACE INTO messages (id, created_at, text, ip, country, region, city, isp, is_jctl) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
-            (_id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban),
-        )?;       
-    }
+pub fn insert_new_message(conn: &Connection, id: Option<usize>, created_at: DateTime<Local>,  text:&str, ip:&str, country:&str, region:&str, city:&str, isp:&str, is_jctl:bool, is_ban:bool) -> Result<()> {
+    insert_new_message_with_ssh(conn, id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban, None, None)
+}
+
+/// Same as `insert_new_message`, also stores the fields of a parsed sshd line and the journal source of the line.
+#[allow(clippy::too_many_arguments)]
+pub fn insert_new_message_with_ssh(conn: &Connection, id: Option<usize>, created_at: DateTime<Local>,  text:&str, ip:&str, country:&str, region:&str, city:&str, isp:&str, is_jctl:bool, is_ban:bool, ssh: Option<&SshEvent>, source: Option<&str>) -> Result<()> {
+    // 0 leaves the id to the database, like no id
+    let id = id.filter(|id| *id != 0);
+    let ssh_user = ssh.and_then(|x| x.user.clone());
+    let ssh_port = ssh.and_then(|x| x.port);
+    let ssh_method = ssh.and_then(|x| x.method.clone());
+    let ssh_outcome = ssh.map(|x| x.outcome.to_string());
+    conn.execute(
+        "INSERT OR REPLACE INTO messages (id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban, ssh_user, ssh_port, ssh_method, ssh_outcome, source) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![id, created_at.timestamp(), text, ip, country, region, city, isp, is_jctl, is_ban, ssh_user, ssh_port, ssh_method, ssh_outcome, source],
+    )?;
 
     Ok(())
 }
+/// Timestamp of the newest message of a journal source, or of fail2ban's log for `None`, where a backfill continues from.
+/// Journal lines stored before messages kept their source count for every source.
+pub fn select_last_timestamp(conn: &Connection, source: Option<&str>) -> Result<Option<DateTime<Local>>> {
+    conn.query_row(
+        "SELECT MAX(created_at) FROM messages WHERE is_jctl = ?1 AND (source = ?2 OR source IS NULL);",
+        params![source.is_some(), source],
+        |row| match row.get::<_, Option<i64>>(0)? {
+            Some(_) => super::timestamp(row, 0).map(Some),
+            None => Ok(None),
+        },
+    )
+}
+
+/// True if the exact line was stored already, used to skip lines a backfill reads twice.
+pub fn contains_text(conn: &Connection, text: &str) -> Result<bool> {
+    conn.query_row("SELECT EXISTS(SELECT 1 FROM messages WHERE text = ?1);", [text], |row| row.get(0))
+}
+
 /// return all messages for a given ip
 pub fn select_message_by_ip(conn: &Connection, ip:&str) -> Result<Vec<Option<Message>>> {
     let mut stmt = conn.prepare(
@@ -61,7 +82,7 @@ pub fn select_message_by_ip(conn: &Connection, ip:&str) -> Result<Vec<Option<Mes
     let ip_iter = stmt.query_map(&[(":ip", ip)], |row| {
         Ok( Message {
             id: row.get(0)?,
-            created_at: row.get(1)?,
+            created_at: super::timestamp(row, 1)?,
             text: row.get(2)?,
             ip: row.get(3)?,
             country: row.get(4)?,
@@ -70,6 +91,11 @@ pub fn select_message_by_ip(conn: &Connection, ip:&str) -> Result<Vec<Option<Mes
             isp: row.get(7)?,       
             is_jctl: row.get(8)?,
             is_ban: row.get(9)?,
+            ssh_user: row.get(10)?,
+            ssh_port: row.get(11)?,
+            ssh_method: row.get(12)?,
+            ssh_outcome: row.get(13)?,
+            source: row.get(14)?,
         })
     })?;
 
@@ -89,90 +115,38 @@ pub fn select_message_by_ip(conn: &Connection, ip:&str) -> Result<Vec<Option<Mes
 
 /// returns message timestamps for country
 pub fn get_message_timestamps_by_country(conn: &Connection, country:&str) -> Result<Vec<MiniMessage>> {
-    let mut stmt = conn.prepare(
-        "SELECT created_at, ip FROM messages WHERE country=:country;"
-    )?;    
-    let country_iter = stmt.query_map(&[(":country", country)], |row| {
-        let mut message = Message::default();
-        message.created_at = row.get(0)?;
-        message.ip = row.get(1)?;
-        Ok(message)
-    })?;
-
-    let mut results: Vec<MiniMessage> = vec![];
-
-    for raip in country_iter {
-        let aip = raip.unwrap_or_default();
-        let min_msg: MiniMessage = MiniMessage { ip: aip.ip, created_at: aip.created_at };
-        results.push(min_msg);
-    }
-    Ok(results)   
+    select_timestamps(conn, "country=?1", &country)
 }
 
 /// returns message timestamps for region
 pub fn get_message_timestamps_by_region(conn: &Connection, region:&str) -> Result<Vec<MiniMessage>> {
-    let mut stmt = conn.prepare(
-        "SELECT created_at, ip FROM messages WHERE region=:region;"
-    )?;    
-    let region_iter = stmt.query_map(&[(":region", region)], |row| {
-        let mut message = Message::default();
-        message.created_at = row.get(0)?;
-        message.ip = row.get(1)?;
-        Ok(message)
-    })?;
-
-    let mut results: Vec<MiniMessage> = vec![];
-
-    for raip in region_iter {
-        let aip = raip.unwrap_or_default();
-        let min_msg: MiniMessage = MiniMessage { ip: aip.ip, created_at: aip.created_at };
-        results.push(min_msg);
-    }
-    Ok(results)   
+    select_timestamps(conn, "region=?1", &region)
 }
 
 /// returns message timestamps for city
 pub fn get_message_timestamps_by_city(conn: &Connection, city:&str) -> Result<Vec<MiniMessage>> {
-    let mut stmt = conn.prepare(
-        "SELECT created_at, ip FROM messages WHERE city=:city;"
-    )?;    
-    let city_iter = stmt.query_map(&[(":city", city)], |row| {
-        let mut message = Message::default();
-        message.created_at = row.get(0)?;
-        message.ip = row.get(1)?;
-        Ok(message)
-    })?;
-
-    let mut results: Vec<MiniMessage> = vec![];
-
-    for raip in city_iter {
-        let aip = raip.unwrap_or_default();
-        let min_msg: MiniMessage = MiniMessage { ip: aip.ip, created_at: aip.created_at };
-        results.push(min_msg);
-    }
-    Ok(results)   
+    select_timestamps(conn, "city=?1", &city)
 }
 
 /// returns message timestamps for isp
 pub fn get_message_timestamps_by_isp(conn: &Connection, isp:&str) -> Result<Vec<MiniMessage>> {
+    select_timestamps(conn, "isp=?1", &isp)
+}
+/// returns message timestamps for an autonomous system, the AS is known per IP
+pub fn get_message_timestamps_by_asn(conn: &Connection, asn: u32) -> Result<Vec<MiniMessage>> {
+    select_timestamps(conn, "ip IN (SELECT ip FROM ipmeta WHERE asn=?1)", &asn)
+}
 
-    let mut stmt = conn.prepare(
-        "SELECT created_at, ip FROM messages WHERE isp=:isp;"
-    )?;    
-    let isp_iter = stmt.query_map(&[(":isp", isp)], |row| {
-        let mut message = Message::default();
-        message.created_at = row.get(0)?;
-        message.ip = row.get(1)?;
-        Ok(message)
-    })?;
-
-    let mut results: Vec<MiniMessage> = vec![];
-
-    for raip in isp_iter {
-        let aip = raip.unwrap_or_default();
-        let min_msg: MiniMessage = MiniMessage { ip: aip.ip.clone(), created_at: aip.created_at.clone() };
-        let min_msg: MiniMessage = MiniMessage { ip: aip.ip.clone(), created_at: aip.created_at.clone() };
-        results.push(min_msg);
-    }
-    Ok(results)   
-}
\ No newline at end of file
+/// Timestamps of the messages matching `condition`, by IP.
+/// Pruned messages only have their count per day left, it comes back as one row at local noon of its day.
+fn select_timestamps(conn: &Connection, condition: &str, value: &dyn rusqlite::ToSql) -> Result<Vec<MiniMessage>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT created_at, ip, 1 FROM messages WHERE {0}
+        UNION ALL
+        SELECT unixepoch(day, '+12 hours', 'utc'), ip, warnings FROM messages_daily WHERE {0}
+        ORDER BY 2, 1;",
+        condition
+    ))?;
+    let rows = stmt.query_map([value], |row| Ok(MiniMessage { created_at: super::timestamp(row, 0)?, ip: row.get(1)?, count: row.get(2)? }))?;
+    rows.collect()
+}
@@ -0,0 +1,834 @@
+//! Versioned schema migrations.
+//! Every migration brings the database from the version before it to its own, the applied versions are
+//! recorded in `schema_version`. Databases from before the table existed are at version 0, the first
+//! migrations only add what is missing so they work on any of them.
+
+use rusqlite::{Connection, Result};
+
+use super::ban_event;
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    up: fn(&Connection) -> Result<()>,
+}
+
+/// All migrations, oldest first. Released migrations must never change, add a new one instead.
+pub const MIGRATIONS: [Migration; 12] = [
+    Migration { version: 1, description: "initial tables", up: initial_tables },
+    Migration { version: 2, description: "sshd fields on messages", up: sshd_fields },
+    Migration { version: 3, description: "AS and announced prefix", up: network },
+    Migration { version: 4, description: "location expiry and history", up: location_history },
+    Migration { version: 5, description: "reverse DNS cache", up: reverse_dns },
+    Migration { version: 6, description: "foreign keys of ipmeta networks and reverse DNS", up: network_foreign_keys },
+    Migration { version: 7, description: "indexes for lookups and stats", up: indexes },
+    Migration { version: 8, description: "stats counted by triggers on messages", up: message_counters },
+    Migration { version: 9, description: "ban and unban events", up: ban_events },
+    Migration { version: 10, description: "daily counts of pruned messages", up: daily_counts },
+    Migration { version: 11, description: "epoch timestamps and numeric coordinates", up: typed_columns },
+    Migration { version: 12, description: "journal source of messages", up: message_source },
+];
+
+/// Version of a database with every migration applied.
+pub const LATEST: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+const CREATE_SCHEMA_VERSION_SQL: &str = "CREATE TABLE IF NOT EXISTS schema_version(
+    version INTEGER NOT NULL PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+)
+";
+
+/// Version the database is at, 0 if no migration ran yet.
+pub fn current_version(conn: &Connection) -> Result<u32> {
+    conn.execute(CREATE_SCHEMA_VERSION_SQL, [])?;
+    conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))
+}
+
+/// Applies the migrations the database is missing, each in its own transaction.
+/// Returns the version the database was at before.
+pub fn migrate(conn: &Connection) -> Result<u32> {
+    let from = current_version(conn)?;
+    if from > LATEST {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+            Some(format!("database is at schema version {}, this build only knows up to {}", from, LATEST)),
+        ));
+    }
+    // tables are rebuilt to change their constraints, which must not touch the rows referencing them,
+    // migrations rebuilding a table check its keys themselves
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = MIGRATIONS.iter().filter(|migration| migration.version > from).try_for_each(|migration| apply(conn, migration));
+    // a message replaced by its id is deleted first, its counts only go away if delete triggers fire for that
+    conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA recursive_triggers = ON")?;
+    result.map(|_| from)
+}
+
+fn apply(conn: &Connection, migration: &Migration) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    (migration.up)(&tx)?;
+    tx.execute(
+        "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, ?3)",
+        (migration.version, migration.description, chrono::offset::Local::now().to_rfc3339()),
+    )?;
+    tx.commit()
+}
+
+/// Fails if rows of the table reference rows that don't exist.
+fn check_foreign_keys(conn: &Connection, table: &str) -> Result<()> {
+    if conn.prepare(&format!("PRAGMA foreign_key_check('{}')", table))?.query([])?.next()?.is_some() {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY),
+            Some(format!("rows of {} reference missing rows", table)),
+        ));
+    }
+    Ok(())
+}
+
+/// Adds a column unless the table has it, unversioned databases got some of them before migrations existed.
+fn add_column(conn: &Connection, table: &str, column: &str, sqltype: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}');", table))?;
+    let existing: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+    if !existing.iter().any(|name| name == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, sqltype), [])?;
+    }
+    Ok(())
+}
+
+fn initial_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS country(
+            name TEXT NOT NULL PRIMARY KEY,
+            code TEXT,
+            banned INTEGER,
+            warnings INTEGER,
+            is_blocked INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS city(
+            name TEXT NOT NULL PRIMARY KEY,
+            banned INTEGER,
+            warnings INTEGER,
+            region TEXT REFERENCES region(name),
+            country TEXT NOT NULL REFERENCES country(name),
+            is_blocked INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS region(
+            name TEXT NOT NULL PRIMARY KEY,
+            banned INTEGER,
+            warnings INTEGER,
+            country TEXT NOT NULL REFERENCES country(name),
+            is_blocked INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS isp(
+            name TEXT NOT NULL PRIMARY KEY,
+            banned INTEGER,
+            messages INTEGER,
+            country TEXT NOT NULL REFERENCES country(name),
+            is_blocked INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS ipmeta(
+            ip TEXT NOT NULL PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            lon TEXT NOT NULL,
+            lat TEXT NOT NULL,
+            isp TEXT NOT NULL REFERENCES isp(name),
+            city TEXT NOT NULL REFERENCES city(name),
+            region TEXT REFERENCES region(name),
+            country TEXT NOT NULL REFERENCES country(name),
+            countrycode TEXT,
+            banned_times INTEGER NOT NULL,
+            is_banned INTEGER NOT NULL,
+            warnings INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS messages(
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            text TEXT NOT NULL,
+            ip TEXT NOT NULL REFERENCES ipmeta(ip),
+            country TEXT NOT NULL REFERENCES country(name),
+            region TEXT NOT NULL REFERENCES region(name),
+            city TEXT NOT NULL REFERENCES city(name),
+            isp TEXT NOT NULL REFERENCES isp(name),
+            is_jctl INTEGER NOT NULL,
+            is_ban INTEGER NOT NULL
+        );",
+    )
+}
+
+fn sshd_fields(conn: &Connection) -> Result<()> {
+    add_column(conn, "messages", "ssh_user", "TEXT")?;
+    add_column(conn, "messages", "ssh_port", "INTEGER")?;
+    add_column(conn, "messages", "ssh_method", "TEXT")?;
+    add_column(conn, "messages", "ssh_outcome", "TEXT")
+}
+
+fn network(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS asn(
+            number INTEGER NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            banned INTEGER,
+            messages INTEGER,
+            country TEXT NOT NULL REFERENCES country(name),
+            is_blocked INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS prefix(
+            prefix TEXT NOT NULL PRIMARY KEY,
+            asn INTEGER REFERENCES asn(number),
+            banned INTEGER,
+            messages INTEGER,
+            is_blocked INTEGER NOT NULL
+        );",
+    )?;
+    add_column(conn, "ipmeta", "asn", "INTEGER")?;
+    add_column(conn, "ipmeta", "as_org", "TEXT")?;
+    add_column(conn, "ipmeta", "prefix", "TEXT")
+}
+
+fn location_history(conn: &Connection) -> Result<()> {
+    add_column(conn, "ipmeta", "geo_updated_at", "TEXT")?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ipmeta_history(
+            id INTEGER PRIMARY KEY,
+            ip TEXT NOT NULL REFERENCES ipmeta(ip),
+            lon TEXT NOT NULL,
+            lat TEXT NOT NULL,
+            isp TEXT NOT NULL,
+            city TEXT NOT NULL,
+            region TEXT,
+            country TEXT NOT NULL,
+            countrycode TEXT,
+            asn INTEGER,
+            as_org TEXT,
+            prefix TEXT,
+            valid_from TEXT NOT NULL,
+            valid_until TEXT NOT NULL
+        );",
+    )
+}
+
+fn reverse_dns(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS rdns(
+            ip TEXT NOT NULL PRIMARY KEY,
+            hostname TEXT,
+            confirmed INTEGER NOT NULL,
+            looked_up_at TEXT NOT NULL
+        );",
+    )
+}
+
+/// ipmeta's AS and prefix and the IP of reverse lookups become references, SQLite can only add those by
+/// rebuilding the tables. Networks only stored on the IP get their own rows, empty ones become NULL.
+fn network_foreign_keys(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "UPDATE ipmeta SET as_org = NULLIF(as_org, ''), prefix = NULLIF(prefix, '');
+        INSERT OR IGNORE INTO asn (number, name, banned, messages, country, is_blocked)
+            SELECT asn, COALESCE(MAX(as_org), ''), 0, 0, MAX(country), 0 FROM ipmeta WHERE asn IS NOT NULL GROUP BY asn;
+        INSERT OR IGNORE INTO prefix (prefix, asn, banned, messages, is_blocked)
+            SELECT prefix, MAX(asn), 0, 0, 0 FROM ipmeta WHERE prefix IS NOT NULL GROUP BY prefix;
+        CREATE TABLE ipmeta_new(
+            ip TEXT NOT NULL PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            lon TEXT NOT NULL,
+            lat TEXT NOT NULL,
+            isp TEXT NOT NULL REFERENCES isp(name),
+            city TEXT NOT NULL REFERENCES city(name),
+            region TEXT REFERENCES region(name),
+            country TEXT NOT NULL REFERENCES country(name),
+            countrycode TEXT,
+            banned_times INTEGER NOT NULL,
+            is_banned INTEGER NOT NULL,
+            warnings INTEGER NOT NULL,
+            asn INTEGER REFERENCES asn(number),
+            as_org TEXT,
+            prefix TEXT REFERENCES prefix(prefix),
+            geo_updated_at TEXT
+        );
+        INSERT INTO ipmeta_new (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings, asn, as_org, prefix, geo_updated_at)
+            SELECT ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings, asn, as_org, prefix, geo_updated_at FROM ipmeta;
+        DROP TABLE ipmeta;
+        ALTER TABLE ipmeta_new RENAME TO ipmeta;
+        CREATE TABLE rdns_new(
+            ip TEXT NOT NULL PRIMARY KEY REFERENCES ipmeta(ip),
+            hostname TEXT,
+            confirmed INTEGER NOT NULL,
+            looked_up_at TEXT NOT NULL
+        );
+        INSERT INTO rdns_new (ip, hostname, confirmed, looked_up_at)
+            SELECT ip, hostname, confirmed, looked_up_at FROM rdns WHERE ip IN (SELECT ip FROM ipmeta);
+        DROP TABLE rdns;
+        ALTER TABLE rdns_new RENAME TO rdns;",
+    )?;
+    check_foreign_keys(conn, "ipmeta")?;
+    check_foreign_keys(conn, "rdns")
+}
+
+fn indexes(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS messages_ip ON messages(ip);
+        CREATE INDEX IF NOT EXISTS messages_source_created_at ON messages(is_jctl, created_at);
+        CREATE INDEX IF NOT EXISTS messages_text ON messages(text);
+        CREATE INDEX IF NOT EXISTS messages_country ON messages(country);
+        CREATE INDEX IF NOT EXISTS messages_region ON messages(region);
+        CREATE INDEX IF NOT EXISTS messages_city ON messages(city);
+        CREATE INDEX IF NOT EXISTS messages_isp ON messages(isp);
+        CREATE INDEX IF NOT EXISTS ipmeta_asn ON ipmeta(asn);
+        CREATE INDEX IF NOT EXISTS ipmeta_prefix ON ipmeta(prefix);
+        CREATE INDEX IF NOT EXISTS ipmeta_history_ip ON ipmeta_history(ip);",
+    )
+}
+
+/// The warnings and bans of countries, regions, cities, ISPs, networks and IPs follow the messages table
+/// through triggers instead of being rewritten by the code storing a line. The counters written before
+/// had drifted, they are counted again once.
+fn message_counters(conn: &Connection) -> Result<()> {
+    // AS and prefix of a message are those of its IP, they move along when the IP's network changes
+    let count = |sign: char, row: &str| {
+        format!(
+            "UPDATE country SET warnings = COALESCE(warnings, 0) {sign} 1, banned = COALESCE(banned, 0) {sign} {row}.is_ban WHERE name = {row}.country;
+            UPDATE region SET warnings = COALESCE(warnings, 0) {sign} 1, banned = COALESCE(banned, 0) {sign} {row}.is_ban WHERE name = {row}.region;
+            UPDATE city SET warnings = COALESCE(warnings, 0) {sign} 1, banned = COALESCE(banned, 0) {sign} {row}.is_ban WHERE name = {row}.city;
+            UPDATE isp SET messages = COALESCE(messages, 0) {sign} 1, banned = COALESCE(banned, 0) {sign} {row}.is_ban WHERE name = {row}.isp;
+            UPDATE asn SET messages = COALESCE(messages, 0) {sign} 1, banned = COALESCE(banned, 0) {sign} {row}.is_ban
+                WHERE number = (SELECT asn FROM ipmeta WHERE ip = {row}.ip);
+            UPDATE prefix SET messages = COALESCE(messages, 0) {sign} 1, banned = COALESCE(banned, 0) {sign} {row}.is_ban
+                WHERE prefix = (SELECT prefix FROM ipmeta WHERE ip = {row}.ip);
+            UPDATE ipmeta SET warnings = warnings {sign} 1, banned_times = banned_times {sign} {row}.is_ban WHERE ip = {row}.ip;"
+        )
+    };
+    let recount = |table: &str, warnings: &str, banned: &str, source: &str| {
+        format!(
+            "UPDATE {table} SET ({warnings}, {banned}) = (SELECT COUNT(*), COALESCE(SUM(messages.is_ban), 0) {source});"
+        )
+    };
+    conn.execute_batch(&format!(
+        "CREATE TRIGGER messages_count AFTER INSERT ON messages BEGIN {} END;
+        CREATE TRIGGER messages_uncount AFTER DELETE ON messages BEGIN {} END;
+        CREATE TRIGGER messages_recount AFTER UPDATE OF ip, country, region, city, isp, is_ban ON messages BEGIN {} {} END;
+        CREATE TRIGGER ipmeta_network_recount AFTER UPDATE OF asn, prefix ON ipmeta BEGIN
+            UPDATE asn SET messages = messages - OLD.warnings, banned = banned - OLD.banned_times
+                WHERE number = OLD.asn AND OLD.asn IS NOT NEW.asn;
+            UPDATE asn SET messages = messages + NEW.warnings, banned = banned + NEW.banned_times
+                WHERE number = NEW.asn AND OLD.asn IS NOT NEW.asn;
+            UPDATE prefix SET messages = messages - OLD.warnings, banned = banned - OLD.banned_times
+                WHERE prefix = OLD.prefix AND OLD.prefix IS NOT NEW.prefix;
+            UPDATE prefix SET messages = messages + NEW.warnings, banned = banned + NEW.banned_times
+                WHERE prefix = NEW.prefix AND OLD.prefix IS NOT NEW.prefix;
+        END;
+        {}{}{}{}{}{}{}",
+        count('+', "NEW"),
+        count('-', "OLD"),
+        count('-', "OLD"),
+        count('+', "NEW"),
+        recount("country", "warnings", "banned", "FROM messages WHERE messages.country = country.name"),
+        recount("region", "warnings", "banned", "FROM messages WHERE messages.region = region.name"),
+        recount("city", "warnings", "banned", "FROM messages WHERE messages.city = city.name"),
+        recount("isp", "messages", "banned", "FROM messages WHERE messages.isp = isp.name"),
+        recount("asn", "messages", "banned", "FROM messages JOIN ipmeta ON ipmeta.ip = messages.ip WHERE ipmeta.asn = asn.number"),
+        recount("prefix", "messages", "banned", "FROM messages JOIN ipmeta ON ipmeta.ip = messages.ip WHERE ipmeta.prefix = prefix.prefix"),
+        recount("ipmeta", "warnings", "banned_times", "FROM messages WHERE messages.ip = ipmeta.ip"),
+    ))
+}
+
+/// Bans used to be a flag on messages only. IPs banned by hand need not be in ipmeta, so the IP is no reference.
+/// The fail2ban lines stored so far become the first events.
+fn ban_events(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE ban_events(
+            id INTEGER PRIMARY KEY,
+            ip TEXT NOT NULL,
+            jail TEXT,
+            action TEXT NOT NULL,
+            source TEXT NOT NULL,
+            reason TEXT,
+            timestamp TEXT NOT NULL,
+            duration INTEGER
+        );
+        CREATE INDEX ban_events_ip ON ban_events(ip, timestamp);",
+    )?;
+    let now = chrono::offset::Local::now();
+    let mut stmt = conn.prepare("SELECT ip, created_at, text FROM messages WHERE is_jctl = 0 ORDER BY id")?;
+    let lines: Vec<(String, String, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?.collect::<Result<_>>()?;
+    for (ip, created_at, text) in lines {
+        let created_at = chrono::DateTime::from_timestamp(epoch(&created_at, now.timestamp()), 0).map_or(now, |utc| utc.with_timezone(&chrono::offset::Local));
+        let event = crate::fail2ban::parse_line(&text).and_then(|event| ban_event::BanEvent::from_f2b(&event, &ip, created_at));
+        if let Some(event) = event {
+            ban_event::insert_ban_event(conn, &event)?;
+        }
+    }
+    Ok(())
+}
+
+/// Messages past the retention are rolled up into one row per day, IP and location. The rows count like
+/// the messages they replace, the triggers of `messages` take them off when the messages go.
+fn daily_counts(conn: &Connection) -> Result<()> {
+    let count = |sign: char, row: &str| {
+        format!(
+            "UPDATE country SET warnings = COALESCE(warnings, 0) {sign} {row}.warnings, banned = COALESCE(banned, 0) {sign} {row}.banned WHERE name = {row}.country;
+            UPDATE region SET warnings = COALESCE(warnings, 0) {sign} {row}.warnings, banned = COALESCE(banned, 0) {sign} {row}.banned WHERE name = {row}.region;
+            UPDATE city SET warnings = COALESCE(warnings, 0) {sign} {row}.warnings, banned = COALESCE(banned, 0) {sign} {row}.banned WHERE name = {row}.city;
+            UPDATE isp SET messages = COALESCE(messages, 0) {sign} {row}.warnings, banned = COALESCE(banned, 0) {sign} {row}.banned WHERE name = {row}.isp;
+            UPDATE asn SET messages = COALESCE(messages, 0) {sign} {row}.warnings, banned = COALESCE(banned, 0) {sign} {row}.banned
+                WHERE number = (SELECT asn FROM ipmeta WHERE ip = {row}.ip);
+            UPDATE prefix SET messages = COALESCE(messages, 0) {sign} {row}.warnings, banned = COALESCE(banned, 0) {sign} {row}.banned
+                WHERE prefix = (SELECT prefix FROM ipmeta WHERE ip = {row}.ip);
+            UPDATE ipmeta SET warnings = warnings {sign} {row}.warnings, banned_times = banned_times {sign} {row}.banned WHERE ip = {row}.ip;"
+        )
+    };
+    conn.execute_batch(&format!(
+        "CREATE TABLE messages_daily(
+            day TEXT NOT NULL,
+            ip TEXT NOT NULL REFERENCES ipmeta(ip),
+            country TEXT NOT NULL REFERENCES country(name),
+            region TEXT NOT NULL REFERENCES region(name),
+            city TEXT NOT NULL REFERENCES city(name),
+            isp TEXT NOT NULL REFERENCES isp(name),
+            warnings INTEGER NOT NULL,
+            banned INTEGER NOT NULL,
+            PRIMARY KEY (day, ip, country, region, city, isp)
+        );
+        CREATE INDEX messages_daily_ip ON messages_daily(ip);
+        CREATE INDEX messages_daily_country ON messages_daily(country);
+        CREATE INDEX messages_daily_region ON messages_daily(region);
+        CREATE INDEX messages_daily_city ON messages_daily(city);
+        CREATE INDEX messages_daily_isp ON messages_daily(isp);
+        CREATE TRIGGER messages_daily_count AFTER INSERT ON messages_daily BEGIN {} END;
+        CREATE TRIGGER messages_daily_uncount AFTER DELETE ON messages_daily BEGIN {} END;
+        CREATE TRIGGER messages_daily_recount AFTER UPDATE ON messages_daily BEGIN {} {} END;
+        CREATE TABLE maintenance(
+            task TEXT NOT NULL PRIMARY KEY,
+            last_run TEXT NOT NULL
+        );",
+        count('+', "NEW"),
+        count('-', "OLD"),
+        count('-', "OLD"),
+        count('+', "NEW"),
+    ))
+}
+
+/// Every timestamp becomes seconds since the UNIX epoch and coordinates become REAL, the column types can only
+/// change by rebuilding the tables. Text that doesn't parse is converted here once.
+fn typed_columns(conn: &Connection) -> Result<()> {
+    let now = chrono::offset::Local::now().timestamp();
+    rebuild(
+        conn,
+        "ipmeta",
+        "CREATE TABLE ipmeta_new(
+            ip TEXT NOT NULL PRIMARY KEY,
+            created_at INTEGER NOT NULL CHECK (typeof(created_at) = 'integer'),
+            lon REAL,
+            lat REAL,
+            isp TEXT NOT NULL REFERENCES isp(name),
+            city TEXT NOT NULL REFERENCES city(name),
+            region TEXT REFERENCES region(name),
+            country TEXT NOT NULL REFERENCES country(name),
+            countrycode TEXT,
+            banned_times INTEGER NOT NULL,
+            is_banned INTEGER NOT NULL,
+            warnings INTEGER NOT NULL,
+            asn INTEGER REFERENCES asn(number),
+            as_org TEXT,
+            prefix TEXT REFERENCES prefix(prefix),
+            geo_updated_at INTEGER CHECK (geo_updated_at IS NULL OR typeof(geo_updated_at) = 'integer')
+        )",
+        "ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings, asn, as_org, prefix, geo_updated_at",
+        "ip, 0, NULL, NULL, isp, city, region, country, countrycode, banned_times, is_banned, warnings, asn, as_org, prefix, NULL",
+        |conn| {
+            let mut stmt = conn.prepare("SELECT ip, created_at, lon, lat FROM ipmeta")?;
+            let rows: Vec<(String, String, Option<String>, Option<String>)> =
+                stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?.collect::<Result<_>>()?;
+            let mut update = conn.prepare("UPDATE ipmeta_new SET created_at = ?2, lon = ?3, lat = ?4 WHERE ip = ?1")?;
+            for (ip, created_at, lon, lat) in rows {
+                update.execute((ip, epoch(&created_at, now), coordinate(lon), coordinate(lat)))?;
+            }
+            // an unreadable lookup time counts from the creation, like a missing one
+            convert_timestamps(conn, "ipmeta", "ip", "geo_updated_at", None)
+        },
+    )?;
+    rebuild(
+        conn,
+        "messages",
+        "CREATE TABLE messages_new(
+            id INTEGER PRIMARY KEY,
+            created_at INTEGER NOT NULL CHECK (typeof(created_at) = 'integer'),
+            text TEXT NOT NULL,
+            ip TEXT NOT NULL REFERENCES ipmeta(ip),
+            country TEXT NOT NULL REFERENCES country(name),
+            region TEXT NOT NULL REFERENCES region(name),
+            city TEXT NOT NULL REFERENCES city(name),
+            isp TEXT NOT NULL REFERENCES isp(name),
+            is_jctl INTEGER NOT NULL,
+            is_ban INTEGER NOT NULL,
+            ssh_user TEXT,
+            ssh_port INTEGER,
+            ssh_method TEXT,
+            ssh_outcome TEXT
+        )",
+        "id, created_at, text, ip, country, region, city, isp, is_jctl, is_ban, ssh_user, ssh_port, ssh_method, ssh_outcome",
+        "id, 0, text, ip, country, region, city, isp, is_jctl, is_ban, ssh_user, ssh_port, ssh_method, ssh_outcome",
+        |conn| {
+            let mut stmt = conn.prepare("SELECT id, created_at FROM messages")?;
+            let rows: Vec<(i64, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<_>>()?;
+            let mut update = conn.prepare("UPDATE messages_new SET created_at = ?2 WHERE id = ?1")?;
+            for (id, created_at) in rows {
+                update.execute((id, epoch(&created_at, now)))?;
+            }
+            Ok(())
+        },
+    )?;
+    rebuild(
+        conn,
+        "ipmeta_history",
+        "CREATE TABLE ipmeta_history_new(
+            id INTEGER PRIMARY KEY,
+            ip TEXT NOT NULL REFERENCES ipmeta(ip),
+            lon REAL,
+            lat REAL,
+            isp TEXT NOT NULL,
+            city TEXT NOT NULL,
+            region TEXT,
+            country TEXT NOT NULL,
+            countrycode TEXT,
+            asn INTEGER,
+            as_org TEXT,
+            prefix TEXT,
+            valid_from INTEGER NOT NULL CHECK (typeof(valid_from) = 'integer'),
+            valid_until INTEGER NOT NULL CHECK (typeof(valid_until) = 'integer')
+        )",
+        "id, ip, lon, lat, isp, city, region, country, countrycode, asn, as_org, prefix, valid_from, valid_until",
+        "id, ip, NULL, NULL, isp, city, region, country, countrycode, asn, as_org, prefix, 0, 0",
+        |conn| {
+            let mut stmt = conn.prepare("SELECT id, lon, lat FROM ipmeta_history")?;
+            let rows: Vec<(i64, Option<String>, Option<String>)> =
+                stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?.collect::<Result<_>>()?;
+            let mut update = conn.prepare("UPDATE ipmeta_history_new SET lon = ?2, lat = ?3 WHERE id = ?1")?;
+            for (id, lon, lat) in rows {
+                update.execute((id, coordinate(lon), coordinate(lat)))?;
+            }
+            convert_timestamps(conn, "ipmeta_history", "id", "valid_from", Some(now))?;
+            convert_timestamps(conn, "ipmeta_history", "id", "valid_until", Some(now))
+        },
+    )?;
+    rebuild(
+        conn,
+        "rdns",
+        "CREATE TABLE rdns_new(
+            ip TEXT NOT NULL PRIMARY KEY REFERENCES ipmeta(ip),
+            hostname TEXT,
+            confirmed INTEGER NOT NULL,
+            looked_up_at INTEGER NOT NULL CHECK (typeof(looked_up_at) = 'integer')
+        )",
+        "ip, hostname, confirmed, looked_up_at",
+        // an unreadable lookup is done again
+        "ip, hostname, confirmed, 0",
+        |conn| convert_timestamps(conn, "rdns", "ip", "looked_up_at", Some(0)),
+    )?;
+    rebuild(
+        conn,
+        "ban_events",
+        "CREATE TABLE ban_events_new(
+            id INTEGER PRIMARY KEY,
+            ip TEXT NOT NULL,
+            jail TEXT,
+            action TEXT NOT NULL,
+            source TEXT NOT NULL,
+            reason TEXT,
+            timestamp INTEGER NOT NULL CHECK (typeof(timestamp) = 'integer'),
+            duration INTEGER
+        )",
+        "id, ip, jail, action, source, reason, timestamp, duration",
+        "id, ip, jail, action, source, reason, 0, duration",
+        |conn| convert_timestamps(conn, "ban_events", "id", "timestamp", Some(now)),
+    )?;
+    rebuild(
+        conn,
+        "maintenance",
+        "CREATE TABLE maintenance_new(
+            task TEXT NOT NULL PRIMARY KEY,
+            last_run INTEGER NOT NULL CHECK (typeof(last_run) = 'integer')
+        )",
+        "task, last_run",
+        // an unreadable run is due again
+        "task, 0",
+        |conn| convert_timestamps(conn, "maintenance", "task", "last_run", Some(0)),
+    )?;
+    // the hourly refresh picks the oldest lookups
+    conn.execute("CREATE INDEX ipmeta_geo_updated_at ON ipmeta(COALESCE(geo_updated_at, created_at))", [])?;
+    Ok(())
+}
+
+/// Converts the text timestamps in `column` of `table` to the rebuilt `{table}_new`, matching rows by `key`.
+/// Missing and unreadable ones become `fallback`.
+fn convert_timestamps(conn: &Connection, table: &str, key: &str, column: &str, fallback: Option<i64>) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("SELECT {key}, {column} FROM {table}"))?;
+    let rows: Vec<(rusqlite::types::Value, Option<String>)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<_>>()?;
+    let mut update = conn.prepare(&format!("UPDATE {table}_new SET {column} = ?2 WHERE {key} = ?1"))?;
+    for (key, text) in rows {
+        update.execute((key, text.as_deref().and_then(parse_epoch).or(fallback)))?;
+    }
+    Ok(())
+}
+
+/// Replaces `table` by the one `create` makes as `{table}_new`, copying `columns` as `select` picks them from
+/// the old rows before `convert` fills in the rest. The indexes and triggers of the table are made again.
+fn rebuild(conn: &Connection, table: &str, create: &str, columns: &str, select: &str, convert: impl FnOnce(&Connection) -> Result<()>) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT sql FROM sqlite_master WHERE tbl_name = ?1 AND type IN ('index', 'trigger') AND sql IS NOT NULL")?;
+    let dependents: Vec<String> = stmt.query_map([table], |row| row.get(0))?.collect::<Result<_>>()?;
+    conn.execute_batch(&format!("{create}; INSERT INTO {table}_new ({columns}) SELECT {select} FROM {table};"))?;
+    convert(conn)?;
+    // triggers of other tables name this one, a rename rewriting them fails while it is dropped
+    conn.execute_batch(&format!(
+        "DROP TABLE {table};
+        PRAGMA legacy_alter_table = ON;
+        ALTER TABLE {table}_new RENAME TO {table};
+        PRAGMA legacy_alter_table = OFF;"
+    ))?;
+    for sql in dependents {
+        conn.execute_batch(&sql)?;
+    }
+    check_foreign_keys(conn, table)
+}
+
+/// Seconds since the UNIX epoch of a stored timestamp, `fallback` for anything unreadable.
+fn epoch(text: &str, fallback: i64) -> i64 {
+    parse_epoch(text).unwrap_or(fallback)
+}
+
+/// Releases stored RFC 3339, tests of older ones `2022-03-11 23:45:31:512` in local time. Ban events the
+/// migration of version 9 copied from the messages are epoch seconds already, stored as text.
+fn parse_epoch(text: &str) -> Option<i64> {
+    if let Ok(timestamp) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Some(timestamp.timestamp());
+    }
+    if let Ok(secs) = text.parse::<i64>() {
+        return Some(secs);
+    }
+    ["%Y-%m-%d %H:%M:%S:%3f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(text, format).ok())
+        .and_then(|naive| naive.and_local_timezone(chrono::offset::Local).earliest())
+        .map(|timestamp| timestamp.timestamp())
+}
+
+/// Journal lines remember the source that read them, each source backfills from its own newest line.
+/// Lines stored before keep no source, they can't be told apart anymore.
+fn message_source(conn: &Connection) -> Result<()> {
+    add_column(conn, "messages", "source", "TEXT")?;
+    conn.execute("CREATE INDEX IF NOT EXISTS messages_journal_source ON messages(source, created_at)", [])?;
+    Ok(())
+}
+
+/// Latitude or longitude stored as text, empty when the lookup couldn't place the IP.
+fn coordinate(text: Option<String>) -> Option<f64> {
+    text?.trim().parse::<f64>().ok().filter(|degrees| degrees.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::schema::{city, country, ip, isp, message, recompute_stats, region};
+
+    /// Database as the releases before `schema_version` left it, with the tables of the first `upto` migrations.
+    fn unversioned(upto: usize) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..upto] {
+            (migration.up)(&conn).unwrap();
+        }
+        fill(&conn, upto as u32);
+        conn
+    }
+
+    /// Database migrated to `version` by this build.
+    fn versioned(version: u32) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        current_version(&conn).unwrap();
+        for migration in MIGRATIONS.iter().take_while(|migration| migration.version <= version) {
+            apply(&conn, migration).unwrap();
+        }
+        fill(&conn, version);
+        conn
+    }
+
+    /// Rows as the code of that version wrote them.
+    fn fill(conn: &Connection, version: u32) {
+        if version == 0 {
+            return;
+        }
+        // counters drifted until the triggers took over counting, from then on the messages count
+        let warned = if version >= 8 { 0 } else { 2 };
+        let banned = if version >= 8 { 0 } else { 1 };
+        // 2023-11-19T12:00:00+00:00
+        let created_at = if version >= 11 { "1700395200" } else { "'2023-11-19T12:00:00+00:00'" };
+        country::insert_new_country(conn, "Germany", Some("DE"), Some(0), Some(warned), false).unwrap();
+        region::insert_new_region(conn, "Bremen", "Germany", Some(0), Some(warned), false).unwrap();
+        city::insert_new_city(conn, "Bremen", "Germany", "Bremen", Some(0), Some(warned), false).unwrap();
+        isp::insert_new_ISP(conn, "Example", Some(0), Some(warned), "Germany", false).unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings)
+                VALUES ('192.0.2.1', {created_at}, '8.8', '53.1', 'Example', 'Bremen', 'Bremen', 'Germany', 'DE', 0, 0, {warned});
+            INSERT INTO messages (created_at, text, ip, country, region, city, isp, is_jctl, is_ban)
+                VALUES ({created_at}, 'Failed password for root from 192.0.2.1', '192.0.2.1', 'Germany', 'Bremen', 'Bremen', 'Example', 1, 0);",
+        ))
+        .unwrap();
+        if version >= 6 {
+            conn.execute_batch(&format!(
+                "INSERT INTO asn VALUES (64496, 'Example AS', {banned}, {banned}, 'Germany', 0);
+                INSERT INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings, asn, as_org, prefix)
+                    VALUES ('192.0.2.2', {created_at}, '8.8', '53.1', 'Example', 'Bremen', 'Bremen', 'Germany', 'DE', {banned}, 1, {banned}, 64496, 'Example AS', NULL);",
+            ))
+            .unwrap();
+        } else if version >= 3 {
+            // the AS could end up only on the IP, prefixes were stored empty
+            conn.execute_batch(
+                "INSERT INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings, asn, as_org, prefix)
+                    VALUES ('192.0.2.2', '2023-11-19T12:00:00+00:00', '8.8', '53.1', 'Example', 'Bremen', 'Bremen', 'Germany', 'DE', 1, 1, 1, 64496, 'Example AS', '');",
+            )
+            .unwrap();
+        }
+        if version >= 5 {
+            conn.execute(&format!("INSERT INTO rdns VALUES ('192.0.2.1', 'mail.example.net', 1, {created_at})"), []).unwrap();
+        }
+        if version == 5 {
+            // lookups of IPs that were never stored
+            conn.execute("INSERT INTO rdns VALUES ('198.51.100.1', 'gone.example.net', 0, '2023-11-19T12:00:00+00:00')", []).unwrap();
+        }
+    }
+
+    fn schema(conn: &Connection) -> Vec<(String, Option<String>)> {
+        let mut stmt = conn.prepare("SELECT name, sql FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' ORDER BY name").unwrap();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        rows.collect::<Result<_>>().unwrap()
+    }
+
+    fn assert_migrated(conn: &Connection, from: u32, fresh: &[(String, Option<String>)]) {
+        assert_eq!(migrate(conn).unwrap(), from);
+        assert_eq!(current_version(conn).unwrap(), LATEST);
+        assert_eq!(schema(conn), fresh, "schema after migrating from version {}", from);
+        // a second run has nothing to do
+        assert_eq!(migrate(conn).unwrap(), LATEST);
+        if from == 0 && conn.query_row("SELECT COUNT(*) FROM ipmeta", [], |row| row.get::<_, u32>(0)).unwrap() == 0 {
+            return;
+        }
+
+        let stored = ip::select_ip(conn, "192.0.2.1").unwrap().unwrap();
+        assert_eq!((stored.city.as_str(), stored.warnings), ("Bremen", 1));
+        assert_eq!((stored.created_at.timestamp(), stored.coordinates()), (1700395200, Some((53.1, 8.8))));
+        assert_eq!(recompute_stats(conn).unwrap(), 0);
+        assert_eq!(message::select_message_by_ip(conn, "192.0.2.1").unwrap().len(), 1);
+        if let Some(networked) = ip::select_ip(conn, "192.0.2.2").unwrap() {
+            assert_eq!((networked.asn, networked.prefix.as_str()), (Some(64496), ""));
+            let asn: String = conn.query_row("SELECT name FROM asn WHERE number = 64496", [], |row| row.get(0)).unwrap();
+            assert_eq!(asn, "Example AS");
+        }
+        let rdns: Vec<String> = conn.prepare("SELECT ip FROM rdns").unwrap().query_map([], |row| row.get(0)).unwrap().flatten().collect();
+        assert!(rdns.iter().all(|ip| ip == "192.0.2.1"));
+        // references hold now
+        assert!(message::insert_new_message(conn, None, chrono::offset::Local::now(), "unknown", "203.0.113.1", "Germany", "Bremen", "Bremen", "Example", false, false).is_err());
+    }
+
+    #[test]
+    fn test_migrate_every_version() {
+        let fresh = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&fresh).unwrap(), 0);
+        let fresh = schema(&fresh);
+        assert!(fresh.iter().any(|(name, _)| name == "messages_source_created_at"));
+
+        for version in 0..=LATEST {
+            assert_migrated(&versioned(version), version, &fresh);
+        }
+        // releases before migrations only knew the tables of the first five
+        for upto in 1..=5 {
+            assert_migrated(&unversioned(upto), 0, &fresh);
+        }
+    }
+
+    #[test]
+    fn test_migrate_ban_events() {
+        let conn = versioned(8);
+        conn.execute_batch(
+            "INSERT INTO messages (created_at, text, ip, country, region, city, isp, is_jctl, is_ban) VALUES
+                ('2023-11-19T16:08:12+00:00', '2023-11-19 16:08:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Ban 192.0.2.1', '192.0.2.1', 'Germany', 'Bremen', 'Bremen', 'Example', 0, 1),
+                ('2023-11-19T16:18:12+00:00', '2023-11-19 16:18:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Unban 192.0.2.1', '192.0.2.1', 'Germany', 'Bremen', 'Bremen', 'Example', 0, 0),
+                ('2023-11-19T16:20:00+00:00', '2023-11-19 16:20:00,000 fail2ban.filter         [804]: INFO    [sshd] Found 192.0.2.1', '192.0.2.1', 'Germany', 'Bremen', 'Bremen', 'Example', 0, 0);",
+        )
+        .unwrap();
+        assert_eq!(migrate(&conn).unwrap(), 8);
+
+        let events = ban_event::select_ban_events_by_ip(&conn, "192.0.2.1").unwrap();
+        let summary: Vec<_> = events.iter().map(|event| (event.action, event.jail.as_deref(), event.source, event.timestamp.timestamp())).collect();
+        assert_eq!(summary, vec![
+            (ban_event::BanAction::Ban, Some("sshd"), ban_event::BanSource::Fail2Ban, 1700410092),
+            (ban_event::BanAction::Unban, Some("sshd"), ban_event::BanSource::Fail2Ban, 1700410692),
+        ]);
+    }
+
+    #[test]
+    fn test_migrate_typed_columns() {
+        let conn = versioned(10);
+        conn.execute_batch(
+            "INSERT INTO ipmeta (ip, created_at, lon, lat, isp, city, region, country, countrycode, banned_times, is_banned, warnings)
+                VALUES ('192.0.2.3', '2023-11-19T13:00:00.123456+01:00', '', '', 'Example', 'Bremen', 'Bremen', 'Germany', 'DE', 0, 0, 0);
+            INSERT INTO messages (created_at, text, ip, country, region, city, isp, is_jctl, is_ban) VALUES
+                ('2023-11-19T13:00:00.123456+01:00', 'first', '192.0.2.3', 'Germany', 'Bremen', 'Bremen', 'Example', 1, 0),
+                ('2022-03-11 23:45:31:512', 'from a test', '192.0.2.3', 'Germany', 'Bremen', 'Bremen', 'Example', 1, 0),
+                ('yesterday', 'unreadable', '192.0.2.3', 'Germany', 'Bremen', 'Bremen', 'Example', 1, 0);
+            INSERT INTO ipmeta_history (ip, lon, lat, isp, city, region, country, countrycode, valid_from, valid_until)
+                VALUES ('192.0.2.3', '8.81', ' 53.08', 'Example', 'Bremen', 'Bremen', 'Germany', 'DE', '2023-11-19T12:00:00+00:00', '2023-11-20T12:00:00+00:00');
+            UPDATE ipmeta SET geo_updated_at = '2023-11-20T13:00:00+01:00' WHERE ip = '192.0.2.3';
+            UPDATE ipmeta SET geo_updated_at = 'soon' WHERE ip = '192.0.2.1';
+            INSERT INTO rdns VALUES ('192.0.2.3', 'gone.example.net', 0, 'never');
+            INSERT INTO ban_events (ip, jail, action, source, reason, timestamp, duration) VALUES
+                ('192.0.2.3', 'sshd', 'ban', 'fail2ban', NULL, '2023-11-19T19:30:00+00:00', NULL),
+                ('192.0.2.3', 'sshd', 'unban', 'manual', NULL, '2023-11-19T20:00:00+01:00', NULL);
+            INSERT INTO maintenance VALUES ('vacuum', '2023-11-19T12:00:00+00:00');",
+        )
+        .unwrap();
+        let before = chrono::offset::Local::now().timestamp();
+        assert_eq!(migrate(&conn).unwrap(), 10);
+
+        let unplaced = ip::select_ip(&conn, "192.0.2.3").unwrap().unwrap();
+        assert_eq!((unplaced.created_at.timestamp(), unplaced.lat, unplaced.lon, unplaced.warnings), (1700395200, None, None, 3));
+        let messages: Vec<i64> = message::select_message_by_ip(&conn, "192.0.2.3").unwrap().into_iter().flatten().map(|msg| msg.created_at.timestamp()).collect();
+        let legacy = chrono::NaiveDate::from_ymd_opt(2022, 3, 11).unwrap().and_hms_opt(23, 45, 31).unwrap().and_local_timezone(chrono::offset::Local).unwrap();
+        assert_eq!(messages[..2], [1700395200, legacy.timestamp()]);
+        assert!(messages[2] >= before);
+        let history = crate::database::schema::ip_history::select_location_history(&conn, "192.0.2.3").unwrap();
+        assert_eq!((history[0].lat, history[0].lon), (Some(53.08), Some(8.81)));
+        assert_eq!((history[0].valid_from.timestamp(), history[0].valid_until.timestamp()), (1700395200, 1700481600));
+        assert_eq!(unplaced.geo_updated_at.map(|at| at.timestamp()), Some(1700481600));
+        // an unreadable lookup time counts from the creation
+        assert_eq!(ip::select_ip(&conn, "192.0.2.1").unwrap().unwrap().geo_updated_at, None);
+        // an unreadable reverse lookup is done again
+        assert!(crate::rdns::is_stale(unplaced.rdns.as_ref()));
+        // events sort by when they happened, not by their text
+        let events: Vec<i64> = ban_event::select_ban_events_by_ip(&conn, "192.0.2.3").unwrap().iter().map(|event| event.timestamp.timestamp()).collect();
+        assert_eq!(events, vec![1700420400, 1700422200]);
+        let vacuumed = chrono::DateTime::from_timestamp(1700395200 + 3600, 0).unwrap().with_timezone(&chrono::offset::Local);
+        assert!(!crate::database::schema::retention::vacuum_due(&conn, chrono::Duration::days(1), vacuumed).unwrap());
+
+        let types: (String, String, String) = conn
+            .query_row("SELECT typeof(created_at), typeof(lat), (SELECT typeof(created_at) FROM messages LIMIT 1) FROM ipmeta WHERE ip = '192.0.2.1'", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        assert_eq!(types, (String::from("integer"), String::from("real"), String::from("integer")));
+        // timestamps only go in as numbers now
+        for update in [
+            "UPDATE messages SET created_at = '2023-11-19T12:00:00+00:00'",
+            "UPDATE ipmeta SET geo_updated_at = '2023-11-19T12:00:00+00:00'",
+            "UPDATE ipmeta_history SET valid_until = '2023-11-19T12:00:00+00:00'",
+            "UPDATE rdns SET looked_up_at = '2023-11-19T12:00:00+00:00'",
+            "UPDATE ban_events SET timestamp = '2023-11-19T12:00:00+00:00'",
+            "UPDATE maintenance SET last_run = '2023-11-19T12:00:00+00:00'",
+        ] {
+            assert!(conn.execute(update, []).is_err(), "{}", update);
+        }
+        assert_eq!(recompute_stats(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_migrate_newer_database() {
+        let conn = versioned(LATEST);
+        conn.execute("INSERT INTO schema_version VALUES (?1, 'from the future', '')", [LATEST + 1]).unwrap();
+        assert!(migrate(&conn).is_err());
+    }
+}
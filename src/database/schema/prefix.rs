@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, Result, Row};
+
+
+/// A network announced by an AS, e.g. `192.0.2.0/24`.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Prefix {
+    pub prefix: String,
+    pub asn: Option<u32>,
+    pub banned: usize,
+    pub warnings: usize,
+    pub is_blocked: bool,
+}
+fn from_row(row: &Row) -> Result<Prefix> {
+    Ok( Prefix {
+        prefix: row.get(0)?,
+        asn: row.get(1)?,
+        banned: row.get(2)?,
+        warnings: row.get(3)?,
+        is_blocked: row.get(4)?,
+    })
+}
+
+pub fn insert_new_prefix(conn: &Connection, prefix: &str, asn: Option<u32>, num_banned:Option<usize>, num_messages:Option<usize>, is_blocked: bool) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO prefix (prefix, asn, banned, messages, is_blocked) VALUES (?1, ?2, ?3, ?4, ?5)",
+        (prefix, asn, num_banned.unwrap_or(0), num_messages.unwrap_or(0), is_blocked),
+    )?;
+    Ok(())
+}
+
+pub fn select_prefix(conn: &Connection, prefix: &str) -> Result<Option<Prefix>> {
+    let mut stmt = conn.prepare(
+        "SELECT prefix, asn, banned, messages, is_blocked FROM prefix WHERE prefix=?1;"
+    )?;
+    let mut rows = stmt.query_map([prefix], from_row)?;
+    rows.next().transpose()
+}
+
+pub fn get_all_prefixes(conn: &Connection) -> Result<Vec<Prefix>> {
+    let mut stmt = conn.prepare(
+        "SELECT prefix, asn, banned, messages, is_blocked FROM prefix"
+    )?;
+    let prefixes = stmt.query_map([], from_row)?.flatten().collect();
+    Ok(prefixes)
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use rusqlite::{Connection, OptionalExtension, Result};
+
+
+/// Cached reverse lookup of an IP.
+#[derive(Default, Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct ReverseDns {
+    /// PTR name, `None` if the address has none.
+    pub hostname: Option<String>,
+    /// The hostname resolves back to the address.
+    pub confirmed: bool,
+    pub looked_up_at: DateTime<Local>,
+}
+
+pub fn insert_rdns(conn: &Connection, ip: &str, x: &ReverseDns) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO rdns (ip, hostname, confirmed, looked_up_at) VALUES (?1, ?2, ?3, ?4)",
+        (ip, &x.hostname, x.confirmed, x.looked_up_at.timestamp()),
+    )?;
+    Ok(())
+}
+
+pub fn select_rdns(conn: &Connection, ip: &str) -> Result<Option<ReverseDns>> {
+    conn.query_row(
+        "SELECT hostname, confirmed, looked_up_at FROM rdns WHERE ip=?1;",
+        [ip],
+        |row| Ok(ReverseDns { hostname: row.get(0)?, confirmed: row.get(1)?, looked_up_at: super::timestamp(row, 2)? }),
+    ).optional()
+}
@@ -11,16 +11,6 @@ pub struct Region {
     pub country: String,
     pub is_blocked: bool,
 }
-pub const CREATE_REGION_DB_SQL: &str = "CREATE TABLE IF NOT EXISTS region(
-    name TEXT NOT NULL PRIMARY KEY,
-    banned INTEGER,
-    warnings INTEGER,
-    country TEXT NOT NULL REFERENCES country(name),
-    is_blocked INTEGER NOT NULL
-
-)
-";
-
 pub fn insert_new_region(conn: &Connection, name: &str, country: &str, num_banned:Option<usize>, num_messages:Option<usize>, is_blocked:bool) -> Result<()> {
     let _banned = num_banned.unwrap_or(0);
     let _msgs = num_messages.unwrap_or(0);
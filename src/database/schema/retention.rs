@@ -0,0 +1,166 @@
+//! Keeps the messages table in bounds.
+//! Messages past the retention are rolled into daily counts per IP and location before they are deleted,
+//! the stats and the charts keep counting them from there.
+
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, Result};
+
+/// Which messages are kept in full, `None` for no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_age: Option<chrono::Duration>,
+    pub max_rows: Option<usize>,
+    pub max_per_ip: Option<usize>,
+}
+
+/// Rolls the messages outside the retention into `messages_daily` and deletes them.
+/// Returns how many messages were rolled up.
+pub fn prune(conn: &Connection, retention: &Retention, now: DateTime<Local>) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch("CREATE TEMP TABLE IF NOT EXISTS pruned(id INTEGER PRIMARY KEY); DELETE FROM temp.pruned;")?;
+    if let Some(max_age) = retention.max_age {
+        tx.execute("INSERT OR IGNORE INTO temp.pruned SELECT id FROM messages WHERE created_at < ?1", [(now - max_age).timestamp()])?;
+    }
+    if let Some(max_per_ip) = retention.max_per_ip {
+        tx.execute(
+            "INSERT OR IGNORE INTO temp.pruned SELECT id FROM (
+                SELECT id, ROW_NUMBER() OVER (PARTITION BY ip ORDER BY created_at DESC, id DESC) AS newer FROM messages
+            ) WHERE newer > ?1",
+            [max_per_ip],
+        )?;
+    }
+    if let Some(max_rows) = retention.max_rows {
+        tx.execute(
+            "INSERT OR IGNORE INTO temp.pruned SELECT id FROM messages ORDER BY created_at DESC, id DESC LIMIT -1 OFFSET ?1",
+            [max_rows],
+        )?;
+    }
+    // the local day, like the charts count them
+    tx.execute(
+        "INSERT INTO messages_daily (day, ip, country, region, city, isp, warnings, banned)
+            SELECT date(created_at, 'unixepoch', 'localtime'), ip, country, region, city, isp, COUNT(*), SUM(is_ban) FROM messages
+            WHERE id IN (SELECT id FROM temp.pruned) GROUP BY 1, 2, 3, 4, 5, 6
+            ON CONFLICT (day, ip, country, region, city, isp) DO UPDATE SET warnings = warnings + excluded.warnings, banned = banned + excluded.banned",
+        [],
+    )?;
+    let pruned = tx.execute("DELETE FROM messages WHERE id IN (SELECT id FROM temp.pruned)", [])?;
+    tx.execute("DELETE FROM temp.pruned", [])?;
+    tx.commit()?;
+    Ok(pruned)
+}
+
+/// Whether the last VACUUM is `interval` or longer ago, a database never vacuumed is due.
+pub fn vacuum_due(conn: &Connection, interval: chrono::Duration, now: DateTime<Local>) -> Result<bool> {
+    let last: Option<DateTime<Local>> =
+        conn.query_row("SELECT last_run FROM maintenance WHERE task = 'vacuum'", [], |row| super::timestamp(row, 0)).optional()?;
+    Ok(last.is_none_or(|last| now - last >= interval))
+}
+
+/// Gives the space of pruned rows back to the file system.
+pub fn vacuum(conn: &Connection, now: DateTime<Local>) -> Result<()> {
+    conn.execute_batch("VACUUM")?;
+    conn.execute("INSERT OR REPLACE INTO maintenance (task, last_run) VALUES ('vacuum', ?1)", [now.timestamp()])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::schema::{self, city, country, message};
+
+    fn at(rfc3339: &str) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Local)
+    }
+
+    fn insert(conn: &Connection, ip: &str, created_at: &str, is_ban: bool) {
+        message::insert_new_message(conn, None, at(created_at), "Failed password", ip, "Germany", "Bremen", "Bremen", "Example", true, is_ban).unwrap();
+    }
+
+    fn stored(conn: &Connection) -> Vec<DateTime<Local>> {
+        let mut stmt = conn.prepare("SELECT created_at FROM messages ORDER BY created_at").unwrap();
+        stmt.query_map([], |row| schema::timestamp(row, 0)).unwrap().flatten().collect()
+    }
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        schema::create_tables(&conn).unwrap();
+        schema::test::insert_located(&conn, &["192.0.2.1", "192.0.2.2"], at("2023-01-01T00:00:00+00:00")).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_prune_by_age() {
+        let conn = database();
+        insert(&conn, "192.0.2.1", "2023-01-10T10:00:00+00:00", true);
+        insert(&conn, "192.0.2.1", "2023-01-10T13:30:00+00:00", false);
+        insert(&conn, "192.0.2.2", "2023-01-10T11:00:00+00:00", false);
+        insert(&conn, "192.0.2.1", "2023-02-01T12:00:00+00:00", false);
+        insert(&conn, "192.0.2.1", "2023-11-19T12:00:00+00:00", false);
+        let timestamps = |conn: &Connection| message::get_message_timestamps_by_country(conn, "Germany").unwrap().iter().map(|msg| msg.count).sum::<usize>();
+        let before = (country::select_country(&conn, "Germany").unwrap(), city::select_city(&conn, "Bremen").unwrap(), timestamps(&conn));
+
+        let now = at("2023-12-01T00:00:00+01:00");
+        let retention = Retention { max_age: Some(chrono::Duration::days(180)), ..Default::default() };
+        assert_eq!(prune(&conn, &retention, now).unwrap(), 4);
+        assert_eq!(stored(&conn), vec![at("2023-11-19T12:00:00+00:00")]);
+
+        // the stats and charts still count what was rolled up, on the day it was logged
+        assert_eq!((country::select_country(&conn, "Germany").unwrap(), city::select_city(&conn, "Bremen").unwrap(), timestamps(&conn)), before);
+        assert_eq!(schema::recompute_stats(&conn).unwrap(), 0);
+        let days: Vec<(String, String, u32, u32)> = conn
+            .prepare("SELECT day, ip, warnings, banned FROM messages_daily ORDER BY day, ip").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap().flatten().collect();
+        assert_eq!(days, vec![
+            (String::from("2023-01-10"), String::from("192.0.2.1"), 2, 1),
+            (String::from("2023-01-10"), String::from("192.0.2.2"), 1, 0),
+            (String::from("2023-02-01"), String::from("192.0.2.1"), 1, 0),
+        ]);
+        let noon = chrono::NaiveDate::from_ymd_opt(2023, 1, 10).unwrap().and_hms_opt(12, 0, 0).unwrap().and_local_timezone(Local).unwrap();
+        let by_ip = message::get_message_timestamps_by_city(&conn, "Bremen").unwrap();
+        let rolled: Vec<(&str, usize)> = by_ip.iter().filter(|msg| msg.created_at == noon).map(|msg| (msg.ip.as_str(), msg.count)).collect();
+        assert_eq!(rolled, vec![("192.0.2.1", 2), ("192.0.2.2", 1)]);
+
+        // a second run adds to the days already rolled up
+        insert(&conn, "192.0.2.2", "2023-01-10T12:00:00+00:00", true);
+        assert_eq!(prune(&conn, &retention, now).unwrap(), 1);
+        let day: (u32, u32) = conn.query_row("SELECT warnings, banned FROM messages_daily WHERE day = '2023-01-10' AND ip = '192.0.2.2'", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(day, (2, 1));
+        assert_eq!(schema::recompute_stats(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_prune_by_rows() {
+        let conn = database();
+        for hour in 10..16 {
+            insert(&conn, "192.0.2.1", &format!("2023-11-19T{}:00:00+01:00", hour), false);
+        }
+        insert(&conn, "192.0.2.2", "2023-11-19T09:00:00+01:00", false);
+        insert(&conn, "192.0.2.2", "2023-11-19T16:00:00+01:00", false);
+        let now = Local::now();
+
+        // the noisy IP keeps its newest three, the quiet one all
+        let per_ip = Retention { max_per_ip: Some(3), ..Default::default() };
+        assert_eq!(prune(&conn, &per_ip, now).unwrap(), 3);
+        assert_eq!(stored(&conn), ["09", "13", "14", "15", "16"].map(|hour| at(&format!("2023-11-19T{}:00:00+01:00", hour))));
+
+        let rows = Retention { max_rows: Some(2), ..Default::default() };
+        assert_eq!(prune(&conn, &rows, now).unwrap(), 3);
+        assert_eq!(stored(&conn), vec![at("2023-11-19T15:00:00+01:00"), at("2023-11-19T16:00:00+01:00")]);
+        // one row per day and IP, counting what it stands for
+        let msgs = message::get_message_timestamps_by_isp(&conn, "Example").unwrap();
+        assert_eq!((msgs.len(), msgs.iter().map(|msg| msg.count).sum::<usize>()), (4, 8));
+        assert_eq!(schema::recompute_stats(&conn).unwrap(), 0);
+        assert_eq!(prune(&conn, &Retention::default(), now).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vacuum_schedule() {
+        let conn = database();
+        let now = at("2023-12-01T00:00:00+01:00");
+        let week = chrono::Duration::days(7);
+        assert!(vacuum_due(&conn, week, now).unwrap());
+        vacuum(&conn, now).unwrap();
+        assert!(!vacuum_due(&conn, week, now + chrono::Duration::days(6)).unwrap());
+        assert!(vacuum_due(&conn, week, now + week).unwrap());
+    }
+}
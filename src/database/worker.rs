@@ -0,0 +1,110 @@
+//! Database worker.
+//! One thread owns the connection and runs the queries sent to it one after the other, so queries never
+//! block the runtime and writes can't interleave. Components hand it closures over the connection, either
+//! awaiting their typed result with `call` or leaving them to run in order with `execute`.
+
+use color_eyre::eyre::{eyre, Result};
+use rusqlite::Connection;
+use tokio::sync::{mpsc, oneshot};
+
+type Job = Box<dyn FnOnce(&Connection) + Send>;
+
+/// Handle to the database worker, cheap to clone into tasks.
+#[derive(Clone)]
+pub struct DbConn {
+  tx: mpsc::UnboundedSender<Job>,
+}
+
+impl DbConn {
+  /// Moves the connection to its own thread, which runs until the last handle is dropped.
+  pub fn spawn(conn: Connection) -> Self {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Job>();
+    std::thread::Builder::new()
+      .name(String::from("db"))
+      .spawn(move || {
+        while let Some(job) = rx.blocking_recv() {
+          job(&conn);
+        }
+      })
+      .expect("Failed to spawn the database thread");
+    Self { tx }
+  }
+
+  /// Runs `query` on the worker and waits for its result.
+  pub async fn call<T, F>(&self, query: F) -> Result<T>
+  where
+    T: Send + 'static,
+    F: FnOnce(&Connection) -> T + Send + 'static,
+  {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    self.execute(move |conn| {
+      // the caller may have stopped waiting
+      let _ = reply_tx.send(query(conn));
+    })?;
+    reply_rx.await.map_err(|_| eyre!("Database worker stopped"))
+  }
+
+  /// Queues `query` without waiting for it, queries run in the order they were sent.
+  pub fn execute<F>(&self, query: F) -> Result<()>
+  where
+    F: FnOnce(&Connection) + Send + 'static,
+  {
+    self.tx.send(Box::new(query)).map_err(|_| eyre!("Database worker stopped"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::schema;
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+  async fn test_concurrent_queries() {
+    let conn = Connection::open_in_memory().unwrap();
+    schema::create_tables(&conn).unwrap();
+    conn.execute("INSERT INTO country VALUES ('Germany', 'DE', 0, 0, 0)", []).unwrap();
+    let db = DbConn::spawn(conn);
+
+    // every task bumps the counter and reads it back, no update may get lost between them
+    let tasks: Vec<_> = (0..64)
+      .map(|task| {
+        let db = db.clone();
+        tokio::spawn(async move {
+          let mut seen = vec![];
+          for _ in 0..25 {
+            if task % 2 == 0 {
+              db.execute(|conn| {
+                conn.execute("UPDATE country SET warnings = warnings + 1 WHERE name = 'Germany'", []).unwrap();
+              })
+              .unwrap();
+            } else {
+              let warnings = db
+                .call(|conn| {
+                  conn.execute("UPDATE country SET warnings = warnings + 1 WHERE name = 'Germany'", []).unwrap();
+                  conn.query_row("SELECT warnings FROM country WHERE name = 'Germany'", [], |row| row.get::<_, u32>(0)).unwrap()
+                })
+                .await
+                .unwrap();
+              seen.push(warnings);
+            }
+          }
+          seen
+        })
+      })
+      .collect();
+    let mut seen = vec![];
+    for task in tasks {
+      let task_seen = task.await.unwrap();
+      // replies of one task come back in the order it sent its queries
+      assert!(task_seen.windows(2).all(|pair| pair[0] < pair[1]));
+      seen.extend(task_seen);
+    }
+    // every reply saw its own update, so no two saw the same count
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 32 * 25);
+
+    let total = db.call(|conn| conn.query_row("SELECT warnings FROM country WHERE name = 'Germany'", [], |row| row.get::<_, u32>(0))).await.unwrap().unwrap();
+    assert_eq!(total, 64 * 25);
+  }
+}
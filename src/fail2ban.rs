@@ -0,0 +1,11 @@
+//! Native fail2ban support.
+//! Talks to the fail2ban server socket directly instead of spawning `fail2ban-client`.
+
+mod banned;
+mod client;
+mod parser;
+pub mod pickle;
+
+pub use banned::{spawn_refresher, BannedSet, REFRESH_INTERVAL};
+pub use client::{split_jail_list, F2BClient, JailStatus, DEFAULT_SOCKET};
+pub use parser::{parse_line, F2BEvent, F2BEventKind};
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use color_eyre::eyre::Result;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{F2BClient, F2BEvent, F2BEventKind};
+use crate::action::Action;
+
+/// How often the banned set is reloaded from the fail2ban server.
+pub const REFRESH_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+/// In-memory copy of the addresses fail2ban currently bans, per jail.
+///
+/// Cheap to clone, all clones share the same set.
+#[derive(Debug, Clone, Default)]
+pub struct BannedSet {
+  jails: Arc<RwLock<HashMap<String, HashSet<IpAddr>>>>,
+}
+
+impl BannedSet {
+  /// Starts keeping track of a jail, it is filled on the next refresh.
+  pub fn track(&self, jail: &str) {
+    self.jails.write().unwrap().entry(jail.to_string()).or_default();
+  }
+
+  pub fn tracked_jails(&self) -> Vec<String> {
+    let mut jails: Vec<String> = self.jails.read().unwrap().keys().cloned().collect();
+    jails.sort();
+    jails
+  }
+
+  pub fn is_banned(&self, jail: &str, ip: &IpAddr) -> bool {
+    self.jails.read().unwrap().get(jail).is_some_and(|banned| banned.contains(ip))
+  }
+
+  /// Returns the jails the IP is currently banned in, unparseable addresses are never banned.
+  pub fn banned_jails(&self, ip: &str) -> Vec<String> {
+    let Ok(ip) = ip.parse::<IpAddr>() else { return vec![] };
+    let mut jails: Vec<String> =
+      self.jails.read().unwrap().iter().filter(|(_, banned)| banned.contains(&ip)).map(|(jail, _)| jail.clone()).collect();
+    jails.sort();
+    jails
+  }
+
+  pub fn insert(&self, jail: &str, ip: IpAddr) {
+    self.jails.write().unwrap().entry(jail.to_string()).or_default().insert(ip);
+  }
+
+  pub fn remove(&self, jail: &str, ip: &IpAddr) {
+    if let Some(banned) = self.jails.write().unwrap().get_mut(jail) {
+      banned.remove(ip);
+    }
+  }
+
+  pub fn replace(&self, jail: &str, banned: HashSet<IpAddr>) {
+    self.jails.write().unwrap().insert(jail.to_string(), banned);
+  }
+
+  /// Reloads every tracked jail from the fail2ban server.
+  /// A jail that fails to load keeps its previous addresses and doesn't stop the others from refreshing.
+  pub async fn refresh(&self, client: &F2BClient) -> Result<()> {
+    let mut result = Ok(());
+    for jail in self.tracked_jails() {
+      match client.get_banned(&jail).await {
+        Ok(ips) => self.replace(&jail, ips.iter().filter_map(|ip| ip.parse::<IpAddr>().ok()).collect()),
+        Err(e) => result = Err(e),
+      }
+    }
+    result
+  }
+
+  /// Applies ban and unban events of fail2ban.log right away,
+  /// so we don't have to wait for the next refresh. Returns true if the event changed the set.
+  pub fn apply_event(&self, event: &F2BEvent) -> bool {
+    let (Some(jail), Some(ip)) = (&event.jail, &event.ip) else { return false };
+    match event.kind {
+      F2BEventKind::Ban | F2BEventKind::RestoreBan | F2BEventKind::AlreadyBanned => self.insert(jail, *ip),
+      F2BEventKind::Unban => self.remove(jail, ip),
+      _ => return false,
+    }
+    true
+  }
+}
+
+/// Keeps the banned set in sync with the fail2ban server until the receiving end of `tx` is gone.
+pub fn spawn_refresher(client: F2BClient, banned: BannedSet, tx: UnboundedSender<Action>) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    let mut reachable = true;
+    loop {
+      interval.tick().await;
+      let result = banned.refresh(&client).await;
+      // only report changes, otherwise a missing socket floods the log
+      let msg = match (&result, reachable) {
+        (Err(e), true) => Some(format!("   Failed to refresh banned IPs: {}", e)),
+        (Ok(_), false) => Some(String::from(" ✓ Refreshed banned IPs")),
+        _ => None,
+      };
+      reachable = result.is_ok();
+      if let Some(msg) = msg {
+        if tx.send(Action::InternalLog(msg)).is_err() {
+          break;
+        }
+      } else if tx.is_closed() {
+        break;
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fail2ban::client::tests::fake_server;
+  use crate::fail2ban::parse_line;
+
+  fn apply_log_line(banned: &BannedSet, line: &str) -> bool {
+    banned.apply_event(&parse_line(line).unwrap())
+  }
+
+  #[test]
+  fn test_no_substring_match() {
+    let banned = BannedSet::default();
+    banned.insert("sshd", "10.0.0.12".parse().unwrap());
+    assert!(banned.banned_jails("10.0.0.1").is_empty());
+    assert_eq!(banned.banned_jails("10.0.0.12"), vec!["sshd"]);
+    assert!(banned.banned_jails("not an ip").is_empty());
+  }
+
+  #[test]
+  fn test_apply_event() {
+    let banned = BannedSet::default();
+    let ip: IpAddr = "192.168.0.5".parse().unwrap();
+    assert!(apply_log_line(&banned, "2023-11-19 16:08:12,365 fail2ban.actions [804]: NOTICE  [sshd] Ban 192.168.0.5"));
+    assert!(banned.is_banned("sshd", &ip));
+    assert!(apply_log_line(&banned, "2023-11-19 16:18:12,365 fail2ban.actions [804]: NOTICE  [nginx] Restore Ban 192.168.0.5"));
+    assert_eq!(banned.banned_jails("192.168.0.5"), vec!["nginx", "sshd"]);
+    assert!(apply_log_line(&banned, "2023-11-19 16:28:12,365 fail2ban.actions [804]: NOTICE  [sshd] Unban 192.168.0.5"));
+    assert_eq!(banned.banned_jails("192.168.0.5"), vec!["nginx"]);
+    assert!(!apply_log_line(&banned, "2023-11-19 16:08:10,101 fail2ban.filter [804]: INFO    [sshd] Found 192.168.0.5"));
+  }
+
+  #[tokio::test]
+  async fn test_refresh() {
+    let (client, state) = fake_server("refresh");
+    let banned = BannedSet::default();
+    banned.track("sshd");
+    banned.insert("sshd", "10.0.0.99".parse().unwrap());
+    banned.refresh(&client).await.unwrap();
+    assert_eq!(banned.banned_jails("10.0.0.1"), vec!["sshd"]);
+    assert!(banned.banned_jails("10.0.0.99").is_empty());
+
+    state.lock().unwrap().clear();
+    banned.refresh(&client).await.unwrap();
+    assert!(banned.banned_jails("10.0.0.1").is_empty());
+  }
+}
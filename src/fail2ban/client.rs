@@ -0,0 +1,280 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use super::pickle::{self, Value};
+
+/// Default location of the fail2ban server socket.
+pub const DEFAULT_SOCKET: &str = "/var/run/fail2ban/fail2ban.sock";
+
+const END_COMMAND: &[u8] = b"<F2B_END_COMMAND>";
+const CLOSE_COMMAND: &[u8] = b"<F2B_CLOSE_COMMAND>";
+
+/// Counters and banned IPs of a single jail as reported by `status <jail>`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JailStatus {
+  pub currently_failed: i64,
+  pub total_failed: i64,
+  pub currently_banned: i64,
+  pub total_banned: i64,
+  pub banned: Vec<String>,
+}
+
+/// Talks to the fail2ban server over its unix socket, one connection per command like `fail2ban-client` does.
+#[derive(Debug, Clone)]
+pub struct F2BClient {
+  socket_path: PathBuf,
+  timeout: tokio::time::Duration,
+}
+
+impl Default for F2BClient {
+  fn default() -> Self {
+    Self::new(DEFAULT_SOCKET)
+  }
+}
+
+impl F2BClient {
+  pub fn new(socket_path: impl AsRef<Path>) -> Self {
+    Self { socket_path: socket_path.as_ref().to_path_buf(), timeout: tokio::time::Duration::from_secs(5) }
+  }
+
+  pub fn socket_path(&self) -> &Path {
+    &self.socket_path
+  }
+
+  /// Sends a command and returns the server's result, errors raised by fail2ban are turned into `Err`.
+  pub async fn send(&self, command: &[&str]) -> Result<Value> {
+    let request = Value::List(command.iter().map(|arg| Value::String(arg.to_string())).collect());
+    let response = tokio::time::timeout(self.timeout, self.exchange(pickle::dumps(&request)))
+      .await
+      .map_err(|_| eyre!("fail2ban socket timed out on `{}`", command.join(" ")))??;
+
+    match pickle::loads(&response)? {
+      Value::List(mut x) | Value::Tuple(mut x) if x.len() == 2 => {
+        let result = x.pop().unwrap_or(Value::None);
+        match x[0].as_int() {
+          Some(0) => Ok(result),
+          _ => Err(eyre!("fail2ban failed `{}`: {}", command.join(" "), result)),
+        }
+      },
+      other => Err(eyre!("unexpected fail2ban response: {}", other)),
+    }
+  }
+
+  async fn exchange(&self, request: Vec<u8>) -> Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(&self.socket_path).await?;
+    stream.write_all(&request).await?;
+    stream.write_all(END_COMMAND).await?;
+
+    let mut response: Vec<u8> = vec![];
+    let mut buf = [0u8; 4096];
+    while !response.ends_with(END_COMMAND) {
+      let n = stream.read(&mut buf).await?;
+      if n == 0 {
+        return Err(eyre!("fail2ban closed the connection mid-response"));
+      }
+      response.extend_from_slice(&buf[..n]);
+    }
+    response.truncate(response.len() - END_COMMAND.len());
+
+    // polite goodbye, the server would drop us anyway
+    let _ = stream.write_all(&[CLOSE_COMMAND, END_COMMAND].concat()).await;
+    Ok(response)
+  }
+
+  /// Names of all active jails.
+  pub async fn jails(&self) -> Result<Vec<String>> {
+    let status = self.send(&["status"]).await?;
+    let list = status.get_pair("Jail list").and_then(|x| x.as_str()).unwrap_or_default();
+    Ok(split_jail_list(list))
+  }
+
+  pub async fn jail_status(&self, jail: &str) -> Result<JailStatus> {
+    let status = self.send(&["status", jail]).await?;
+    let count = |section: &str, key: &str| {
+      status.get_pair(section).and_then(|x| x.get_pair(key)).and_then(|x| x.as_int()).unwrap_or_default()
+    };
+    let banned = status
+      .get_pair("Actions")
+      .and_then(|x| x.get_pair("Banned IP list"))
+      .and_then(|x| x.as_list())
+      .map(|ips| ips.iter().map(|ip| ip.to_string()).collect())
+      .unwrap_or_default();
+    Ok(JailStatus {
+      currently_failed: count("Filter", "Currently failed"),
+      total_failed: count("Filter", "Total failed"),
+      currently_banned: count("Actions", "Currently banned"),
+      total_banned: count("Actions", "Total banned"),
+      banned,
+    })
+  }
+
+  pub async fn ban_ip(&self, jail: &str, ip: &str) -> Result<()> {
+    self.send(&["set", jail, "banip", ip]).await.map(|_| ())
+  }
+
+  pub async fn unban_ip(&self, jail: &str, ip: &str) -> Result<()> {
+    self.send(&["set", jail, "unbanip", ip]).await.map(|_| ())
+  }
+
+  /// How long the jail bans for in seconds, negative if forever.
+  pub async fn bantime(&self, jail: &str) -> Result<i64> {
+    self.send(&["get", jail, "bantime"]).await?.as_int().ok_or_else(|| eyre!("fail2ban sent no ban time for {}", jail))
+  }
+
+  /// All IPs currently banned in the jail.
+  pub async fn get_banned(&self, jail: &str) -> Result<Vec<String>> {
+    let banned = self.send(&["get", jail, "banned"]).await?;
+    Ok(banned.as_list().map(|ips| ips.iter().map(|ip| ip.to_string()).collect()).unwrap_or_default())
+  }
+
+  pub async fn is_banned(&self, jail: &str, ip: &str) -> Result<bool> {
+    Ok(self.get_banned(jail).await?.iter().any(|x| x == ip))
+  }
+
+  /// Returns the jails out of `jails` the IP is currently banned in.
+  pub async fn banned_jails(&self, ip: &str, jails: &[String]) -> Result<Vec<String>> {
+    let mut banned = vec![];
+    for jail in jails {
+      if self.is_banned(jail, ip).await? {
+        banned.push(jail.clone());
+      }
+    }
+    Ok(banned)
+  }
+}
+
+/// Splits fail2ban's comma separated jail list, e.g. `nginx-http-auth, sshd`.
+pub fn split_jail_list(list: &str) -> Vec<String> {
+  list.split(',').map(|jail| jail.trim().to_string()).filter(|jail| !jail.is_empty()).collect()
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use tokio::net::UnixListener;
+
+  use super::*;
+
+  fn pair(key: &str, value: Value) -> Value {
+    Value::Tuple(vec![Value::String(key.to_string()), value])
+  }
+
+  /// Answers like a fail2ban server with a single `sshd` jail.
+  fn respond(command: &[String], banned: &mut Vec<String>) -> Value {
+    let args: Vec<&str> = command.iter().map(|x| x.as_str()).collect();
+    let ok = |x: Value| Value::Tuple(vec![Value::Int(0), x]);
+    let ips = |banned: &Vec<String>| Value::List(banned.iter().map(|x| Value::String(x.clone())).collect());
+    match args.as_slice() {
+      ["status"] => {
+        ok(Value::List(vec![pair("Number of jail", Value::Int(1)), pair("Jail list", Value::String("sshd".into()))]))
+      },
+      ["status", "sshd"] => ok(Value::List(vec![
+        pair("Filter", Value::List(vec![pair("Currently failed", Value::Int(2)), pair("Total failed", Value::Int(7))])),
+        pair(
+          "Actions",
+          Value::List(vec![
+            pair("Currently banned", Value::Int(banned.len() as i64)),
+            pair("Total banned", Value::Int(3)),
+            pair("Banned IP list", ips(banned)),
+          ]),
+        ),
+      ])),
+      ["get", "sshd", "banned"] => ok(ips(banned)),
+      ["set", "sshd", "banip", ip] => {
+        banned.push(ip.to_string());
+        ok(Value::Int(1))
+      },
+      ["set", "sshd", "unbanip", ip] => {
+        banned.retain(|x| x != ip);
+        ok(Value::Int(1))
+      },
+      _ => Value::Tuple(vec![
+        Value::Int(1),
+        Value::Reduce(
+          Box::new(Value::Global("builtins".into(), "UnknownJailException".into())),
+          Box::new(Value::Tuple(vec![Value::String(args[1..].join(" "))])),
+        ),
+      ]),
+    }
+  }
+
+  pub(crate) fn fake_server(name: &str) -> (F2BClient, Arc<Mutex<Vec<String>>>) {
+    let path = std::env::temp_dir().join(format!("s2b-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let banned = Arc::new(Mutex::new(vec![String::from("10.0.0.1")]));
+    let state = banned.clone();
+    tokio::spawn(async move {
+      loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let state = state.clone();
+        tokio::spawn(async move {
+          let mut request: Vec<u8> = vec![];
+          let mut buf = [0u8; 1024];
+          while !request.ends_with(END_COMMAND) {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+              return;
+            }
+            request.extend_from_slice(&buf[..n]);
+          }
+          let command = pickle::loads(&request[..request.len() - END_COMMAND.len()]).unwrap();
+          let command: Vec<String> = command.as_list().unwrap().iter().map(|x| x.to_string()).collect();
+          let response = respond(&command, &mut state.lock().unwrap());
+          stream.write_all(&pickle::dumps(&response)).await.unwrap();
+          stream.write_all(END_COMMAND).await.unwrap();
+        });
+      }
+    });
+    (F2BClient::new(path), banned)
+  }
+
+  #[tokio::test]
+  async fn test_status() {
+    let (client, _) = fake_server("status");
+    assert_eq!(client.jails().await.unwrap(), vec!["sshd"]);
+    let status = client.jail_status("sshd").await.unwrap();
+    assert_eq!(status, JailStatus {
+      currently_failed: 2,
+      total_failed: 7,
+      currently_banned: 1,
+      total_banned: 3,
+      banned: vec![String::from("10.0.0.1")],
+    });
+  }
+
+  #[tokio::test]
+  async fn test_ban_unban() {
+    let (client, banned) = fake_server("banunban");
+    assert!(!client.is_banned("sshd", "192.168.1.10").await.unwrap());
+    client.ban_ip("sshd", "192.168.1.10").await.unwrap();
+    assert!(client.is_banned("sshd", "192.168.1.10").await.unwrap());
+    assert_eq!(client.banned_jails("192.168.1.10", &[String::from("sshd")]).await.unwrap(), vec!["sshd"]);
+    client.unban_ip("sshd", "10.0.0.1").await.unwrap();
+    assert_eq!(*banned.lock().unwrap(), vec![String::from("192.168.1.10")]);
+    assert_eq!(client.get_banned("sshd").await.unwrap(), vec!["192.168.1.10"]);
+  }
+
+  #[tokio::test]
+  async fn test_server_error() {
+    let (client, _) = fake_server("error");
+    let err = client.ban_ip("nginx", "1.2.3.4").await.unwrap_err();
+    assert!(err.to_string().contains("UnknownJailException"));
+  }
+
+  #[tokio::test]
+  async fn test_missing_socket() {
+    let client = F2BClient::new("/nonexistent/fail2ban.sock");
+    assert!(client.jails().await.is_err());
+  }
+
+  #[test]
+  fn test_split_jail_list() {
+    assert_eq!(split_jail_list("nginx-http-auth, postfix, sshd"), vec!["nginx-http-auth", "postfix", "sshd"]);
+    assert!(split_jail_list("").is_empty());
+  }
+}
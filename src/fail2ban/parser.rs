@@ -0,0 +1,167 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use chrono::NaiveDateTime;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::ipextract;
+
+/// What a fail2ban.log line is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum F2BEventKind {
+  /// A filter matched, the IP collects a failure.
+  Found,
+  Ban,
+  Unban,
+  /// Ban re-applied from the fail2ban db on (re)start.
+  RestoreBan,
+  AlreadyBanned,
+  JailStarted,
+  JailStopped,
+  /// Any other line, e.g. server start up or config messages.
+  Other,
+}
+
+/// A single parsed fail2ban.log line.
+///
+/// 2023-11-19 16:08:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Ban 192.168.0.5
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct F2BEvent {
+  pub kind: F2BEventKind,
+  #[serde(skip)]
+  pub timestamp: Option<NaiveDateTime>,
+  /// Logging component, e.g. `fail2ban.actions`.
+  pub component: String,
+  /// Log level, e.g. `NOTICE`.
+  pub level: String,
+  pub jail: Option<String>,
+  pub ip: Option<IpAddr>,
+}
+
+impl F2BEvent {
+  /// Only actual new bans count as a ban, restored bans were counted when they first happened.
+  pub fn is_ban(&self) -> bool {
+    self.kind == F2BEventKind::Ban
+  }
+
+  /// True for lines that change or confirm the ban state of an IP.
+  pub fn is_ban_action(&self) -> bool {
+    matches!(self.kind, F2BEventKind::Ban | F2BEventKind::Unban | F2BEventKind::RestoreBan | F2BEventKind::AlreadyBanned)
+  }
+}
+
+fn line_regex() -> &'static Regex {
+  static RE: OnceLock<Regex> = OnceLock::new();
+  RE.get_or_init(|| {
+    Regex::new(r"^(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:,\d{1,6})?)\s+(fail2ban\.[\w.]+)\s*\[\d+\]:\s+(\w+)\s+(.*)$").unwrap()
+  })
+}
+
+fn jail_regex() -> &'static Regex {
+  static RE: OnceLock<Regex> = OnceLock::new();
+  RE.get_or_init(|| Regex::new(r"^\[([^\]]+)\]\s+(.*)$").unwrap())
+}
+
+fn jail_state_regex() -> &'static Regex {
+  static RE: OnceLock<Regex> = OnceLock::new();
+  RE.get_or_init(|| Regex::new(r"^Jail '([^']+)' (started|stopped)").unwrap())
+}
+
+/// Parses a fail2ban.log line, returns `None` for lines that are not in fail2ban's log format.
+pub fn parse_line(line: &str) -> Option<F2BEvent> {
+  let captures = line_regex().captures(line.trim_end())?;
+  // fail2ban separates the milliseconds with a comma
+  let timestamp = NaiveDateTime::parse_from_str(&captures[1].replace(',', "."), "%Y-%m-%d %H:%M:%S%.f").ok();
+  let mut event = F2BEvent {
+    kind: F2BEventKind::Other,
+    timestamp,
+    component: captures[2].to_string(),
+    level: captures[3].to_string(),
+    jail: None,
+    ip: None,
+  };
+  let message = &captures[4];
+
+  if let Some(jail_state) = jail_state_regex().captures(message) {
+    event.jail = Some(jail_state[1].to_string());
+    event.kind = if &jail_state[2] == "started" { F2BEventKind::JailStarted } else { F2BEventKind::JailStopped };
+    return Some(event);
+  }
+
+  let Some(jailed) = jail_regex().captures(message) else { return Some(event) };
+  event.jail = Some(jailed[1].to_string());
+  let rest = &jailed[2];
+  let mut words = rest.split_whitespace();
+  let (kind, ip) = match (words.next(), words.next(), words.next()) {
+    (Some("Found"), Some(ip), _) => (F2BEventKind::Found, ip),
+    (Some("Ban"), Some(ip), _) => (F2BEventKind::Ban, ip),
+    (Some("Unban"), Some(ip), _) => (F2BEventKind::Unban, ip),
+    (Some("Restore"), Some("Ban"), Some(ip)) => (F2BEventKind::RestoreBan, ip),
+    (Some(ip), Some("already"), Some("banned")) => (F2BEventKind::AlreadyBanned, ip),
+    _ => return Some(event),
+  };
+  event.kind = kind;
+  event.ip = ipextract::parse_ip(ip);
+  Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_ban_events() {
+    let ban = parse_line("2023-11-19 16:08:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Ban 192.168.0.5").unwrap();
+    assert_eq!(ban.kind, F2BEventKind::Ban);
+    assert_eq!(ban.jail.as_deref(), Some("sshd"));
+    assert_eq!(ban.level, "NOTICE");
+    assert_eq!(ban.component, "fail2ban.actions");
+    assert_eq!(ban.ip, Some("192.168.0.5".parse().unwrap()));
+    assert_eq!(ban.timestamp.unwrap().to_string(), "2023-11-19 16:08:12.365");
+    assert!(ban.is_ban());
+
+    let unban = parse_line("2023-11-19 16:18:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Unban 192.168.0.5").unwrap();
+    assert_eq!(unban.kind, F2BEventKind::Unban);
+    assert!(!unban.is_ban());
+    assert!(unban.is_ban_action());
+
+    let restore = parse_line("2023-11-19 16:00:01,017 fail2ban.actions        [804]: NOTICE  [nginx-http-auth] Restore Ban 2001:db8::1").unwrap();
+    assert_eq!(restore.kind, F2BEventKind::RestoreBan);
+    assert_eq!(restore.jail.as_deref(), Some("nginx-http-auth"));
+    assert_eq!(restore.ip, Some("2001:db8::1".parse().unwrap()));
+    assert!(!restore.is_ban());
+
+    let already = parse_line("2023-11-19 16:08:13,001 fail2ban.actions        [804]: NOTICE  [sshd] 192.168.0.5 already banned").unwrap();
+    assert_eq!(already.kind, F2BEventKind::AlreadyBanned);
+    assert_eq!(already.ip, Some("192.168.0.5".parse().unwrap()));
+  }
+
+  #[test]
+  fn test_parse_found_and_jail_state() {
+    let found =
+      parse_line("2023-11-19 16:08:10,101 fail2ban.filter         [804]: INFO    [sshd] Found 192.168.0.5 - 2023-11-19 16:08:10").unwrap();
+    assert_eq!(found.kind, F2BEventKind::Found);
+    assert_eq!(found.level, "INFO");
+    assert_eq!(found.ip, Some("192.168.0.5".parse().unwrap()));
+
+    let started = parse_line("2023-11-19 15:59:58,402 fail2ban.jail           [804]: INFO    Jail 'sshd' started").unwrap();
+    assert_eq!(started.kind, F2BEventKind::JailStarted);
+    assert_eq!(started.jail.as_deref(), Some("sshd"));
+    assert_eq!(started.ip, None);
+
+    let stopped = parse_line("2023-11-19 17:00:00,000 fail2ban.jail           [804]: INFO    Jail 'sshd' stopped").unwrap();
+    assert_eq!(stopped.kind, F2BEventKind::JailStopped);
+  }
+
+  #[test]
+  fn test_parse_other_lines() {
+    let other = parse_line("2023-11-19 15:59:58,120 fail2ban.server         [804]: INFO    Starting Fail2ban v1.0.2").unwrap();
+    assert_eq!(other.kind, F2BEventKind::Other);
+    assert_eq!(other.jail, None);
+    let trailing =
+      parse_line("2023-11-19 16:08:12,365 fail2ban.filter         [804]: INFO    [sshd] Found 1.2.3.4, bad - 2023-11-19 16:08:12").unwrap();
+    assert_eq!(trailing.ip, Some("1.2.3.4".parse().unwrap()));
+    assert!(parse_line("Nov 19 16:08:10 host sshd[1234]: Failed password for root from 1.2.3.4 port 22 ssh2").is_none());
+  }
+}
@@ -0,0 +1,475 @@
+//! Minimal pickle codec for talking to the fail2ban server.
+//! Only covers the opcodes fail2ban uses for commands and responses (protocols 2 to 5).
+
+use color_eyre::eyre::{eyre, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  None,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(String),
+  Bytes(Vec<u8>),
+  List(Vec<Value>),
+  Tuple(Vec<Value>),
+  Dict(Vec<(Value, Value)>),
+  /// module, name
+  Global(String, String),
+  /// callable, args - e.g. exceptions raised by the server
+  Reduce(Box<Value>, Box<Value>),
+}
+
+impl Value {
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::String(x) => Some(x.as_str()),
+      Value::Bytes(x) => std::str::from_utf8(x).ok(),
+      _ => None,
+    }
+  }
+
+  pub fn as_int(&self) -> Option<i64> {
+    match self {
+      Value::Int(x) => Some(*x),
+      Value::Bool(x) => Some(*x as i64),
+      _ => None,
+    }
+  }
+
+  /// Lists, tuples and sets all look the same to us.
+  pub fn as_list(&self) -> Option<&[Value]> {
+    match self {
+      Value::List(x) | Value::Tuple(x) => Some(x.as_slice()),
+      _ => None,
+    }
+  }
+
+  /// Looks up a value in a list of (key, value) pairs as returned by fail2ban's status commands.
+  pub fn get_pair(&self, key: &str) -> Option<&Value> {
+    self.as_list()?.iter().find_map(|pair| {
+      let pair = pair.as_list()?;
+      if pair.len() == 2 && pair[0].as_str() == Some(key) {
+        Some(&pair[1])
+      } else {
+        None
+      }
+    })
+  }
+}
+
+impl std::fmt::Display for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Value::None => write!(f, "None"),
+      Value::Bool(x) => write!(f, "{}", x),
+      Value::Int(x) => write!(f, "{}", x),
+      Value::Float(x) => write!(f, "{}", x),
+      Value::String(x) => write!(f, "{}", x),
+      Value::Bytes(x) => write!(f, "{}", String::from_utf8_lossy(x)),
+      Value::List(x) | Value::Tuple(x) => {
+        let items: Vec<String> = x.iter().map(|v| v.to_string()).collect();
+        write!(f, "[{}]", items.join(", "))
+      },
+      Value::Dict(x) => {
+        let items: Vec<String> = x.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+        write!(f, "{{{}}}", items.join(", "))
+      },
+      Value::Global(module, name) => write!(f, "{}.{}", module, name),
+      Value::Reduce(callable, args) => write!(f, "{}{}", callable, args),
+    }
+  }
+}
+
+const PROTO: u8 = 0x80;
+const FRAME: u8 = 0x95;
+const STOP: u8 = b'.';
+const MARK: u8 = b'(';
+const EMPTY_LIST: u8 = b']';
+const APPEND: u8 = b'a';
+const APPENDS: u8 = b'e';
+const EMPTY_TUPLE: u8 = b')';
+const TUPLE: u8 = b't';
+const TUPLE1: u8 = 0x85;
+const TUPLE2: u8 = 0x86;
+const TUPLE3: u8 = 0x87;
+const EMPTY_DICT: u8 = b'}';
+const SETITEM: u8 = b's';
+const SETITEMS: u8 = b'u';
+const EMPTY_SET: u8 = 0x8f;
+const ADDITEMS: u8 = 0x90;
+const FROZENSET: u8 = 0x91;
+const NONE: u8 = b'N';
+const NEWTRUE: u8 = 0x88;
+const NEWFALSE: u8 = 0x89;
+const BININT: u8 = b'J';
+const BININT1: u8 = b'K';
+const BININT2: u8 = b'M';
+const LONG1: u8 = 0x8a;
+const BINFLOAT: u8 = b'G';
+const BINUNICODE: u8 = b'X';
+const SHORT_BINUNICODE: u8 = 0x8c;
+const BINUNICODE8: u8 = 0x8d;
+const SHORT_BINBYTES: u8 = b'C';
+const BINBYTES: u8 = b'B';
+const BINBYTES8: u8 = 0x8e;
+const SHORT_BINSTRING: u8 = b'U';
+const BINSTRING: u8 = b'T';
+const MEMOIZE: u8 = 0x94;
+const BINPUT: u8 = b'q';
+const LONG_BINPUT: u8 = b'r';
+const BINGET: u8 = b'h';
+const LONG_BINGET: u8 = b'j';
+const GLOBAL: u8 = b'c';
+const STACK_GLOBAL: u8 = 0x93;
+const REDUCE: u8 = b'R';
+const NEWOBJ: u8 = 0x81;
+const BUILD: u8 = b'b';
+
+/// Serializes a value with pickle protocol 2.
+pub fn dumps(value: &Value) -> Vec<u8> {
+  let mut out = vec![PROTO, 2];
+  write_value(&mut out, value);
+  out.push(STOP);
+  out
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+  match value {
+    Value::None => out.push(NONE),
+    Value::Bool(true) => out.push(NEWTRUE),
+    Value::Bool(false) => out.push(NEWFALSE),
+    Value::Int(x) => {
+      if let Ok(x) = i32::try_from(*x) {
+        out.push(BININT);
+        out.extend_from_slice(&x.to_le_bytes());
+      } else {
+        out.push(LONG1);
+        out.push(8);
+        out.extend_from_slice(&x.to_le_bytes());
+      }
+    },
+    Value::Float(x) => {
+      out.push(BINFLOAT);
+      out.extend_from_slice(&x.to_be_bytes());
+    },
+    Value::String(x) => {
+      out.push(BINUNICODE);
+      out.extend_from_slice(&(x.len() as u32).to_le_bytes());
+      out.extend_from_slice(x.as_bytes());
+    },
+    Value::Bytes(x) => {
+      out.push(BINBYTES);
+      out.extend_from_slice(&(x.len() as u32).to_le_bytes());
+      out.extend_from_slice(x);
+    },
+    Value::List(items) => {
+      out.push(EMPTY_LIST);
+      if !items.is_empty() {
+        out.push(MARK);
+        items.iter().for_each(|item| write_value(out, item));
+        out.push(APPENDS);
+      }
+    },
+    Value::Tuple(items) => {
+      out.push(MARK);
+      items.iter().for_each(|item| write_value(out, item));
+      out.push(TUPLE);
+    },
+    Value::Dict(items) => {
+      out.push(EMPTY_DICT);
+      if !items.is_empty() {
+        out.push(MARK);
+        for (key, value) in items {
+          write_value(out, key);
+          write_value(out, value);
+        }
+        out.push(SETITEMS);
+      }
+    },
+    Value::Global(module, name) => {
+      out.push(GLOBAL);
+      out.extend_from_slice(module.as_bytes());
+      out.push(b'\n');
+      out.extend_from_slice(name.as_bytes());
+      out.push(b'\n');
+    },
+    Value::Reduce(callable, args) => {
+      write_value(out, callable);
+      write_value(out, args);
+      out.push(REDUCE);
+    },
+  }
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+    // the length of BINUNICODE8 and BINBYTES8 comes from the data, it may point anywhere
+    let end = self.pos.checked_add(n).filter(|end| *end <= self.data.len())
+      .ok_or_else(|| eyre!("pickle data truncated at byte {}", self.pos))?;
+    let slice = &self.data[self.pos..end];
+    self.pos = end;
+    Ok(slice)
+  }
+
+  fn byte(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<usize> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into()?) as usize)
+  }
+
+  fn u64(&mut self) -> Result<usize> {
+    Ok(usize::try_from(u64::from_le_bytes(self.take(8)?.try_into()?))?)
+  }
+
+  fn line(&mut self) -> Result<String> {
+    let start = self.pos;
+    while self.byte()? != b'\n' {}
+    Ok(String::from_utf8_lossy(&self.data[start..self.pos - 1]).to_string())
+  }
+
+  fn string(&mut self, n: usize) -> Result<Value> {
+    Ok(Value::String(String::from_utf8_lossy(self.take(n)?).to_string()))
+  }
+}
+
+/// Deserializes a pickled value.
+///
+/// Memoized objects are stored as copies, which is fine for the flat data fail2ban sends.
+pub fn loads(data: &[u8]) -> Result<Value> {
+  let mut reader = Reader { data, pos: 0 };
+  let mut stack: Vec<Value> = vec![];
+  let mut marks: Vec<usize> = vec![];
+  let mut memo: Vec<Value> = vec![];
+
+  fn pop(stack: &mut Vec<Value>) -> Result<Value> {
+    stack.pop().ok_or(eyre!("pickle stack underflow"))
+  }
+  fn pop_mark(stack: &mut Vec<Value>, marks: &mut Vec<usize>) -> Result<Vec<Value>> {
+    let mark = marks.pop().ok_or(eyre!("pickle mark missing"))?;
+    if mark > stack.len() {
+      return Err(eyre!("pickle mark out of range"));
+    }
+    Ok(stack.split_off(mark))
+  }
+  fn extend(target: &mut Value, items: Vec<Value>) -> Result<()> {
+    match target {
+      Value::List(x) => {
+        x.extend(items);
+        Ok(())
+      },
+      Value::Dict(x) => {
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+          x.push((key, value));
+        }
+        Ok(())
+      },
+      _ => Err(eyre!("pickle cannot add items to {:?}", target)),
+    }
+  }
+  fn put(memo: &mut Vec<Value>, idx: usize, value: Value) {
+    if memo.len() <= idx {
+      memo.resize(idx + 1, Value::None);
+    }
+    memo[idx] = value;
+  }
+  fn get(memo: &[Value], idx: usize) -> Result<Value> {
+    memo.get(idx).cloned().ok_or(eyre!("pickle memo {} missing", idx))
+  }
+
+  loop {
+    let opcode = reader.byte()?;
+    match opcode {
+      PROTO => {
+        reader.byte()?;
+      },
+      FRAME => {
+        reader.take(8)?;
+      },
+      STOP => return pop(&mut stack),
+      MARK => marks.push(stack.len()),
+      EMPTY_LIST => stack.push(Value::List(vec![])),
+      EMPTY_TUPLE => stack.push(Value::Tuple(vec![])),
+      EMPTY_DICT => stack.push(Value::Dict(vec![])),
+      EMPTY_SET => stack.push(Value::List(vec![])),
+      APPEND => {
+        let item = pop(&mut stack)?;
+        let target = stack.last_mut().ok_or(eyre!("pickle stack underflow"))?;
+        extend(target, vec![item])?;
+      },
+      APPENDS | SETITEMS | ADDITEMS => {
+        let items = pop_mark(&mut stack, &mut marks)?;
+        let target = stack.last_mut().ok_or(eyre!("pickle stack underflow"))?;
+        extend(target, items)?;
+      },
+      SETITEM => {
+        let value = pop(&mut stack)?;
+        let key = pop(&mut stack)?;
+        let target = stack.last_mut().ok_or(eyre!("pickle stack underflow"))?;
+        extend(target, vec![key, value])?;
+      },
+      TUPLE => {
+        let items = pop_mark(&mut stack, &mut marks)?;
+        stack.push(Value::Tuple(items));
+      },
+      FROZENSET => {
+        let items = pop_mark(&mut stack, &mut marks)?;
+        stack.push(Value::List(items));
+      },
+      TUPLE1 | TUPLE2 | TUPLE3 => {
+        let n = (opcode - TUPLE1 + 1) as usize;
+        if stack.len() < n {
+          return Err(eyre!("pickle stack underflow"));
+        }
+        let items = stack.split_off(stack.len() - n);
+        stack.push(Value::Tuple(items));
+      },
+      NONE => stack.push(Value::None),
+      NEWTRUE => stack.push(Value::Bool(true)),
+      NEWFALSE => stack.push(Value::Bool(false)),
+      BININT => stack.push(Value::Int(i32::from_le_bytes(reader.take(4)?.try_into()?) as i64)),
+      BININT1 => stack.push(Value::Int(reader.byte()? as i64)),
+      BININT2 => stack.push(Value::Int(u16::from_le_bytes(reader.take(2)?.try_into()?) as i64)),
+      LONG1 => {
+        let n = reader.byte()? as usize;
+        let bytes = reader.take(n)?;
+        if n > 8 {
+          return Err(eyre!("pickle integer too large"));
+        }
+        let mut buf = if bytes.last().is_some_and(|b| b & 0x80 != 0) { [0xff; 8] } else { [0; 8] };
+        buf[..n].copy_from_slice(bytes);
+        stack.push(Value::Int(i64::from_le_bytes(buf)));
+      },
+      BINFLOAT => stack.push(Value::Float(f64::from_be_bytes(reader.take(8)?.try_into()?))),
+      BINUNICODE => {
+        let n = reader.u32()?;
+        stack.push(reader.string(n)?);
+      },
+      SHORT_BINUNICODE | SHORT_BINSTRING => {
+        let n = reader.byte()? as usize;
+        stack.push(reader.string(n)?);
+      },
+      BINUNICODE8 => {
+        let n = reader.u64()?;
+        stack.push(reader.string(n)?);
+      },
+      BINSTRING => {
+        let n = reader.u32()?;
+        stack.push(reader.string(n)?);
+      },
+      SHORT_BINBYTES => {
+        let n = reader.byte()? as usize;
+        stack.push(Value::Bytes(reader.take(n)?.to_vec()));
+      },
+      BINBYTES => {
+        let n = reader.u32()?;
+        stack.push(Value::Bytes(reader.take(n)?.to_vec()));
+      },
+      BINBYTES8 => {
+        let n = reader.u64()?;
+        stack.push(Value::Bytes(reader.take(n)?.to_vec()));
+      },
+      MEMOIZE => {
+        let top = stack.last().cloned().ok_or(eyre!("pickle stack underflow"))?;
+        let idx = memo.len();
+        put(&mut memo, idx, top);
+      },
+      BINPUT => {
+        let idx = reader.byte()? as usize;
+        let top = stack.last().cloned().ok_or(eyre!("pickle stack underflow"))?;
+        put(&mut memo, idx, top);
+      },
+      LONG_BINPUT => {
+        let idx = reader.u32()?;
+        let top = stack.last().cloned().ok_or(eyre!("pickle stack underflow"))?;
+        put(&mut memo, idx, top);
+      },
+      BINGET => {
+        let idx = reader.byte()? as usize;
+        stack.push(get(&memo, idx)?);
+      },
+      LONG_BINGET => {
+        let idx = reader.u32()?;
+        stack.push(get(&memo, idx)?);
+      },
+      GLOBAL => {
+        let module = reader.line()?;
+        let name = reader.line()?;
+        stack.push(Value::Global(module, name));
+      },
+      STACK_GLOBAL => {
+        let name = pop(&mut stack)?;
+        let module = pop(&mut stack)?;
+        stack.push(Value::Global(module.to_string(), name.to_string()));
+      },
+      REDUCE | NEWOBJ => {
+        let args = pop(&mut stack)?;
+        let callable = pop(&mut stack)?;
+        stack.push(Value::Reduce(Box::new(callable), Box::new(args)));
+      },
+      BUILD => {
+        // object state is of no interest to us
+        pop(&mut stack)?;
+      },
+      _ => return Err(eyre!("unsupported pickle opcode 0x{:02x} at byte {}", opcode, reader.pos - 1)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_roundtrip() {
+    let value = Value::Tuple(vec![
+      Value::Int(0),
+      Value::List(vec![
+        Value::Tuple(vec![Value::String("Jail list".to_string()), Value::String("sshd, nginx".to_string())]),
+        Value::Int(-5),
+        Value::Int(1 << 40),
+        Value::None,
+        Value::Bool(true),
+        Value::Float(1.5),
+      ]),
+    ]);
+    assert_eq!(loads(&dumps(&value)).unwrap(), value);
+  }
+
+  #[test]
+  fn test_loads_python_protocol_4() {
+    // pickle.dumps((0, [('Number of jail', 1), ('Jail list', 'sshd')]), 4)
+    let data: &[u8] = b"\x80\x04\x953\x00\x00\x00\x00\x00\x00\x00K\x00]\x94(\x8c\x0eNumber of jail\x94K\x01\x86\x94\x8c\tJail list\x94\x8c\x04sshd\x94\x86\x94e\x86\x94.";
+    let value = loads(data).unwrap();
+    let response = value.as_list().unwrap();
+    assert_eq!(response[0], Value::Int(0));
+    assert_eq!(response[1].get_pair("Jail list").and_then(|v| v.as_str()), Some("sshd"));
+    assert_eq!(response[1].get_pair("Number of jail").and_then(|v| v.as_int()), Some(1));
+  }
+
+  #[test]
+  fn test_loads_exception() {
+    // pickle.dumps((1, ValueError('boom')), 4)
+    let data: &[u8] = b"\x80\x04\x95*\x00\x00\x00\x00\x00\x00\x00K\x01\x8c\x08builtins\x94\x8c\nValueError\x94\x93\x94\x8c\x04boom\x94\x85\x94R\x94\x86\x94.";
+    let value = loads(data).unwrap();
+    let response = value.as_list().unwrap();
+    assert_eq!(response[0], Value::Int(1));
+    assert_eq!(response[1].to_string(), "builtins.ValueError[boom]");
+  }
+
+  #[test]
+  fn test_loads_truncated() {
+    assert!(loads(b"\x80\x04\x8c\x04ss").is_err());
+    // lengths past the end of memory
+    assert!(loads(b"\x80\x04\x8d\xff\xff\xff\xff\xff\xff\xff\xffss").is_err());
+    assert!(loads(b"\x80\x04\x8e\xfe\xff\xff\xff\xff\xff\xff\xffss").is_err());
+  }
+}
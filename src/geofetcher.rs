@@ -1,52 +1,138 @@
+//! Geolocation providers.
+//! Where the location of an IP comes from is picked in the `geo` section of the config:
+//! ip-api.com over the network, or offline from an MMDB (GeoLite2, DB-IP) or IP range CSV file.
+
+mod csv_db;
+mod ip_api;
+mod mmdb;
+mod queue;
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use futures::future::BoxFuture;
+
+pub use csv_db::CsvProvider;
+pub use ip_api::{deserialize_geolocation, fetch_geolocation, IpApiProvider};
+pub use mmdb::MmdbProvider;
+pub use queue::{GeoQueue, GeoRequest};
+
+use crate::config::{GeoConfig, GeoProviderKind};
 use crate::database::schema::ip::IP;
+use crate::ipextract::AddrClass;
 
-pub async fn fetch_geolocation(ip: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-    let url = format!("http://ip-api.com/json/{ip}");
-    let resp = reqwest::get(url)
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
-    //println!("{:#?}", resp);
-    Ok(resp) 
+pub trait GeoProvider: Send + Sync {
+    /// Name used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Location of the address, `None` if the provider doesn't know it.
+    fn lookup(&self, addr: IpAddr) -> BoxFuture<'_, Result<Option<IP>, GeoError>>;
+
+    /// Most addresses a `lookup_batch` call takes.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// `lookup_batch` calls allowed per minute, `None` if there is no limit.
+    fn rate_limit(&self) -> Option<u32> {
+        None
+    }
+
+    /// Looks up several addresses at once, the outer error is for the whole batch, e.g. being rate limited.
+    fn lookup_batch(&self, addrs: Vec<IpAddr>) -> BoxFuture<'_, BatchResult> {
+        Box::pin(async move {
+            let mut results = vec![];
+            for addr in addrs {
+                results.push((addr, self.lookup(addr).await));
+            }
+            Ok(results)
+        })
+    }
 }
 
-pub fn deserialize_geolocation(geodat: serde_json::Value, is_banned: bool) -> Option<IP> {
+pub type BatchResult = Result<Vec<(IpAddr, Result<Option<IP>, GeoError>)>, GeoError>;
+
+/// Why a lookup gave no location.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeoError {
+    /// The provider couldn't be reached.
+    Request(String),
+    /// The provider answered but couldn't locate the address, with its reason.
+    Failed(String),
+    /// Private, loopback or otherwise reserved address that has no location.
+    Reserved(String),
+    /// Too many requests, with the time until the limit resets if the provider told us.
+    RateLimited(Option<Duration>),
+    /// The response or database record didn't have the expected shape.
+    Malformed(String),
+}
 
-    let geoip = geodat.get("query");
-    if geoip.is_none() {
-        return None
+impl std::fmt::Display for GeoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeoError::Request(e) => write!(f, "request failed: {}", e),
+            GeoError::Failed(e) => write!(f, "lookup failed: {}", e),
+            GeoError::Reserved(e) => write!(f, "reserved address: {}", e),
+            GeoError::RateLimited(Some(ttl)) => write!(f, "rate limited for {}s", ttl.as_secs()),
+            GeoError::RateLimited(None) => write!(f, "rate limited"),
+            GeoError::Malformed(e) => write!(f, "malformed response: {}", e),
+        }
     }
-    let geoip = geoip.unwrap().as_str().unwrap();
-        
-    let timestamp = chrono::offset::Local::now().to_rfc3339();
-
-    let geolat = geodat.get("lat").unwrap().as_number().unwrap().to_string(); // CRASH ON PI
-    let geolon = geodat.get("lon").unwrap().as_number().unwrap().to_string();
-    let geoisp = String::from(geodat.get("isp").unwrap().as_str().unwrap());
-
-    let geocountry = String::from(geodat.get("country").unwrap().as_str().unwrap());
-    let geocity = String::from(geodat.get("city").unwrap().as_str().unwrap());
-    let geocountrycode = String::from(geodat.get("countryCode").unwrap().as_str().unwrap());
-    let georegionname = String::from(geodat.get("regionName").unwrap().as_str().unwrap());
-
-
-    let mut geodata: IP = IP::default();
-    geodata.created_at = timestamp;
-    geodata.ip = geoip.to_string();
-    geodata.lat = geolat;
-    geodata.lon = geolon;
-    geodata.isp = geoisp;
-    geodata.is_banned = is_banned;
-    geodata.banned_times = match is_banned {false => 0, true => 1};
-    geodata.country = geocountry;
-    geodata.countrycode = geocountrycode;
-    geodata.city = geocity;
-    geodata.region = georegionname;
-    geodata.warnings = 1;
+}
+
+impl std::error::Error for GeoError {}
+
+/// Builds the provider the config asks for, offline databases are loaded right away.
+pub fn provider_from_config(config: &GeoConfig) -> Result<Arc<dyn GeoProvider>> {
+    Ok(match config.provider {
+        GeoProviderKind::IpApi => Arc::new(IpApiProvider),
+        GeoProviderKind::Mmdb => Arc::new(MmdbProvider::open(&config.city_db, &config.asn_db)?),
+        GeoProviderKind::Csv => Arc::new(CsvProvider::open(&config.csv_db)?),
+    })
+}
+
+/// Country and ISP of LAN hosts, which no provider can locate.
+pub const LOCAL_NETWORK: &str = "Local network";
+/// Country and ISP of documentation and other special purpose addresses.
+pub const RESERVED_RANGE: &str = "Reserved range";
 
+/// Synthetic record for addresses that aren't looked up remotely, `None` for public addresses.
+/// They have no coordinates, so they are listed but not drawn on the map.
+pub fn unlocatable(addr: IpAddr) -> Option<IP> {
+    let class = crate::ipextract::classify(addr);
+    let name = match class {
+        AddrClass::Public => return None,
+        class if class.is_local() => LOCAL_NETWORK,
+        _ => RESERVED_RANGE,
+    };
+    let mut geodata = located(addr);
+    geodata.country = String::from(name);
+    geodata.isp = String::from(name);
+    geodata.region = String::from(class.label());
+    geodata.city = String::from(class.label());
     Some(geodata)
 }
 
+/// Fresh record for a located address, the provider fills in the location.
+fn located(addr: IpAddr) -> IP {
+    let mut geodata = IP::from_addr(addr);
+    geodata.created_at = chrono::offset::Local::now();
+    geodata.geo_updated_at = Some(geodata.created_at);
+    geodata.warnings = 1;
+    geodata
+}
+
+/// The network of `len` leading bits `addr` is in, e.g. `192.0.2.0/24`.
+fn network_prefix(addr: IpAddr, len: u32) -> String {
+    let network = match addr {
+        IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & u32::MAX.checked_shl(32 - len.min(32)).unwrap_or(0)).into()),
+        IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & u128::MAX.checked_shl(128 - len.min(128)).unwrap_or(0)).into()),
+    };
+    format!("{}/{}", network, len)
+}
+
 pub async fn fetch_home() -> Result<String, Box<dyn std::error::Error>>{
     let url = format!("https://ident.me/");
     let resp = reqwest::get(url)
@@ -56,3 +142,27 @@ pub async fn fetch_home() -> Result<String, Box<dyn std::error::Error>>{
     Ok(text)  
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlocatable() {
+        let lan = unlocatable("192.168.1.20".parse().unwrap()).unwrap();
+        assert_eq!((lan.country.as_str(), lan.isp.as_str(), lan.city.as_str()), (LOCAL_NETWORK, LOCAL_NETWORK, "Private"));
+        assert_eq!(lan.ip, "192.168.1.20");
+        assert_eq!(lan.coordinates(), None);
+        assert_eq!(unlocatable("::1".parse().unwrap()).unwrap().city, "Loopback");
+        assert_eq!(unlocatable("100.64.1.1".parse().unwrap()).unwrap().country, LOCAL_NETWORK);
+        assert_eq!(unlocatable("203.0.113.9".parse().unwrap()).unwrap().country, RESERVED_RANGE);
+        assert!(unlocatable("8.8.8.8".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_network_prefix() {
+        assert_eq!(network_prefix("192.0.2.77".parse().unwrap(), 24), "192.0.2.0/24");
+        assert_eq!(network_prefix("192.0.2.77".parse().unwrap(), 32), "192.0.2.77/32");
+        assert_eq!(network_prefix("192.0.2.77".parse().unwrap(), 0), "0.0.0.0/0");
+        assert_eq!(network_prefix("2001:db8:1::5".parse().unwrap(), 32), "2001:db8::/32");
+    }
+}
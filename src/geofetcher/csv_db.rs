@@ -0,0 +1,232 @@
+use std::net::IpAddr;
+
+use color_eyre::eyre::{eyre, Result};
+use futures::future::BoxFuture;
+
+use super::{GeoError, GeoProvider};
+use crate::database::schema::ip::IP;
+
+/// Looks addresses up offline in a CSV file of IP ranges.
+/// Files with a header row are read by column name (`ip_start`, `ip_end` or `network`, `country`, `country_code`,
+/// `region`, `city`, `latitude`, `longitude`, `isp`, `asn`, `as_org`), files without one in the DB-IP City Lite layout
+/// `ip_start,ip_end,continent,country_code,region,city,latitude,longitude`.
+pub struct CsvProvider {
+    /// Ranges sorted by start, IPv4 before IPv6.
+    ranges: Vec<Range>,
+}
+
+struct Range {
+    start: (bool, u128),
+    end: (bool, u128),
+    location: Location,
+}
+
+#[derive(Default)]
+struct Location {
+    country: String,
+    countrycode: String,
+    region: String,
+    city: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    isp: String,
+    asn: Option<u32>,
+    as_org: String,
+    prefix: String,
+}
+
+/// Column index of every field, `None` if the file doesn't have it.
+#[derive(Default)]
+struct Columns {
+    start: Option<usize>,
+    end: Option<usize>,
+    network: Option<usize>,
+    country: Option<usize>,
+    countrycode: Option<usize>,
+    region: Option<usize>,
+    city: Option<usize>,
+    lat: Option<usize>,
+    lon: Option<usize>,
+    isp: Option<usize>,
+    asn: Option<usize>,
+    as_org: Option<usize>,
+}
+
+impl Columns {
+    fn dbip() -> Self {
+        Columns { start: Some(0), end: Some(1), countrycode: Some(3), region: Some(4), city: Some(5), lat: Some(6), lon: Some(7), ..Default::default() }
+    }
+
+    fn from_header(header: &csv::StringRecord) -> Self {
+        let find = |names: &[&str]| header.iter().position(|column| names.contains(&column.trim().to_lowercase().as_str()));
+        Columns {
+            start: find(&["ip_start", "start_ip", "start"]),
+            end: find(&["ip_end", "end_ip", "end"]),
+            network: find(&["network", "cidr"]),
+            country: find(&["country", "country_name"]),
+            countrycode: find(&["country_code", "countrycode", "country_iso_code"]),
+            region: find(&["region", "region_name", "stateprov", "subdivision"]),
+            city: find(&["city", "city_name"]),
+            lat: find(&["latitude", "lat"]),
+            lon: find(&["longitude", "lon"]),
+            isp: find(&["isp", "organization", "autonomous_system_organization", "as_org"]),
+            asn: find(&["asn", "autonomous_system_number", "as_number"]),
+            as_org: find(&["as_org", "autonomous_system_organization"]),
+        }
+    }
+
+    fn range(&self, record: &csv::StringRecord) -> Option<((bool, u128), (bool, u128))> {
+        let field = |idx: Option<usize>| idx.and_then(|idx| record.get(idx)).map(str::trim);
+        if let Some(network) = field(self.network) {
+            return parse_network(network);
+        }
+        let start = key(field(self.start)?.parse().ok()?);
+        let end = key(field(self.end)?.parse().ok()?);
+        (start.0 == end.0 && start <= end).then_some((start, end))
+    }
+
+    fn location(&self, record: &csv::StringRecord) -> Location {
+        let field = |idx: Option<usize>| idx.and_then(|idx| record.get(idx)).unwrap_or_default().trim().to_string();
+        let countrycode = field(self.countrycode);
+        Location {
+            // DB-IP only has the code
+            country: if self.country.is_some() { field(self.country) } else { countrycode.clone() },
+            countrycode,
+            region: field(self.region),
+            city: field(self.city),
+            lat: field(self.lat).parse().ok(),
+            lon: field(self.lon).parse().ok(),
+            isp: field(self.isp),
+            asn: field(self.asn).trim_start_matches("AS").parse().ok(),
+            as_org: field(self.as_org),
+            // ranges given as start and end aren't necessarily a single network
+            prefix: normalized_network(&field(self.network)).unwrap_or_default(),
+        }
+    }
+}
+
+impl CsvProvider {
+    pub fn open(path: &str) -> Result<Self> {
+        if path.is_empty() {
+            return Err(eyre!("the csv provider needs geo.csv_db"));
+        }
+        let file = std::fs::File::open(path).map_err(|e| eyre!("{}: {}", path, e))?;
+        Self::from_reader(file)
+    }
+
+    fn from_reader(reader: impl std::io::Read) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(reader);
+        let mut records = reader.records().peekable();
+        let columns = match records.peek() {
+            // data rows start with an address or network
+            Some(Ok(first)) if first.get(0).is_some_and(|field| field.trim().parse::<IpAddr>().is_err() && parse_network(field.trim()).is_none()) => {
+                let header = records.next().unwrap()?;
+                Columns::from_header(&header)
+            },
+            _ => Columns::dbip(),
+        };
+        if columns.network.is_none() && (columns.start.is_none() || columns.end.is_none()) {
+            return Err(eyre!("csv has neither ip_start/ip_end nor network columns"));
+        }
+        let mut ranges = vec![];
+        for record in records {
+            let record = record?;
+            match columns.range(&record) {
+                Some((start, end)) => ranges.push(Range { start, end, location: columns.location(&record) }),
+                None => log::warn!("Skipping csv row without a valid range: {:?}", record),
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(Self { ranges })
+    }
+
+    pub fn lookup_addr(&self, addr: IpAddr) -> Option<IP> {
+        let key = key(addr);
+        let idx = self.ranges.partition_point(|range| range.start <= key);
+        let range = self.ranges.get(idx.checked_sub(1)?).filter(|range| key <= range.end)?;
+        let location = &range.location;
+        let mut geodata = super::located(addr);
+        geodata.country = location.country.clone();
+        geodata.countrycode = location.countrycode.clone();
+        geodata.region = location.region.clone();
+        geodata.city = location.city.clone();
+        geodata.lat = location.lat;
+        geodata.lon = location.lon;
+        geodata.isp = location.isp.clone();
+        geodata.asn = location.asn;
+        geodata.as_org = location.as_org.clone();
+        geodata.prefix = location.prefix.clone();
+        Some(geodata)
+    }
+}
+
+impl GeoProvider for CsvProvider {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn lookup(&self, addr: IpAddr) -> BoxFuture<'_, Result<Option<IP>, GeoError>> {
+        Box::pin(std::future::ready(Ok(self.lookup_addr(addr))))
+    }
+}
+
+/// `192.0.2.77/24` as `192.0.2.0/24`, `None` if it isn't a network.
+fn normalized_network(network: &str) -> Option<String> {
+    parse_network(network)?;
+    let (addr, len) = network.split_once('/')?;
+    Some(super::network_prefix(addr.parse().ok()?, len.parse().ok()?))
+}
+
+/// Sort key, IPv4 addresses sort before all IPv6 ones.
+fn key(addr: IpAddr) -> (bool, u128) {
+    match addr {
+        IpAddr::V4(v4) => (false, u32::from(v4) as u128),
+        IpAddr::V6(v6) => (true, u128::from(v6)),
+    }
+}
+
+/// First and last address of a `192.0.2.0/24` network.
+fn parse_network(network: &str) -> Option<((bool, u128), (bool, u128))> {
+    let (addr, prefix) = network.split_once('/')?;
+    let (is_v6, value) = key(addr.parse().ok()?);
+    let bits = if is_v6 { 128 } else { 32 };
+    let prefix: u32 = prefix.parse().ok().filter(|prefix| *prefix <= bits)?;
+    let host_mask = if prefix == 0 { u128::MAX >> (128 - bits) } else { (1u128 << (bits - prefix)) - 1 };
+    let start = value & !host_mask;
+    Some(((is_v6, start), (is_v6, start | host_mask)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_lookup() {
+        let dbip = "\
+1.0.0.0,1.0.0.255,OC,AU,Queensland,\"South Brisbane\",-27.4767,153.017
+192.0.2.0,192.0.2.127,EU,DE,Berlin,Berlin,52.52,13.405
+2001:db8::,2001:db8::ffff,EU,NL,\"North Holland\",Amsterdam,52.37,4.89
+";
+        let provider = CsvProvider::from_reader(dbip.as_bytes()).unwrap();
+        let ip = provider.lookup_addr("192.0.2.77".parse().unwrap()).unwrap();
+        assert_eq!((ip.country.as_str(), ip.city.as_str(), ip.lat), ("DE", "Berlin", Some(52.52)));
+        assert_eq!(provider.lookup_addr("1.0.0.1".parse().unwrap()).unwrap().city, "South Brisbane");
+        assert_eq!(ip.prefix, "");
+        assert_eq!(provider.lookup_addr("2001:db8::1".parse().unwrap()).unwrap().city, "Amsterdam");
+        assert!(provider.lookup_addr("192.0.2.200".parse().unwrap()).is_none());
+        assert!(provider.lookup_addr("0.0.0.1".parse().unwrap()).is_none());
+
+        let named = "\
+network,country_name,country_code,city,latitude,longitude,isp,asn
+198.51.100.0/24,Germany,DE,Hamburg,53.55,9.99,Example Networks,AS64496
+not-a-network,Nowhere,XX,,,,,
+";
+        let provider = CsvProvider::from_reader(named.as_bytes()).unwrap();
+        let ip = provider.lookup_addr("198.51.100.255".parse().unwrap()).unwrap();
+        assert_eq!((ip.country.as_str(), ip.countrycode.as_str(), ip.isp.as_str()), ("Germany", "DE", "Example Networks"));
+        assert_eq!((ip.asn, ip.prefix.as_str()), (Some(64496), "198.51.100.0/24"));
+        assert!(provider.lookup_addr("198.51.101.0".parse().unwrap()).is_none());
+
+        assert!(CsvProvider::from_reader("country,city\nDE,Berlin\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+
+use super::{BatchResult, GeoError, GeoProvider};
+use crate::database::schema::ip::IP;
+
+/// Looks addresses up at ip-api.com, needs outbound internet.
+pub struct IpApiProvider;
+
+impl GeoProvider for IpApiProvider {
+    fn name(&self) -> &'static str {
+        "ip-api"
+    }
+
+    fn lookup(&self, addr: IpAddr) -> BoxFuture<'_, Result<Option<IP>, GeoError>> {
+        Box::pin(async move { fetch_geolocation(&addr.to_string()).await.map(Some) })
+    }
+
+    fn batch_size(&self) -> usize {
+        BATCH_SIZE
+    }
+
+    fn rate_limit(&self) -> Option<u32> {
+        Some(BATCH_REQUESTS_PER_MINUTE)
+    }
+
+    fn lookup_batch(&self, addrs: Vec<IpAddr>) -> BoxFuture<'_, BatchResult> {
+        Box::pin(async move {
+            let queries: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+            let resp = reqwest::Client::new()
+                .post("http://ip-api.com/batch")
+                .json(&queries)
+                .send()
+                .await
+                .map_err(|e| GeoError::Request(e.to_string()))?;
+            let body = rate_limited_body(resp).await?;
+            let results = deserialize_batch(&body)?;
+            if results.len() != addrs.len() {
+                return Err(GeoError::Malformed(format!("{} answers for {} addresses", results.len(), addrs.len())));
+            }
+            Ok(addrs.into_iter().zip(results.into_iter().map(|result| result.map(Some))).collect())
+        })
+    }
+}
+
+/// Addresses ip-api takes in one batch request.
+const BATCH_SIZE: usize = 100;
+/// ip-api allows 45 single lookups a minute, but only 15 batch requests.
+const BATCH_REQUESTS_PER_MINUTE: u32 = 15;
+
+/// Answer of `http://ip-api.com/json/{ip}`, every location field may be missing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpApiResponse {
+    status: String,
+    message: Option<String>,
+    query: Option<String>,
+    country: Option<String>,
+    country_code: Option<String>,
+    region_name: Option<String>,
+    city: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    isp: Option<String>,
+    /// Number and name of the AS, e.g. `AS64496 Example`.
+    #[serde(rename = "as")]
+    autonomous_system: Option<String>,
+}
+
+pub async fn fetch_geolocation(ip: &str) -> Result<IP, GeoError> {
+    let url = format!("http://ip-api.com/json/{ip}");
+    let resp = reqwest::get(url).await.map_err(|e| GeoError::Request(e.to_string()))?;
+    let body = rate_limited_body(resp).await?;
+    deserialize_geolocation(&body)
+}
+
+async fn rate_limited_body(resp: reqwest::Response) -> Result<String, GeoError> {
+    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        // seconds until the limit resets
+        let ttl = resp.headers().get("X-Ttl").and_then(|ttl| ttl.to_str().ok()?.parse().ok());
+        return Err(GeoError::RateLimited(ttl.map(Duration::from_secs)));
+    }
+    resp.text().await.map_err(|e| GeoError::Request(e.to_string()))
+}
+
+/// Turns an ip-api answer into a record, fields ip-api left out stay empty.
+pub fn deserialize_geolocation(body: &str) -> Result<IP, GeoError> {
+    let resp: IpApiResponse = serde_json::from_str(body).map_err(|e| GeoError::Malformed(e.to_string()))?;
+    resp.into_ip()
+}
+
+/// Answers of the batch endpoint, one per address in the order they were sent.
+pub fn deserialize_batch(body: &str) -> Result<Vec<Result<IP, GeoError>>, GeoError> {
+    let resps: Vec<IpApiResponse> = serde_json::from_str(body).map_err(|e| GeoError::Malformed(e.to_string()))?;
+    Ok(resps.into_iter().map(IpApiResponse::into_ip).collect())
+}
+
+/// Splits `AS64496 Example` into number and organisation.
+fn parse_as(field: &str) -> Option<(u32, String)> {
+    let (number, org) = field.split_once(' ').unwrap_or((field, ""));
+    let number = number.strip_prefix("AS")?.parse().ok()?;
+    Some((number, org.trim().to_string()))
+}
+
+impl IpApiResponse {
+    fn into_ip(self) -> Result<IP, GeoError> {
+        if self.status != "success" {
+            let message = self.message.unwrap_or(self.status);
+            return Err(match message.as_str() {
+                "private range" | "reserved range" => GeoError::Reserved(message),
+                _ => GeoError::Failed(message),
+            });
+        }
+        let query = self.query.ok_or_else(|| GeoError::Malformed(String::from("no query in response")))?;
+        let addr = crate::ipextract::parse_ip(&query).ok_or_else(|| GeoError::Malformed(format!("invalid query {:?}", query)))?;
+
+        let mut geodata = super::located(addr);
+        geodata.lat = self.lat;
+        geodata.lon = self.lon;
+        geodata.isp = self.isp.unwrap_or_default();
+        geodata.country = self.country.unwrap_or_default();
+        geodata.countrycode = self.country_code.unwrap_or_default();
+        geodata.city = self.city.unwrap_or_default();
+        geodata.region = self.region_name.unwrap_or_default();
+        if let Some((asn, as_org)) = self.autonomous_system.as_deref().and_then(parse_as) {
+            geodata.asn = Some(asn);
+            geodata.as_org = as_org;
+        }
+
+        Ok(geodata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_geolocation() {
+        let full = r#"{"status":"success","country":"Germany","countryCode":"DE","region":"BE","regionName":"Land Berlin",
+            "city":"Berlin","zip":"10115","lat":52.52,"lon":13.405,"timezone":"Europe/Berlin","isp":"Example Networks",
+            "org":"","as":"AS64496 Example","query":"192.0.2.1"}"#;
+        let ip = deserialize_geolocation(full).unwrap();
+        assert_eq!(ip.ip, "192.0.2.1");
+        assert_eq!((ip.country.as_str(), ip.countrycode.as_str(), ip.region.as_str(), ip.city.as_str()), ("Germany", "DE", "Land Berlin", "Berlin"));
+        assert_eq!((ip.lat, ip.lon, ip.isp.as_str()), (Some(52.52), Some(13.405), "Example Networks"));
+        assert_eq!(ip.coordinates(), Some((52.52, 13.405)));
+        assert_eq!((ip.asn, ip.as_org.as_str()), (Some(64496), "Example"));
+
+        let partial = deserialize_geolocation(r#"{"status":"success","country":"Germany","countryCode":"DE","query":"2001:db8::1"}"#).unwrap();
+        assert_eq!((partial.ip.as_str(), partial.country.as_str(), partial.city.as_str()), ("2001:db8::1", "Germany", ""));
+        assert_eq!(partial.coordinates(), None);
+        assert_eq!(partial.asn, None);
+        assert_eq!(parse_as("AS64496"), Some((64496, String::new())));
+        assert_eq!(parse_as(""), None);
+
+        assert_eq!(
+            deserialize_geolocation(r#"{"status":"fail","message":"private range","query":"192.168.1.1"}"#),
+            Err(GeoError::Reserved(String::from("private range")))
+        );
+        assert_eq!(
+            deserialize_geolocation(r#"{"status":"fail","message":"reserved range","query":"198.18.0.1"}"#),
+            Err(GeoError::Reserved(String::from("reserved range")))
+        );
+        assert_eq!(
+            deserialize_geolocation(r#"{"status":"fail","message":"invalid query","query":"nope"}"#),
+            Err(GeoError::Failed(String::from("invalid query")))
+        );
+        assert!(matches!(deserialize_geolocation("<html>429 Too Many Requests</html>"), Err(GeoError::Malformed(_))));
+        assert!(matches!(deserialize_geolocation(r#"{"status":"success","lat":"52.52","query":"192.0.2.1"}"#), Err(GeoError::Malformed(_))));
+        assert!(matches!(deserialize_geolocation(r#"{"status":"success","country":"Germany"}"#), Err(GeoError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_deserialize_batch() {
+        let body = r#"[{"status":"success","country":"Germany","lat":52.52,"lon":13.405,"query":"192.0.2.1"},
+            {"status":"fail","message":"private range","query":"10.0.0.1"}]"#;
+        let results = deserialize_batch(body).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().country, "Germany");
+        assert_eq!(results[1], Err(GeoError::Reserved(String::from("private range"))));
+        assert!(matches!(deserialize_batch(r#"{"status":"fail"}"#), Err(GeoError::Malformed(_))));
+    }
+}
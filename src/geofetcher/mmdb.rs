@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use color_eyre::eyre::{eyre, Result};
+use futures::future::BoxFuture;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use super::{GeoError, GeoProvider};
+use crate::database::schema::ip::IP;
+
+/// Looks addresses up offline in MaxMind GeoLite2 or DB-IP lite `.mmdb` files.
+/// The City database gives the location, the optional ASN database the network operator, its AS and the announced prefix.
+pub struct MmdbProvider {
+    city: Reader<Vec<u8>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl MmdbProvider {
+    pub fn open(city_db: &str, asn_db: &str) -> Result<Self> {
+        if city_db.is_empty() {
+            return Err(eyre!("the mmdb provider needs geo.city_db"));
+        }
+        let city = Reader::open_readfile(city_db).map_err(|e| eyre!("{}: {}", city_db, e))?;
+        let asn = if asn_db.is_empty() {
+            None
+        } else {
+            Some(Reader::open_readfile(asn_db).map_err(|e| eyre!("{}: {}", asn_db, e))?)
+        };
+        Ok(Self { city, asn })
+    }
+
+    pub fn lookup_addr(&self, addr: IpAddr) -> Result<Option<IP>, GeoError> {
+        let Some(city) = not_found_as_none(self.city.lookup::<geoip2::City>(addr))? else { return Ok(None) };
+        let mut geodata = super::located(addr);
+        if let Some(country) = city.country {
+            geodata.country = name(&country.names).unwrap_or(country.iso_code.unwrap_or_default()).to_string();
+            geodata.countrycode = country.iso_code.unwrap_or_default().to_string();
+        }
+        if let Some(subdivision) = city.subdivisions.as_ref().and_then(|subdivisions| subdivisions.first()) {
+            geodata.region = name(&subdivision.names).unwrap_or_default().to_string();
+        }
+        if let Some(city) = city.city {
+            geodata.city = name(&city.names).unwrap_or_default().to_string();
+        }
+        if let Some(location) = city.location {
+            geodata.lat = location.latitude;
+            geodata.lon = location.longitude;
+        }
+        if let Some(reader) = &self.asn {
+            if let Some((asn, prefix_len)) = not_found_as_none(reader.lookup_prefix::<geoip2::Asn>(addr))? {
+                geodata.isp = asn.autonomous_system_organization.unwrap_or_default().to_string();
+                geodata.as_org = geodata.isp.clone();
+                geodata.asn = asn.autonomous_system_number;
+                geodata.prefix = super::network_prefix(addr, prefix_len as u32);
+            }
+        }
+        Ok(Some(geodata))
+    }
+}
+
+impl GeoProvider for MmdbProvider {
+    fn name(&self) -> &'static str {
+        "mmdb"
+    }
+
+    fn lookup(&self, addr: IpAddr) -> BoxFuture<'_, Result<Option<IP>, GeoError>> {
+        Box::pin(std::future::ready(self.lookup_addr(addr)))
+    }
+}
+
+/// English name, or any name if the database has no English one.
+fn name<'a>(names: &Option<BTreeMap<&'a str, &'a str>>) -> Option<&'a str> {
+    let names = names.as_ref()?;
+    names.get("en").or_else(|| names.values().next()).copied()
+}
+
+fn not_found_as_none<T>(result: Result<T, MaxMindDBError>) -> Result<Option<T>, GeoError> {
+    match result {
+        Ok(record) => Ok(Some(record)),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+        Err(e) => Err(GeoError::Malformed(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Minimal MMDB writer, enough for an IPv4 database with a single network.
+    pub(crate) struct Mmdb(Vec<u8>);
+
+    impl Mmdb {
+        pub(crate) fn new() -> Self {
+            Mmdb(vec![])
+        }
+
+        fn control(mut self, kind: u8, size: usize) -> Self {
+            // sizes from 29 on take an extra byte
+            let (size_bits, extra) = if size < 29 { (size as u8, None) } else { (29, Some((size - 29) as u8)) };
+            if kind <= 7 {
+                self.0.push(kind << 5 | size_bits);
+            } else {
+                self.0.push(size_bits);
+                self.0.push(kind - 7);
+            }
+            self.0.extend(extra);
+            self
+        }
+
+        pub(crate) fn map(self, pairs: usize) -> Self {
+            self.control(7, pairs)
+        }
+
+        pub(crate) fn array(self, len: usize) -> Self {
+            self.control(11, len)
+        }
+
+        pub(crate) fn str(self, value: &str) -> Self {
+            let mut out = self.control(2, value.len());
+            out.0.extend_from_slice(value.as_bytes());
+            out
+        }
+
+        pub(crate) fn double(self, value: f64) -> Self {
+            let mut out = self.control(3, 8);
+            out.0.extend_from_slice(&value.to_be_bytes());
+            out
+        }
+
+        pub(crate) fn uint(self, kind: u8, value: u64) -> Self {
+            let bytes = value.to_be_bytes();
+            let skip = bytes.iter().take_while(|b| **b == 0).count();
+            let mut out = self.control(kind, 8 - skip);
+            out.0.extend_from_slice(&bytes[skip..]);
+            out
+        }
+
+        /// Database whose only network is `network/prefix` holding `data`.
+        pub(crate) fn database(network: [u8; 4], prefix: usize, data: Mmdb, database_type: &str) -> Vec<u8> {
+            let node_count = prefix as u32;
+            let bits = u32::from_be_bytes(network);
+            let mut out = vec![];
+            for node in 0..node_count {
+                let next = if node + 1 == node_count { node_count + 16 } else { node + 1 };
+                let records = if bits >> (31 - node) & 1 == 0 { [next, node_count] } else { [node_count, next] };
+                for record in records {
+                    out.extend_from_slice(&record.to_be_bytes()[1..]);
+                }
+            }
+            out.extend_from_slice(&[0; 16]);
+            out.extend_from_slice(&data.0);
+            out.extend_from_slice(b"\xAB\xCD\xEFMaxMind.com");
+            let metadata = Mmdb::new()
+                .map(9)
+                .str("binary_format_major_version").uint(5, 2)
+                .str("binary_format_minor_version").uint(5, 0)
+                .str("build_epoch").uint(9, 1_700_000_000)
+                .str("database_type").str(database_type)
+                .str("description").map(1).str("en").str("fixture")
+                .str("ip_version").uint(5, 4)
+                .str("languages").array(1).str("en")
+                .str("node_count").uint(6, node_count as u64)
+                .str("record_size").uint(5, 24);
+            out.extend_from_slice(&metadata.0);
+            out
+        }
+    }
+
+    pub(crate) fn city_fixture() -> Vec<u8> {
+        let data = Mmdb::new()
+            .map(4)
+            .str("city").map(1).str("names").map(1).str("en").str("Berlin")
+            .str("country").map(2).str("iso_code").str("DE").str("names").map(2).str("de").str("Deutschland").str("en").str("Germany")
+            .str("location").map(2).str("latitude").double(52.52).str("longitude").double(13.405)
+            .str("subdivisions").array(1).map(1).str("names").map(1).str("en").str("Land Berlin");
+        Mmdb::database([192, 0, 2, 0], 24, data, "GeoLite2-City")
+    }
+
+    pub(crate) fn asn_fixture() -> Vec<u8> {
+        let data = Mmdb::new()
+            .map(2)
+            .str("autonomous_system_number").uint(6, 64496)
+            .str("autonomous_system_organization").str("Example Networks");
+        Mmdb::database([192, 0, 2, 0], 24, data, "GeoLite2-ASN")
+    }
+
+    #[test]
+    fn test_mmdb_lookup() {
+        let dir = std::env::temp_dir().join(format!("s2b-mmdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let city_db = dir.join("city.mmdb");
+        let asn_db = dir.join("asn.mmdb");
+        std::fs::write(&city_db, city_fixture()).unwrap();
+        std::fs::write(&asn_db, asn_fixture()).unwrap();
+
+        let provider = MmdbProvider::open(city_db.to_str().unwrap(), asn_db.to_str().unwrap()).unwrap();
+        let ip = provider.lookup_addr("192.0.2.77".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(ip.ip, "192.0.2.77");
+        assert_eq!(ip.country, "Germany");
+        assert_eq!(ip.countrycode, "DE");
+        assert_eq!(ip.region, "Land Berlin");
+        assert_eq!(ip.city, "Berlin");
+        assert_eq!(ip.lat, Some(52.52));
+        assert_eq!(ip.lon, Some(13.405));
+        assert_eq!(ip.isp, "Example Networks");
+        assert_eq!((ip.asn, ip.as_org.as_str(), ip.prefix.as_str()), (Some(64496), "Example Networks", "192.0.2.0/24"));
+
+        assert!(provider.lookup_addr("198.51.100.1".parse().unwrap()).unwrap().is_none());
+        assert!(provider.lookup_addr("2001:db8::1".parse().unwrap()).unwrap().is_none());
+
+        let without_asn = MmdbProvider::open(city_db.to_str().unwrap(), "").unwrap();
+        assert_eq!(without_asn.lookup_addr("192.0.2.1".parse().unwrap()).unwrap().unwrap().isp, "");
+        assert!(MmdbProvider::open("", "").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::{GeoError, GeoProvider};
+use crate::{action::Action, app::models::IOMessage, database::schema::ip::IP};
+
+/// Lookups that may wait for the worker before new ones are turned away.
+const QUEUE_CAPACITY: usize = 1000;
+/// Tries per address before the worker gives up on it.
+const MAX_ATTEMPTS: u32 = 5;
+/// Longest wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A line waiting for the location of its IP.
+#[derive(Debug)]
+pub struct GeoRequest {
+    pub addr: IpAddr,
+    pub is_banned: bool,
+    pub jails: Vec<String>,
+    /// `None` for re-lookups of stale records, those are answered with `Action::GeoRefreshed`
+    /// or `Action::GeoRefreshFailed`.
+    pub message: Option<IOMessage>,
+}
+
+impl GeoRequest {
+    /// Re-lookup of an IP whose stored location expired.
+    pub fn refresh(addr: IpAddr) -> Self {
+        GeoRequest { addr, is_banned: false, jails: vec![], message: None }
+    }
+}
+
+/// Handle to the geolocation worker.
+/// The worker batches queued addresses, keeps to the provider's rate limit, looks every address up once
+/// no matter how many lines wait for it, retries failed batches with backoff and answers with `Action::GotGeo`.
+#[derive(Clone)]
+pub struct GeoQueue {
+    tx: mpsc::Sender<GeoRequest>,
+}
+
+impl GeoQueue {
+    pub fn spawn(provider: Arc<dyn GeoProvider>, action_tx: mpsc::UnboundedSender<Action>) -> Self {
+        Self::spawn_with_backoff(provider, action_tx, Duration::from_secs(1))
+    }
+
+    fn spawn_with_backoff(provider: Arc<dyn GeoProvider>, action_tx: mpsc::UnboundedSender<Action>, backoff: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let worker = Worker {
+            bucket: provider.rate_limit().map(TokenBucket::per_minute),
+            provider,
+            action_tx,
+            backoff,
+            waiting: HashMap::new(),
+            order: VecDeque::new(),
+            attempts: HashMap::new(),
+            failures: 0,
+        };
+        tokio::spawn(worker.run(rx));
+        Self { tx }
+    }
+
+    /// Queues a lookup, `false` if the queue is full or the worker is gone.
+    pub fn request(&self, request: GeoRequest) -> bool {
+        self.tx.try_send(request).is_ok()
+    }
+}
+
+struct Worker {
+    provider: Arc<dyn GeoProvider>,
+    action_tx: mpsc::UnboundedSender<Action>,
+    bucket: Option<TokenBucket>,
+    backoff: Duration,
+    // lines per address, an address is only looked up once however many lines wait for it
+    waiting: HashMap<IpAddr, Vec<GeoRequest>>,
+    // addresses in the order they came in
+    order: VecDeque<IpAddr>,
+    attempts: HashMap<IpAddr, u32>,
+    // failed batches in a row
+    failures: u32,
+}
+
+impl Worker {
+    async fn run(mut self, mut rx: mpsc::Receiver<GeoRequest>) {
+        loop {
+            if self.order.is_empty() {
+                match rx.recv().await {
+                    Some(request) => self.queue(request),
+                    None => return,
+                }
+            }
+            if let Some(bucket) = self.bucket.as_mut() {
+                bucket.acquire().await;
+            }
+            // whatever came in meanwhile can go into the same batch
+            while let Ok(request) = rx.try_recv() {
+                self.queue(request);
+            }
+            let size = self.order.len().min(self.provider.batch_size().max(1));
+            let batch: Vec<IpAddr> = self.order.drain(..size).collect();
+            match self.provider.lookup_batch(batch.clone()).await {
+                Ok(results) => {
+                    self.failures = 0;
+                    let mut located = 0;
+                    for (addr, result) in results {
+                        located += self.answer(addr, result) as usize;
+                    }
+                    // addresses the provider skipped
+                    for addr in batch.iter().filter(|addr| self.waiting.contains_key(addr)).copied().collect::<Vec<_>>() {
+                        self.answer(addr, Ok(None));
+                    }
+                    if batch.len() > 1 || !self.order.is_empty() {
+                        self.log(format!(" ⌖ Located {} of {} IPs, {} waiting", located, batch.len(), self.order.len()));
+                    }
+                },
+                Err(e) => self.retry(batch, e).await,
+            }
+        }
+    }
+
+    fn queue(&mut self, request: GeoRequest) {
+        let lines = self.waiting.entry(request.addr).or_default();
+        if lines.is_empty() {
+            self.order.push_back(request.addr);
+        }
+        lines.push(request);
+    }
+
+    /// Hands the result to every line waiting for the address, true if it was located.
+    fn answer(&mut self, addr: IpAddr, result: Result<Option<IP>, GeoError>) -> bool {
+        self.attempts.remove(&addr);
+        let requests = self.waiting.remove(&addr).unwrap_or_default();
+        if !matches!(result, Ok(Some(_))) && requests.iter().any(|request| request.message.is_none()) {
+            self.action_tx.send(Action::GeoRefreshFailed(addr.to_string())).unwrap_or_default();
+        }
+        match result {
+            Ok(Some(geodata)) => {
+                for request in requests {
+                    let mut geodata = geodata.clone();
+                    let Some(message) = request.message else {
+                        self.action_tx.send(Action::GeoRefreshed(geodata)).unwrap_or_default();
+                        continue;
+                    };
+                    geodata.is_banned = request.is_banned;
+                    geodata.banned_times = if request.is_banned {1} else {0};
+                    geodata.jails = request.jails;
+                    self.action_tx.send(Action::GotGeo(geodata, message, false)).unwrap_or_default(); // false, GeoData was acquired freshly
+                }
+                true
+            },
+            Ok(None) => {
+                self.log(format!("  Could not find location for IP {} ", addr));
+                false
+            },
+            Err(e) => {
+                log::error!("{} lookup of {} failed: {}", self.provider.name(), addr, e);
+                self.log(format!(" ❌ Could not locate IP {}: {}", addr, e));
+                false
+            },
+        }
+    }
+
+    /// Puts a failed batch back in front of the queue and waits before the next try.
+    async fn retry(&mut self, batch: Vec<IpAddr>, e: GeoError) {
+        self.failures += 1;
+        let mut retried = 0;
+        for addr in batch.into_iter().rev() {
+            let attempts = self.attempts.entry(addr).or_default();
+            *attempts += 1;
+            if *attempts < MAX_ATTEMPTS {
+                self.order.push_front(addr);
+                retried += 1;
+            } else {
+                self.answer(addr, Err(e.clone()));
+            }
+        }
+        let delay = match e {
+            GeoError::RateLimited(Some(ttl)) => ttl,
+            _ => (self.backoff * 2u32.saturating_pow(self.failures - 1)).min(MAX_BACKOFF),
+        };
+        self.log(format!(" ❌ {} lookup failed: {}, retrying {} IPs in {}s", self.provider.name(), e, retried, delay.as_secs_f32()));
+        tokio::time::sleep(delay).await;
+    }
+
+    fn log(&self, msg: String) {
+        self.action_tx.send(Action::InternalLog(msg)).unwrap_or_default();
+    }
+}
+
+/// Allows `capacity` requests at once and refills evenly over a minute.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(requests: u32) -> Self {
+        let capacity = requests.max(1) as f64;
+        Self { capacity, tokens: capacity, refill_per_sec: capacity / 60.0, last: Instant::now() }
+    }
+
+    /// Takes a token if there is one, otherwise tells how long until there is.
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec))
+        }
+    }
+
+    async fn acquire(&mut self) {
+        while let Err(wait) = self.try_take(Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::app::models::IOProducer;
+
+    /// Fails the first batch, then locates everything.
+    #[derive(Default)]
+    struct StubProvider {
+        batches: Mutex<Vec<Vec<IpAddr>>>,
+    }
+
+    impl GeoProvider for StubProvider {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        fn lookup(&self, addr: IpAddr) -> BoxFuture<'_, Result<Option<IP>, GeoError>> {
+            Box::pin(std::future::ready(Ok(Some(IP::from_addr(addr)))))
+        }
+
+        fn batch_size(&self) -> usize {
+            2
+        }
+
+        fn lookup_batch(&self, addrs: Vec<IpAddr>) -> BoxFuture<'_, super::super::BatchResult> {
+            let mut batches = self.batches.lock().unwrap();
+            batches.push(addrs.clone());
+            let result = if batches.len() == 1 {
+                Err(GeoError::Request(String::from("connection refused")))
+            } else {
+                Ok(addrs.into_iter().map(|addr| (addr, Ok(Some(IP::from_addr(addr))))).collect())
+            };
+            Box::pin(std::future::ready(result))
+        }
+    }
+
+    fn request(ip: &str, line: &str) -> GeoRequest {
+        GeoRequest {
+            addr: ip.parse().unwrap(),
+            is_banned: false,
+            jails: vec![],
+            message: Some(IOMessage::from_line(String::from(line), IOProducer::Log)),
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::per_minute(15);
+        bucket.last = start;
+        for _ in 0..15 {
+            assert!(bucket.try_take(start).is_ok());
+        }
+        let wait = bucket.try_take(start).unwrap_err();
+        assert_eq!(wait.as_secs(), 4);
+        assert!(bucket.try_take(start + Duration::from_secs(4)).is_ok());
+        assert!(bucket.try_take(start + Duration::from_secs(5)).is_err());
+        // refills up to capacity only
+        for _ in 0..15 {
+            assert!(bucket.try_take(start + Duration::from_secs(600)).is_ok());
+        }
+        assert!(bucket.try_take(start + Duration::from_secs(600)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_queue_dedupe_and_retry() {
+        let provider = Arc::new(StubProvider::default());
+        let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+        let queue = GeoQueue::spawn_with_backoff(provider.clone(), action_tx, Duration::from_millis(10));
+        assert!(queue.request(request("192.0.2.1", "first line")));
+        assert!(queue.request(request("192.0.2.1", "second line")));
+        assert!(queue.request(request("192.0.2.2", "third line")));
+        assert!(queue.request(request("192.0.2.3", "fourth line")));
+        assert!(queue.request(GeoRequest::refresh("192.0.2.3".parse().unwrap())));
+
+        let mut located = vec![];
+        let mut refreshed = vec![];
+        let mut logs = vec![];
+        while located.len() < 4 || refreshed.is_empty() {
+            match tokio::time::timeout(Duration::from_secs(5), action_rx.recv()).await.unwrap().unwrap() {
+                Action::GotGeo(ip, message, false) => located.push((ip.ip, message.destructure(" ").0)),
+                Action::GeoRefreshed(ip) => refreshed.push(ip.ip),
+                Action::InternalLog(msg) => logs.push(msg),
+                action => panic!("unexpected {:?}", action),
+            }
+        }
+        assert_eq!(located, vec![
+            (String::from("192.0.2.1"), String::from("first line")),
+            (String::from("192.0.2.1"), String::from("second line")),
+            (String::from("192.0.2.2"), String::from("third line")),
+            (String::from("192.0.2.3"), String::from("fourth line")),
+        ]);
+        assert_eq!(refreshed, vec![String::from("192.0.2.3")]);
+        let addr = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        assert_eq!(*provider.batches.lock().unwrap(), vec![
+            vec![addr("192.0.2.1"), addr("192.0.2.2")],
+            vec![addr("192.0.2.1"), addr("192.0.2.2")],
+            vec![addr("192.0.2.3")],
+        ]);
+        assert!(logs[0].contains("retrying 2 IPs"));
+        assert!(logs[1].contains("Located 2 of 2 IPs, 1 waiting"));
+    }
+
+    /// Knows no address.
+    struct NowhereProvider;
+
+    impl GeoProvider for NowhereProvider {
+        fn name(&self) -> &'static str {
+            "nowhere"
+        }
+
+        fn lookup(&self, _addr: IpAddr) -> BoxFuture<'_, Result<Option<IP>, GeoError>> {
+            Box::pin(std::future::ready(Ok(None)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_not_found() {
+        let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+        let queue = GeoQueue::spawn(Arc::new(NowhereProvider), action_tx);
+        assert!(queue.request(GeoRequest::refresh("192.0.2.1".parse().unwrap())));
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), action_rx.recv()).await.unwrap().unwrap() {
+                Action::GeoRefreshFailed(ip) => {
+                    assert_eq!(ip, "192.0.2.1");
+                    break;
+                },
+                Action::InternalLog(_) => (),
+                action => panic!("unexpected {:?}", action),
+            }
+        }
+    }
+}
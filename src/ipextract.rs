@@ -0,0 +1,207 @@
+//! IP address extraction from log lines.
+//! Handles IPv4, full and compressed IPv6, bracketed forms like `[2001:db8::1]:22` and IPv4-mapped IPv6.
+
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Runs of characters that could make up an address, brackets, ports and zone ids included.
+fn candidate_regex() -> &'static Regex {
+  static RE: OnceLock<Regex> = OnceLock::new();
+  RE.get_or_init(|| Regex::new(r"[0-9A-Fa-f:.\[\]]+(?:%[0-9A-Za-z_]+)?").unwrap())
+}
+
+/// Parses a single address, tolerating surrounding brackets, a trailing port or punctuation and zone ids.
+/// IPv4-mapped IPv6 addresses are turned into plain IPv4 so both spellings end up as the same IP.
+pub fn parse_ip(s: &str) -> Option<IpAddr> {
+  let mut s = s.trim();
+  // [2001:db8::1]:22
+  if let Some(rest) = s.strip_prefix('[') {
+    s = rest.split(']').next().unwrap_or_default();
+  }
+  s = s.trim_end_matches([']', ',', ';']);
+  let s = s.split('%').next().unwrap_or_default();
+
+  let parsed = s
+    .parse::<IpAddr>()
+    .ok()
+    // end of a sentence or `from 2001:db8::1:` like sshd writes it
+    .or_else(|| s.trim_end_matches(['.', ':']).parse::<IpAddr>().ok())
+    // 1.2.3.4:22
+    .or_else(|| {
+      let (host, port) = s.rsplit_once(':')?;
+      if host.contains('.') && !host.contains(':') && port.parse::<u16>().is_ok() {
+        host.parse::<IpAddr>().ok()
+      } else {
+        None
+      }
+    })?;
+
+  let parsed = parsed.to_canonical();
+  if parsed.is_unspecified() {
+    return None;
+  }
+  Some(parsed)
+}
+
+/// Returns all addresses found in the line in order of appearance.
+pub fn extract_ips(line: &str) -> Vec<IpAddr> {
+  candidate_regex()
+    .find_iter(line)
+    .filter(|m| m.as_str().contains(['.', ':']))
+    .filter_map(|m| parse_ip(m.as_str()))
+    .collect()
+}
+
+/// First address in the line in its canonical text form, the way it is stored in the db.
+pub fn first_ip(line: &str) -> Option<String> {
+  extract_ips(line).first().map(|ip| ip.to_string())
+}
+
+/// What kind of network an address belongs to, only public addresses have a location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrClass {
+  Public,
+  Loopback,
+  /// RFC 1918 and IPv6 unique local addresses
+  Private,
+  LinkLocal,
+  /// Carrier-grade NAT, 100.64.0.0/10
+  Shared,
+  /// 192.0.2.0/24, 198.51.100.0/24, 203.0.113.0/24 and 2001:db8::/32
+  Documentation,
+  /// Benchmarking, multicast, broadcast and other special purpose ranges
+  Reserved,
+}
+
+impl AddrClass {
+  /// Addresses of hosts on the same network as us.
+  pub fn is_local(&self) -> bool {
+    matches!(self, AddrClass::Loopback | AddrClass::Private | AddrClass::LinkLocal | AddrClass::Shared)
+  }
+
+  pub fn label(&self) -> &'static str {
+    match self {
+      AddrClass::Public => "Public",
+      AddrClass::Loopback => "Loopback",
+      AddrClass::Private => "Private",
+      AddrClass::LinkLocal => "Link-local",
+      AddrClass::Shared => "CGNAT",
+      AddrClass::Documentation => "Documentation",
+      AddrClass::Reserved => "Reserved",
+    }
+  }
+}
+
+/// Sorts an address into its IANA special purpose range, IPv4-mapped addresses are classified as IPv4.
+pub fn classify(addr: IpAddr) -> AddrClass {
+  match addr.to_canonical() {
+    IpAddr::V4(v4) => {
+      let [a, b, c, _] = v4.octets();
+      match (a, b, c) {
+        (127, _, _) => AddrClass::Loopback,
+        (10, _, _) | (172, 16..=31, _) | (192, 168, _) => AddrClass::Private,
+        (169, 254, _) => AddrClass::LinkLocal,
+        (100, 64..=127, _) => AddrClass::Shared,
+        (192, 0, 2) | (198, 51, 100) | (203, 0, 113) => AddrClass::Documentation,
+        // this network, IETF protocol assignments, benchmarking, multicast and class E incl. broadcast
+        (0, _, _) | (192, 0, 0) | (198, 18..=19, _) | (224..=255, _, _) => AddrClass::Reserved,
+        _ => AddrClass::Public,
+      }
+    },
+    IpAddr::V6(v6) => {
+      let segments = v6.segments();
+      if v6.is_loopback() {
+        AddrClass::Loopback
+      } else if segments[0] & 0xfe00 == 0xfc00 {
+        AddrClass::Private
+      } else if segments[0] & 0xffc0 == 0xfe80 {
+        AddrClass::LinkLocal
+      } else if segments[0] == 0x2001 && segments[1] == 0x0db8 {
+        AddrClass::Documentation
+      } else if v6.is_unspecified() || segments[0] & 0xff00 == 0xff00 || segments[..4] == [0x100, 0, 0, 0] {
+        // multicast and the discard prefix
+        AddrClass::Reserved
+      } else {
+        AddrClass::Public
+      }
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ips(line: &str) -> Vec<String> {
+    extract_ips(line).iter().map(|ip| ip.to_string()).collect()
+  }
+
+  #[test]
+  fn test_fail2ban_lines() {
+    assert_eq!(
+      ips("2023-11-19 16:08:10,101 fail2ban.filter         [804]: INFO    [sshd] Found 2001:db8:85a3::8a2e:370:7334 - 2023-11-19 16:08:10"),
+      vec!["2001:db8:85a3::8a2e:370:7334"]
+    );
+    assert_eq!(ips("2023-11-19 16:08:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Ban 2a01:4f8:c17:1b2c::1"), vec!["2a01:4f8:c17:1b2c::1"]);
+    assert_eq!(ips("2023-11-19 16:18:12,365 fail2ban.actions        [804]: NOTICE  [sshd] Unban 203.0.113.7"), vec!["203.0.113.7"]);
+    assert_eq!(ips("2023-11-19 16:18:12,365 fail2ban.actions        [804]: NOTICE  [sshd] 2001:DB8:0:0:0:0:0:1 already banned"), vec!["2001:db8::1"]);
+  }
+
+  #[test]
+  fn test_sshd_lines() {
+    assert_eq!(ips("Failed password for root from 2001:db8::dead:beef port 52234 ssh2"), vec!["2001:db8::dead:beef"]);
+    assert_eq!(ips("Connection closed by invalid user admin 2001:db8::5 port 41922 [preauth]"), vec!["2001:db8::5"]);
+    assert_eq!(ips("Disconnected from 198.51.100.23 port 22:"), vec!["198.51.100.23"]);
+    assert_eq!(ips("error: kex_exchange_identification: Connection closed by remote host from [2001:db8::7]:51234"), vec!["2001:db8::7"]);
+    assert_eq!(ips("Accepted publickey for me from ::ffff:192.0.2.10 port 22 ssh2"), vec!["192.0.2.10"]);
+    assert_eq!(ips("Received disconnect from fe80::1%eth0 port 22:11: Bye"), vec!["fe80::1"]);
+  }
+
+  #[test]
+  fn test_no_false_positives() {
+    assert!(ips("2023-11-19 16:08:10,101 fail2ban.server [804]: INFO Jail list:: sshd").is_empty());
+    assert!(ips("sshd[1234]: pam_unix(sshd:session): session opened for user deadbeef").is_empty());
+    assert!(ips("link aa:bb:cc:dd:ee:ff up, version 1.2.3").is_empty());
+    assert!(ips("bogus 999.1.1.1").is_empty());
+  }
+
+  #[test]
+  fn test_classify() {
+    let class = |s: &str| classify(s.parse().unwrap());
+    assert_eq!(class("127.0.0.1"), AddrClass::Loopback);
+    assert_eq!(class("::1"), AddrClass::Loopback);
+    assert_eq!(class("10.1.2.3"), AddrClass::Private);
+    assert_eq!(class("172.16.0.1"), AddrClass::Private);
+    assert_eq!(class("172.32.0.1"), AddrClass::Public);
+    assert_eq!(class("192.168.178.20"), AddrClass::Private);
+    assert_eq!(class("::ffff:192.168.1.1"), AddrClass::Private);
+    assert_eq!(class("fd12:3456::1"), AddrClass::Private);
+    assert_eq!(class("169.254.1.1"), AddrClass::LinkLocal);
+    assert_eq!(class("fe80::1"), AddrClass::LinkLocal);
+    assert_eq!(class("100.64.0.1"), AddrClass::Shared);
+    assert_eq!(class("100.128.0.1"), AddrClass::Public);
+    assert_eq!(class("192.0.2.1"), AddrClass::Documentation);
+    assert_eq!(class("198.51.100.7"), AddrClass::Documentation);
+    assert_eq!(class("203.0.113.9"), AddrClass::Documentation);
+    assert_eq!(class("2001:db8::1"), AddrClass::Documentation);
+    assert_eq!(class("198.18.0.1"), AddrClass::Reserved);
+    assert_eq!(class("239.255.255.250"), AddrClass::Reserved);
+    assert_eq!(class("255.255.255.255"), AddrClass::Reserved);
+    assert_eq!(class("ff02::1"), AddrClass::Reserved);
+    assert_eq!(class("8.8.8.8"), AddrClass::Public);
+    assert_eq!(class("2a00:1450:4001::1"), AddrClass::Public);
+    assert!(class("100.64.0.1").is_local());
+    assert!(!class("192.0.2.1").is_local());
+  }
+
+  #[test]
+  fn test_parse_ip() {
+    assert_eq!(parse_ip("[2001:db8::1]").unwrap().to_string(), "2001:db8::1");
+    assert_eq!(parse_ip("10.0.0.1:8080").unwrap().to_string(), "10.0.0.1");
+    assert_eq!(parse_ip("2001:0db8:0000:0000:0000:0000:0000:0001").unwrap().to_string(), "2001:db8::1");
+    assert!(parse_ip("10.0.0").is_none());
+    assert!(parse_ip("::").is_none());
+  }
+}
@@ -0,0 +1,13 @@
+//! Native systemd journal support.
+//! Reads journal files directly and keeps the structured fields of every entry, instead of parsing `journalctl` output.
+//! The libsystemd crate only covers the sending side of the journal, so the on-disk format is read here.
+
+mod entry;
+mod export;
+mod file;
+mod reader;
+
+pub use entry::JournalEntry;
+pub use export::ExportReader;
+pub use file::JournalFile;
+pub use reader::{JournalMatch, JournalReader, JournalSource, SYSTEM_JOURNAL_DIRS};
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, TimeZone};
+use serde::Serialize;
+
+/// A single journal entry with all of its fields, e.g. `MESSAGE`, `_PID`, `_HOSTNAME`, `SYSLOG_IDENTIFIER`.
+///
+/// Fields starting with `__` are the entry metadata journalctl exports as well, e.g. `__REALTIME_TIMESTAMP`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct JournalEntry {
+  pub fields: BTreeMap<String, String>,
+}
+
+impl JournalEntry {
+  pub fn get(&self, field: &str) -> Option<&str> {
+    self.fields.get(field).map(|value| value.as_str())
+  }
+
+  /// Sets a field, fields that appear more than once keep their first value.
+  pub fn insert(&mut self, field: &str, value: String) {
+    self.fields.entry(field.to_string()).or_insert(value);
+  }
+
+  pub fn message(&self) -> &str {
+    self.get("MESSAGE").unwrap_or_default()
+  }
+
+  pub fn pid(&self) -> Option<u32> {
+    self.get("_PID").or(self.get("SYSLOG_PID")).and_then(|pid| pid.parse().ok())
+  }
+
+  pub fn hostname(&self) -> Option<&str> {
+    self.get("_HOSTNAME")
+  }
+
+  /// Syslog identifier, falls back to the command name like journalctl does.
+  pub fn identifier(&self) -> Option<&str> {
+    self.get("SYSLOG_IDENTIFIER").or(self.get("_COMM"))
+  }
+
+  pub fn unit(&self) -> Option<&str> {
+    self.get("_SYSTEMD_UNIT")
+  }
+
+  /// Microseconds since the epoch the entry was written at.
+  pub fn realtime_usec(&self) -> Option<u64> {
+    self.get("__REALTIME_TIMESTAMP").and_then(|usec| usec.parse().ok())
+  }
+
+  pub fn timestamp(&self) -> Option<DateTime<Local>> {
+    let usec = i64::try_from(self.realtime_usec()?).ok()?;
+    Local.timestamp_micros(usec).single()
+  }
+
+  /// Formats the entry like `journalctl -o short` does, e.g. `Nov 19 16:08:10 host sshd[1234]: Failed password for ...`.
+  pub fn short_line(&self) -> String {
+    let mut line = String::new();
+    if let Some(timestamp) = self.timestamp() {
+      line.push_str(&timestamp.format("%b %d %H:%M:%S ").to_string());
+    }
+    if let Some(hostname) = self.hostname() {
+      line.push_str(hostname);
+      line.push(' ');
+    }
+    line.push_str(self.identifier().unwrap_or("unknown"));
+    if let Some(pid) = self.pid() {
+      line.push_str(&format!("[{}]", pid));
+    }
+    line.push_str(": ");
+    line.push_str(self.message());
+    line
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_short_line() {
+    let mut entry = JournalEntry::default();
+    entry.insert("MESSAGE", "Failed password for root from 192.0.2.4 port 22 ssh2".to_string());
+    entry.insert("_HOSTNAME", "host".to_string());
+    entry.insert("SYSLOG_IDENTIFIER", "sshd".to_string());
+    entry.insert("_PID", "1234".to_string());
+    assert_eq!(entry.short_line(), "host sshd[1234]: Failed password for root from 192.0.2.4 port 22 ssh2");
+
+    entry.insert("__REALTIME_TIMESTAMP", "1700410090000000".to_string());
+    let expected = Local.timestamp_micros(1700410090000000).unwrap().format("%b %d %H:%M:%S").to_string();
+    assert!(entry.short_line().starts_with(&expected));
+    assert_eq!(entry.pid(), Some(1234));
+    // first value wins
+    entry.insert("_PID", "1".to_string());
+    assert_eq!(entry.pid(), Some(1234));
+  }
+}
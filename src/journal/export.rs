@@ -0,0 +1,132 @@
+use std::io::{self, BufRead, Read};
+
+use super::JournalEntry;
+
+/// Reads entries in the journal export format, as written by `journalctl -o export`.
+///
+/// Text fields are `FIELD=value` lines, binary fields are the field name, a little endian 64 bit length and the raw
+/// value. Entries are separated by an empty line.
+pub struct ExportReader<R> {
+  inner: R,
+  consumed: u64,
+}
+
+impl<R: BufRead> ExportReader<R> {
+  pub fn new(inner: R) -> Self {
+    Self { inner, consumed: 0 }
+  }
+
+  /// Bytes of complete entries read so far, a trailing entry that is still being written is not counted.
+  pub fn consumed(&self) -> u64 {
+    self.consumed
+  }
+
+  /// Returns the next complete entry, `None` once the input ends.
+  pub fn next_entry(&mut self) -> io::Result<Option<JournalEntry>> {
+    let mut entry = JournalEntry::default();
+    let mut read: u64 = 0;
+    loop {
+      let mut line = Vec::new();
+      let n = self.inner.read_until(b'\n', &mut line)?;
+      // an entry without its terminating empty line is not complete yet
+      if n == 0 || line.last() != Some(&b'\n') {
+        return Ok(None);
+      }
+      read += n as u64;
+      line.pop();
+      if line.is_empty() {
+        if entry.fields.is_empty() {
+          continue;
+        }
+        self.consumed += read;
+        return Ok(Some(entry));
+      }
+      match line.iter().position(|b| *b == b'=') {
+        Some(eq) => {
+          let field = String::from_utf8_lossy(&line[..eq]);
+          entry.insert(&field, String::from_utf8_lossy(&line[eq + 1..]).into_owned());
+        },
+        None => {
+          let mut len = [0u8; 8];
+          if !self.read_complete(&mut len)? {
+            return Ok(None);
+          }
+          let len = u64::from_le_bytes(len);
+          let mut value = Vec::new();
+          (&mut self.inner).take(len + 1).read_to_end(&mut value)?;
+          if value.len() as u64 != len + 1 {
+            return Ok(None);
+          }
+          value.pop();
+          read += 8 + len + 1;
+          entry.insert(&String::from_utf8_lossy(&line), String::from_utf8_lossy(&value).into_owned());
+        },
+      }
+    }
+  }
+
+  fn read_complete(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+    match self.inner.read_exact(buf) {
+      Ok(()) => Ok(true),
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+      Err(err) => Err(err),
+    }
+  }
+}
+
+impl<R: BufRead> Iterator for ExportReader<R> {
+  type Item = io::Result<JournalEntry>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_entry().transpose()
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  /// Two sshd entries and one unrelated one, in the format `journalctl -o export` writes.
+  pub(crate) fn fixture() -> Vec<u8> {
+    let mut data = b"__CURSOR=s=1;i=1\n__REALTIME_TIMESTAMP=1700410090000000\n_PID=1234\n_HOSTNAME=host\n\
+SYSLOG_IDENTIFIER=sshd\n_SYSTEMD_UNIT=ssh.service\nMESSAGE=Failed password for root from 192.0.2.4 port 52234 ssh2\n\n\
+__REALTIME_TIMESTAMP=1700410091000000\n_PID=1\n_HOSTNAME=host\nSYSLOG_IDENTIFIER=systemd\nMESSAGE=Started Session 1.\n\n"
+      .to_vec();
+    // binary field, the message contains a newline
+    data.extend_from_slice(b"__REALTIME_TIMESTAMP=1700410092000000\n_PID=1235\n_HOSTNAME=host\nSYSLOG_IDENTIFIER=sshd\n_SYSTEMD_UNIT=ssh.service\nMESSAGE\n");
+    let message = b"Invalid user oracle from 203.0.113.9 port 44312\nsecond line";
+    data.extend_from_slice(&(message.len() as u64).to_le_bytes());
+    data.extend_from_slice(message);
+    data.extend_from_slice(b"\n\n");
+    data
+  }
+
+  #[test]
+  fn test_read_export() {
+    let data = fixture();
+    let entries: Vec<JournalEntry> = ExportReader::new(&data[..]).collect::<io::Result<_>>().unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].pid(), Some(1234));
+    assert_eq!(entries[0].identifier(), Some("sshd"));
+    assert_eq!(entries[0].unit(), Some("ssh.service"));
+    assert_eq!(entries[0].realtime_usec(), Some(1700410090000000));
+    assert_eq!(entries[1].message(), "Started Session 1.");
+    assert_eq!(entries[2].message(), "Invalid user oracle from 203.0.113.9 port 44312\nsecond line");
+  }
+
+  #[test]
+  fn test_partial_entry() {
+    let data = fixture();
+    let cut = data.len() - 20;
+    let mut reader = ExportReader::new(&data[..cut]);
+    assert!(reader.next_entry().unwrap().is_some());
+    assert!(reader.next_entry().unwrap().is_some());
+    let complete = reader.consumed();
+    assert!(reader.next_entry().unwrap().is_none());
+    assert_eq!(reader.consumed(), complete);
+
+    let mut rest = ExportReader::new(&data[complete as usize..]);
+    assert_eq!(rest.next_entry().unwrap().unwrap().pid(), Some(1235));
+    assert_eq!(complete + rest.consumed(), data.len() as u64);
+  }
+}
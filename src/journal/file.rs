@@ -0,0 +1,444 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use super::JournalEntry;
+
+const SIGNATURE: &[u8; 8] = b"LPKSHHRH";
+
+// header offsets, see systemd's journal-def.h
+const HEADER_INCOMPATIBLE_FLAGS: u64 = 12;
+const HEADER_STATE: u64 = 16;
+const HEADER_FILE_ID: u64 = 24;
+const HEADER_SIZE: u64 = 88;
+const HEADER_N_ENTRIES: u64 = 152;
+const HEADER_ENTRY_ARRAY_OFFSET: u64 = 176;
+const HEADER_TAIL_ENTRY_REALTIME: u64 = 192;
+const HEADER_MIN_SIZE: u64 = 208;
+
+const INCOMPATIBLE_COMPACT: u32 = 1 << 4;
+
+const STATE_ARCHIVED: u8 = 2;
+
+const OBJECT_DATA: u8 = 1;
+const OBJECT_ENTRY: u8 = 3;
+const OBJECT_ENTRY_ARRAY: u8 = 6;
+const OBJECT_COMPRESSED_MASK: u8 = 0b111;
+const OBJECT_COMPRESSED_XZ: u8 = 1 << 0;
+const OBJECT_COMPRESSED_LZ4: u8 = 1 << 1;
+const OBJECT_COMPRESSED_ZSTD: u8 = 1 << 2;
+const OBJECT_HEADER_SIZE: u64 = 16;
+
+/// Payload of a data object starts after hash, 4 offsets and the entry count, compact files add two 32 bit fields.
+const DATA_PAYLOAD: u64 = 64;
+const DATA_PAYLOAD_COMPACT: u64 = 72;
+const ENTRY_ITEMS: u64 = 64;
+const ENTRY_ARRAY_ITEMS: u64 = 24;
+
+/// Sanity limit for a single object, journald never writes anything near this size.
+const MAX_OBJECT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Reader for a single `.journal` file.
+///
+/// Walks the global entry array chain in order and remembers its position, so calling `next_entry` again after
+/// journald appended to the file continues with the new entries.
+pub struct JournalFile {
+  file: File,
+  path: PathBuf,
+  file_id: [u8; 16],
+  compact: bool,
+  /// Entries returned or skipped so far.
+  consumed: u64,
+  array_offset: u64,
+  array_index: u64,
+}
+
+impl JournalFile {
+  pub fn open(path: &Path) -> io::Result<Self> {
+    let file = File::open(path)?;
+    let mut header = [0u8; HEADER_MIN_SIZE as usize];
+    file.read_exact_at(&mut header, 0)?;
+    if &header[..8] != SIGNATURE {
+      return Err(invalid(format!("{} is not a journal file", path.display())));
+    }
+    let at = HEADER_INCOMPATIBLE_FLAGS as usize;
+    let incompatible = u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    let file_id = header[HEADER_FILE_ID as usize..HEADER_FILE_ID as usize + 16].try_into().unwrap();
+    Ok(Self {
+      file,
+      path: path.to_path_buf(),
+      file_id,
+      compact: incompatible & INCOMPATIBLE_COMPACT != 0,
+      consumed: 0,
+      array_offset: 0,
+      array_index: 0,
+    })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Random id of the file, stays the same when journald renames the file on rotation.
+  pub fn file_id(&self) -> [u8; 16] {
+    self.file_id
+  }
+
+  pub fn n_entries(&self) -> io::Result<u64> {
+    self.read_u64(HEADER_N_ENTRIES)
+  }
+
+  /// Time of the newest entry in microseconds since the epoch.
+  pub fn tail_realtime(&self) -> io::Result<u64> {
+    self.read_u64(HEADER_TAIL_ENTRY_REALTIME)
+  }
+
+  /// Archived files are not written to anymore, journald continues in a new file.
+  pub fn is_archived(&self) -> io::Result<bool> {
+    let mut state = [0u8; 1];
+    self.file.read_exact_at(&mut state, HEADER_STATE)?;
+    Ok(state[0] == STATE_ARCHIVED)
+  }
+
+  /// True once every entry written so far has been returned.
+  pub fn at_end(&self) -> io::Result<bool> {
+    Ok(self.consumed >= self.n_entries()?)
+  }
+
+  /// Skips all entries written so far, only entries added afterwards are returned.
+  pub fn seek_tail(&mut self) -> io::Result<()> {
+    let n_entries = self.n_entries()?;
+    while self.consumed < n_entries {
+      if self.next_entry_offset()?.is_none() {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns the next entry, `None` if journald did not write any further entry yet.
+  pub fn next_entry(&mut self) -> io::Result<Option<JournalEntry>> {
+    match self.next_entry_offset()? {
+      Some(offset) => self.read_entry(offset).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  fn next_entry_offset(&mut self) -> io::Result<Option<u64>> {
+    if self.consumed >= self.n_entries()? {
+      return Ok(None);
+    }
+    if self.array_offset == 0 {
+      self.array_offset = self.read_u64(HEADER_ENTRY_ARRAY_OFFSET)?;
+      self.array_index = 0;
+      if self.array_offset == 0 {
+        return Ok(None);
+      }
+    }
+    let item_size = self.item_size();
+    // entry arrays are chained, each one is larger than the previous and may have unused slots at its end
+    for _ in 0..64 {
+      let (kind, size) = self.object_header(self.array_offset)?;
+      if kind != OBJECT_ENTRY_ARRAY {
+        return Err(invalid(format!("expected entry array at {}", self.array_offset)));
+      }
+      let capacity = size.saturating_sub(ENTRY_ARRAY_ITEMS) / item_size;
+      if self.array_index < capacity {
+        let entry = self.read_item(self.array_offset + ENTRY_ARRAY_ITEMS + self.array_index * item_size)?;
+        if entry == 0 {
+          return Ok(None);
+        }
+        self.array_index += 1;
+        self.consumed += 1;
+        return Ok(Some(entry));
+      }
+      let next = self.read_u64(self.array_offset + OBJECT_HEADER_SIZE)?;
+      if next == 0 {
+        return Ok(None);
+      }
+      self.array_offset = next;
+      self.array_index = 0;
+    }
+    Err(invalid("entry array chain does not end".to_string()))
+  }
+
+  fn read_entry(&self, offset: u64) -> io::Result<JournalEntry> {
+    let (kind, size) = self.object_header(offset)?;
+    if kind != OBJECT_ENTRY || size < ENTRY_ITEMS {
+      return Err(invalid(format!("expected entry at {}", offset)));
+    }
+    let mut object = vec![0u8; size as usize];
+    self.file.read_exact_at(&mut object, offset)?;
+    let le64 = |at: usize| u64::from_le_bytes(object[at..at + 8].try_into().unwrap());
+
+    let mut entry = JournalEntry::default();
+    entry.insert("__SEQNUM", le64(16).to_string());
+    entry.insert("__REALTIME_TIMESTAMP", le64(24).to_string());
+    entry.insert("__MONOTONIC_TIMESTAMP", le64(32).to_string());
+    entry.insert("_BOOT_ID", hex(&object[40..56]));
+
+    // regular files store offset and hash per item, compact files only a 32 bit offset
+    let stride = if self.compact { 4 } else { 16 };
+    for item in object[ENTRY_ITEMS as usize..].chunks_exact(stride) {
+      let data_offset = match self.compact {
+        true => u32::from_le_bytes(item[..4].try_into().unwrap()) as u64,
+        false => u64::from_le_bytes(item[..8].try_into().unwrap()),
+      };
+      if let Some((field, value)) = self.read_data(data_offset)? {
+        entry.insert(&field, value);
+      }
+    }
+    Ok(entry)
+  }
+
+  /// Reads a `FIELD=value` data object, decompressing what journald compressed. A field that doesn't decompress is
+  /// left out of its entry.
+  fn read_data(&self, offset: u64) -> io::Result<Option<(String, String)>> {
+    let mut header = [0u8; OBJECT_HEADER_SIZE as usize];
+    self.file.read_exact_at(&mut header, offset)?;
+    let size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let start = if self.compact { DATA_PAYLOAD_COMPACT } else { DATA_PAYLOAD };
+    if header[0] != OBJECT_DATA || size < start || size > MAX_OBJECT_SIZE {
+      return Err(invalid(format!("expected data at {}", offset)));
+    }
+    let mut payload = vec![0u8; (size - start) as usize];
+    self.file.read_exact_at(&mut payload, offset + start)?;
+    let payload = match header[1] & OBJECT_COMPRESSED_MASK {
+      0 => payload,
+      compression => match decompress(compression, &payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+          log::warn!("Dropping journal field at {} in {}: {}", offset, self.path.display(), e);
+          return Ok(None);
+        },
+      },
+    };
+    let Some(eq) = payload.iter().position(|b| *b == b'=') else { return Ok(None) };
+    Ok(Some((String::from_utf8_lossy(&payload[..eq]).into_owned(), String::from_utf8_lossy(&payload[eq + 1..]).into_owned())))
+  }
+
+  fn object_header(&self, offset: u64) -> io::Result<(u8, u64)> {
+    let header_size = self.read_u64(HEADER_SIZE)?;
+    if offset < header_size || !offset.is_multiple_of(8) {
+      return Err(invalid(format!("invalid object offset {}", offset)));
+    }
+    let mut header = [0u8; OBJECT_HEADER_SIZE as usize];
+    self.file.read_exact_at(&mut header, offset)?;
+    let size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    if size > MAX_OBJECT_SIZE {
+      return Err(invalid(format!("object at {} is too large", offset)));
+    }
+    Ok((header[0], size))
+  }
+
+  fn item_size(&self) -> u64 {
+    if self.compact {
+      4
+    } else {
+      8
+    }
+  }
+
+  fn read_item(&self, offset: u64) -> io::Result<u64> {
+    if self.compact {
+      let mut buf = [0u8; 4];
+      self.file.read_exact_at(&mut buf, offset)?;
+      Ok(u32::from_le_bytes(buf) as u64)
+    } else {
+      self.read_u64(offset)
+    }
+  }
+
+  fn read_u64(&self, offset: u64) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    self.file.read_exact_at(&mut buf, offset)?;
+    Ok(u64::from_le_bytes(buf))
+  }
+}
+
+/// Payload of a compressed data object, journald compresses a field with one of xz, lz4 or zstd.
+fn decompress(compression: u8, payload: &[u8]) -> io::Result<Vec<u8>> {
+  let mut out = vec![];
+  match compression {
+    OBJECT_COMPRESSED_XZ => {
+      lzma_rs::xz_decompress(&mut &payload[..], &mut out).map_err(|e| invalid(format!("xz: {}", e)))?;
+    },
+    OBJECT_COMPRESSED_LZ4 => {
+      // journald puts the decompressed size in front of the lz4 block
+      let Some((size, block)) = payload.split_first_chunk::<8>() else {
+        return Err(invalid(String::from("lz4: missing size")));
+      };
+      let size = u64::from_le_bytes(*size);
+      if size > MAX_OBJECT_SIZE {
+        return Err(invalid(format!("lz4: {} bytes is too large", size)));
+      }
+      out = lz4_flex::block::decompress(block, size as usize).map_err(|e| invalid(format!("lz4: {}", e)))?;
+    },
+    OBJECT_COMPRESSED_ZSTD => {
+      let decoder = ruzstd::decoding::StreamingDecoder::new(payload).map_err(|e| invalid(format!("zstd: {}", e)))?;
+      decoder.take(MAX_OBJECT_SIZE).read_to_end(&mut out)?;
+    },
+    _ => return Err(invalid(format!("unknown compression {:#x}", compression))),
+  }
+  Ok(out)
+}
+
+fn invalid(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  /// Writes a minimal uncompressed journal file, enough for journalctl and this reader to walk its entries.
+  /// `arrays` splits the entries over that many chained entry arrays, the last one has an unused slot like journald leaves.
+  pub(crate) fn write_journal(path: &Path, file_id: u8, entries: &[Vec<(&str, &str)>], arrays: usize) {
+    const HEADER: usize = 272;
+    let mut out = vec![0u8; HEADER];
+    let align = |out: &mut Vec<u8>| out.resize(out.len().div_ceil(8) * 8, 0);
+    let object = |out: &mut Vec<u8>, kind: u8, body: &[u8]| -> u64 {
+      let offset = out.len() as u64;
+      out.push(kind);
+      out.extend_from_slice(&[0u8; 7]);
+      out.extend_from_slice(&(OBJECT_HEADER_SIZE + body.len() as u64).to_le_bytes());
+      out.extend_from_slice(body);
+      align(out);
+      offset
+    };
+
+    let mut entry_offsets = vec![];
+    for (i, fields) in entries.iter().enumerate() {
+      let mut data_offsets = vec![];
+      for (field, value) in fields {
+        let mut body = vec![0u8; (DATA_PAYLOAD - OBJECT_HEADER_SIZE) as usize];
+        body.extend_from_slice(format!("{}={}", field, value).as_bytes());
+        data_offsets.push(object(&mut out, OBJECT_DATA, &body));
+      }
+      let mut body = vec![];
+      body.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+      body.extend_from_slice(&(1_700_410_090_000_000u64 + i as u64 * 1_000_000).to_le_bytes());
+      body.extend_from_slice(&(1_000_000u64 + i as u64).to_le_bytes());
+      body.extend_from_slice(&[0xab; 16]);
+      body.extend_from_slice(&0u64.to_le_bytes());
+      for offset in data_offsets {
+        body.extend_from_slice(&offset.to_le_bytes());
+        body.extend_from_slice(&0u64.to_le_bytes());
+      }
+      entry_offsets.push(object(&mut out, OBJECT_ENTRY, &body));
+    }
+
+    // chained entry arrays, journald only ever links forward
+    let chunks: Vec<&[u64]> = entry_offsets.chunks(entry_offsets.len().div_ceil(arrays.max(1)).max(1)).collect();
+    let head = out.len() as u64;
+    for (i, items) in chunks.iter().enumerate() {
+      let last = i + 1 == chunks.len();
+      let unused: &[u64] = if last { &[0] } else { &[] };
+      let size = ENTRY_ARRAY_ITEMS + (items.len() + unused.len()) as u64 * 8;
+      let next = if last { 0 } else { out.len() as u64 + size };
+      let mut body = next.to_le_bytes().to_vec();
+      for offset in items.iter().chain(unused) {
+        body.extend_from_slice(&offset.to_le_bytes());
+      }
+      object(&mut out, OBJECT_ENTRY_ARRAY, &body);
+    }
+
+    let put = |out: &mut Vec<u8>, at: usize, value: u64| out[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    out[..8].copy_from_slice(SIGNATURE);
+    out[HEADER_FILE_ID as usize..HEADER_FILE_ID as usize + 16].copy_from_slice(&[file_id; 16]);
+    out[40..56].copy_from_slice(&[0x11; 16]);
+    out[56..72].copy_from_slice(&[0xab; 16]);
+    out[72..88].copy_from_slice(&[0x22; 16]);
+    put(&mut out, HEADER_SIZE as usize, HEADER as u64);
+    let arena_size = (out.len() - HEADER) as u64;
+    put(&mut out, 96, arena_size);
+    put(&mut out, HEADER_N_ENTRIES as usize, entries.len() as u64);
+    put(&mut out, 160, entries.len() as u64);
+    put(&mut out, 168, 1);
+    put(&mut out, HEADER_ENTRY_ARRAY_OFFSET as usize, head);
+    put(&mut out, HEADER_TAIL_ENTRY_REALTIME as usize, 1_700_410_090_000_000u64 + entries.len().saturating_sub(1) as u64 * 1_000_000);
+    std::fs::write(path, out).unwrap();
+  }
+
+  pub(crate) fn set_n_entries(path: &Path, n_entries: u64) {
+    let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.write_all_at(&n_entries.to_le_bytes(), HEADER_N_ENTRIES).unwrap();
+  }
+
+  pub(crate) fn sshd_entry(pid: &'static str, message: &'static str) -> Vec<(&'static str, &'static str)> {
+    vec![
+      ("_PID", pid),
+      ("_HOSTNAME", "host"),
+      ("SYSLOG_IDENTIFIER", "sshd"),
+      ("_SYSTEMD_UNIT", "ssh.service"),
+      ("MESSAGE", message),
+    ]
+  }
+
+  #[test]
+  fn test_read_entries() {
+    let path = std::env::temp_dir().join(format!("s2b-read-{}.journal", std::process::id()));
+    let entries = vec![
+      sshd_entry("1234", "Failed password for root from 192.0.2.4 port 52234 ssh2"),
+      vec![("_PID", "1"), ("SYSLOG_IDENTIFIER", "systemd"), ("MESSAGE", "Started Session 1.")],
+      sshd_entry("1235", "Invalid user oracle from 203.0.113.9 port 44312"),
+    ];
+    write_journal(&path, 1, &entries, 2);
+
+    let mut journal = JournalFile::open(&path).unwrap();
+    assert_eq!(journal.n_entries().unwrap(), 3);
+    let first = journal.next_entry().unwrap().unwrap();
+    assert_eq!(first.pid(), Some(1234));
+    assert_eq!(first.hostname(), Some("host"));
+    assert_eq!(first.identifier(), Some("sshd"));
+    assert_eq!(first.realtime_usec(), Some(1_700_410_090_000_000));
+    assert_eq!(first.get("__SEQNUM"), Some("1"));
+    assert_eq!(journal.next_entry().unwrap().unwrap().message(), "Started Session 1.");
+    assert_eq!(journal.next_entry().unwrap().unwrap().message(), "Invalid user oracle from 203.0.113.9 port 44312");
+    assert!(journal.next_entry().unwrap().is_none());
+    assert!(journal.at_end().unwrap());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_seek_tail_and_follow() {
+    let path = std::env::temp_dir().join(format!("s2b-tail-{}.journal", std::process::id()));
+    let entries = vec![
+      sshd_entry("1", "Connection closed by 192.0.2.1 port 1 [preauth]"),
+      sshd_entry("2", "Connection closed by 192.0.2.2 port 2 [preauth]"),
+    ];
+    write_journal(&path, 2, &entries, 1);
+    set_n_entries(&path, 1);
+    let mut journal = JournalFile::open(&path).unwrap();
+    journal.seek_tail().unwrap();
+    assert!(journal.next_entry().unwrap().is_none());
+
+    // journald fills the next array slot and bumps the entry count afterwards
+    set_n_entries(&path, 2);
+    assert_eq!(journal.next_entry().unwrap().unwrap().pid(), Some(2));
+    assert!(journal.next_entry().unwrap().is_none());
+    assert!(JournalFile::open(&std::env::temp_dir()).is_err());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_decompress() {
+    let field = b"MESSAGE=Failed password for root from 192.0.2.4 port 52234 ssh2";
+    let mut xz = vec![];
+    lzma_rs::xz_compress(&mut &field[..], &mut xz).unwrap();
+    assert_eq!(decompress(OBJECT_COMPRESSED_XZ, &xz).unwrap(), field);
+    let mut lz4 = (field.len() as u64).to_le_bytes().to_vec();
+    lz4.extend(lz4_flex::block::compress(field));
+    assert_eq!(decompress(OBJECT_COMPRESSED_LZ4, &lz4).unwrap(), field);
+    let zstd = ruzstd::encoding::compress_to_vec(&field[..], ruzstd::encoding::CompressionLevel::Fastest);
+    assert_eq!(decompress(OBJECT_COMPRESSED_ZSTD, &zstd).unwrap(), field);
+
+    assert!(decompress(OBJECT_COMPRESSED_LZ4, &lz4[..4]).is_err());
+    assert!(decompress(OBJECT_COMPRESSED_ZSTD, &xz).is_err());
+  }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use super::{ExportReader, JournalEntry, JournalFile};
+
+/// Where journald keeps persistent and volatile journals.
+pub const SYSTEM_JOURNAL_DIRS: [&str; 2] = ["/var/log/journal", "/run/log/journal"];
+
+/// Fallback poll interval in case a change notification gets lost.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalSource {
+  /// The journals of the local system in `SYSTEM_JOURNAL_DIRS`.
+  System,
+  /// A journal directory, e.g. a copy of `/var/log/journal/<machine-id>`.
+  Directory(PathBuf),
+  /// A single `.journal` file.
+  File(PathBuf),
+  /// A file in journal export format, e.g. written by `journalctl -o export`.
+  Export(PathBuf),
+}
+
+impl JournalSource {
+  /// Picks the source type from the path: directories, `.journal` files and anything else as export file.
+  pub fn from_path(path: &Path) -> Self {
+    if path.is_dir() {
+      JournalSource::Directory(path.to_path_buf())
+    } else if path.extension().is_some_and(|ext| ext == "journal") {
+      JournalSource::File(path.to_path_buf())
+    } else {
+      JournalSource::Export(path.to_path_buf())
+    }
+  }
+
+  /// Directories to watch for changes.
+  fn watch_dirs(&self) -> Vec<PathBuf> {
+    match self {
+      JournalSource::System => SYSTEM_JOURNAL_DIRS.iter().map(PathBuf::from).filter(|dir| dir.is_dir()).collect(),
+      JournalSource::Directory(dir) => vec![dir.clone()],
+      JournalSource::File(path) | JournalSource::Export(path) => path.parent().map(Path::to_path_buf).into_iter().collect(),
+    }
+  }
+
+  /// Journal files currently written to. Archived files carry an `@` in their name and hold history only.
+  fn active_files(&self) -> io::Result<Vec<PathBuf>> {
+    let mut files = self.all_files()?;
+    files.retain(|path| !path.file_name().unwrap_or_default().to_string_lossy().contains('@'));
+    Ok(files)
+  }
+
+  /// Active and archived journal files.
+  fn all_files(&self) -> io::Result<Vec<PathBuf>> {
+    let dirs = match self {
+      JournalSource::System => SYSTEM_JOURNAL_DIRS.iter().map(PathBuf::from).filter(|dir| dir.is_dir()).collect(),
+      JournalSource::Directory(dir) => vec![dir.clone()],
+      JournalSource::File(path) => return Ok(vec![path.clone()]),
+      JournalSource::Export(_) => return Ok(vec![]),
+    };
+    let mut files = vec![];
+    for dir in dirs {
+      // journald puts the files into a subdirectory named after the machine id
+      for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+          for sub in std::fs::read_dir(&path)? {
+            files.push(sub?.path());
+          }
+        } else {
+          files.push(path);
+        }
+      }
+    }
+    // archived files end in `.journal~` when journald found them unclean
+    files.retain(|path| {
+      let name = path.file_name().unwrap_or_default().to_string_lossy();
+      name.ends_with(".journal") || name.ends_with(".journal~")
+    });
+    files.sort();
+    Ok(files)
+  }
+}
+
+/// A `FIELD=value` match, an entry matches if it has the field with exactly that value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalMatch {
+  pub field: String,
+  pub value: String,
+}
+
+impl JournalMatch {
+  pub fn new(field: &str, value: &str) -> Self {
+    Self { field: field.to_string(), value: value.to_string() }
+  }
+
+  /// Parses `FIELD=value` the way journalctl takes matches on the command line.
+  pub fn parse(s: &str) -> Option<Self> {
+    let (field, value) = s.split_once('=')?;
+    if field.is_empty() {
+      return None;
+    }
+    Some(Self::new(field, value))
+  }
+
+  pub fn matches(&self, entry: &JournalEntry) -> bool {
+    entry.get(&self.field) == Some(self.value.as_str())
+  }
+
+  /// What `journalctl -u ssh` used to show, on distributions calling the unit either ssh or sshd.
+  pub fn ssh_defaults() -> Vec<Self> {
+    vec![
+      Self::new("_SYSTEMD_UNIT", "ssh.service"),
+      Self::new("_SYSTEMD_UNIT", "sshd.service"),
+      Self::new("SYSLOG_IDENTIFIER", "sshd"),
+    ]
+  }
+}
+
+/// Follows a journal source and returns new entries that match any of the matches, all entries if there are none.
+///
+/// Journal files are tracked by their file id, so entries left in a file journald just rotated are still read
+/// and the new file it continues in is read from its start.
+pub struct JournalReader {
+  source: JournalSource,
+  matches: Vec<JournalMatch>,
+  files: Vec<JournalFile>,
+  seen_files: HashSet<[u8; 16]>,
+  export_offset: u64,
+}
+
+impl JournalReader {
+  pub fn new(source: JournalSource, matches: Vec<JournalMatch>) -> Self {
+    Self { source, matches, files: vec![], seen_files: HashSet::new(), export_offset: 0 }
+  }
+
+  pub fn source(&self) -> &JournalSource {
+    &self.source
+  }
+
+  /// Skips everything written so far, only entries added afterwards are returned.
+  pub fn seek_tail(&mut self) -> io::Result<()> {
+    self.open_new_files()?;
+    for file in self.files.iter_mut() {
+      file.seek_tail()?;
+    }
+    if let JournalSource::Export(path) = &self.source {
+      let mut reader = ExportReader::new(BufReader::new(File::open(path)?));
+      while reader.next_entry()?.is_some() {}
+      self.export_offset = reader.consumed();
+    }
+    Ok(())
+  }
+
+  /// Returns the matching entries written since the last call, ordered by time.
+  pub fn read_new(&mut self) -> io::Result<Vec<JournalEntry>> {
+    let mut entries = match &self.source {
+      JournalSource::Export(path) => {
+        let path = path.clone();
+        self.read_export(&path)?
+      },
+      _ => {
+        self.open_new_files()?;
+        let mut entries = vec![];
+        for file in self.files.iter_mut() {
+          while let Some(entry) = file.next_entry()? {
+            entries.push(entry);
+          }
+        }
+        // a rotated file is never written to again
+        self.files.retain(|file| !(file.is_archived().unwrap_or(true) && file.at_end().unwrap_or(true)));
+        entries
+      },
+    };
+    entries.retain(|entry| self.matches.is_empty() || self.matches.iter().any(|m| m.matches(entry)));
+    entries.sort_by_key(|entry| entry.realtime_usec());
+    Ok(entries)
+  }
+
+  /// Reads the matching entries written after `since` and before `until`, both in microseconds since the epoch,
+  /// from archived files as well. Does not change what `read_new` returns.
+  pub fn read_range(&self, since: Option<u64>, until: u64) -> io::Result<Vec<JournalEntry>> {
+    let since = since.unwrap_or(0);
+    let mut entries = vec![];
+    match &self.source {
+      JournalSource::Export(path) => {
+        for entry in ExportReader::new(BufReader::new(File::open(path)?)) {
+          entries.push(entry?);
+        }
+      },
+      _ => {
+        for path in self.source.all_files()? {
+          let mut file = match JournalFile::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+              log::warn!("Skipping journal file {}: {}", path.display(), err);
+              continue;
+            },
+          };
+          // nothing new in there
+          if file.tail_realtime()? <= since {
+            continue;
+          }
+          while let Some(entry) = file.next_entry()? {
+            entries.push(entry);
+          }
+        }
+      },
+    }
+    entries.retain(|entry| entry.realtime_usec().is_some_and(|usec| usec > since && usec < until));
+    entries.retain(|entry| self.matches.is_empty() || self.matches.iter().any(|m| m.matches(entry)));
+    entries.sort_by_key(|entry| entry.realtime_usec());
+    Ok(entries)
+  }
+
+  fn open_new_files(&mut self) -> io::Result<()> {
+    for path in self.source.active_files()? {
+      let file = match JournalFile::open(&path) {
+        Ok(file) => file,
+        // journald is still creating the file
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => continue,
+        Err(err) => return Err(err),
+      };
+      if self.seen_files.insert(file.file_id()) {
+        self.files.push(file);
+      }
+    }
+    Ok(())
+  }
+
+  fn read_export(&mut self, path: &Path) -> io::Result<Vec<JournalEntry>> {
+    let mut file = File::open(path)?;
+    // truncated or replaced, start over
+    if file.metadata()?.len() < self.export_offset {
+      self.export_offset = 0;
+    }
+    file.seek(SeekFrom::Start(self.export_offset))?;
+    let mut reader = ExportReader::new(BufReader::new(file));
+    let mut entries = vec![];
+    while let Some(entry) = reader.next_entry()? {
+      entries.push(entry);
+    }
+    self.export_offset += reader.consumed();
+    Ok(entries)
+  }
+
+  /// Sends new entries until cancelled. Wakes up on changes in the journal directories and polls as fallback.
+  pub async fn follow(mut self, tx: UnboundedSender<JournalEntry>, cancel: CancellationToken) {
+    let (changed_tx, mut changed_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let watcher = notify::recommended_watcher(move |_res: notify::Result<notify::Event>| {
+      let _ = changed_tx.send(());
+    });
+    let _watcher = match watcher {
+      Ok(mut watcher) => {
+        for dir in self.source.watch_dirs() {
+          if let Err(err) = watcher.watch(&dir, RecursiveMode::Recursive) {
+            log::warn!("Could not watch {}: {}", dir.display(), err);
+          }
+        }
+        Some(watcher)
+      },
+      Err(err) => {
+        log::warn!("Journal change notifications unavailable, polling: {}", err);
+        None
+      },
+    };
+
+    let mut last_error = String::new();
+    loop {
+      match self.read_new() {
+        Ok(entries) => {
+          last_error.clear();
+          for entry in entries {
+            if tx.send(entry).is_err() {
+              return;
+            }
+          }
+        },
+        Err(err) => {
+          // only log when the error changes, the source may be missing for a while
+          if err.to_string() != last_error {
+            log::error!("Reading journal {:?} failed: {}", self.source, err);
+            last_error = err.to_string();
+          }
+        },
+      }
+      tokio::select! {
+        _ = cancel.cancelled() => break,
+        _ = changed_rx.recv() => {},
+        _ = tokio::time::sleep(POLL_INTERVAL) => {},
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::super::export::tests::fixture;
+  use super::super::file::tests::{set_n_entries, sshd_entry, write_journal};
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("s2b-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("machine-id")).unwrap();
+    dir
+  }
+
+  #[test]
+  fn test_directory_rotation() {
+    let dir = temp_dir("journal-dir");
+    let active = dir.join("machine-id").join("system.journal");
+    let entries = vec![
+      sshd_entry("1", "Failed password for root from 192.0.2.1 port 1 ssh2"),
+      vec![("_PID", "1"), ("SYSLOG_IDENTIFIER", "systemd"), ("MESSAGE", "Started Session 1.")],
+      sshd_entry("2", "Failed password for root from 192.0.2.2 port 2 ssh2"),
+    ];
+    write_journal(&active, 1, &entries, 2);
+    set_n_entries(&active, 1);
+
+    let mut reader = JournalReader::new(JournalSource::from_path(&dir), JournalMatch::ssh_defaults());
+    reader.seek_tail().unwrap();
+    assert!(reader.read_new().unwrap().is_empty());
+
+    set_n_entries(&active, 3);
+    let new: Vec<Option<u32>> = reader.read_new().unwrap().iter().map(|entry| entry.pid()).collect();
+    assert_eq!(new, vec![Some(2)]);
+
+    // journald archives the file and continues in a new one with another file id
+    std::fs::rename(&active, dir.join("machine-id").join("system@0001-0002.journal~")).unwrap();
+    write_journal(&active, 2, &[sshd_entry("3", "Invalid user oracle from 203.0.113.9 port 44312")], 1);
+    let new: Vec<Option<u32>> = reader.read_new().unwrap().iter().map(|entry| entry.pid()).collect();
+    assert_eq!(new, vec![Some(3)]);
+    assert!(reader.read_new().unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_read_range() {
+    let dir = temp_dir("journal-range");
+    let archived = dir.join("machine-id").join("system@0001-0001.journal");
+    write_journal(&archived, 1, &[sshd_entry("1", "Failed password for root from 192.0.2.1 port 1 ssh2")], 1);
+    let active = dir.join("machine-id").join("system.journal");
+    let entries = vec![
+      sshd_entry("2", "Failed password for root from 192.0.2.2 port 2 ssh2"),
+      sshd_entry("3", "Failed password for root from 192.0.2.3 port 3 ssh2"),
+      sshd_entry("4", "Failed password for root from 192.0.2.4 port 4 ssh2"),
+    ];
+    write_journal(&active, 2, &entries, 1);
+
+    let reader = JournalReader::new(JournalSource::Directory(dir.clone()), JournalMatch::ssh_defaults());
+    let pids = |entries: Vec<JournalEntry>| entries.iter().map(|entry| entry.pid().unwrap()).collect::<Vec<u32>>();
+    let mut all = pids(reader.read_range(None, u64::MAX).unwrap());
+    // both files start at the same time in the fixture, only the order within a file is given
+    assert_eq!(all[2..], [3, 4]);
+    all.sort();
+    assert_eq!(all, vec![1, 2, 3, 4]);
+    assert_eq!(pids(reader.read_range(Some(1_700_410_090_000_000), 1_700_410_092_000_000).unwrap()), vec![3]);
+    assert!(reader.read_range(Some(1_700_410_092_000_000), u64::MAX).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_export_file() {
+    let dir = temp_dir("journal-export");
+    let path = dir.join("sshd.export");
+    let data = fixture();
+    let split = data.len() - 20;
+    std::fs::write(&path, &data[..split]).unwrap();
+
+    let mut reader = JournalReader::new(JournalSource::from_path(&path), JournalMatch::ssh_defaults());
+    assert_eq!(reader.source(), &JournalSource::Export(path.clone()));
+    let first = reader.read_new().unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].message(), "Failed password for root from 192.0.2.4 port 52234 ssh2");
+
+    std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&data[split..]).unwrap();
+    let rest = reader.read_new().unwrap();
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0].pid(), Some(1235));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_follow() {
+    let dir = temp_dir("journal-follow");
+    let path = dir.join("sshd.export");
+    std::fs::write(&path, b"").unwrap();
+    let mut reader = JournalReader::new(JournalSource::Export(path.clone()), vec![JournalMatch::parse("_PID=1235").unwrap()]);
+    reader.seek_tail().unwrap();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let cancel = CancellationToken::new();
+    let handle = tokio::spawn(reader.follow(tx, cancel.clone()));
+    std::fs::write(&path, fixture()).unwrap();
+    let entry = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+    assert_eq!(entry.pid(), Some(1235));
+    cancel.cancel();
+    handle.await.unwrap();
+    assert!(JournalMatch::parse("=x").is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
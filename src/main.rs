@@ -16,6 +16,12 @@ pub mod themes;
 pub mod animations;
 pub mod database;
 pub mod action_handlers;
+pub mod backfill;
+pub mod fail2ban;
+pub mod ipextract;
+pub mod rdns;
+pub mod journal;
+pub mod sshd;
 pub mod ui;
 
 use clap::Parser;
@@ -33,7 +39,10 @@ async fn tokio_main() -> Result<()> {
   initialize_panic_handler()?;
 
   let args = Cli::parse();
-  let mut app = App::new(args.tick_rate, args.frame_rate)?;
+  if args.recompute_stats {
+    return database::repair_stats(args.db);
+  }
+  let mut app = App::new(args.tick_rate, args.frame_rate, args.db)?;
   app.run().await?;
 
   Ok(())
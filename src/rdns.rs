@@ -0,0 +1,278 @@
+//! Reverse DNS of attacking addresses.
+//! A PTR name alone is whatever the owner of the address block chose, it only means something
+//! if the name resolves back to the address (forward-confirmed reverse DNS).
+
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::{mpsc::UnboundedSender, Semaphore};
+
+use crate::{action::Action, database::schema::rdns::ReverseDns};
+
+/// Resolver threads blocked at once.
+const MAX_CONCURRENT: usize = 8;
+/// How long a cached lookup is trusted.
+pub const RDNS_TTL: chrono::Duration = chrono::Duration::days(1);
+
+/// Name resolution, swapped for a stub in tests.
+pub trait Resolver: Send + Sync {
+  /// PTR name of the address, `None` if it has none.
+  fn reverse(&self, addr: IpAddr) -> BoxFuture<'_, io::Result<Option<String>>>;
+  /// Addresses the name resolves to.
+  fn forward(&self, host: String) -> BoxFuture<'_, io::Result<Vec<IpAddr>>>;
+}
+
+/// Resolves through the system resolver, like every other program on the host.
+/// The resolver blocks a thread per lookup and can't be cancelled, a timed out lookup keeps its slot until it returns.
+pub struct SystemResolver {
+  slots: Arc<Semaphore>,
+}
+
+impl SystemResolver {
+  pub fn new() -> Self {
+    Self { slots: Arc::new(Semaphore::new(MAX_CONCURRENT)) }
+  }
+
+  /// Runs `resolve` on a blocking thread once a slot is free, the slot is given back when it returns.
+  async fn blocking<T: Send + 'static>(&self, resolve: impl FnOnce() -> io::Result<T> + Send + 'static) -> io::Result<T> {
+    let permit = self.slots.clone().acquire_owned().await.map_err(io::Error::other)?;
+    tokio::task::spawn_blocking(move || {
+      let _permit = permit;
+      resolve()
+    })
+    .await
+    .map_err(io::Error::other)?
+  }
+}
+
+impl Default for SystemResolver {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Resolver for SystemResolver {
+  fn reverse(&self, addr: IpAddr) -> BoxFuture<'_, io::Result<Option<String>>> {
+    Box::pin(self.blocking(move || name_info(addr)))
+  }
+
+  fn forward(&self, host: String) -> BoxFuture<'_, io::Result<Vec<IpAddr>>> {
+    Box::pin(self.blocking(move || Ok((host.as_str(), 0).to_socket_addrs()?.map(|sock| sock.ip()).collect())))
+  }
+}
+
+/// getnameinfo(3) insisting on a name, the std library only resolves forward.
+fn name_info(addr: IpAddr) -> io::Result<Option<String>> {
+  // SAFETY: the sockaddr is zeroed before the fields for its family are set and outlives the call,
+  // getnameinfo writes a NUL terminated name of at most `host.len()` bytes into `host`.
+  unsafe {
+    let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+    let len = match addr {
+      IpAddr::V4(v4) => {
+        let sin = &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in);
+        sin.sin_family = libc::AF_INET as libc::sa_family_t;
+        sin.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+        std::mem::size_of::<libc::sockaddr_in>()
+      },
+      IpAddr::V6(v6) => {
+        let sin6 = &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6);
+        sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        sin6.sin6_addr.s6_addr = v6.octets();
+        std::mem::size_of::<libc::sockaddr_in6>()
+      },
+    };
+    let mut host = [0 as libc::c_char; 1025];
+    let ret = libc::getnameinfo(
+      &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+      len as libc::socklen_t,
+      host.as_mut_ptr(),
+      host.len() as libc::socklen_t,
+      std::ptr::null_mut(),
+      0,
+      libc::NI_NAMEREQD,
+    );
+    match ret {
+      0 => Ok(Some(std::ffi::CStr::from_ptr(host.as_ptr()).to_string_lossy().into_owned())),
+      libc::EAI_NONAME => Ok(None),
+      _ => Err(io::Error::other(std::ffi::CStr::from_ptr(libc::gai_strerror(ret)).to_string_lossy().into_owned())),
+    }
+  }
+}
+
+/// Looks up the PTR name of the address and whether it resolves back to it, both within `timeout`.
+pub async fn lookup(resolver: &dyn Resolver, addr: IpAddr, timeout: Duration) -> io::Result<ReverseDns> {
+  let looked_up_at = chrono::offset::Local::now();
+  let hostname = tokio::time::timeout(timeout, resolver.reverse(addr)).await.map_err(|_| timed_out("reverse"))??;
+  let confirmed = match &hostname {
+    Some(host) => {
+      // a name that doesn't resolve at all is as unconfirmed as one resolving elsewhere
+      match tokio::time::timeout(timeout, resolver.forward(host.clone())).await.map_err(|_| timed_out("forward"))? {
+        Ok(addrs) => addrs.iter().any(|resolved| same_addr(*resolved, addr)),
+        Err(_) => false,
+      }
+    },
+    None => false,
+  };
+  Ok(ReverseDns { hostname: hostname.map(|host| host.trim_end_matches('.').to_string()), confirmed, looked_up_at })
+}
+
+fn timed_out(direction: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::TimedOut, format!("{} lookup timed out", direction))
+}
+
+/// IPv4 addresses may come back mapped into IPv6.
+fn same_addr(a: IpAddr, b: IpAddr) -> bool {
+  a.to_canonical() == b.to_canonical()
+}
+
+/// Whether a cached lookup has to be repeated.
+pub fn is_stale(cached: Option<&ReverseDns>) -> bool {
+  let Some(cached) = cached else { return true };
+  chrono::offset::Local::now() - cached.looked_up_at > RDNS_TTL
+}
+
+/// Runs lookups in the background and answers with `Action::GotReverseDns`.
+/// An address is only looked up once while its lookup is running.
+#[derive(Clone)]
+pub struct ReverseLookup {
+  resolver: Arc<dyn Resolver>,
+  timeout: Duration,
+  pending: Arc<Mutex<HashSet<IpAddr>>>,
+}
+
+impl ReverseLookup {
+  pub fn new(resolver: Arc<dyn Resolver>, timeout: Duration) -> Self {
+    Self { resolver, timeout, pending: Arc::default() }
+  }
+
+  /// LAN and reserved addresses are not looked up, their names only mean something on our network.
+  pub fn request(&self, addr: IpAddr, tx: UnboundedSender<Action>) {
+    let class = crate::ipextract::classify(addr);
+    if class.is_local() || class == crate::ipextract::AddrClass::Reserved {
+      return;
+    }
+    if !self.pending.lock().unwrap().insert(addr) {
+      return;
+    }
+    let this = self.clone();
+    tokio::spawn(async move {
+      let result = lookup(this.resolver.as_ref(), addr, this.timeout).await;
+      this.pending.lock().unwrap().remove(&addr);
+      match result {
+        Ok(rdns) => tx.send(Action::GotReverseDns(addr.to_string(), rdns)).unwrap_or_default(),
+        // timeouts and resolver failures are retried the next time the IP shows up
+        Err(e) => log::debug!("Reverse lookup of {} failed: {}", addr, e),
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+
+  /// Answers from fixed tables, names missing from `forward` don't resolve.
+  #[derive(Default)]
+  struct StubResolver {
+    reverse: HashMap<IpAddr, String>,
+    forward: HashMap<String, Vec<IpAddr>>,
+    delay: Duration,
+  }
+
+  impl Resolver for StubResolver {
+    fn reverse(&self, addr: IpAddr) -> BoxFuture<'_, io::Result<Option<String>>> {
+      Box::pin(async move {
+        tokio::time::sleep(self.delay).await;
+        Ok(self.reverse.get(&addr).cloned())
+      })
+    }
+
+    fn forward(&self, host: String) -> BoxFuture<'_, io::Result<Vec<IpAddr>>> {
+      Box::pin(async move {
+        self.forward.get(&host).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "NXDOMAIN"))
+      })
+    }
+  }
+
+  fn addr(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+  }
+
+  fn stub() -> StubResolver {
+    let mut resolver = StubResolver::default();
+    resolver.reverse.insert(addr("192.0.2.1"), String::from("mail.example.net."));
+    resolver.forward.insert(String::from("mail.example.net."), vec![addr("::ffff:192.0.2.1")]);
+    resolver.reverse.insert(addr("192.0.2.2"), String::from("google.com"));
+    resolver.forward.insert(String::from("google.com"), vec![addr("198.51.100.1")]);
+    resolver.reverse.insert(addr("192.0.2.3"), String::from("dangling.example.net"));
+    resolver
+  }
+
+  #[tokio::test]
+  async fn test_forward_confirmed() {
+    let resolver = stub();
+    let timeout = Duration::from_secs(1);
+    let confirmed = lookup(&resolver, addr("192.0.2.1"), timeout).await.unwrap();
+    assert_eq!((confirmed.hostname.as_deref(), confirmed.confirmed), (Some("mail.example.net"), true));
+    assert!(!is_stale(Some(&confirmed)));
+
+    let spoofed = lookup(&resolver, addr("192.0.2.2"), timeout).await.unwrap();
+    assert_eq!((spoofed.hostname.as_deref(), spoofed.confirmed), (Some("google.com"), false));
+    let dangling = lookup(&resolver, addr("192.0.2.3"), timeout).await.unwrap();
+    assert_eq!((dangling.hostname.as_deref(), dangling.confirmed), (Some("dangling.example.net"), false));
+    let nameless = lookup(&resolver, addr("192.0.2.4"), timeout).await.unwrap();
+    assert_eq!((nameless.hostname, nameless.confirmed), (None, false));
+
+    assert!(is_stale(None));
+    assert!(is_stale(Some(&ReverseDns { looked_up_at: chrono::offset::Local::now() - RDNS_TTL * 2, ..Default::default() })));
+  }
+
+  #[tokio::test]
+  async fn test_lookup_timeout() {
+    let resolver = StubResolver { delay: Duration::from_secs(5), ..stub() };
+    let err = lookup(&resolver, addr("192.0.2.1"), Duration::from_millis(10)).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let lookups = ReverseLookup::new(Arc::new(stub()), Duration::from_secs(1));
+    lookups.request(addr("192.0.2.1"), tx.clone());
+    lookups.request(addr("192.0.2.1"), tx);
+    match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap() {
+      Some(Action::GotReverseDns(ip, rdns)) => assert_eq!((ip.as_str(), rdns.confirmed), ("192.0.2.1", true)),
+      action => panic!("unexpected {:?}", action),
+    }
+    // the second request joined the first
+    assert!(rx.recv().await.is_none());
+  }
+
+  #[tokio::test]
+  async fn test_timed_out_lookup_keeps_slot() {
+    let resolver = SystemResolver::new();
+    let slow = resolver.blocking(|| {
+      std::thread::sleep(Duration::from_millis(200));
+      Ok(())
+    });
+    assert!(tokio::time::timeout(Duration::from_millis(10), slow).await.is_err());
+    // the thread still resolves, no other lookup may take its place
+    assert_eq!(resolver.slots.available_permits(), MAX_CONCURRENT - 1);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(resolver.slots.available_permits(), MAX_CONCURRENT);
+  }
+
+  #[tokio::test]
+  async fn test_skip_local() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let lookups = ReverseLookup::new(Arc::new(stub()), Duration::from_secs(1));
+    for ip in ["192.168.1.1", "10.0.0.1", "fe80::1", "127.0.0.1", "224.0.0.1"] {
+      lookups.request(addr(ip), tx.clone());
+    }
+    drop(tx);
+    assert!(tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().is_none());
+  }
+}
@@ -0,0 +1,204 @@
+//! sshd log line parser.
+//! Turns journal lines of the ssh unit into typed events carrying user, address, port and auth method.
+
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::ipextract;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SshOutcome {
+  Accepted,
+  Failed,
+  InvalidUser,
+  /// Connection closed or reset before or after authentication.
+  Closed,
+  Disconnected,
+  /// Key exchange or banner exchange failed, typically scanners.
+  KexFailed,
+  Timeout,
+}
+
+impl SshOutcome {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SshOutcome::Accepted => "accepted",
+      SshOutcome::Failed => "failed",
+      SshOutcome::InvalidUser => "invalid_user",
+      SshOutcome::Closed => "closed",
+      SshOutcome::Disconnected => "disconnected",
+      SshOutcome::KexFailed => "kex_failed",
+      SshOutcome::Timeout => "timeout",
+    }
+  }
+}
+
+impl std::str::FromStr for SshOutcome {
+  type Err = String;
+
+  fn from_str(outcome: &str) -> Result<Self, Self::Err> {
+    match outcome {
+      "accepted" => Ok(SshOutcome::Accepted),
+      "failed" => Ok(SshOutcome::Failed),
+      "invalid_user" => Ok(SshOutcome::InvalidUser),
+      "closed" => Ok(SshOutcome::Closed),
+      "disconnected" => Ok(SshOutcome::Disconnected),
+      "kex_failed" => Ok(SshOutcome::KexFailed),
+      "timeout" => Ok(SshOutcome::Timeout),
+      _ => Err(format!("Unknown ssh outcome: {}", outcome)),
+    }
+  }
+}
+
+impl std::fmt::Display for SshOutcome {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+/// A single parsed sshd line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SshEvent {
+  pub outcome: SshOutcome,
+  pub user: Option<String>,
+  /// sshd reported the user as not existing.
+  pub invalid_user: bool,
+  pub ip: Option<IpAddr>,
+  pub port: Option<u16>,
+  /// Auth method, e.g. `password` or `publickey`.
+  pub method: Option<String>,
+  /// Line was logged before authentication finished.
+  pub preauth: bool,
+}
+
+/// Patterns for the sshd messages we understand, tried in order.
+/// Named groups: `user`, `invalid`, `ip`, `port`, `method`.
+fn patterns() -> &'static Vec<(SshOutcome, Regex)> {
+  static RE: OnceLock<Vec<(SshOutcome, Regex)>> = OnceLock::new();
+  RE.get_or_init(|| {
+    let user = r"(?:(?P<invalid>invalid|illegal) user |(?:authenticating )?user )?(?P<user>\S*?)";
+    let addr = r"(?P<ip>[0-9A-Fa-f:.\[\]%]+) port (?P<port>\d+)";
+    [
+      (SshOutcome::Accepted, format!(r"Accepted (?P<method>\S+) for (?P<user>\S+) from {addr}")),
+      (SshOutcome::Failed, format!(r"Failed (?P<method>\S+) for {user} from {addr}")),
+      (SshOutcome::Failed, format!(r"maximum authentication attempts exceeded for {user} from {addr}")),
+      (SshOutcome::Failed, r"pam_unix\(sshd:auth\): authentication failure;.*rhost=(?P<ip>\S+)(?:\s+user=(?P<user>\S+))?".to_string()),
+      (SshOutcome::InvalidUser, format!(r"(?P<invalid>Invalid|Illegal) user (?P<user>\S*) from {addr}")),
+      (SshOutcome::Closed, format!(r"Connection (?:closed|reset) by (?:{user} )?{addr}")),
+      (SshOutcome::Disconnected, format!(r"Received disconnect from {addr}")),
+      (SshOutcome::Disconnected, format!(r"Disconnected from (?:{user} )?{addr}")),
+      (SshOutcome::KexFailed, format!(r"Unable to negotiate with {addr}")),
+      (SshOutcome::KexFailed, format!(r"banner exchange: Connection from {addr}")),
+      (SshOutcome::KexFailed, format!(r"kex_exchange_identification: .*?(?:from )?{addr}")),
+      (SshOutcome::KexFailed, r"kex_exchange_identification: ".to_string()),
+      (SshOutcome::Timeout, format!(r"Timeout before authentication for (?:connection from )?{addr}")),
+    ]
+    .into_iter()
+    .map(|(outcome, re)| (outcome, Regex::new(&re).unwrap()))
+    .collect()
+  })
+}
+
+/// Parses an sshd line, with or without the syslog prefix. Returns `None` for lines we don't understand.
+pub fn parse_line(line: &str) -> Option<SshEvent> {
+  let (outcome, captures) = patterns().iter().find_map(|(outcome, re)| re.captures(line).map(|c| (*outcome, c)))?;
+  let user = captures.name("user").map(|m| m.as_str()).filter(|user| !user.is_empty()).map(String::from);
+  Some(SshEvent {
+    outcome,
+    user,
+    invalid_user: captures.name("invalid").is_some(),
+    ip: captures.name("ip").and_then(|m| ipextract::parse_ip(m.as_str())),
+    port: captures.name("port").and_then(|m| m.as_str().parse().ok()),
+    method: captures.name("method").map(|m| m.as_str().to_string()),
+    preauth: line.contains("[preauth]"),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_failed_and_accepted() {
+    let failed = parse_line("Nov 19 16:08:10 host sshd[1234]: Failed password for invalid user admin from 192.0.2.4 port 52234 ssh2").unwrap();
+    assert_eq!(failed.outcome, SshOutcome::Failed);
+    assert_eq!(failed.user.as_deref(), Some("admin"));
+    assert!(failed.invalid_user);
+    assert_eq!(failed.ip, Some("192.0.2.4".parse().unwrap()));
+    assert_eq!(failed.port, Some(52234));
+    assert_eq!(failed.method.as_deref(), Some("password"));
+
+    let root = parse_line("Failed publickey for root from 2001:db8::4 port 40022 ssh2: ED25519 SHA256:abc").unwrap();
+    assert_eq!(root.user.as_deref(), Some("root"));
+    assert!(!root.invalid_user);
+    assert_eq!(root.ip, Some("2001:db8::4".parse().unwrap()));
+    assert_eq!(root.method.as_deref(), Some("publickey"));
+
+    let accepted = parse_line("Accepted publickey for deploy from 198.51.100.2 port 50312 ssh2: RSA SHA256:xyz").unwrap();
+    assert_eq!(accepted.outcome, SshOutcome::Accepted);
+    assert_eq!(accepted.user.as_deref(), Some("deploy"));
+    assert_eq!(accepted.port, Some(50312));
+  }
+
+  #[test]
+  fn test_invalid_user_and_closed() {
+    let invalid = parse_line("Invalid user oracle from 203.0.113.9 port 44312").unwrap();
+    assert_eq!(invalid.outcome, SshOutcome::InvalidUser);
+    assert_eq!(invalid.user.as_deref(), Some("oracle"));
+    assert!(invalid.invalid_user);
+
+    let closed = parse_line("Connection closed by authenticating user root 203.0.113.9 port 44312 [preauth]").unwrap();
+    assert_eq!(closed.outcome, SshOutcome::Closed);
+    assert_eq!(closed.user.as_deref(), Some("root"));
+    assert!(closed.preauth);
+
+    let closed_invalid = parse_line("Connection closed by invalid user test 203.0.113.9 port 44313 [preauth]").unwrap();
+    assert_eq!(closed_invalid.user.as_deref(), Some("test"));
+    assert!(closed_invalid.invalid_user);
+
+    let closed_anon = parse_line("Connection closed by 203.0.113.9 port 44314 [preauth]").unwrap();
+    assert_eq!(closed_anon.user, None);
+    assert_eq!(closed_anon.ip, Some("203.0.113.9".parse().unwrap()));
+
+    let reset = parse_line("Connection reset by 203.0.113.9 port 44315").unwrap();
+    assert_eq!(reset.outcome, SshOutcome::Closed);
+  }
+
+  #[test]
+  fn test_disconnects_and_kex() {
+    let disconnected = parse_line("Disconnected from invalid user admin 192.0.2.4 port 52234 [preauth]").unwrap();
+    assert_eq!(disconnected.outcome, SshOutcome::Disconnected);
+    assert_eq!(disconnected.user.as_deref(), Some("admin"));
+    assert_eq!(disconnected.port, Some(52234));
+
+    let received = parse_line("Received disconnect from 192.0.2.4 port 52234:11: Bye Bye [preauth]").unwrap();
+    assert_eq!(received.outcome, SshOutcome::Disconnected);
+    assert_eq!(received.ip, Some("192.0.2.4".parse().unwrap()));
+
+    let negotiate =
+      parse_line("Unable to negotiate with 192.0.2.7 port 39044: no matching host key type found. Their offer: ssh-rsa [preauth]").unwrap();
+    assert_eq!(negotiate.outcome, SshOutcome::KexFailed);
+    assert_eq!(negotiate.port, Some(39044));
+
+    let banner = parse_line("banner exchange: Connection from 192.0.2.8 port 51234: invalid format").unwrap();
+    assert_eq!(banner.outcome, SshOutcome::KexFailed);
+    assert_eq!(banner.ip, Some("192.0.2.8".parse().unwrap()));
+
+    let kex = parse_line("error: kex_exchange_identification: Connection closed by remote host").unwrap();
+    assert_eq!(kex.outcome, SshOutcome::KexFailed);
+    assert_eq!(kex.ip, None);
+  }
+
+  #[test]
+  fn test_other_lines() {
+    let pam =
+      parse_line("pam_unix(sshd:auth): authentication failure; logname= uid=0 euid=0 tty=ssh ruser= rhost=192.0.2.4  user=root").unwrap();
+    assert_eq!(pam.outcome, SshOutcome::Failed);
+    assert_eq!(pam.user.as_deref(), Some("root"));
+    assert!(parse_line("Server listening on 0.0.0.0 port 22.").is_none());
+    assert_eq!(SshOutcome::KexFailed.as_str().parse::<SshOutcome>(), Ok(SshOutcome::KexFailed));
+  }
+}
@@ -325,8 +325,6 @@ pub struct Theme {
     // Styles
     pub styles_app: AppStyles,
 
-    pub ipregex: Regex,
-
     // Timing
     pub decay_time: tokio::time::Duration,
 
@@ -355,8 +353,8 @@ impl Default for Theme {
             RegexStyleMap{ regex_styles: vec![
                 RegexStylePair::new(Regex::new(r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})").unwrap(), Style::default().fg(colors.accent_color_b_bright.color)), // IP v4
                 RegexStylePair::new(Regex::new(r"(\d{2}:\d{2}:\d{2})").unwrap(), Style::default().fg(colors.accent_color_b_bright.color)), // Timestamp HH:MM:SS
+                RegexStylePair::new(Regex::new(r"^\[?(?i:[0-9a-f]{0,4}:){2,7}(?i:[0-9a-f]{0,4})(?:%\w+)?\]?(?::\d+)?$").unwrap(), Style::default().fg(colors.accent_color_b_bright.color)), // IP v6
             ]},
-            ipregex: Regex::new(r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})").unwrap(),
             decay_time: tokio::time::Duration::from_secs(10),
             symbols: AppSymbols::default(),
 
@@ -394,8 +392,8 @@ impl Theme {
             RegexStyleMap{ regex_styles: vec![
                 RegexStylePair::new(Regex::new(r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})").unwrap(), Style::default().fg(colors.accent_color_a.color)), // IP v4
                 RegexStylePair::new(Regex::new(r"(\d{2}:\d{2}:\d{2})").unwrap(), Style::default().fg(colors.accent_color_a.color)), // Timestamp HH:MM:SS
+                RegexStylePair::new(Regex::new(r"^\[?(?i:[0-9a-f]{0,4}:){2,7}(?i:[0-9a-f]{0,4})(?:%\w+)?\]?(?::\d+)?$").unwrap(), Style::default().fg(colors.accent_color_a.color)), // IP v6
             ]},
-            ipregex: Regex::new(r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})").unwrap(),
             decay_time: tokio::time::Duration::from_secs(10),
             symbols: AppSymbols::default(),
 
@@ -423,8 +421,8 @@ impl Theme {
             RegexStyleMap{ regex_styles: vec![
                 RegexStylePair::new(Regex::new(r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})").unwrap(), Style::default().fg(colors.accent_color_a.color)), // IP v4
                 RegexStylePair::new(Regex::new(r"(\d{2}:\d{2}:\d{2})").unwrap(), Style::default().fg(colors.accent_color_a.color)), // Timestamp HH:MM:SS
+                RegexStylePair::new(Regex::new(r"^\[?(?i:[0-9a-f]{0,4}:){2,7}(?i:[0-9a-f]{0,4})(?:%\w+)?\]?(?::\d+)?$").unwrap(), Style::default().fg(colors.accent_color_a.color)), // IP v6
             ]},
-            ipregex: Regex::new(r"(\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})").unwrap(),
             decay_time: tokio::time::Duration::from_secs(10),
             symbols: AppSymbols::default(),
 